@@ -0,0 +1,14 @@
+DROP TABLE IF EXISTS password_resets;
+DROP TABLE IF EXISTS rate_limits;
+DROP TABLE IF EXISTS login_failures;
+DROP TABLE IF EXISTS api_keys;
+DROP TABLE IF EXISTS invitations;
+DROP TABLE IF EXISTS memberships;
+DROP TABLE IF EXISTS organizations;
+DROP TABLE IF EXISTS user_roles;
+DROP TABLE IF EXISTS permissions;
+DROP TABLE IF EXISTS roles;
+DROP TABLE IF EXISTS saml_assertions;
+DROP TABLE IF EXISTS identities;
+DROP TABLE IF EXISTS verification_codes;
+DROP TABLE IF EXISTS users;
@@ -0,0 +1,5 @@
+-- The users whose emails differ only by case, one group per row, which the unique index of the migration refuses.
+SELECT string_agg(email->>'email' || ' (' || encode(id, 'hex') || ')', ', ' ORDER BY email->>'email')
+FROM users
+GROUP BY LOWER(email->>'email')
+HAVING COUNT(*) > 1;
@@ -0,0 +1,7 @@
+DROP INDEX IF EXISTS users_email_index;
+ALTER TABLE users ADD COLUMN email_document JSONB;
+UPDATE users SET email_document = jsonb_build_object('email', email, 'verified', email_verified_at IS NOT NULL);
+ALTER TABLE users ALTER COLUMN email_document SET NOT NULL;
+ALTER TABLE users DROP COLUMN email, DROP COLUMN email_verified_at;
+ALTER TABLE users RENAME COLUMN email_document TO email;
+CREATE UNIQUE INDEX users_email_index ON users ((email->>'email'));
@@ -0,0 +1,11 @@
+-- The address moves out of the JSON document into a text column, and the verified flag becomes
+-- the time of the verification. Users verified before it was recorded take their creation time.
+ALTER TABLE users RENAME COLUMN email TO email_document;
+ALTER TABLE users ADD COLUMN email TEXT, ADD COLUMN email_verified_at TIMESTAMPTZ;
+UPDATE users SET
+    email = email_document->>'email',
+    email_verified_at = CASE WHEN (email_document->>'verified')::BOOLEAN THEN created_at END;
+ALTER TABLE users ALTER COLUMN email SET NOT NULL;
+DROP INDEX IF EXISTS users_email_index;
+ALTER TABLE users DROP COLUMN email_document;
+CREATE UNIQUE INDEX users_email_index ON users (LOWER(email));
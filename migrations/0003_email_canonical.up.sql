@@ -0,0 +1,7 @@
+-- Users are told apart by the canonical form of their email, the email column keeps it as it was given.
+-- Existing rows take the lower-cased address, which is what the default normalization gives for ASCII domains.
+ALTER TABLE users ADD COLUMN email_canonical TEXT;
+UPDATE users SET email_canonical = LOWER(email);
+ALTER TABLE users ALTER COLUMN email_canonical SET NOT NULL;
+DROP INDEX IF EXISTS users_email_index;
+CREATE UNIQUE INDEX users_email_canonical_index ON users (email_canonical);
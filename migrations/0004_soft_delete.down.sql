@@ -0,0 +1,4 @@
+DROP TABLE IF EXISTS account_restorations;
+DELETE FROM users WHERE deleted_at IS NOT NULL;
+DROP INDEX IF EXISTS users_purge_after_index;
+ALTER TABLE users DROP COLUMN deleted_at, DROP COLUMN purge_after;
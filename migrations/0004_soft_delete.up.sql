@@ -0,0 +1,12 @@
+-- Deleted users are kept, hidden, until purge_after, and can be restored until then.
+ALTER TABLE users ADD COLUMN deleted_at TIMESTAMPTZ, ADD COLUMN purge_after TIMESTAMPTZ;
+CREATE INDEX IF NOT EXISTS users_purge_after_index ON users (purge_after) WHERE purge_after IS NOT NULL;
+
+CREATE TABLE IF NOT EXISTS account_restorations (
+    id UUID PRIMARY KEY,
+    user_id BYTEA NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    expires_at TIMESTAMPTZ NOT NULL,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+CREATE INDEX IF NOT EXISTS account_restorations_user_id_index ON account_restorations (user_id);
@@ -0,0 +1,23 @@
+-- The events outlive the users they are about, so the ids do not reference the users.
+CREATE TABLE IF NOT EXISTS audit_events (
+    id BYTEA PRIMARY KEY,
+    action TEXT NOT NULL,
+    actor_id BYTEA,
+    target_id BYTEA,
+    ip TEXT,
+    user_agent TEXT,
+    details JSONB NOT NULL DEFAULT '{}',
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+CREATE INDEX IF NOT EXISTS audit_events_created_at_index ON audit_events (created_at DESC, id DESC);
+CREATE INDEX IF NOT EXISTS audit_events_actor_id_index ON audit_events (actor_id, created_at DESC);
+CREATE INDEX IF NOT EXISTS audit_events_target_id_index ON audit_events (target_id, created_at DESC);
+CREATE INDEX IF NOT EXISTS audit_events_action_index ON audit_events (action, created_at DESC);
+
+CREATE OR REPLACE FUNCTION audit_events_append_only() RETURNS TRIGGER AS $$
+BEGIN
+    RAISE EXCEPTION 'audit events can not be changed or deleted';
+END;
+$$ LANGUAGE plpgsql;
+CREATE TRIGGER audit_events_append_only BEFORE UPDATE OR DELETE ON audit_events
+    FOR EACH ROW EXECUTE FUNCTION audit_events_append_only();
@@ -0,0 +1,3 @@
+DROP TABLE IF EXISTS audit_checkpoints;
+DROP INDEX IF EXISTS audit_events_sequence_index;
+ALTER TABLE audit_events DROP COLUMN sequence, DROP COLUMN hash;
@@ -0,0 +1,19 @@
+-- The events already recorded are numbered in the order they happened. They keep no hash,
+-- since it is computed by the server, and the chain starts after them.
+ALTER TABLE audit_events ADD COLUMN sequence BIGINT, ADD COLUMN hash TEXT;
+ALTER TABLE audit_events DISABLE TRIGGER audit_events_append_only;
+UPDATE audit_events SET sequence = ordered.sequence
+    FROM (SELECT id, row_number() OVER (ORDER BY created_at, id) AS sequence FROM audit_events) ordered
+    WHERE audit_events.id = ordered.id;
+ALTER TABLE audit_events ENABLE TRIGGER audit_events_append_only;
+ALTER TABLE audit_events ALTER COLUMN sequence SET NOT NULL;
+CREATE UNIQUE INDEX IF NOT EXISTS audit_events_sequence_index ON audit_events (sequence);
+
+CREATE TABLE IF NOT EXISTS audit_checkpoints (
+    sequence BIGINT PRIMARY KEY,
+    hash TEXT NOT NULL,
+    signature TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+CREATE TRIGGER audit_checkpoints_append_only BEFORE UPDATE OR DELETE ON audit_checkpoints
+    FOR EACH ROW EXECUTE FUNCTION audit_events_append_only();
@@ -0,0 +1,4 @@
+-- Where a role assignment comes from. NULL for the roles assigned by hand, otherwise the
+-- directory the role is mapped from, which takes it away again when it stops mapping it.
+-- The assignments made before are all counted as made by hand.
+ALTER TABLE user_roles ADD COLUMN source TEXT;
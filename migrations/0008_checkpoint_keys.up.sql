@@ -0,0 +1,3 @@
+-- The version of the checkpoint key each checkpoint is signed with. NULL for the checkpoints
+-- signed before, with the JWT secret, which are still verified with it.
+ALTER TABLE audit_checkpoints ADD COLUMN key_id BIGINT;
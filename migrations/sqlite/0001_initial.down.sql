@@ -0,0 +1,3 @@
+DROP TABLE IF EXISTS login_failures;
+DROP TABLE IF EXISTS verification_codes;
+DROP TABLE IF EXISTS users;
@@ -0,0 +1,27 @@
+CREATE TABLE IF NOT EXISTS users (
+    id BLOB PRIMARY KEY,
+    email TEXT NOT NULL,
+    user_name TEXT NOT NULL,
+    first_name TEXT NOT NULL,
+    last_name TEXT NOT NULL,
+    password TEXT NOT NULL,
+    profile_picture TEXT,
+    created_at TEXT NOT NULL
+);
+CREATE UNIQUE INDEX IF NOT EXISTS users_email_index ON users (json_extract(email, '$.email'));
+
+CREATE TABLE IF NOT EXISTS verification_codes (
+    id BLOB PRIMARY KEY,
+    user_id BLOB NOT NULL,
+    code TEXT NOT NULL,
+    created_at TEXT NOT NULL,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+CREATE INDEX IF NOT EXISTS user_id_and_created_at_index ON verification_codes (user_id, created_at DESC);
+
+CREATE TABLE IF NOT EXISTS login_failures (
+    subject TEXT PRIMARY KEY,
+    failures INTEGER NOT NULL,
+    last_failed_at TEXT NOT NULL,
+    locked_until TEXT
+);
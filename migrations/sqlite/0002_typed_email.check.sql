@@ -0,0 +1,5 @@
+-- The users whose emails differ only by case, one group per row, which the unique index of the migration refuses.
+SELECT group_concat(json_extract(email, '$.email') || ' (' || lower(hex(id)) || ')', ', ')
+FROM users
+GROUP BY json_extract(email, '$.email') COLLATE NOCASE
+HAVING COUNT(*) > 1;
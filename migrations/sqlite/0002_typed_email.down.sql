@@ -0,0 +1,7 @@
+DROP INDEX IF EXISTS users_email_index;
+ALTER TABLE users ADD COLUMN email_document TEXT NOT NULL DEFAULT '{}';
+UPDATE users SET email_document = json_object('email', email, 'verified', json(CASE WHEN email_verified_at IS NULL THEN 'false' ELSE 'true' END));
+ALTER TABLE users DROP COLUMN email;
+ALTER TABLE users DROP COLUMN email_verified_at;
+ALTER TABLE users RENAME COLUMN email_document TO email;
+CREATE UNIQUE INDEX users_email_index ON users (json_extract(email, '$.email'));
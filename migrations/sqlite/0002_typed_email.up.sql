@@ -0,0 +1,11 @@
+-- The users table is altered in place rather than rebuilt, since dropping it would cascade
+-- to the verification codes. The NOCASE collation makes every comparison of the email ignore case.
+DROP INDEX IF EXISTS users_email_index;
+ALTER TABLE users RENAME COLUMN email TO email_document;
+ALTER TABLE users ADD COLUMN email TEXT NOT NULL DEFAULT '' COLLATE NOCASE;
+ALTER TABLE users ADD COLUMN email_verified_at TEXT;
+UPDATE users SET
+    email = json_extract(email_document, '$.email'),
+    email_verified_at = CASE WHEN json_extract(email_document, '$.verified') THEN created_at END;
+ALTER TABLE users DROP COLUMN email_document;
+CREATE UNIQUE INDEX users_email_index ON users (email);
@@ -0,0 +1,3 @@
+DROP INDEX IF EXISTS users_email_canonical_index;
+ALTER TABLE users DROP COLUMN email_canonical;
+CREATE UNIQUE INDEX users_email_index ON users (email);
@@ -0,0 +1,6 @@
+-- Users are told apart by the canonical form of their email, the email column keeps it as it was given.
+-- Existing rows take the lower-cased address, which is what the default normalization gives for ASCII domains.
+ALTER TABLE users ADD COLUMN email_canonical TEXT NOT NULL DEFAULT '';
+UPDATE users SET email_canonical = LOWER(email);
+DROP INDEX IF EXISTS users_email_index;
+CREATE UNIQUE INDEX users_email_canonical_index ON users (email_canonical);
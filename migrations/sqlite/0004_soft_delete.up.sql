@@ -0,0 +1,4 @@
+-- Deleted users are kept, hidden, until purge_after, and can be restored until then.
+ALTER TABLE users ADD COLUMN deleted_at TEXT;
+ALTER TABLE users ADD COLUMN purge_after TEXT;
+CREATE INDEX IF NOT EXISTS users_purge_after_index ON users (purge_after) WHERE purge_after IS NOT NULL;
@@ -0,0 +1,23 @@
+-- The events outlive the users they are about, so the ids do not reference the users.
+CREATE TABLE IF NOT EXISTS audit_events (
+    id BLOB PRIMARY KEY,
+    action TEXT NOT NULL,
+    actor_id BLOB,
+    target_id BLOB,
+    ip TEXT,
+    user_agent TEXT,
+    details TEXT NOT NULL DEFAULT '{}',
+    created_at TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS audit_events_actor_id_index ON audit_events (actor_id);
+CREATE INDEX IF NOT EXISTS audit_events_target_id_index ON audit_events (target_id);
+CREATE INDEX IF NOT EXISTS audit_events_action_index ON audit_events (action);
+
+CREATE TRIGGER IF NOT EXISTS audit_events_no_update BEFORE UPDATE ON audit_events
+BEGIN
+    SELECT RAISE(ABORT, 'audit events can not be changed or deleted');
+END;
+CREATE TRIGGER IF NOT EXISTS audit_events_no_delete BEFORE DELETE ON audit_events
+BEGIN
+    SELECT RAISE(ABORT, 'audit events can not be changed or deleted');
+END;
@@ -0,0 +1,4 @@
+DROP TABLE IF EXISTS audit_checkpoints;
+DROP INDEX IF EXISTS audit_events_sequence_index;
+ALTER TABLE audit_events DROP COLUMN sequence;
+ALTER TABLE audit_events DROP COLUMN hash;
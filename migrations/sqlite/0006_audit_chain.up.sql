@@ -0,0 +1,26 @@
+-- The events already recorded are numbered in the order they were added. They keep no hash,
+-- since it is computed by the server, and the chain starts after them.
+ALTER TABLE audit_events ADD COLUMN sequence INTEGER;
+ALTER TABLE audit_events ADD COLUMN hash TEXT;
+DROP TRIGGER IF EXISTS audit_events_no_update;
+UPDATE audit_events SET sequence = (SELECT COUNT(*) FROM audit_events earlier WHERE earlier.rowid <= audit_events.rowid);
+CREATE TRIGGER IF NOT EXISTS audit_events_no_update BEFORE UPDATE ON audit_events
+BEGIN
+    SELECT RAISE(ABORT, 'audit events can not be changed or deleted');
+END;
+CREATE UNIQUE INDEX IF NOT EXISTS audit_events_sequence_index ON audit_events (sequence);
+
+CREATE TABLE IF NOT EXISTS audit_checkpoints (
+    sequence INTEGER PRIMARY KEY,
+    hash TEXT NOT NULL,
+    signature TEXT NOT NULL,
+    created_at TEXT NOT NULL
+);
+CREATE TRIGGER IF NOT EXISTS audit_checkpoints_no_update BEFORE UPDATE ON audit_checkpoints
+BEGIN
+    SELECT RAISE(ABORT, 'audit checkpoints can not be changed or deleted');
+END;
+CREATE TRIGGER IF NOT EXISTS audit_checkpoints_no_delete BEFORE DELETE ON audit_checkpoints
+BEGIN
+    SELECT RAISE(ABORT, 'audit checkpoints can not be changed or deleted');
+END;
@@ -0,0 +1,256 @@
+use clap::{Parser, Subcommand, ValueEnum};
+use crate::import::{self, FileFormat};
+use crate::{audit, canonical_email, export, UserStore};
+use crate::email_domain::DomainPolicy;
+use futures_util::StreamExt;
+use tokio::io::AsyncWriteExt;
+use crate::config::{migration, Config};
+use std::path::PathBuf;
+use std::sync::Arc;
+use super::Result;
+
+
+///An authentication server.
+#[derive(Parser)]
+#[command(version, about)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+
+#[derive(Subcommand)]
+pub enum Command {
+    ///Starts the server. This is what runs when no command is given.
+    Serve,
+    ///Builds a breached password filter from the SHA-1 range files of Have I Been Pwned.
+    BuildBreachFilter {
+        ///A directory of range files, or a single file of `HASH:COUNT` lines.
+        source: PathBuf,
+        ///Where to write the filter.
+        output: PathBuf,
+        ///How often a password that was never breached may be reported as breached.
+        #[arg(long, default_value_t = 0.001)]
+        false_positive_rate: f64,
+    },
+    ///Imports users with their ids and password hashes from another system.
+    /// Their bcrypt, scrypt and PBKDF2 hashes are replaced with Argon2 ones as they log in.
+    ImportUsers {
+        ///A JSON Lines or CSV file of users.
+        path: PathBuf,
+        ///The format of the file. Taken from its extension when not given.
+        #[arg(long, value_enum)]
+        format: Option<Format>,
+    },
+    ///Exports every user, oldest first.
+    ExportUsers {
+        #[arg(long, value_enum, default_value = "jsonl")]
+        format: Format,
+        ///A comma separated list of the fields to export. All of them when not given.
+        #[arg(long)]
+        fields: Option<String>,
+        ///Includes the password hashes, to move the users to another server.
+        #[arg(long)]
+        include_hashes: bool,
+        ///The file to write. The standard output when not given.
+        #[arg(long)]
+        output: Option<PathBuf>,
+    },
+    ///Recomputes the canonical emails of the users with the configured normalization, after it was changed.
+    /// The users who would share one keep theirs and are listed, to be merged or given other emails.
+    CanonicalizeEmails {
+        ///Lists the changes and collisions without applying the changes.
+        #[arg(long)]
+        dry_run: bool,
+    },
+    ///Applies, reverts or lists the schema migrations.
+    Migrate {
+        #[command(subcommand)]
+        command: MigrateCommand,
+    },
+    ///Checks the audit log.
+    Audit {
+        #[command(subcommand)]
+        command: AuditCommand,
+    },
+}
+
+
+#[derive(Subcommand)]
+pub enum MigrateCommand {
+    ///Applies every pending migration.
+    Up,
+    ///Reverts the latest applied migrations.
+    Down {
+        ///How many migrations to revert.
+        #[arg(long, default_value_t = 1)]
+        steps: usize,
+    },
+    ///Lists the migrations and when they were applied.
+    Status,
+}
+
+
+#[derive(Subcommand)]
+pub enum AuditCommand {
+    ///Checks that no audit event was changed or removed since it was recorded.
+    /// The checkpoints are checked with the checkpoint keys of the configuration,
+    /// and the ones signed before there were checkpoint keys with its JWT secret.
+    Verify,
+}
+
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Format {
+    Jsonl,
+    Csv,
+}
+
+
+impl From<Format> for FileFormat {
+    fn from(format: Format) -> Self {
+        match format {
+            Format::Jsonl => FileFormat::JsonLines,
+            Format::Csv => FileFormat::Csv,
+        }
+    }
+}
+
+
+impl Format {
+    fn from_path(path: &std::path::Path) -> Self {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some(extension) if extension.eq_ignore_ascii_case("csv") => Format::Csv,
+            _ => Format::Jsonl
+        }
+    }
+}
+
+
+impl Cli {
+    pub async fn run(self) -> Result<()> {
+        match self.command.unwrap_or(Command::Serve) {
+            Command::Serve => crate::server::start().await,
+            Command::BuildBreachFilter{source, output, false_positive_rate} => {
+                if !(false_positive_rate > 0.0 && false_positive_rate < 1.0) {
+                    return Err("the false positive rate has to be between 0 and 1".into());
+                }
+                let filter = crate::breach::build_filter(&source, false_positive_rate)?;
+                tokio::fs::write(&output, filter.to_bytes()).await?;
+                println!("wrote the breached password filter to {}", output.display());
+                Ok(())
+            },
+            Command::ImportUsers{path, format} => {
+                let format = format.unwrap_or_else(|| Format::from_path(&path)).into();
+                let config = Config::read().await?;
+                let users = user_store(&config).await?;
+                let domains = DomainPolicy::load(&config.email_domains)?;
+                let file = std::fs::File::open(&path)?;
+                let report = import::import_users(&*users, &config.email_normalization, &domains, import::read_users(format, file)).await?;
+                for (line, message) in &report.failures {
+                    eprintln!("line {}: {}", line, message);
+                }
+                println!("imported {} users, skipped {}", report.imported, report.failures.len());
+                Ok(())
+            },
+            Command::ExportUsers{format, fields, include_hashes, output} => {
+                let fields = export::export_fields(fields.as_deref(), include_hashes)?;
+                let config = Config::read().await?;
+                let users = user_store(&config).await?;
+                let mut writer: Box<dyn tokio::io::AsyncWrite + Unpin> = match output {
+                    Some(ref path) => Box::new(tokio::io::BufWriter::new(tokio::fs::File::create(path).await?)),
+                    None => Box::new(tokio::io::BufWriter::new(tokio::io::stdout())),
+                };
+                // The users are read on this thread, as the futures of the stores are not `Send`.
+                let local = tokio::task::LocalSet::new();
+                local.run_until(async move {
+                    let mut lines = Box::pin(export::export_users(users, format.into(), fields));
+                    while let Some(line) = lines.next().await {
+                        writer.write_all(&line?).await?;
+                    }
+                    writer.flush().await?;
+                    Ok(())
+                }).await
+            },
+            Command::CanonicalizeEmails{dry_run} => {
+                let config = Config::read().await?;
+                let users = user_store(&config).await?;
+                let report = canonical_email::recanonicalize_users(&*users, &config.email_normalization, dry_run).await?;
+                for (id, email) in &report.invalid {
+                    eprintln!("{} {}: not a valid email, left as it is", id.to_hex(), email);
+                }
+                for (canonical, users) in &report.collisions {
+                    let users: Vec<String> = users.iter().map(|(id, email)| format!("{} ({})", email, id.to_hex())).collect();
+                    eprintln!("{} is shared by {}", canonical, users.join(", "));
+                }
+                let verb = if dry_run {"would change"} else {"changed"};
+                println!("{} the canonical email of {} users, {} collisions left", verb, report.changes.len(), report.collisions.len());
+                match report.collisions.len() {
+                    0 => Ok(()),
+                    collisions => Err(format!("{} canonical emails are shared by several users, merge them or change their emails and run this again", collisions).into()),
+                }
+            },
+            Command::Migrate{command} => {
+                let config = Config::read().await?;
+                let database = &config.database;
+                match command {
+                    MigrateCommand::Up => {
+                        let applied = match database.is_sqlite() {
+                            true => migration::up_sqlite(&database.connect_sqlite().await?).await?,
+                            false => migration::up(&database.connect().await?).await?,
+                        };
+                        for migration in &applied {
+                            println!("applied {} {}", migration.version, migration.name);
+                        }
+                        println!("{} migrations applied", applied.len());
+                    },
+                    MigrateCommand::Down{steps} => {
+                        let reverted = match database.is_sqlite() {
+                            true => migration::down_sqlite(&database.connect_sqlite().await?, steps).await?,
+                            false => migration::down(&database.connect().await?, steps).await?,
+                        };
+                        for migration in reverted {
+                            println!("reverted {} {}", migration.version, migration.name);
+                        }
+                    },
+                    MigrateCommand::Status => {
+                        let statuses = match database.is_sqlite() {
+                            true => migration::status_sqlite(&database.connect_sqlite().await?).await?,
+                            false => migration::status(&database.connect().await?).await?,
+                        };
+                        for status in statuses {
+                            let applied = status.applied_at.map_or("pending".to_string(), |applied_at| applied_at.to_rfc3339());
+                            let modified = if status.modified {" (modified)"} else {""};
+                            println!("{:>4} {:<32} {}{}", status.version, status.name, applied, modified);
+                        }
+                    }
+                }
+                Ok(())
+            },
+            Command::Audit{command: AuditCommand::Verify} => {
+                let config = Config::read().await?;
+                let report = match config.database.is_sqlite() {
+                    true => audit::verify(&config.database.init_sqlite().await?, &config.audit_log, &config.jwt).await?,
+                    false => audit::verify(&config.database.init().await?, &config.audit_log, &config.jwt).await?,
+                };
+                for chain_break in &report.breaks {
+                    eprintln!("{}", chain_break);
+                }
+                println!("checked {} events, {} of them recorded before the log was chained, and {} checkpoints", report.events, report.unchained, report.checkpoints);
+                match report.breaks.len() {
+                    0 => Ok(()),
+                    breaks => Err(format!("the audit log was altered in {} places", breaks).into()),
+                }
+            }
+        }
+    }
+}
+
+
+///The users of the configured database, whichever backend it is.
+async fn user_store(config: &Config) -> Result<Arc<dyn UserStore>> {
+    Ok(match config.database.is_sqlite() {
+        true => Arc::new(config.database.init_sqlite().await?),
+        false => Arc::new(config.database.init().await?),
+    })
+}
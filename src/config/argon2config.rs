@@ -1,12 +1,41 @@
-use argon2::{self, Argon2, Algorithm, Version, ParamsBuilder, Params};
+use argon2::{self, Argon2, Algorithm, Version, ParamsBuilder, Params, KeyId};
+use sha2::{Digest, Sha256};
 use serde::{Serialize, Deserialize, Serializer, Deserializer};
 use serde::de::{self, Visitor};
-use tokio::sync::OnceCell;
 use jwt::algorithm;
-use std::fmt;
+use std::{fmt, io};
 
 
-static PEPPER: OnceCell<String> = OnceCell::const_new();
+///Where a secret is read from, so it does not have to be written in `config.json`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SecretSource {
+    Value(String),
+    ///A file holding the secret. A trailing newline is not part of it.
+    File(String),
+    ///An environment variable holding the secret.
+    Env(String),
+}
+
+
+impl SecretSource {
+    pub fn read(&self) -> io::Result<String> {
+        match self {
+            SecretSource::Value(value) => Ok(value.clone()),
+            SecretSource::File(path) => Ok(std::fs::read_to_string(path)?.trim_end_matches(['\r', '\n']).to_string()),
+            SecretSource::Env(name) => std::env::var(name).map_err(|_| io::Error::new(io::ErrorKind::NotFound, format!("the environment variable {} is not set", name))),
+        }
+    }
+}
+
+
+///A pepper of the password hashes. Its version is recorded as the key id of the hashes made with it.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Pepper {
+    pub version: u32,
+    #[serde(flatten)]
+    pub source: SecretSource,
+}
 
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -18,7 +47,15 @@ pub struct Argon2Config {
     pub algorithm: Algorithm,
     #[serde(serialize_with = "serialize_version", deserialize_with = "deserialize_version")]
     pub version: Version,
+    ///The pepper from before peppers were versioned. It is used for the hashes without a pepper version,
+    /// and for new hashes while no versioned pepper is configured.
     pub pepper: Option<String>,
+    ///New hashes use the pepper with the highest version, the others are kept to verify older hashes.
+    #[serde(default)]
+    pub peppers: Vec<Pepper>,
+    ///The peppers read by `load_peppers`, by the key id they are recorded under.
+    #[serde(skip)]
+    pub secrets: Vec<(KeyId, String)>,
 }
 
 
@@ -106,30 +143,67 @@ where
 }
 
 impl Argon2Config {
-    pub async fn initialize_argon2(&self) -> Argon2<'static> {
-        let params = ParamsBuilder::new()
-            .m_cost(self.memory_cost)
-            .t_cost(self.time_cost)
-            .p_cost(self.parallelism)
-            .build()
-            .unwrap();
-        let closure = ||async {
-            match &self.pepper {
-                Some(pepper) => Ok(pepper.clone()),
-                None => Err(())
-            }
+    ///The key id of the unversioned pepper, derived from it without giving it away.
+    fn legacy_pepper_id(pepper: &str) -> KeyId {
+        let digest = Sha256::digest(pepper.as_bytes());
+        KeyId::new(&digest[..Params::MAX_KEYID_LEN]).unwrap()
+    }
+
+    ///Reads the versioned peppers from their sources. Has to be called before any password is hashed or verified.
+    pub fn load_peppers(&mut self) -> io::Result<()> {
+        let mut secrets = Vec::new();
+        if let Some(ref pepper) = self.pepper {
+            secrets.push((Self::legacy_pepper_id(pepper), pepper.clone()));
+        }
+        let mut peppers = self.peppers.clone();
+        peppers.sort_by_key(|pepper| pepper.version);
+        if peppers.windows(2).any(|pair| pair[0].version == pair[1].version) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "two peppers have the same version"));
+        }
+        for pepper in peppers {
+            secrets.push((KeyId::new(&pepper.version.to_be_bytes()).unwrap(), pepper.source.read()?));
+        }
+        self.secrets = secrets;
+        Ok(())
+    }
+
+    ///The pepper new hashes are made with and its key id.
+    fn current_pepper(&self) -> Option<&(KeyId, String)> {
+        self.secrets.last()
+    }
+
+    ///The parameters new hashes are made with.
+    pub fn params(&self) -> Params {
+        let mut builder = ParamsBuilder::new();
+        builder.m_cost(self.memory_cost).t_cost(self.time_cost).p_cost(self.parallelism);
+        if let Some((keyid, _)) = self.current_pepper() {
+            builder.keyid(*keyid);
+        }
+        builder.build().unwrap()
+    }
+
+    ///The hasher that verifies the hashes recorded with the given key id.
+    /// A hash without one uses the unversioned pepper, and there is none for the key id of a removed pepper.
+    pub fn argon2_for(&self, keyid: &[u8]) -> Option<Argon2<'_>> {
+        let pepper = match keyid.is_empty() {
+            true => self.pepper.as_deref(),
+            false => Some(self.secrets.iter().find(|(id, _)| id.as_bytes() == keyid)?.1.as_str()),
         };
-        if let Ok(pepper) = PEPPER.get_or_try_init(closure).await {
-            Argon2::new_with_secret(pepper.as_bytes(), self.algorithm, self.version, params).unwrap()
-        } else {
-            Argon2::new(self.algorithm, self.version, params)
+        match pepper {
+            Some(pepper) => Argon2::new_with_secret(pepper.as_bytes(), self.algorithm, self.version, self.params()).ok(),
+            None => Some(Argon2::new(self.algorithm, self.version, self.params()))
         }
     }
 
-    async fn pepper(&self) -> Result<String, ()> {
-        match &self.pepper {
-            Some(pepper) => Ok(pepper.clone()),
-            None => Err(())
+    ///The hasher of new passwords, peppered with the newest pepper.
+    pub fn initialize_argon2(&self) -> Argon2<'static> {
+        match self.current_pepper() {
+            Some((_, pepper)) => {
+                // Lives as long as the server, which hashes with it until it stops.
+                let pepper: &'static str = Box::leak(pepper.clone().into_boxed_str());
+                Argon2::new_with_secret(pepper.as_bytes(), self.algorithm, self.version, self.params()).unwrap()
+            },
+            None => Argon2::new(self.algorithm, self.version, self.params())
         }
     }
 }
@@ -143,8 +217,10 @@ impl Default for Argon2Config {
         let algorithm = Default::default();
         let version = Default::default();
         let pepper = Default::default();
+        let peppers = Default::default();
+        let secrets = Default::default();
 
-        Argon2Config{memory_cost, time_cost, parallelism, algorithm, version, pepper}
+        Argon2Config{memory_cost, time_cost, parallelism, algorithm, version, pepper, peppers, secrets}
     }
 }
 
@@ -154,7 +230,7 @@ impl Default for Argon2Config {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use serde_json;
+    
 
     #[test]
     fn test_serialize_argon2config() {
@@ -165,11 +241,14 @@ mod tests {
             algorithm: Algorithm::Argon2id,
             version: Version::V0x13,
             pepper: Some("pepper".to_string()),
+            peppers: vec![Pepper{version: 1, source: SecretSource::Env("PEPPER_V1".into())}],
+            secrets: Default::default(),
         };
 
         let json = serde_json::to_string(&config).unwrap();
         assert!(json.contains("\"argon2id\""));
         assert!(json.contains("\"V0x13\""));
+        assert!(json.contains(r#"{"version":1,"env":"PEPPER_V1"}"#));
     }
 
     #[test]
@@ -181,12 +260,17 @@ mod tests {
             "parallelism": 1,
             "algorithm": "argon2id",
             "version": "V0x13",
-            "pepper": "pepper"
+            "pepper": "pepper",
+            "peppers": [{"version": 2, "value": "second"}, {"version": 1, "value": "first"}]
         }
         "#;
 
-        let config: Argon2Config = serde_json::from_str(json).unwrap();
+        let mut config: Argon2Config = serde_json::from_str(json).unwrap();
         assert_eq!(config.algorithm, Algorithm::Argon2id);
         assert_eq!(config.version, Version::V0x13);
+        config.load_peppers().unwrap();
+        assert_eq!(config.params().keyid(), 2u32.to_be_bytes());
+        assert!(config.argon2_for(&1u32.to_be_bytes()).is_some());
+        assert!(config.argon2_for(&3u32.to_be_bytes()).is_none());
     }
 }
\ No newline at end of file
@@ -0,0 +1,65 @@
+use serde::{Serialize, Deserialize};
+use super::SecretSource;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::io;
+
+
+///A key audit checkpoints are signed with. Its version is recorded on the checkpoints signed with it.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CheckpointKey {
+    pub version: u32,
+    #[serde(flatten)]
+    pub source: SecretSource,
+}
+
+
+///How the audit log is kept provably unaltered.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AuditLog {
+    ///How often the latest audit event is signed as a checkpoint, in seconds.
+    pub checkpoint_interval: u64,
+    ///New checkpoints are signed with the key with the highest version, the others are kept to verify older checkpoints.
+    /// No checkpoints are signed while there is none.
+    pub checkpoint_keys: Vec<CheckpointKey>,
+    ///The keys read by `load_checkpoint_keys`, by their version.
+    #[serde(skip)]
+    pub secrets: Vec<(u32, String)>,
+}
+
+
+impl Default for AuditLog {
+    fn default() -> Self {
+        Self {
+            checkpoint_interval: 60 * 60,
+            checkpoint_keys: Vec::new(),
+            secrets: Vec::new(),
+        }
+    }
+}
+
+
+impl AuditLog {
+    ///Reads the checkpoint keys from where they are kept.
+    pub fn load_checkpoint_keys(&mut self) -> io::Result<()> {
+        let mut keys = self.checkpoint_keys.clone();
+        keys.sort_by_key(|key| key.version);
+        if keys.windows(2).any(|pair| pair[0].version == pair[1].version) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "two checkpoint keys have the same version"));
+        }
+        self.secrets = keys.iter().map(|key| Ok((key.version, key.source.read()?))).collect::<io::Result<_>>()?;
+        Ok(())
+    }
+
+    ///The version of the key new checkpoints are signed with, if there is one.
+    pub fn current_key(&self) -> Option<u32> {
+        self.secrets.last().map(|(version, _)| *version)
+    }
+
+    ///The HMAC of the key with the version, if it is known.
+    pub fn key(&self, version: u32) -> Option<Hmac<Sha256>> {
+        let (_, secret) = self.secrets.iter().find(|(known, _)| *known == version)?;
+        Some(Hmac::new_from_slice(secret.as_bytes()).expect("HMAC can take a key of any size"))
+    }
+}
@@ -0,0 +1,24 @@
+use serde::{Serialize, Deserialize};
+
+
+///What happens to a password found in the breached password corpus.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BreachAction {
+    ///The password is refused like any other violation of the password policy.
+    #[default]
+    Reject,
+    ///The password is accepted and the client is warned about it.
+    Warn,
+}
+
+
+///The offline corpus of breached passwords that new passwords are checked against.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BreachedPasswords {
+    ///A directory of SHA-1 prefix range files as downloaded from Have I Been Pwned,
+    /// or a filter file built from them with `build-breach-filter`. Nothing is checked without it.
+    pub path: Option<String>,
+    pub action: BreachAction,
+}
@@ -1,9 +1,13 @@
-use tokio::fs::{read_to_string, write};
+use tokio::fs::read_to_string;
 use serde::{Serialize, Deserialize};
 use std::error::Error as StdError;
 use std::io::ErrorKind;
 use std::env::var;
+use std::path::Path;
+use std::net::IpAddr;
+use serde_json::Value;
 use super::*;
+use crate::EmailNormalization;
 
 
 type Result<T> = std::result::Result<T, Box<dyn StdError>>;
@@ -13,32 +17,150 @@ type Result<T> = std::result::Result<T, Box<dyn StdError>>;
 pub struct Config {
     pub mail: Mail,
     pub database: Database,
-    pub argon: Argon2Config
+    pub argon: Argon2Config,
+    pub jwt: Jwt,
+    ///The url the users reach the server at. The links in the emails and the endpoints given to the identity
+    /// providers are built from it, never from the headers of a request.
+    pub public_url: String,
+    #[serde(default)]
+    pub lockout: Lockout,
+    #[serde(default)]
+    pub deletion: Deletion,
+    #[serde(default)]
+    pub audit_log: AuditLog,
+    #[serde(default)]
+    pub rate_limit: RateLimit,
+    #[serde(default)]
+    pub password_policy: PasswordPolicy,
+    #[serde(default)]
+    pub breached_passwords: BreachedPasswords,
+    #[serde(default)]
+    pub email_normalization: EmailNormalization,
+    #[serde(default)]
+    pub email_domains: EmailDomains,
+    #[serde(default)]
+    pub oidc: Vec<OidcProvider>,
+    #[serde(default)]
+    pub ldap: Option<Ldap>,
+    #[serde(default)]
+    pub saml: Vec<SamlProvider>,
+    ///The emails of the users who are given the admin role when the server starts.
+    #[serde(default)]
+    pub admins: Vec<String>,
+    ///The addresses of the reverse proxies in front of the server. The address of the client is only taken
+    /// from the `X-Forwarded-For` header of the requests they forward, anyone else could forge it.
+    #[serde(default)]
+    pub trusted_proxies: Vec<IpAddr>,
+}
+
+
+///Where the configuration file is read from when `CONFIG` is not set. It does not have to exist.
+const DEFAULT_PATH: &str = "./config.json";
+
+
+///The configuration every other layer is applied over, which is the default of every section that has one.
+/// The JWT section has none, as its secret has to be the same on every start.
+fn defaults() -> Result<Value> {
+    let sections = [
+        ("database", serde_json::to_value(Database::default())?),
+        ("argon", serde_json::to_value(Argon2Config::default())?),
+        ("lockout", serde_json::to_value(Lockout::default())?),
+        ("deletion", serde_json::to_value(Deletion::default())?),
+        ("audit_log", serde_json::to_value(AuditLog::default())?),
+        ("rate_limit", serde_json::to_value(RateLimit::default())?),
+        ("password_policy", serde_json::to_value(PasswordPolicy::default())?),
+        ("breached_passwords", serde_json::to_value(BreachedPasswords::default())?),
+        ("email_normalization", serde_json::to_value(EmailNormalization::default())?),
+        ("email_domains", serde_json::to_value(EmailDomains::default())?),
+        ("admins", Value::Array(Vec::new())),
+        ("trusted_proxies", Value::Array(Vec::new())),
+    ];
+    Ok(Value::Object(sections.into_iter().map(|(name, section)| (name.to_string(), section)).collect()))
 }
 
 
 impl Config {
+    ///Reads the configuration in layers, each overriding the one before: the defaults, the file at `CONFIG`
+    /// as JSON, TOML or YAML, and the `AUTH__SECTION__KEY` environment variables.
+    /// Without a mail section, the mail server is read from the `MAIL_` variables. Nothing is written to disk,
+    /// so the JWT secret has to be set.
     pub async fn read() -> Result<Self> {
-        let path = var("CONFIG").unwrap_or(String::from("./config.json"));
-        let json = match read_to_string(&path).await {
-            Ok(json) => json,
-            Err(err) => {
-                match err.kind() {
-                    ErrorKind::NotFound => {
-                        let config = Config{mail: Mail::from_env()?, database: Default::default(), argon: Default::default()};
-                        config.write(&path).await?;
-                        return Ok(config)
-                    },
-                    _ => Err(err)?
-                }
-            }
+        let requested = var("CONFIG").ok();
+        let path = requested.as_deref().unwrap_or(DEFAULT_PATH);
+        let file = match read_to_string(path).await {
+            Ok(contents) => Some(parse_file(Path::new(path), &contents)?),
+            // Only a file that was asked for has to be there.
+            Err(err) if err.kind() == ErrorKind::NotFound && requested.is_none() => None,
+            Err(err) => return Err(format!("could not read {}: {}", path, err).into()),
         };
-        Ok(serde_json::from_str(&json)?)
+        let mut config = Self::from_layers(file, std::env::vars())?;
+        config.argon.load_peppers()?;
+        config.audit_log.load_checkpoint_keys()?;
+        Ok(config)
+    }
+
+    ///Applies the file and the overriding environment variables over the defaults.
+    fn from_layers(file: Option<Value>, vars: impl IntoIterator<Item = (String, String)>) -> Result<Self> {
+        let mut config = defaults()?;
+        if let Some(file) = file {
+            merge(&mut config, file);
+        }
+        apply_env(&mut config, vars)?;
+        if config.get("mail").is_none_or(Value::is_null) {
+            config["mail"] = serde_json::to_value(Mail::from_env()?)?;
+        }
+        if config.pointer("/jwt/secret").and_then(Value::as_str).is_none_or(str::is_empty) {
+            return Err("jwt.secret is not set, set it in the configuration file or with AUTH__JWT__SECRET".into());
+        }
+        if config.get("public_url").and_then(Value::as_str).is_none_or(str::is_empty) {
+            return Err("public_url is not set, set it in the configuration file or with AUTH__PUBLIC_URL".into());
+        }
+        Ok(serde_json::from_value(config)?)
+    }
+
+    ///Returns the public url of the given path, which starts with a slash.
+    pub fn link(&self, path: &str) -> String {
+        format!("{}{}", self.public_url.trim_end_matches('/'), path)
     }
 
-    async fn write(&self, path: &str) -> Result<()> {
-        let json = serde_json::to_string(self)?;
-        let contents = json.as_bytes();
-        Ok(write(path, contents).await?)
+    ///Returns the OpenID Connect provider with the given name if it is configured.
+    pub fn oidc_provider(&self, name: &str) -> Option<&OidcProvider> {
+        self.oidc.iter().find(|provider| provider.name == name)
     }
-}
\ No newline at end of file
+
+    ///Returns the SAML identity provider with the given name if it is configured.
+    pub fn saml_provider(&self, name: &str) -> Option<&SamlProvider> {
+        self.saml.iter().find(|provider| provider.name == name)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_partial_sections_are_filled_in_from_the_defaults() {
+        let file = json!({"mail": {"credentials": null, "url": "smtp://localhost:25", "sender": "Auth <auth@example.com>"}, "argon": {"time_cost": 3}});
+        let vars = [("AUTH__JWT__SECRET_FILE".to_string(), "/nonexistent".to_string())];
+        assert!(Config::from_layers(Some(file.clone()), vars).is_err(), "a secret file that can not be read is an error");
+        assert!(Config::from_layers(Some(file.clone()), []).is_err(), "the JWT secret has no default");
+        let vars = [("AUTH__JWT__SECRET".to_string(), "1234".to_string())];
+        assert!(Config::from_layers(Some(file.clone()), vars).is_err(), "the public url has no default");
+        let vars = [
+            ("AUTH__PUBLIC_URL".to_string(), "https://auth.example.com/".to_string()),
+            ("AUTH__MAIL__URL".to_string(), "smtp://mail:2525".to_string()),
+            ("AUTH__ADMINS".to_string(), r#"["a@example.com"]"#.to_string()),
+            ("AUTH__JWT__SECRET".to_string(), "1234".to_string()),
+        ];
+        let config = Config::from_layers(Some(file), vars).unwrap();
+        assert_eq!(config.mail.url, "smtp://mail:2525");
+        assert_eq!(config.argon.time_cost, 3);
+        assert_eq!(config.argon.memory_cost, Argon2Config::default().memory_cost);
+        assert_eq!(config.admins, ["a@example.com"]);
+        assert_eq!(config.jwt.secret, "1234");
+        assert_eq!(config.jwt.expires_in, Jwt::default().expires_in);
+        assert_eq!(config.link("/magic-link/1"), "https://auth.example.com/magic-link/1");
+    }
+}
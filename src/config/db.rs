@@ -1,15 +1,18 @@
-use sqlx::{Pool, Postgres, Error, query};
+use sqlx::{Pool, Postgres, Sqlite, Error, query};
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+use std::str::FromStr;
 use serde::{Serialize, Deserialize};
 use std::error::Error as StdError;
 use url::Url;
 use super::*;
+use super::migration;
 
 
 type Result<T> = std::result::Result<T, Box<dyn StdError>>;
 
 
-const DEFAULT_DB_NAME: &'static str = "postgres";
-const DB_NAME: &'static str = "interphlix";
+const DEFAULT_DB_NAME: &str = "postgres";
+const DB_NAME: &str = "interphlix";
 
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -18,6 +21,8 @@ pub struct Database {
     pub credentials: Option<Credentials>,
     pub name: String,
     pub url: String,
+    ///Whether the pending migrations are applied at startup rather than through `migrate up`.
+    pub auto_migrate: bool,
 }
 
 
@@ -26,7 +31,8 @@ impl Default for Database {
         let credentials = None;
         let name = String::from(DB_NAME);
         let url = String::from("postgres://localhost:5432");
-        Self {credentials, name, url}
+        let auto_migrate = true;
+        Self {credentials, name, url, auto_migrate}
     }
 
 }
@@ -35,64 +41,10 @@ impl Default for Database {
 
 impl Database {
     const CREATE_DATABASE_STATEMENT: &'static str = "CREATE DATABASE";
-    const CREATE_USERS_TABLE_STATEMENT: &'static str = r#"
-        CREATE TABLE users (
-            id BYTEA PRIMARY KEY,
-            email JSONB NOT NULL,
-            user_name TEXT NOT NULL,
-            first_name TEXT NOT NULL,
-            last_name TEXT NOT NULL,
-            password TEXT NOT NULL,
-            profile_picture TEXT,
-            created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
-        );
-    "#;
-    const ALTER_USERS_TABLE_STATEMENT: &'static str = r#"
-        ALTER TABLE users
-        ADD COLUMN IF NOT EXISTS id BYTEA PRIMARY KEY,
-        ADD COLUMN IF NOT EXISTS email JSONB NOT NULL,
-        ADD COLUMN IF NOT EXISTS user_name TEXT NOT NULL,
-        ADD COLUMN IF NOT EXISTS first_name TEXT NOT NULL,
-        ADD COLUMN IF NOT EXISTS last_name TEXT NOT NULL,
-        ADD COLUMN IF NOT EXISTS password TEXT NOT NULL,
-        ADD COLUMN IF NOT EXISTS profile_picture TEXT,
-        ADD COLUMN IF NOT EXISTS created_at TIMESTAMPTZ NOT NULL DEFAULT
-CURRENT_TIMESTAMP
-    "#;
-    const EMAIL_INDEX_ON_USERS_TABLE_STATEMENT: &'static str = r#"
-        DO $$
-        BEGIN
-            IF NOT EXISTS (
-                SELECT 1
-                FROM pg_class c
-                JOIN pg_namespace n ON n.oid = c.relnamespace
-                WHERE c.relname = 'users_email_index'
-                AND n.nspname = 'public'
-            ) THEN
-                EXECUTE 'CREATE UNIQUE INDEX users_email_index ON users
-                ((email->>''email''))';
-            END IF;
-        END $$;
-    "#;
-    const CREATE_VERIFICATION_CODES_TABLE_STATEMENT: &'static str = r#"
-        CREATE TABLE IF NOT EXISTS verification_codes (
-            id UUID PRIMARY KEY,
-            user_id BYTEA NOT NULL,
-            code TEXT NOT NULL,
-            created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
-            FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
-        );
-    "#;
-    const INDEX_USER_ID_CREATED_AT_STATEMENT: &'static str = r#"
-        CREATE INDEX IF NOT EXISTS user_id_and_created_at_index ON verification_codes (user_id, created_at DESC);
-    "#;
-    const INDEX_CREATED_AT_STATEMENT: &'static str = r#"
-        CREATE INDEX IF NOT EXISTS created_at_index ON verification_codes (created_at);
-    "#;
     const ERROR_CODE_DB_DOES_NOT_EXIST: &'static str = "3D000";
-    const ERROR_CODE_TABLE_EXISTS: &'static str = "42P07";
 
-    pub async fn init(&self) -> Result<Pool<Postgres>> {
+    ///Connects to the database, creating it when it does not exist.
+    pub async fn connect(&self) -> Result<Pool<Postgres>> {
         let mut result: Option<Result<Pool<Postgres>>> = None;
         let mut uris: std::collections::VecDeque<Url> = Default::default();
         let uri = self.db_url();
@@ -120,20 +72,45 @@ CURRENT_TIMESTAMP
             }
         }
         match result {
-            Some(result) => {
-                match result {
-                    Ok(pool) => {
-                        self.create_users_table(&pool).await?;
-                        self.create_verification_codes_table(&pool).await?;
-                        Ok(pool)
-                    },
-                    Err(err) => Err(err.into())
-                }
-            },
+            Some(result) => result,
             None => Err("Unknown error".into())
         }
     }
 
+    ///Connects to the database and, unless disabled, applies the pending migrations.
+    pub async fn init(&self) -> Result<Pool<Postgres>> {
+        let pool = self.connect().await?;
+        if self.auto_migrate {
+            migration::up(&pool).await?;
+        }
+        Ok(pool)
+    }
+
+    ///Whether the url points to a SQLite file, such as `sqlite://auth.db` or `sqlite::memory:`, rather than a Postgres server.
+    pub fn is_sqlite(&self) -> bool {
+        self.url.starts_with("sqlite:")
+    }
+
+    ///Opens the SQLite database of the url, creating the file when it does not exist.
+    pub async fn connect_sqlite(&self) -> Result<Pool<Sqlite>> {
+        let options = SqliteConnectOptions::from_str(&self.url)?.create_if_missing(true).foreign_keys(true);
+        // Every connection to an in-memory database opens a database of its own.
+        let max_connections = match self.url.contains(":memory:") {
+            true => 1,
+            false => 10,
+        };
+        Ok(SqlitePoolOptions::new().max_connections(max_connections).connect_with(options).await?)
+    }
+
+    ///Opens the SQLite database and, unless disabled, applies the pending migrations.
+    pub async fn init_sqlite(&self) -> Result<Pool<Sqlite>> {
+        let pool = self.connect_sqlite().await?;
+        if self.auto_migrate {
+            migration::up_sqlite(&pool).await?;
+        }
+        Ok(pool)
+    }
+
     async fn create_db(&self, name: &str, url: &str) -> Result<()> {
         let pool = Pool::<Postgres>::connect(url).await?;
         let sql = format!("{} {}", Self::CREATE_DATABASE_STATEMENT, name);
@@ -158,54 +135,4 @@ CURRENT_TIMESTAMP
         }
         url
     }
-
-    pub async fn create_users_table(&self, pool: &Pool<Postgres>) -> Result<()> {
-        let sql = Self::CREATE_USERS_TABLE_STATEMENT;
-        match query(sql).execute(pool).await {
-            Ok(_) => self.create_users_index(pool).await,
-            Err(err) => {
-                match err.as_database_error() {
-                    Some(db_err) => {
-                        if db_err.code().as_deref() == Some(Self::ERROR_CODE_TABLE_EXISTS) {
-                            self.alter_users_table(pool).await
-                        } else {
-                            Err(err.into())
-                        }
-                    }
-                    _ => Err(err.into()),
-                }
-            }
-        }
-    }
-
-    pub async fn alter_users_table(&self, pool: &Pool<Postgres>) -> Result<()> {
-        let sql = Self::ALTER_USERS_TABLE_STATEMENT;
-        query(sql).execute(pool).await?;
-        self.create_users_index(pool).await?;
-        Ok(())
-    }
-
-    pub async fn create_users_index(&self, pool: &Pool<Postgres>) -> Result<()> {
-        let sql = Self::EMAIL_INDEX_ON_USERS_TABLE_STATEMENT;
-        query(sql).execute(pool).await?;
-        Ok(())
-    }
-
-
-    pub async fn create_verification_codes_table(&self, pool: &Pool<Postgres>) -> Result<()> {
-        let sql = Self::CREATE_VERIFICATION_CODES_TABLE_STATEMENT;
-        query(sql).execute(pool).await?;
-        self.create_verification_codes_indexes(pool).await?;
-        Ok(())
-    }
-
-    pub async fn create_verification_codes_indexes(&self, pool: &Pool<Postgres>) -> Result<()> {
-        let sql_user_id_created_at = Self::INDEX_USER_ID_CREATED_AT_STATEMENT;
-        query(sql_user_id_created_at).execute(pool).await?;
-        
-        let sql_created_at = Self::INDEX_CREATED_AT_STATEMENT;
-        query(sql_created_at).execute(pool).await?;
-        
-        Ok(())
-    }
 }
@@ -0,0 +1,22 @@
+use serde::{Serialize, Deserialize};
+
+
+///How long deleted users are kept before they are purged for good.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Deletion {
+    ///How long a deleted user can be restored, in seconds.
+    pub grace_period: i64,
+    ///How often the users past their grace period are purged, in seconds.
+    pub purge_interval: u64,
+}
+
+
+impl Default for Deletion {
+    fn default() -> Self {
+        Self {
+            grace_period: 30 * 24 * 60 * 60,
+            purge_interval: 60 * 60,
+        }
+    }
+}
@@ -0,0 +1,32 @@
+use serde::{Serialize, Deserialize};
+
+
+///Which email domains can sign up.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EmailDomains {
+    ///Only addresses at these domains or their subdomains can sign up. Any domain can when empty.
+    pub allow: Vec<String>,
+    ///Addresses at these domains or their subdomains can not sign up, even when allowed.
+    pub block: Vec<String>,
+    ///Refuses the domains of disposable email services, unless they are allowed.
+    pub block_disposable: bool,
+    ///A file of disposable domains, one per line, used instead of the list bundled with the server.
+    pub disposable_list: Option<String>,
+    ///Refuses domains that could not have a mail server, such as those without a dot or with malformed labels.
+    /// Nothing is looked up.
+    pub check_mx_syntax: bool,
+}
+
+
+impl Default for EmailDomains {
+    fn default() -> Self {
+        Self {
+            allow: Vec::new(),
+            block: Vec::new(),
+            block_disposable: true,
+            disposable_list: None,
+            check_mx_syntax: false,
+        }
+    }
+}
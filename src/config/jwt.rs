@@ -0,0 +1,41 @@
+use rand::{distributions::Alphanumeric, Rng};
+use serde::{Serialize, Deserialize};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+
+const DEFAULT_ISSUER: &str = "interphlix";
+const DEFAULT_EXPIRES_IN: i64 = 60 * 60;
+const SECRET_LENGTH: usize = 64;
+
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Jwt {
+    ///The secret used to sign and verify the tokens issued by this server.
+    pub secret: String,
+    ///The value of the `iss` claim of the tokens issued by this server.
+    pub issuer: String,
+    ///How long an issued token stays valid, in seconds.
+    pub expires_in: i64,
+    ///Whether the roles and permissions of the user are included in the tokens.
+    pub include_access: bool,
+}
+
+
+impl Jwt {
+    pub fn key(&self) -> Hmac<Sha256> {
+        Hmac::new_from_slice(self.secret.as_bytes()).expect("HMAC can take a key of any size")
+    }
+}
+
+
+impl Default for Jwt {
+    fn default() -> Self {
+        let secret = rand::thread_rng().sample_iter(&Alphanumeric).take(SECRET_LENGTH).map(char::from).collect();
+        let issuer = String::from(DEFAULT_ISSUER);
+        let expires_in = DEFAULT_EXPIRES_IN;
+        let include_access = false;
+        Self {secret, issuer, expires_in, include_access}
+    }
+}
@@ -0,0 +1,142 @@
+use serde_json::{Map, Value};
+use std::error::Error as StdError;
+use std::path::Path;
+use super::SecretSource;
+
+
+type Result<T> = std::result::Result<T, Box<dyn StdError>>;
+
+
+///The prefix of the environment variables that override the configuration, as in `AUTH__MAIL__URL`.
+pub const ENV_PREFIX: &str = "AUTH__";
+///Separates the sections and keys in the name of an overriding variable.
+const ENV_SEPARATOR: &str = "__";
+///Ends the name of a variable holding the path of a file to read the value from, as Docker and Kubernetes secrets are.
+const FILE_SUFFIX: &str = "_FILE";
+
+
+///Parses a configuration file as TOML or YAML when its extension says so, and as JSON otherwise.
+pub fn parse_file(path: &Path, contents: &str) -> Result<Value> {
+    let extension = path.extension().and_then(|extension| extension.to_str()).map(str::to_lowercase);
+    Ok(match extension.as_deref() {
+        Some("toml") => toml::from_str(contents)?,
+        Some("yaml" | "yml") => serde_yaml::from_str(contents)?,
+        _ => serde_json::from_str(contents)?,
+    })
+}
+
+
+///Merges a layer into the one below it. Objects are merged key by key, anything else in the layer replaces what is below.
+pub fn merge(base: &mut Value, layer: Value) {
+    match (base, layer) {
+        (Value::Object(base), Value::Object(layer)) => {
+            for (key, value) in layer {
+                match base.get_mut(&key) {
+                    Some(below) => merge(below, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        },
+        (base, layer) => *base = layer,
+    }
+}
+
+
+///The item of a list at the index, or the value of an object at the key.
+/// Whatever is not an object yet is replaced by one, or by a list when the key is a number,
+/// so overrides can add sections and items that are not there. A list is padded to the index.
+fn child<'a>(node: &'a mut Value, key: &str) -> &'a mut Value {
+    match key.parse::<usize>() {
+        Ok(index) if !node.is_object() => {
+            if !node.is_array() {
+                *node = Value::Array(Vec::new());
+            }
+            let Value::Array(items) = node else { unreachable!("the node was just made a list") };
+            if items.len() <= index {
+                items.resize(index + 1, Value::Null);
+            }
+            &mut items[index]
+        },
+        _ => {
+            if !node.is_object() {
+                *node = Value::Object(Map::new());
+            }
+            let Value::Object(object) = node else { unreachable!("the node was just made an object") };
+            object.entry(key).or_insert(Value::Null)
+        }
+    }
+}
+
+
+///Applies the `AUTH__SECTION__KEY` environment variables over the configuration. The names are case insensitive,
+/// and a number picks an item of a list, as in `AUTH__OIDC__0__CLIENT_SECRET`.
+/// A value is a string, unless it replaces a number, a boolean, a list or an object, when it is read as JSON.
+/// A variable ending in `_FILE` sets its key to the contents of the file it names, which are always a string.
+pub fn apply_env(config: &mut Value, vars: impl IntoIterator<Item = (String, String)>) -> Result<()> {
+    let mut vars: Vec<(String, String)> = vars.into_iter().filter(|(name, _)| name.starts_with(ENV_PREFIX)).collect();
+    // Sorted so a `_FILE` variable comes after the plain one for the same key, and wins.
+    vars.sort();
+    for (variable, value) in vars {
+        let name = variable[ENV_PREFIX.len()..].to_lowercase();
+        let (name, from_file) = match name.strip_suffix(&FILE_SUFFIX.to_lowercase()) {
+            Some(name) => (name.to_string(), true),
+            None => (name, false),
+        };
+        let path: Vec<&str> = name.split(ENV_SEPARATOR).collect();
+        if path.iter().any(|key| key.is_empty()) {
+            return Err(format!("{} does not name a configuration key", variable).into());
+        }
+        let slot = path.into_iter().fold(&mut *config, child);
+        *slot = match slot {
+            _ if from_file => Value::String(SecretSource::File(value.clone()).read().map_err(|err| format!("could not read {}: {}", value, err))?),
+            Value::Null | Value::String(_) => Value::String(value),
+            _ => serde_json::from_str(&value).map_err(|err| format!("{} is not valid JSON: {}", variable, err))?,
+        };
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_files_of_every_format_parse_alike() {
+        let json = parse_file(Path::new("config.json"), r#"{"jwt": {"issuer": "auth", "expires_in": 60}, "admins": ["a@b.c"]}"#).unwrap();
+        let toml = parse_file(Path::new("config.toml"), "admins = [\"a@b.c\"]\n[jwt]\nissuer = \"auth\"\nexpires_in = 60\n").unwrap();
+        let yaml = parse_file(Path::new("config.YML"), "jwt:\n  issuer: auth\n  expires_in: 60\nadmins:\n  - a@b.c\n").unwrap();
+        assert_eq!(json, toml);
+        assert_eq!(json, yaml);
+    }
+
+    #[test]
+    fn test_layers_override_the_ones_below() {
+        let mut config = json!({"jwt": {"issuer": "auth", "expires_in": 60, "include_access": false}, "oidc": [{"name": "a", "client_secret": "old"}]});
+        merge(&mut config, json!({"jwt": {"expires_in": 120}}));
+        let secret = std::env::temp_dir().join(format!("auth-secret-{}", std::process::id()));
+        std::fs::write(&secret, "from a file\n").unwrap();
+        apply_env(&mut config, [
+            ("AUTH__JWT__ISSUER".to_string(), "1234".to_string()),
+            ("AUTH__JWT__INCLUDE_ACCESS".to_string(), "true".to_string()),
+            ("AUTH__OIDC__0__CLIENT_SECRET_FILE".to_string(), secret.display().to_string()),
+            ("AUTH__OIDC__0__CLIENT_SECRET".to_string(), "ignored".to_string()),
+            ("AUTH__MAIL__CREDENTIALS__NAME".to_string(), "mailer".to_string()),
+            ("AUTH__MAIL__CREDENTIALS__SECRET".to_string(), "1234".to_string()),
+            ("AUTH__SAML__1__NAME".to_string(), "true".to_string()),
+            ("OTHER__JWT__ISSUER".to_string(), "ignored".to_string()),
+        ]).unwrap();
+        std::fs::remove_file(&secret).unwrap();
+        assert_eq!(config, json!({
+            "jwt": {"issuer": "1234", "expires_in": 120, "include_access": true},
+            "oidc": [{"name": "a", "client_secret": "from a file"}],
+            "mail": {"credentials": {"name": "mailer", "secret": "1234"}},
+            "saml": [null, {"name": "true"}],
+        }));
+        assert!(apply_env(&mut config, [("AUTH__JWT____ISSUER".to_string(), "x".to_string())]).is_err());
+        assert!(apply_env(&mut config, [("AUTH__JWT__EXPIRES_IN".to_string(), "soon".to_string())]).is_err());
+    }
+}
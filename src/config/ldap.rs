@@ -0,0 +1,65 @@
+use serde::{Serialize, Deserialize};
+use std::collections::HashMap;
+
+
+///An LDAP directory users can sign in with using their directory credentials.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Ldap {
+    ///The url of the directory server. e.g `ldaps://ldap.example.com:636`
+    pub url: String,
+    ///The DN of the service account used to search for users.
+    /// The search is done anonymously when no DN is set.
+    #[serde(default)]
+    pub bind_dn: Option<String>,
+    #[serde(default)]
+    pub bind_password: Option<String>,
+    ///The DN users are searched under.
+    pub base_dn: String,
+    ///The filter used to find a user. `{username}` is replaced by the escaped user name.
+    #[serde(default = "default_user_filter")]
+    pub user_filter: String,
+    #[serde(default)]
+    pub attributes: LdapAttributes,
+    ///Maps the DN of a directory group to the roles its members get.
+    #[serde(default)]
+    pub group_roles: HashMap<String, Vec<String>>,
+    ///The email domains the directory may vouch for. A first sign in with an email of one of them
+    /// is tied to the existing account with that email, if its owner has verified it.
+    /// Otherwise they have to sign in to the account and link the identity themselves.
+    #[serde(default)]
+    pub trusted_domains: Vec<String>,
+}
+
+
+///The directory attributes the `User` fields are read from.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LdapAttributes {
+    ///A stable unique id of the entry. e.g `entryUUID`.
+    /// The DN of the entry is used when this is not set.
+    pub id: Option<String>,
+    pub email: String,
+    pub user_name: String,
+    pub first_name: String,
+    pub last_name: String,
+    ///The attribute listing the DNs of the groups the user is a member of.
+    pub groups: String,
+}
+
+
+impl Default for LdapAttributes {
+    fn default() -> Self {
+        let id = None;
+        let email = String::from("mail");
+        let user_name = String::from("uid");
+        let first_name = String::from("givenName");
+        let last_name = String::from("sn");
+        let groups = String::from("memberOf");
+        Self {id, email, user_name, first_name, last_name, groups}
+    }
+}
+
+
+fn default_user_filter() -> String {
+    String::from("(uid={username})")
+}
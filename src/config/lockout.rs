@@ -0,0 +1,34 @@
+use serde::{Serialize, Deserialize};
+
+
+///How failed password logins are slowed down and locked out.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Lockout {
+    ///The failed logins after which an account is locked.
+    pub account_threshold: u32,
+    ///The failed logins from one IP address after which the address is locked.
+    pub ip_threshold: u32,
+    ///How long a lock lasts, in seconds.
+    pub lock_duration: i64,
+    ///Failures older than this many seconds are forgotten.
+    pub window: i64,
+    ///The delay of the response to the first failure, in milliseconds. It doubles with every failure.
+    pub base_delay: u64,
+    ///The longest delay of a response to a failure, in milliseconds.
+    pub max_delay: u64,
+}
+
+
+impl Default for Lockout {
+    fn default() -> Self {
+        Self {
+            account_threshold: 5,
+            ip_threshold: 20,
+            lock_duration: 15 * 60,
+            window: 15 * 60,
+            base_delay: 250,
+            max_delay: 8_000,
+        }
+    }
+}
@@ -0,0 +1,354 @@
+use sqlx::{query, query_as, query_scalar, raw_sql, Acquire, Pool, Postgres, PgConnection, Sqlite, SqliteConnection};
+use chrono::{DateTime, Utc};
+use sha2::{Digest, Sha256};
+use serde::Serialize;
+use std::error::Error as StdError;
+
+
+type Result<T> = std::result::Result<T, Box<dyn StdError>>;
+
+
+///A change of the schema, with the SQL that makes it and the SQL that undoes it.
+pub struct Migration {
+    pub version: i64,
+    pub name: &'static str,
+    pub up: &'static str,
+    pub down: &'static str,
+    ///Checked before the migration is applied. It is not part of the checksum, so it can be added to applied migrations.
+    pub check: Option<Check>,
+}
+
+
+///A query listing the rows a migration can not be applied to, one text per problem, and what to do about them.
+pub struct Check {
+    pub sql: &'static str,
+    pub advice: &'static str,
+}
+
+
+///The users whose emails differ only by case, which the unique index of the typed email migration refuses.
+const DUPLICATE_EMAILS_ADVICE: &str = "these users have emails that differ only by case, which have to be unique. \
+    Merge each group into one account, or change the emails of the others, then migrate again";
+
+
+impl Migration {
+    ///Tells when the SQL of an applied migration was edited afterwards.
+    pub fn checksum(&self) -> String {
+        Sha256::digest(self.up.as_bytes()).iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+}
+
+
+///Every migration, in the order they are applied. Applied migrations are never edited, changes go in a new one.
+pub const MIGRATIONS: &[Migration] = &[
+    Migration{version: 1, name: "initial", up: include_str!("../../migrations/0001_initial.up.sql"), down: include_str!("../../migrations/0001_initial.down.sql"), check: None},
+    Migration{version: 2, name: "typed_email", up: include_str!("../../migrations/0002_typed_email.up.sql"), down: include_str!("../../migrations/0002_typed_email.down.sql"),
+        check: Some(Check{sql: include_str!("../../migrations/0002_typed_email.check.sql"), advice: DUPLICATE_EMAILS_ADVICE})},
+    Migration{version: 3, name: "email_canonical", up: include_str!("../../migrations/0003_email_canonical.up.sql"), down: include_str!("../../migrations/0003_email_canonical.down.sql"), check: None},
+    Migration{version: 4, name: "soft_delete", up: include_str!("../../migrations/0004_soft_delete.up.sql"), down: include_str!("../../migrations/0004_soft_delete.down.sql"), check: None},
+    Migration{version: 5, name: "audit_events", up: include_str!("../../migrations/0005_audit_events.up.sql"), down: include_str!("../../migrations/0005_audit_events.down.sql"), check: None},
+    Migration{version: 6, name: "audit_chain", up: include_str!("../../migrations/0006_audit_chain.up.sql"), down: include_str!("../../migrations/0006_audit_chain.down.sql"), check: None},
+    Migration{version: 7, name: "role_sources", up: include_str!("../../migrations/0007_role_sources.up.sql"), down: include_str!("../../migrations/0007_role_sources.down.sql"), check: None},
+    Migration{version: 8, name: "checkpoint_keys", up: include_str!("../../migrations/0008_checkpoint_keys.up.sql"), down: include_str!("../../migrations/0008_checkpoint_keys.down.sql"), check: None},
+];
+
+
+///The migrations of the SQLite schema, which only holds the tables of the stores.
+pub const SQLITE_MIGRATIONS: &[Migration] = &[
+    Migration{version: 1, name: "initial", up: include_str!("../../migrations/sqlite/0001_initial.up.sql"), down: include_str!("../../migrations/sqlite/0001_initial.down.sql"), check: None},
+    Migration{version: 2, name: "typed_email", up: include_str!("../../migrations/sqlite/0002_typed_email.up.sql"), down: include_str!("../../migrations/sqlite/0002_typed_email.down.sql"),
+        check: Some(Check{sql: include_str!("../../migrations/sqlite/0002_typed_email.check.sql"), advice: DUPLICATE_EMAILS_ADVICE})},
+    Migration{version: 3, name: "email_canonical", up: include_str!("../../migrations/sqlite/0003_email_canonical.up.sql"), down: include_str!("../../migrations/sqlite/0003_email_canonical.down.sql"), check: None},
+    Migration{version: 4, name: "soft_delete", up: include_str!("../../migrations/sqlite/0004_soft_delete.up.sql"), down: include_str!("../../migrations/sqlite/0004_soft_delete.down.sql"), check: None},
+    Migration{version: 5, name: "audit_events", up: include_str!("../../migrations/sqlite/0005_audit_events.up.sql"), down: include_str!("../../migrations/sqlite/0005_audit_events.down.sql"), check: None},
+    Migration{version: 6, name: "audit_chain", up: include_str!("../../migrations/sqlite/0006_audit_chain.up.sql"), down: include_str!("../../migrations/sqlite/0006_audit_chain.down.sql"), check: None},
+    Migration{version: 7, name: "checkpoint_keys", up: include_str!("../../migrations/sqlite/0007_checkpoint_keys.up.sql"), down: include_str!("../../migrations/sqlite/0007_checkpoint_keys.down.sql"), check: None},
+    Migration{version: 8, name: "stores", up: include_str!("../../migrations/sqlite/0008_stores.up.sql"), down: include_str!("../../migrations/sqlite/0008_stores.down.sql"), check: None},
+];
+
+
+///Held while migrating so replicas starting together do not migrate at the same time.
+const ADVISORY_LOCK_KEY: i64 = 0x6175_7468_6d69_6772;
+
+const CREATE_SCHEMA_MIGRATIONS_TABLE_STATEMENT: &str = r#"
+    CREATE TABLE IF NOT EXISTS schema_migrations (
+        version BIGINT PRIMARY KEY,
+        name TEXT NOT NULL,
+        checksum TEXT NOT NULL,
+        applied_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+    );
+"#;
+
+const CREATE_SQLITE_SCHEMA_MIGRATIONS_TABLE_STATEMENT: &str = r#"
+    CREATE TABLE IF NOT EXISTS schema_migrations (
+        version INTEGER PRIMARY KEY,
+        name TEXT NOT NULL,
+        checksum TEXT NOT NULL,
+        applied_at TEXT NOT NULL
+    );
+"#;
+
+
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct AppliedMigration {
+    pub version: i64,
+    pub name: String,
+    pub checksum: String,
+    pub applied_at: DateTime<Utc>,
+}
+
+
+#[derive(Debug, Serialize)]
+pub struct MigrationStatus {
+    pub version: i64,
+    pub name: String,
+    pub applied_at: Option<DateTime<Utc>>,
+    ///Whether the SQL of an applied migration was edited since, or the migration is unknown to this build.
+    pub modified: bool,
+}
+
+
+///The migrations still to apply. Fails when an applied migration was edited or is unknown to this build.
+pub fn pending<'a>(migrations: &'a [Migration], applied: &[AppliedMigration]) -> Result<Vec<&'a Migration>> {
+    for applied in applied {
+        match migrations.iter().find(|migration| migration.version == applied.version) {
+            Some(migration) if migration.checksum() != applied.checksum => {
+                return Err(format!("migration {} {} was edited after it was applied", migration.version, migration.name).into());
+            },
+            Some(_) => (),
+            None => return Err(format!("migration {} {} is not known to this build", applied.version, applied.name).into()),
+        }
+    }
+    Ok(migrations.iter().filter(|migration| !applied.iter().any(|applied| applied.version == migration.version)).collect())
+}
+
+
+///Fails with the problems the check of the migration found, if there are any.
+fn refuse(migration: &Migration, problems: Vec<String>) -> Result<()> {
+    match (&migration.check, problems.is_empty()) {
+        (Some(check), false) => Err(format!("migration {} {} can not be applied, {}:\n{}", migration.version, migration.name, check.advice, problems.join("\n")).into()),
+        _ => Ok(()),
+    }
+}
+
+
+async fn applied_migrations(connection: &mut PgConnection) -> Result<Vec<AppliedMigration>> {
+    query(CREATE_SCHEMA_MIGRATIONS_TABLE_STATEMENT).execute(&mut *connection).await?;
+    Ok(query_as("SELECT version, name, checksum, applied_at FROM schema_migrations ORDER BY version").fetch_all(&mut *connection).await?)
+}
+
+
+///Runs `f` on a connection holding the migration lock.
+async fn locked<T>(pool: &Pool<Postgres>, f: impl AsyncFnOnce(&mut PgConnection) -> Result<T>) -> Result<T> {
+    let mut connection = pool.acquire().await?;
+    query("SELECT pg_advisory_lock($1)").bind(ADVISORY_LOCK_KEY).execute(&mut *connection).await?;
+    let result = f(&mut connection).await;
+    query("SELECT pg_advisory_unlock($1)").bind(ADVISORY_LOCK_KEY).execute(&mut *connection).await?;
+    result
+}
+
+
+///Applies the pending migrations, each in a transaction of its own, and returns them.
+pub async fn up(pool: &Pool<Postgres>) -> Result<Vec<&'static Migration>> {
+    locked(pool, async |connection: &mut PgConnection| {
+        let applied = applied_migrations(connection).await?;
+        let pending = pending(MIGRATIONS, &applied)?;
+        for migration in &pending {
+            let mut transaction = connection.begin().await?;
+            if let Some(ref check) = migration.check {
+                refuse(migration, query_scalar(check.sql).fetch_all(&mut *transaction).await?)?;
+            }
+            raw_sql(migration.up).execute(&mut *transaction).await
+                .map_err(|err| format!("migration {} {} failed: {}", migration.version, migration.name, err))?;
+            query("INSERT INTO schema_migrations (version, name, checksum) VALUES ($1, $2, $3)")
+                .bind(migration.version).bind(migration.name).bind(migration.checksum())
+                .execute(&mut *transaction).await?;
+            transaction.commit().await?;
+        }
+        Ok(pending)
+    }).await
+}
+
+
+///Reverts the latest applied migrations, newest first, and returns them.
+pub async fn down(pool: &Pool<Postgres>, steps: usize) -> Result<Vec<&'static Migration>> {
+    locked(pool, async |connection: &mut PgConnection| {
+        let applied = applied_migrations(connection).await?;
+        pending(MIGRATIONS, &applied)?;
+        let mut reverted = Vec::new();
+        for applied in applied.iter().rev().take(steps) {
+            let migration = MIGRATIONS.iter().find(|migration| migration.version == applied.version).ok_or("unknown migration")?;
+            let mut transaction = connection.begin().await?;
+            raw_sql(migration.down).execute(&mut *transaction).await
+                .map_err(|err| format!("reverting migration {} {} failed: {}", migration.version, migration.name, err))?;
+            query("DELETE FROM schema_migrations WHERE version = $1").bind(migration.version).execute(&mut *transaction).await?;
+            transaction.commit().await?;
+            reverted.push(migration);
+        }
+        Ok(reverted)
+    }).await
+}
+
+
+///Every migration known to this build or applied to the database, in order.
+fn statuses(migrations: &[Migration], applied: &[AppliedMigration]) -> Vec<MigrationStatus> {
+    let mut statuses: Vec<MigrationStatus> = migrations.iter().map(|migration| {
+        let applied = applied.iter().find(|applied| applied.version == migration.version);
+        MigrationStatus {
+            version: migration.version,
+            name: migration.name.into(),
+            applied_at: applied.map(|applied| applied.applied_at),
+            modified: applied.is_some_and(|applied| applied.checksum != migration.checksum()),
+        }
+    }).collect();
+    for applied in applied.iter().filter(|applied| !migrations.iter().any(|migration| migration.version == applied.version)) {
+        statuses.push(MigrationStatus{version: applied.version, name: applied.name.clone(), applied_at: Some(applied.applied_at), modified: true});
+    }
+    statuses.sort_by_key(|status| status.version);
+    statuses
+}
+
+
+pub async fn status(pool: &Pool<Postgres>) -> Result<Vec<MigrationStatus>> {
+    let mut connection = pool.acquire().await?;
+    let applied = applied_migrations(&mut connection).await?;
+    Ok(statuses(MIGRATIONS, &applied))
+}
+
+
+async fn applied_sqlite_migrations(connection: &mut SqliteConnection) -> Result<Vec<AppliedMigration>> {
+    query(CREATE_SQLITE_SCHEMA_MIGRATIONS_TABLE_STATEMENT).execute(&mut *connection).await?;
+    Ok(query_as("SELECT version, name, checksum, applied_at FROM schema_migrations ORDER BY version").fetch_all(&mut *connection).await?)
+}
+
+
+///Applies the pending SQLite migrations. SQLite lets one writer in at a time, so no lock is taken
+/// and a second server migrating at once fails on the version it recorded first.
+pub async fn up_sqlite(pool: &Pool<Sqlite>) -> Result<Vec<&'static Migration>> {
+    let mut connection = pool.acquire().await?;
+    let applied = applied_sqlite_migrations(&mut connection).await?;
+    let pending = pending(SQLITE_MIGRATIONS, &applied)?;
+    for migration in &pending {
+        let mut transaction = connection.begin().await?;
+        if let Some(ref check) = migration.check {
+            refuse(migration, query_scalar(check.sql).fetch_all(&mut *transaction).await?)?;
+        }
+        raw_sql(migration.up).execute(&mut *transaction).await
+            .map_err(|err| format!("migration {} {} failed: {}", migration.version, migration.name, err))?;
+        query("INSERT INTO schema_migrations (version, name, checksum, applied_at) VALUES ($1, $2, $3, $4)")
+            .bind(migration.version).bind(migration.name).bind(migration.checksum()).bind(Utc::now())
+            .execute(&mut *transaction).await?;
+        transaction.commit().await?;
+    }
+    Ok(pending)
+}
+
+
+pub async fn down_sqlite(pool: &Pool<Sqlite>, steps: usize) -> Result<Vec<&'static Migration>> {
+    let mut connection = pool.acquire().await?;
+    let applied = applied_sqlite_migrations(&mut connection).await?;
+    pending(SQLITE_MIGRATIONS, &applied)?;
+    let mut reverted = Vec::new();
+    for applied in applied.iter().rev().take(steps) {
+        let migration = SQLITE_MIGRATIONS.iter().find(|migration| migration.version == applied.version).ok_or("unknown migration")?;
+        let mut transaction = connection.begin().await?;
+        raw_sql(migration.down).execute(&mut *transaction).await
+            .map_err(|err| format!("reverting migration {} {} failed: {}", migration.version, migration.name, err))?;
+        query("DELETE FROM schema_migrations WHERE version = $1").bind(migration.version).execute(&mut *transaction).await?;
+        transaction.commit().await?;
+        reverted.push(migration);
+    }
+    Ok(reverted)
+}
+
+
+pub async fn status_sqlite(pool: &Pool<Sqlite>) -> Result<Vec<MigrationStatus>> {
+    let mut connection = pool.acquire().await?;
+    let applied = applied_sqlite_migrations(&mut connection).await?;
+    Ok(statuses(SQLITE_MIGRATIONS, &applied))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_user;
+
+    #[test]
+    fn test_migrations_are_ordered() {
+        for migrations in [MIGRATIONS, SQLITE_MIGRATIONS] {
+            assert!(migrations.windows(2).all(|pair| pair[0].version < pair[1].version));
+        }
+    }
+
+    #[test]
+    fn test_pending_checks_applied_migrations() {
+        let applied = |version: i64, checksum: String| AppliedMigration{version, name: "initial".into(), checksum, applied_at: Utc::now()};
+        assert_eq!(pending(MIGRATIONS, &[]).unwrap().len(), MIGRATIONS.len());
+        assert_eq!(pending(MIGRATIONS, &[applied(1, MIGRATIONS[0].checksum())]).unwrap().len(), MIGRATIONS.len() - 1);
+        assert!(pending(MIGRATIONS, &[applied(1, "edited".into())]).is_err());
+        assert!(pending(MIGRATIONS, &[applied(9999, String::new())]).is_err());
+    }
+
+    #[actix_web::test]
+    async fn test_typed_email_migration_keeps_verified_users() {
+        use crate::{EmailAddress, User, UserStore};
+        let database = super::super::Database{url: "sqlite::memory:".into(), ..Default::default()};
+        let pool = database.connect_sqlite().await.unwrap();
+        up_sqlite(&pool).await.unwrap();
+        let user = User{email: EmailAddress::Verified("Jane@example.com".parse().unwrap()), ..test_user("Jane@example.com")};
+        pool.create_user(&user, "jane@example.com").await.unwrap();
+
+        down_sqlite(&pool, 7).await.unwrap();
+        let verified: bool = sqlx::query_scalar("SELECT json_extract(email, '$.verified') FROM users").fetch_one(&pool).await.unwrap();
+        assert!(verified);
+
+        up_sqlite(&pool).await.unwrap();
+        let migrated = pool.get_user_by_email("jane@example.com").await.unwrap();
+        assert_eq!(migrated.email, user.email);
+        assert_eq!(migrated.email_verified_at.map(|at| at.timestamp()), Some(user.created_at.timestamp()));
+    }
+
+    #[actix_web::test]
+    async fn test_typed_email_migration_lists_emails_differing_by_case() {
+        let database = super::super::Database{url: "sqlite::memory:".into(), ..Default::default()};
+        let pool = database.connect_sqlite().await.unwrap();
+        up_sqlite(&pool).await.unwrap();
+        down_sqlite(&pool, 7).await.unwrap();
+        for email in ["Jane@example.com", "jane@example.com", "john@example.com"] {
+            sqlx::query("INSERT INTO users (id, email, user_name, first_name, last_name, password, created_at) VALUES ($1, $2, '', '', '', '', $3)")
+                .bind(crate::Id::default()).bind(serde_json::json!({"email": email, "verified": false}).to_string()).bind(Utc::now())
+                .execute(&pool).await.unwrap();
+        }
+
+        let error = up_sqlite(&pool).await.err().unwrap().to_string();
+        assert!(error.contains("migration 2 typed_email can not be applied"));
+        assert!(error.contains("Jane@example.com (") && error.contains("jane@example.com ("));
+        assert!(!error.contains("john@example.com"));
+        assert_eq!(status_sqlite(&pool).await.unwrap().iter().filter(|status| status.applied_at.is_some()).count(), 1);
+    }
+
+    #[actix_web::test]
+    async fn test_audit_chain_migration_numbers_recorded_events() {
+        use crate::{audit, AuditStore};
+        let database = super::super::Database{url: "sqlite::memory:".into(), ..Default::default()};
+        let pool = database.connect_sqlite().await.unwrap();
+        up_sqlite(&pool).await.unwrap();
+        down_sqlite(&pool, 3).await.unwrap();
+        for action in ["user.signup", "login.succeeded"] {
+            sqlx::query("INSERT INTO audit_events (id, action, created_at) VALUES ($1, $2, $3)")
+                .bind(crate::Id::default()).bind(action).bind(Utc::now())
+                .execute(&pool).await.unwrap();
+        }
+        assert!(sqlx::query("DELETE FROM audit_events").execute(&pool).await.is_err());
+
+        up_sqlite(&pool).await.unwrap();
+        let details = serde_json::from_value(serde_json::json!({"a": 1, "b": {"c": "d", "e": [1, 2]}, "f": "g", "h": true})).unwrap();
+        audit::record(&pool, &Default::default(), None, "user.deleted", None, details).await.unwrap();
+        let chain = pool.get_chain().await.unwrap();
+        assert_eq!(chain.iter().map(|event| event.sequence).collect::<Vec<_>>(), [1, 2, 3]);
+        let report = audit::verify(&pool, &Default::default(), &Default::default()).await.unwrap();
+        assert_eq!((report.events, report.unchained), (3, 2));
+        assert!(report.breaks.is_empty());
+    }
+}
@@ -1,11 +1,37 @@
 mod argon2config;
 mod credentials;
+#[allow(clippy::module_inception)]
 mod config;
 mod mail;
+mod oidc;
+mod ldap;
+mod saml;
+mod jwt;
+mod lockout;
+mod deletion;
+mod audit_log;
+mod rate_limit;
+mod password_policy;
+mod breached_passwords;
+mod email_domains;
 mod db;
+mod layers;
+pub mod migration;
 
 pub use argon2config::*;
 pub use credentials::*;
 pub use config::*;
 pub use mail::*;
-pub use db::*;
\ No newline at end of file
+pub use oidc::*;
+pub use ldap::*;
+pub use saml::*;
+pub use jwt::*;
+pub use lockout::*;
+pub use deletion::*;
+pub use audit_log::*;
+pub use rate_limit::*;
+pub use password_policy::*;
+pub use breached_passwords::*;
+pub use email_domains::*;
+pub use db::*;
+pub use layers::*;
\ No newline at end of file
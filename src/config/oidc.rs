@@ -0,0 +1,28 @@
+use serde::{Serialize, Deserialize};
+
+
+const DEFAULT_SCOPES: &[&str] = &["openid", "email", "profile"];
+
+
+///An external OpenID Connect provider users can sign in with.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct OidcProvider {
+    ///The name used in the login and callback urls. e.g `/oidc/{name}/login`
+    pub name: String,
+    ///The issuer url. The provider metadata is discovered from `{issuer}/.well-known/openid-configuration`
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: String,
+    #[serde(default = "default_scopes")]
+    pub scopes: Vec<String>,
+    ///The email domains the provider may vouch for. A first sign in with an email of one of them
+    /// is linked to the existing account with that email, if its owner has verified it.
+    /// Otherwise they have to sign in to the account and link the identity themselves.
+    #[serde(default)]
+    pub trusted_domains: Vec<String>,
+}
+
+
+fn default_scopes() -> Vec<String> {
+    DEFAULT_SCOPES.iter().map(|scope| scope.to_string()).collect()
+}
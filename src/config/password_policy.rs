@@ -0,0 +1,36 @@
+use serde::{Serialize, Deserialize};
+
+
+///The rules a new password has to follow.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PasswordPolicy {
+    ///The least number of characters.
+    pub min_length: usize,
+    ///The most number of characters. Keeps hashing long inputs from being abused.
+    pub max_length: usize,
+    pub require_lowercase: bool,
+    pub require_uppercase: bool,
+    pub require_digit: bool,
+    pub require_symbol: bool,
+    ///Rejects passwords which contain, or are contained in, the email, user name or names of the user.
+    pub reject_user_data: bool,
+    ///Rejects passwords from the list of common passwords bundled with the server.
+    pub reject_common: bool,
+}
+
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        Self {
+            min_length: 8,
+            max_length: 128,
+            require_lowercase: false,
+            require_uppercase: false,
+            require_digit: false,
+            require_symbol: false,
+            reject_user_data: true,
+            reject_common: true,
+        }
+    }
+}
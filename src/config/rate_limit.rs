@@ -0,0 +1,74 @@
+use serde::{Serialize, Deserialize};
+
+
+///Where the token buckets are kept.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RateLimitBackend {
+    ///In the memory of this process. Every replica limits on its own.
+    #[default]
+    Memory,
+    ///In the database, so the limits are shared by every replica.
+    Postgres,
+}
+
+
+///What the requests of a policy are counted by.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RateLimitKey {
+    ///The IP address of the client.
+    Ip,
+    ///The `{id}` of the path, or else the subject of the bearer token. Falls back to the IP address.
+    User,
+    ///The `email` of the JSON body, case insensitively. Falls back to the IP address.
+    Email,
+}
+
+
+///A token bucket for the requests to a route.
+/// It holds up to `capacity` requests and refills completely over `period` seconds.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RateLimitPolicy {
+    pub method: String,
+    ///The path of the route. Segments like `{id}` match any single segment.
+    pub path: String,
+    pub key: RateLimitKey,
+    pub capacity: u32,
+    pub period: u64,
+}
+
+
+impl RateLimitPolicy {
+    fn new(method: &str, path: &str, key: RateLimitKey, capacity: u32, period: u64) -> Self {
+        Self{method: method.into(), path: path.into(), key, capacity, period}
+    }
+}
+
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RateLimit {
+    pub backend: RateLimitBackend,
+    pub policies: Vec<RateLimitPolicy>,
+}
+
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        use RateLimitKey::*;
+        let policies = vec![
+            RateLimitPolicy::new("POST", "/signup", Ip, 10, 60 * 60),
+            RateLimitPolicy::new("POST", "/signup", Email, 3, 60 * 60),
+            RateLimitPolicy::new("POST", "/login", Ip, 30, 15 * 60),
+            RateLimitPolicy::new("POST", "/login", Email, 10, 15 * 60),
+            RateLimitPolicy::new("GET", "/magic-link/{id}", Ip, 10, 15 * 60),
+            RateLimitPolicy::new("PATCH", "/users/verify-email/{id}", User, 5, 15 * 60),
+            RateLimitPolicy::new("PATCH", "/users/verify-email/{id}", Ip, 20, 15 * 60),
+            RateLimitPolicy::new("POST", "/password-reset", Ip, 10, 60 * 60),
+            RateLimitPolicy::new("POST", "/password-reset", Email, 3, 60 * 60),
+            RateLimitPolicy::new("POST", "/password-reset/{id}", Ip, 10, 15 * 60),
+        ];
+        Self{backend: Default::default(), policies}
+    }
+}
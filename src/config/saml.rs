@@ -0,0 +1,45 @@
+use serde::{Serialize, Deserialize};
+
+
+///An external SAML 2.0 identity provider users can sign in with.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SamlProvider {
+    ///The name used in the urls of the service provider. e.g `/saml/{name}/login`
+    pub name: String,
+    ///The entity id of the identity provider. It has to match the `Issuer` of its responses.
+    pub entity_id: String,
+    ///The single sign on url of the identity provider for the HTTP-Redirect binding.
+    pub sso_url: String,
+    ///The PEM encoded certificate the identity provider signs its assertions with.
+    pub certificate: String,
+    #[serde(default)]
+    pub attributes: SamlAttributes,
+    ///The email domains the provider may vouch for. A first sign in with an email of one of them
+    /// is linked to the existing account with that email, if its owner has verified it.
+    /// Otherwise they have to sign in to the account and link the identity themselves.
+    #[serde(default)]
+    pub trusted_domains: Vec<String>,
+}
+
+
+///The assertion attributes the `User` fields are read from.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SamlAttributes {
+    ///The email is read from the `NameID` when this is not set.
+    pub email: Option<String>,
+    pub user_name: Option<String>,
+    pub first_name: String,
+    pub last_name: String,
+}
+
+
+impl Default for SamlAttributes {
+    fn default() -> Self {
+        let email = None;
+        let user_name = None;
+        let first_name = String::from("givenName");
+        let last_name = String::from("sn");
+        Self {email, user_name, first_name, last_name}
+    }
+}
@@ -0,0 +1,35 @@
+use sqlx::{query, query_as, Pool, Postgres, types::Uuid};
+use crate::{AccountRestoration, Error};
+use chrono::Utc;
+use super::Id;
+
+type Executor = Pool<Postgres>;
+type Result<T> = std::result::Result<T, Error>;
+
+
+pub async fn create_account_restoration(executor: &Executor, restoration: &AccountRestoration) -> Result<()> {
+    query(r#"
+    INSERT INTO account_restorations (id, user_id, created_at, expires_at)
+    VALUES ($1, $2, $3, $4);"#)
+    .bind(restoration.id)
+    .bind(&restoration.user_id)
+    .bind(restoration.created_at)
+    .bind(restoration.expires_at)
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+
+///Returns the account restoration with the given id unless it has expired.
+pub async fn get_account_restoration(executor: &Executor, id: &Uuid) -> Result<Option<AccountRestoration>> {
+    let sql = "SELECT * FROM account_restorations WHERE id = $1 AND expires_at > $2";
+    Ok(query_as(sql).bind(id).bind(Utc::now()).fetch_optional(executor).await?)
+}
+
+
+///Deletes every account restoration of a user, once they are restored.
+pub async fn delete_account_restorations_by_user_id(executor: &Executor, user_id: &Id) -> Result<()> {
+    query("DELETE FROM account_restorations WHERE user_id = $1").bind(user_id).execute(executor).await?;
+    Ok(())
+}
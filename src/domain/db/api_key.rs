@@ -0,0 +1,59 @@
+use sqlx::{query, query_as, Pool, Postgres, types::Uuid};
+use crate::{ApiKey, Error};
+use actix_web::http::StatusCode;
+use chrono::{DateTime, Utc};
+use super::Id;
+
+type Executor = Pool<Postgres>;
+type Result<T> = std::result::Result<T, Error>;
+
+
+pub async fn create_api_key(executor: &Executor, api_key: &ApiKey) -> Result<()> {
+    query(r#"
+    INSERT INTO api_keys (id, user_id, name, prefix, secret_hash, scopes, expires_at, created_at)
+    VALUES ($1, $2, $3, $4, $5, $6, $7, $8);"#)
+    .bind(api_key.id)
+    .bind(&api_key.user_id)
+    .bind(&api_key.name)
+    .bind(&api_key.prefix)
+    .bind(&api_key.secret_hash)
+    .bind(&api_key.scopes)
+    .bind(api_key.expires_at)
+    .bind(api_key.created_at)
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+
+pub async fn get_api_key_by_prefix(executor: &Executor, prefix: &str) -> Result<Option<ApiKey>> {
+    let api_key = query_as::<_, ApiKey>("SELECT * FROM api_keys WHERE prefix = $1 AND user_id IN (SELECT id FROM users WHERE deleted_at IS NULL)")
+        .bind(prefix)
+        .fetch_optional(executor)
+        .await?;
+    Ok(api_key)
+}
+
+
+pub async fn get_api_keys_by_user_id(executor: &Executor, user_id: &Id) -> Result<Vec<ApiKey>> {
+    let sql = "SELECT * FROM api_keys WHERE user_id = $1 ORDER BY created_at";
+    Ok(query_as(sql).bind(user_id).fetch_all(executor).await?)
+}
+
+
+///Revokes a key of the user. Revoking a revoked key does nothing.
+pub async fn revoke_api_key(executor: &Executor, user_id: &Id, id: &Uuid) -> Result<()> {
+    let result = query("UPDATE api_keys SET revoked_at = COALESCE(revoked_at, NOW()) WHERE id = $1 AND user_id = $2;")
+        .bind(id).bind(user_id)
+        .execute(executor).await?;
+    match result.rows_affected() {
+        0 => Err(Error::Custom(StatusCode::NOT_FOUND, "api key not found".into())),
+        _ => Ok(())
+    }
+}
+
+
+pub async fn touch_api_key(executor: &Executor, id: &Uuid, used_at: DateTime<Utc>) -> Result<()> {
+    query("UPDATE api_keys SET last_used_at = $1 WHERE id = $2;").bind(used_at).bind(id).execute(executor).await?;
+    Ok(())
+}
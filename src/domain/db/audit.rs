@@ -0,0 +1,98 @@
+use sqlx::{query, query_as, types::Json, Pool, Postgres, QueryBuilder};
+use crate::{AuditCheckpoint, AuditEvent, AuditQuery, ChainHead, Error};
+
+type Executor = Pool<Postgres>;
+type Result<T> = std::result::Result<T, Error>;
+
+
+const AUDIT_EVENT_FIELDS: &str = "id, sequence, hash, action, actor_id, target_id, ip, user_agent, details, created_at";
+
+
+///Chains the event to the latest one. The table is locked until the event is added,
+/// so events recorded at the same time are chained one after the other.
+pub async fn record_event(executor: &Executor, event: &AuditEvent) -> Result<()> {
+    let mut transaction = executor.begin().await?;
+    query("LOCK TABLE audit_events IN SHARE ROW EXCLUSIVE MODE;").execute(&mut *transaction).await?;
+    let previous = query_as("SELECT sequence, hash FROM audit_events ORDER BY sequence DESC LIMIT 1")
+        .fetch_optional(&mut *transaction).await?;
+    let event = event.clone().chained(previous);
+    query(r#"
+        INSERT INTO audit_events (id, sequence, hash, action, actor_id, target_id, ip, user_agent, details, created_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10);
+    "#)
+        .bind(&event.id).bind(event.sequence).bind(&event.hash).bind(&event.action).bind(&event.actor_id).bind(&event.target_id)
+        .bind(&event.ip).bind(&event.user_agent).bind(Json(&event.details)).bind(event.created_at)
+        .execute(&mut *transaction).await?;
+    transaction.commit().await?;
+    Ok(())
+}
+
+
+fn build(audit_query: &AuditQuery) -> QueryBuilder<'_, Postgres> {
+    let mut builder = QueryBuilder::new(format!("SELECT {} FROM audit_events WHERE TRUE", AUDIT_EVENT_FIELDS));
+    if let Some(ref actor_id) = audit_query.actor_id {
+        builder.push(" AND actor_id = ").push_bind(actor_id);
+    }
+    if let Some(ref target_id) = audit_query.target_id {
+        builder.push(" AND target_id = ").push_bind(target_id);
+    }
+    if let Some(ref action) = audit_query.action {
+        builder.push(" AND action = ").push_bind(action);
+    }
+    if let Some(created_after) = audit_query.created_after {
+        builder.push(" AND created_at >= ").push_bind(created_after);
+    }
+    if let Some(created_before) = audit_query.created_before {
+        builder.push(" AND created_at < ").push_bind(created_before);
+    }
+    if let Some(ref cursor) = audit_query.cursor {
+        builder.push(" AND (created_at, id) < (").push_bind(cursor.created_at).push(", ").push_bind(&cursor.id).push(")");
+    }
+    builder.push(" ORDER BY created_at DESC, id DESC LIMIT ").push_bind(audit_query.limit);
+    builder
+}
+
+
+pub async fn get_events(executor: &Executor, audit_query: &AuditQuery) -> Result<Vec<AuditEvent>> {
+    Ok(build(audit_query).build_query_as().fetch_all(executor).await?)
+}
+
+
+pub async fn get_chain_head(executor: &Executor) -> Result<Option<ChainHead>> {
+    Ok(query_as("SELECT sequence, hash FROM audit_events ORDER BY sequence DESC LIMIT 1").fetch_optional(executor).await?)
+}
+
+
+pub async fn get_chain(executor: &Executor) -> Result<Vec<AuditEvent>> {
+    let sql = format!("SELECT {} FROM audit_events ORDER BY sequence", AUDIT_EVENT_FIELDS);
+    Ok(query_as(&sql).fetch_all(executor).await?)
+}
+
+
+pub async fn record_checkpoint(executor: &Executor, checkpoint: &AuditCheckpoint) -> Result<()> {
+    query("INSERT INTO audit_checkpoints (sequence, hash, signature, key_id, created_at) VALUES ($1, $2, $3, $4, $5);")
+        .bind(checkpoint.sequence).bind(&checkpoint.hash).bind(&checkpoint.signature).bind(checkpoint.key_id).bind(checkpoint.created_at)
+        .execute(executor).await?;
+    Ok(())
+}
+
+
+pub async fn get_checkpoints(executor: &Executor) -> Result<Vec<AuditCheckpoint>> {
+    Ok(query_as("SELECT sequence, hash, signature, key_id, created_at FROM audit_checkpoints ORDER BY sequence").fetch_all(executor).await?)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_audit_query_binds_every_value() {
+        let audit_query = AuditQuery{action: Some("login.failed' OR '1'='1".into()), target_id: Some(Default::default()), limit: 10, ..Default::default()};
+        let builder = build(&audit_query);
+        let sql = builder.sql();
+        assert!(!sql.contains("login.failed"));
+        assert!(sql.contains("target_id = $1 AND action = $2"));
+        assert!(sql.ends_with("ORDER BY created_at DESC, id DESC LIMIT $3"));
+    }
+}
@@ -0,0 +1,43 @@
+use sqlx::{query, query_as, Pool, Postgres};
+use crate::{Identity, Error};
+use super::Id;
+
+type Executor = Pool<Postgres>;
+type Result<T> = std::result::Result<T, Error>;
+
+
+pub async fn create_identity(executor: &Executor, identity: &Identity) -> Result<()> {
+    query(r#"
+    INSERT INTO identities (provider, subject, user_id, email, created_at)
+    VALUES ($1, $2, $3, $4, $5);"#)
+    .bind(&identity.provider)
+    .bind(&identity.subject)
+    .bind(&identity.user_id)
+    .bind(&identity.email)
+    .bind(identity.created_at)
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+
+///Returns the identity with the given provider and subject if it has been linked to a user.
+pub async fn get_identity(executor: &Executor, provider: &str, subject: &str) -> Result<Option<Identity>> {
+    let sql = "SELECT * FROM identities WHERE provider = $1 AND subject = $2";
+    let identity = query_as::<_, Identity>(sql)
+        .bind(provider)
+        .bind(subject)
+        .fetch_optional(executor)
+        .await?;
+    Ok(identity)
+}
+
+
+pub async fn get_identities_by_user_id(executor: &Executor, user_id: &Id) -> Result<Vec<Identity>> {
+    let sql = "SELECT * FROM identities WHERE user_id = $1 ORDER BY created_at";
+    let identities = query_as::<_, Identity>(sql)
+        .bind(user_id)
+        .fetch_all(executor)
+        .await?;
+    Ok(identities)
+}
@@ -0,0 +1,44 @@
+use sqlx::{query, query_scalar, Pool, Postgres};
+use chrono::{DateTime, Utc};
+use crate::Error;
+
+type Executor = Pool<Postgres>;
+type Result<T> = std::result::Result<T, Error>;
+
+
+///Returns the latest time any of the subjects is locked until, if one of them is locked.
+pub async fn get_locked_until(executor: &Executor, subjects: &[String]) -> Result<Option<DateTime<Utc>>> {
+    let sql = "SELECT MAX(locked_until) FROM login_failures WHERE subject = ANY($1) AND locked_until > NOW()";
+    Ok(query_scalar(sql).bind(subjects).fetch_one(executor).await?)
+}
+
+
+///Counts a failed login of the subject and returns its failures within the window.
+/// The count starts over once the window has passed since the last failure or once a lock has ended.
+pub async fn record_failure(executor: &Executor, subject: &str, window: i64) -> Result<i32> {
+    let sql = r#"
+        INSERT INTO login_failures (subject, failures, last_failed_at) VALUES ($1, 1, NOW())
+        ON CONFLICT (subject) DO UPDATE SET
+            failures = CASE
+                WHEN login_failures.last_failed_at < NOW() - make_interval(secs => $2) OR login_failures.locked_until <= NOW() THEN 1
+                ELSE login_failures.failures + 1
+            END,
+            last_failed_at = NOW(),
+            locked_until = CASE WHEN login_failures.locked_until > NOW() THEN login_failures.locked_until END
+        RETURNING failures;
+    "#;
+    Ok(query_scalar(sql).bind(subject).bind(window as f64).fetch_one(executor).await?)
+}
+
+
+pub async fn lock(executor: &Executor, subject: &str, until: DateTime<Utc>) -> Result<()> {
+    query("UPDATE login_failures SET locked_until = $1 WHERE subject = $2;").bind(until).bind(subject).execute(executor).await?;
+    Ok(())
+}
+
+
+///Forgets the failures and the lock of the subject.
+pub async fn clear(executor: &Executor, subject: &str) -> Result<()> {
+    query("DELETE FROM login_failures WHERE subject = $1;").bind(subject).execute(executor).await?;
+    Ok(())
+}
@@ -1,5 +1,15 @@
 pub mod verification;
 pub mod user;
+pub mod identity;
+pub mod saml;
+pub mod role;
+pub mod organization;
+pub mod api_key;
+pub mod login_failure;
+pub mod rate_limit;
+pub mod password_reset;
+pub mod account_restoration;
+pub mod audit;
 
 
 use super::*;
\ No newline at end of file
@@ -0,0 +1,194 @@
+use sqlx::{query, query_as, query_scalar, PgConnection, Pool, Postgres, types::Uuid};
+use crate::{Error, Invitation, Membership, Organization, OrganizationRole};
+use actix_web::http::StatusCode;
+use super::Id;
+
+type Executor = Pool<Postgres>;
+type Result<T> = std::result::Result<T, Error>;
+
+
+///Creates an organization with the given user as its owner.
+pub async fn create_organization(executor: &Executor, organization: &Organization, owner: &Id) -> Result<()> {
+    let mut transaction = executor.begin().await?;
+    query("INSERT INTO organizations (id, name, created_at) VALUES ($1, $2, $3);")
+        .bind(&organization.id).bind(&organization.name).bind(organization.created_at)
+        .execute(&mut *transaction).await?;
+    query("INSERT INTO memberships (organization_id, user_id, role) VALUES ($1, $2, $3);")
+        .bind(&organization.id).bind(owner).bind(OrganizationRole::Owner)
+        .execute(&mut *transaction).await?;
+    transaction.commit().await?;
+    Ok(())
+}
+
+
+pub async fn get_organization(executor: &Executor, id: &Id) -> Result<Organization> {
+    let organization = query_as::<_, Organization>("SELECT * FROM organizations WHERE id = $1")
+        .bind(id)
+        .fetch_optional(executor)
+        .await?;
+    organization.ok_or(Error::OrganizationNotFound)
+}
+
+
+///Returns the organizations the user is a member of.
+pub async fn get_user_organizations(executor: &Executor, user_id: &Id) -> Result<Vec<Organization>> {
+    let sql = r#"
+        SELECT o.* FROM organizations o JOIN memberships m ON m.organization_id = o.id
+        WHERE m.user_id = $1 ORDER BY o.created_at
+    "#;
+    Ok(query_as(sql).bind(user_id).fetch_all(executor).await?)
+}
+
+
+pub async fn update_organization(executor: &Executor, id: &Id, name: &str) -> Result<Organization> {
+    let organization = query_as::<_, Organization>("UPDATE organizations SET name = $1 WHERE id = $2 RETURNING *;")
+        .bind(name).bind(id)
+        .fetch_optional(executor)
+        .await?;
+    organization.ok_or(Error::OrganizationNotFound)
+}
+
+
+pub async fn delete_organization(executor: &Executor, id: &Id) -> Result<()> {
+    let result = query("DELETE FROM organizations WHERE id = $1;").bind(id).execute(executor).await?;
+    match result.rows_affected() {
+        0 => Err(Error::OrganizationNotFound),
+        _ => Ok(())
+    }
+}
+
+
+pub async fn get_membership(executor: &Executor, organization_id: &Id, user_id: &Id) -> Result<Option<Membership>> {
+    let membership = query_as::<_, Membership>("SELECT * FROM memberships WHERE organization_id = $1 AND user_id = $2")
+        .bind(organization_id).bind(user_id)
+        .fetch_optional(executor)
+        .await?;
+    Ok(membership)
+}
+
+
+pub async fn get_memberships(executor: &Executor, organization_id: &Id) -> Result<Vec<Membership>> {
+    let sql = "SELECT * FROM memberships WHERE organization_id = $1 ORDER BY created_at";
+    Ok(query_as(sql).bind(organization_id).fetch_all(executor).await?)
+}
+
+
+pub async fn update_membership_role(executor: &Executor, organization_id: &Id, user_id: &Id, role: OrganizationRole) -> Result<Membership> {
+    let membership = query_as::<_, Membership>("UPDATE memberships SET role = $1 WHERE organization_id = $2 AND user_id = $3 RETURNING *;")
+        .bind(role).bind(organization_id).bind(user_id)
+        .fetch_optional(executor)
+        .await?;
+    membership.ok_or_else(|| Error::Custom(StatusCode::NOT_FOUND, "membership not found".into()))
+}
+
+
+pub async fn delete_membership(executor: &Executor, organization_id: &Id, user_id: &Id) -> Result<()> {
+    let result = query("DELETE FROM memberships WHERE organization_id = $1 AND user_id = $2;")
+        .bind(organization_id).bind(user_id)
+        .execute(executor).await?;
+    match result.rows_affected() {
+        0 => Err(Error::Custom(StatusCode::NOT_FOUND, "membership not found".into())),
+        _ => Ok(())
+    }
+}
+
+
+pub async fn count_owners(executor: &Executor, organization_id: &Id) -> Result<i64> {
+    let sql = "SELECT COUNT(*) FROM memberships WHERE organization_id = $1 AND role = $2";
+    Ok(query_scalar(sql).bind(organization_id).bind(OrganizationRole::Owner).fetch_one(executor).await?)
+}
+
+
+///Makes sure no organization is left without an owner when the user goes away.
+/// Organizations the user is the only member of are deleted.
+/// In the other organizations the user is the only owner of, the longest standing admin,
+/// or the longest standing member when there is no admin, becomes the owner.
+/// Runs on the connection of the transaction purging the user.
+pub async fn release_sole_ownerships(connection: &mut PgConnection, user_id: &Id) -> Result<()> {
+    query(r#"
+        DELETE FROM organizations o WHERE EXISTS (
+            SELECT 1 FROM memberships m WHERE m.organization_id = o.id AND m.user_id = $1
+        ) AND NOT EXISTS (
+            SELECT 1 FROM memberships m WHERE m.organization_id = o.id AND m.user_id <> $1
+        );
+    "#).bind(user_id).execute(&mut *connection).await?;
+    query(r#"
+        UPDATE memberships SET role = 'owner' WHERE (organization_id, user_id) IN (
+            SELECT DISTINCT ON (m.organization_id) m.organization_id, m.user_id FROM memberships m
+            WHERE m.user_id <> $1 AND m.organization_id IN (
+                SELECT organization_id FROM memberships WHERE user_id = $1 AND role = 'owner'
+            ) AND NOT EXISTS (
+                SELECT 1 FROM memberships o WHERE o.organization_id = m.organization_id AND o.user_id <> $1 AND o.role = 'owner'
+            )
+            ORDER BY m.organization_id, m.role = 'admin' DESC, m.created_at
+        );
+    "#).bind(user_id).execute(&mut *connection).await?;
+    Ok(())
+}
+
+
+pub async fn create_invitation(executor: &Executor, invitation: &Invitation) -> Result<()> {
+    query(r#"
+    INSERT INTO invitations (id, organization_id, email, role, invited_by, created_at, expires_at)
+    VALUES ($1, $2, $3, $4, $5, $6, $7);"#)
+    .bind(invitation.id)
+    .bind(&invitation.organization_id)
+    .bind(&invitation.email)
+    .bind(invitation.role)
+    .bind(&invitation.invited_by)
+    .bind(invitation.created_at)
+    .bind(invitation.expires_at)
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+
+///Returns the invitation with the given id unless it has expired.
+pub async fn get_invitation(executor: &Executor, id: &Uuid) -> Result<Invitation> {
+    let invitation = query_as::<_, Invitation>("SELECT * FROM invitations WHERE id = $1 AND expires_at > NOW()")
+        .bind(id)
+        .fetch_optional(executor)
+        .await?;
+    invitation.ok_or_else(|| Error::Custom(StatusCode::NOT_FOUND, "invitation not found".into()))
+}
+
+
+///Returns the pending invitations sent to the email address.
+pub async fn get_invitations_by_email(executor: &Executor, email: &str) -> Result<Vec<Invitation>> {
+    let sql = "SELECT * FROM invitations WHERE lower(email) = lower($1) AND expires_at > NOW() ORDER BY created_at";
+    Ok(query_as(sql).bind(email).fetch_all(executor).await?)
+}
+
+
+pub async fn get_invitations_by_organization(executor: &Executor, organization_id: &Id) -> Result<Vec<Invitation>> {
+    let sql = "SELECT * FROM invitations WHERE organization_id = $1 AND expires_at > NOW() ORDER BY created_at";
+    Ok(query_as(sql).bind(organization_id).fetch_all(executor).await?)
+}
+
+
+pub async fn delete_invitation(executor: &Executor, id: &Uuid) -> Result<()> {
+    query("DELETE FROM invitations WHERE id = $1;").bind(id).execute(executor).await?;
+    Ok(())
+}
+
+
+///Adds the member of an accepted invitation and deletes the invitation.
+/// A user who is already a member keeps the higher of the two roles.
+pub async fn accept_invitation(executor: &Executor, invitation: &Invitation, user_id: &Id) -> Result<Membership> {
+    let mut transaction = executor.begin().await?;
+    let membership = query_as::<_, Membership>(r#"
+        INSERT INTO memberships (organization_id, user_id, role) VALUES ($1, $2, $3)
+        ON CONFLICT (organization_id, user_id) DO UPDATE SET role = CASE
+            WHEN memberships.role = 'owner' OR EXCLUDED.role = 'owner' THEN 'owner'
+            WHEN memberships.role = 'admin' OR EXCLUDED.role = 'admin' THEN 'admin'
+            ELSE 'member'
+        END
+        RETURNING *;
+    "#)
+        .bind(&invitation.organization_id).bind(user_id).bind(invitation.role)
+        .fetch_one(&mut *transaction).await?;
+    query("DELETE FROM invitations WHERE id = $1;").bind(invitation.id).execute(&mut *transaction).await?;
+    transaction.commit().await?;
+    Ok(membership)
+}
@@ -0,0 +1,35 @@
+use sqlx::{query, query_as, Pool, Postgres, types::Uuid};
+use crate::{Error, PasswordReset};
+use chrono::Utc;
+use super::Id;
+
+type Executor = Pool<Postgres>;
+type Result<T> = std::result::Result<T, Error>;
+
+
+pub async fn create_password_reset(executor: &Executor, reset: &PasswordReset) -> Result<()> {
+    query(r#"
+    INSERT INTO password_resets (id, user_id, created_at, expires_at)
+    VALUES ($1, $2, $3, $4);"#)
+    .bind(reset.id)
+    .bind(&reset.user_id)
+    .bind(reset.created_at)
+    .bind(reset.expires_at)
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+
+///Returns the password reset with the given id unless it has expired.
+pub async fn get_password_reset(executor: &Executor, id: &Uuid) -> Result<Option<PasswordReset>> {
+    let sql = "SELECT * FROM password_resets WHERE id = $1 AND expires_at > $2";
+    Ok(query_as(sql).bind(id).bind(Utc::now()).fetch_optional(executor).await?)
+}
+
+
+///Deletes every password reset of a user, so a reset link works only once.
+pub async fn delete_password_resets_by_user_id(executor: &Executor, user_id: &Id) -> Result<()> {
+    query("DELETE FROM password_resets WHERE user_id = $1").bind(user_id).execute(executor).await?;
+    Ok(())
+}
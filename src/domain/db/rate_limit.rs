@@ -0,0 +1,45 @@
+use sqlx::{query, query_as, Pool, Postgres};
+use chrono::{DateTime, Utc};
+use crate::rate_limit::Bucket;
+use crate::Error;
+
+type Executor = Pool<Postgres>;
+type Result<T> = std::result::Result<T, Error>;
+
+
+///Takes a token from the bucket of the key, as decided by `take` given the current bucket.
+/// The row is locked while deciding, so concurrent requests from other replicas wait their turn.
+/// A missing row is inserted first, as there would be nothing to lock and two requests could both find the bucket full.
+pub async fn take<F, T>(executor: &Executor, key: &str, take: F) -> Result<T>
+where
+    F: FnOnce(Option<Bucket>) -> (Bucket, DateTime<Utc>, T),
+{
+    let mut transaction = executor.begin().await?;
+    let inserted = query("INSERT INTO rate_limits (key, tokens, updated_at, full_at) VALUES ($1, 0, NOW(), NOW()) ON CONFLICT (key) DO NOTHING RETURNING key;")
+        .bind(key)
+        .fetch_optional(&mut *transaction)
+        .await?;
+    let bucket: Option<(f64, DateTime<Utc>)> = match inserted {
+        Some(_) => None,
+        None => query_as("SELECT tokens, updated_at FROM rate_limits WHERE key = $1 FOR UPDATE")
+            .bind(key)
+            .fetch_optional(&mut *transaction)
+            .await?,
+    };
+    let (bucket, full_at, decision) = take(bucket.map(|(tokens, updated_at)| Bucket{tokens, updated_at}));
+    query(r#"
+        INSERT INTO rate_limits (key, tokens, updated_at, full_at) VALUES ($1, $2, $3, $4)
+        ON CONFLICT (key) DO UPDATE SET tokens = EXCLUDED.tokens, updated_at = EXCLUDED.updated_at, full_at = EXCLUDED.full_at;
+    "#)
+        .bind(key).bind(bucket.tokens).bind(bucket.updated_at).bind(full_at)
+        .execute(&mut *transaction).await?;
+    transaction.commit().await?;
+    Ok(decision)
+}
+
+
+///Deletes the buckets which have filled up again. A missing bucket is a full one.
+pub async fn delete_full(executor: &Executor) -> Result<()> {
+    query("DELETE FROM rate_limits WHERE full_at <= NOW();").execute(executor).await?;
+    Ok(())
+}
@@ -0,0 +1,154 @@
+use sqlx::{query, query_as, query_scalar, Error as SqlxError, Pool, Postgres};
+use crate::{Access, Error, Role, PERMISSION_ALL};
+use actix_web::http::StatusCode;
+use super::Id;
+
+type Executor = Pool<Postgres>;
+type Result<T> = std::result::Result<T, Error>;
+
+
+const SELECT_ROLES: &str = r#"
+    SELECT r.name, r.description, r.created_at,
+    COALESCE(array_agg(p.permission ORDER BY p.permission) FILTER (WHERE p.permission IS NOT NULL), '{}') AS permissions
+    FROM roles r LEFT JOIN permissions p ON p.role = r.name
+"#;
+const ERROR_CODE_UNIQUE_VIOLATION: &str = "23505";
+const ERROR_CODE_FOREIGN_KEY_VIOLATION: &str = "23503";
+
+
+pub async fn get_roles(executor: &Executor) -> Result<Vec<Role>> {
+    let sql = format!("{} GROUP BY r.name ORDER BY r.name", SELECT_ROLES);
+    Ok(query_as(&sql).fetch_all(executor).await?)
+}
+
+
+pub async fn get_role(executor: &Executor, name: &str) -> Result<Role> {
+    let sql = format!("{} WHERE r.name = $1 GROUP BY r.name", SELECT_ROLES);
+    match query_as(&sql).bind(name).fetch_one(executor).await {
+        Ok(role) => Ok(role),
+        Err(SqlxError::RowNotFound) => Err(Error::RoleNotFound),
+        Err(err) => Err(err)?
+    }
+}
+
+
+///Creates a role with its permissions.
+pub async fn create_role(executor: &Executor, role: &Role) -> Result<()> {
+    let mut transaction = executor.begin().await?;
+    let result = query("INSERT INTO roles (name, description, created_at) VALUES ($1, $2, $3);")
+        .bind(&role.name).bind(&role.description).bind(role.created_at)
+        .execute(&mut *transaction).await;
+    if let Err(err) = result {
+        return match err.as_database_error().and_then(|err| err.code()).as_deref() {
+            Some(ERROR_CODE_UNIQUE_VIOLATION) => Err(Error::Custom(StatusCode::CONFLICT, "a role with the same name already exists".into())),
+            _ => Err(err)?
+        };
+    }
+    query("INSERT INTO permissions (role, permission) SELECT $1, unnest($2::TEXT[]) ON CONFLICT DO NOTHING;")
+        .bind(&role.name).bind(&role.permissions)
+        .execute(&mut *transaction).await?;
+    transaction.commit().await?;
+    Ok(())
+}
+
+
+///Updates the description of a role and replaces its permissions with the given ones.
+pub async fn update_role(executor: &Executor, name: &str, description: Option<&str>, permissions: Option<&[String]>) -> Result<Role> {
+    let mut transaction = executor.begin().await?;
+    let result = query("UPDATE roles SET description = COALESCE($1, description) WHERE name = $2;")
+        .bind(description).bind(name)
+        .execute(&mut *transaction).await?;
+    if result.rows_affected() == 0 {
+        return Err(Error::RoleNotFound);
+    }
+    if let Some(permissions) = permissions {
+        query("DELETE FROM permissions WHERE role = $1;").bind(name).execute(&mut *transaction).await?;
+        query("INSERT INTO permissions (role, permission) SELECT $1, unnest($2::TEXT[]) ON CONFLICT DO NOTHING;")
+            .bind(name).bind(permissions)
+            .execute(&mut *transaction).await?;
+    }
+    transaction.commit().await?;
+    get_role(executor, name).await
+}
+
+
+pub async fn delete_role(executor: &Executor, name: &str) -> Result<()> {
+    let result = query("DELETE FROM roles WHERE name = $1;").bind(name).execute(executor).await?;
+    match result.rows_affected() {
+        0 => Err(Error::RoleNotFound),
+        _ => Ok(())
+    }
+}
+
+
+pub async fn get_user_roles(executor: &Executor, user_id: &Id) -> Result<Vec<Role>> {
+    let sql = format!("{} WHERE r.name IN (SELECT role FROM user_roles WHERE user_id = $1) GROUP BY r.name ORDER BY r.name", SELECT_ROLES);
+    Ok(query_as(&sql).bind(user_id).fetch_all(executor).await?)
+}
+
+
+///Assigns a role by hand. A role the user already has from a directory stays theirs when the directory stops mapping it.
+pub async fn assign_role(executor: &Executor, user_id: &Id, role: &str) -> Result<()> {
+    let result = query("INSERT INTO user_roles (user_id, role) VALUES ($1, $2) ON CONFLICT (user_id, role) DO UPDATE SET source = NULL;")
+        .bind(user_id).bind(role)
+        .execute(executor).await;
+    match result {
+        Ok(_) => Ok(()),
+        Err(err) => match err.as_database_error().and_then(|err| err.code()).as_deref() {
+            Some(ERROR_CODE_FOREIGN_KEY_VIOLATION) => Err(Error::Custom(StatusCode::NOT_FOUND, "user or role not found".into())),
+            _ => Err(err)?
+        }
+    }
+}
+
+
+///Makes the roles the source maps to the user the given ones which exist. Unknown roles are ignored.
+/// The roles the source no longer maps are taken away, and the roles assigned by hand are left as they are.
+pub async fn sync_source_roles(executor: &Executor, user_id: &Id, source: &str, roles: &[String]) -> Result<()> {
+    let mut transaction = executor.begin().await?;
+    query("DELETE FROM user_roles WHERE user_id = $1 AND source = $2 AND NOT (role = ANY($3));")
+        .bind(user_id).bind(source).bind(roles)
+        .execute(&mut *transaction).await?;
+    query("INSERT INTO user_roles (user_id, role, source) SELECT $1, name, $2 FROM roles WHERE name = ANY($3) ON CONFLICT DO NOTHING;")
+        .bind(user_id).bind(source).bind(roles)
+        .execute(&mut *transaction).await?;
+    transaction.commit().await?;
+    Ok(())
+}
+
+
+pub async fn unassign_role(executor: &Executor, user_id: &Id, role: &str) -> Result<()> {
+    query("DELETE FROM user_roles WHERE user_id = $1 AND role = $2;").bind(user_id).bind(role).execute(executor).await?;
+    Ok(())
+}
+
+
+///Checks whether one of the roles of a user grants the permission. Deleted users have none.
+pub async fn has_permission(executor: &Executor, user_id: &Id, permission: &str) -> Result<bool> {
+    let sql = r#"
+        SELECT EXISTS (
+            SELECT 1 FROM user_roles ur JOIN permissions p ON p.role = ur.role JOIN users u ON u.id = ur.user_id
+            WHERE ur.user_id = $1 AND (p.permission = $2 OR p.permission = $3) AND u.deleted_at IS NULL
+        );
+    "#;
+    Ok(query_scalar(sql).bind(user_id).bind(permission).bind(PERMISSION_ALL).fetch_one(executor).await?)
+}
+
+
+///The roles of a user and the permissions they grant. Deleted users have none.
+pub async fn get_user_access(executor: &Executor, user_id: &Id) -> Result<Access> {
+    let sql = format!(r#"{} WHERE r.name IN (
+        SELECT ur.role FROM user_roles ur JOIN users u ON u.id = ur.user_id WHERE ur.user_id = $1 AND u.deleted_at IS NULL
+    ) GROUP BY r.name ORDER BY r.name"#, SELECT_ROLES);
+    let roles: Vec<Role> = query_as(&sql).bind(user_id).fetch_all(executor).await?;
+    let mut access = Access::default();
+    for role in roles {
+        for permission in role.permissions {
+            if !access.permissions.contains(&permission) {
+                access.permissions.push(permission);
+            }
+        }
+        access.roles.push(role.name);
+    }
+    Ok(access)
+}
@@ -0,0 +1,22 @@
+use chrono::{DateTime, Utc};
+use sqlx::{query, Pool, Postgres};
+use crate::Error;
+
+type Executor = Pool<Postgres>;
+type Result<T> = std::result::Result<T, Error>;
+
+
+///Records that an assertion has been consumed until it expires.
+/// Returns false when the assertion had already been recorded, meaning it is being replayed.
+pub async fn record_assertion(executor: &Executor, id: &str, expires_at: &DateTime<Utc>) -> Result<bool> {
+    query("DELETE FROM saml_assertions WHERE expires_at < $1;").bind(Utc::now()).execute(executor).await?;
+    let result = query(r#"
+    INSERT INTO saml_assertions (id, expires_at)
+    VALUES ($1, $2)
+    ON CONFLICT (id) DO NOTHING;"#)
+    .bind(id)
+    .bind(expires_at)
+    .execute(executor)
+    .await?;
+    Ok(result.rows_affected() == 1)
+}
@@ -1,4 +1,7 @@
-use sqlx::{query, query_as, Error as SqlxError, Execute, Pool, Postgres};
+use sqlx::{query, query_as, query_scalar, Error as SqlxError, Execute, Pool, Postgres, QueryBuilder};
+use futures_util::stream::BoxStream;
+use static_init::dynamic;
+use chrono::{DateTime, Utc};
 use actix_web::http::StatusCode;
 use std::collections::HashMap;
 use super::Value;
@@ -7,27 +10,42 @@ use super::*;
 type Executor = Pool<Postgres>;
 type Result<T> = std::result::Result<T, Error>;
 
+#[dynamic]
+static SELECT_ALL_USERS: String = format!("SELECT {} FROM users WHERE deleted_at IS NULL ORDER BY created_at, id", User::fields().join(", "));
+#[dynamic]
+static SELECT_ALL_USERS_WITH_PASSWORD: String = format!("SELECT {} FROM users WHERE deleted_at IS NULL ORDER BY created_at, id", FIELDS.join(", "));
+const ERROR_CODE_UNIQUE_VIOLATION: &str = "23505";
+
 ///This function inserts a new user into the database.
 /// This function first makes sure that a usert does not exist before it creates a new one.
-pub async fn create_user(executor: &Executor, user: &User) -> Result<()> {
-    user_by_email_does_not_exist(executor, &user.email).await?;
+/// The canonical email is what tells users apart, the email is kept as it was given.
+pub async fn create_user(executor: &Executor, user: &User, canonical_email: &str) -> Result<()> {
+    user_by_email_does_not_exist(executor, canonical_email).await?;
     query(r#"
     INSERT INTO users 
-    (id, email, user_name, first_name, last_name, password, created_at, profile_picture)
-    VALUES ($1, $2, $3, $4, $5, $6, $7, $8);"#,)
-    .bind(&user.id).bind(&user.email).bind(&user.user_name).bind(&user.first_name).bind(&user.last_name).bind(&user.password).bind(user.created_at).bind(&user.profile_picture)
-    .execute(executor).await?;
+    (id, email, email_canonical, email_verified_at, user_name, first_name, last_name, password, created_at, profile_picture)
+    VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10);"#,)
+    .bind(&user.id).bind(user.email.address().to_string()).bind(canonical_email).bind(user.verified_at()).bind(&user.user_name).bind(&user.first_name).bind(&user.last_name).bind(&user.password).bind(user.created_at).bind(&user.profile_picture)
+    .execute(executor).await.map_err(email_taken)?;
     Ok(())
 }
 
 
-///This function checks if a user with the provided email is in the database.
+///Maps the violation of the unique index on the canonical emails to `Error::UserWithEmailExists`.
+/// Another user can take the email between the check and the insert or update.
+fn email_taken(err: SqlxError) -> Error {
+    match err.as_database_error().and_then(|err| err.code()).as_deref() {
+        Some(ERROR_CODE_UNIQUE_VIOLATION) => Error::UserWithEmailExists,
+        _ => err.into()
+    }
+}
+
+
+///This function checks if a user with the provided canonical email is in the database.
 /// If a user with that email exists it returns an error.
 /// SO this function just makes sure that a user with that email does not exist.
-async fn user_by_email_does_not_exist(executor: &Executor, email: &EmailAddress) -> Result<()> {
-    use EmailAddress::*;
-    let email = match email{New(address)=>address.to_string(), Verified(address)=>address.to_string()};
-    let result = query(r#"SELECT id FROM users WHERE email->>'email' = $1;"#).bind(email).fetch_one(executor).await;
+async fn user_by_email_does_not_exist(executor: &Executor, canonical_email: &str) -> Result<()> {
+    let result = query(r#"SELECT id FROM users WHERE email_canonical = $1;"#).bind(canonical_email).fetch_one(executor).await;
     match result {
         Ok(record) => Err(Error::UserWithEmailExists),
         Err(err) => {
@@ -40,9 +58,26 @@ async fn user_by_email_does_not_exist(executor: &Executor, email: &EmailAddress)
 }
 
 
-/// This function gets a particular user by his id.
+/// This function replaces the email of a user with one that has not been verified yet.
+/// It fails when another user has the canonical email, deleted users included.
+pub async fn change_email(executor: &Executor, id: &Id, email: &str, canonical_email: &str) -> Result<User> {
+    let taken: bool = query_scalar("SELECT EXISTS (SELECT 1 FROM users WHERE email_canonical = $1 AND id <> $2);").bind(canonical_email).bind(id).fetch_one(executor).await?;
+    if taken {
+        return Err(Error::UserWithEmailExists);
+    }
+    let sql = &format!("UPDATE users SET email = $1, email_canonical = $2, email_verified_at = NULL WHERE id = $3 AND deleted_at IS NULL RETURNING {};", User::fields().join(", "));
+    match query_as(sql).bind(email).bind(canonical_email).bind(id).fetch_one(executor).await {
+        Ok(user) => Ok(user),
+        Err(SqlxError::RowNotFound) => Err(Error::UserNotFound),
+        Err(err) => Err(email_taken(err))
+    }
+}
+
+
+/// This function gets a particular user by his id. Deleted users are not found by this or any of the functions below
+/// that do not say otherwise.
 pub async fn get_user_by_id(executor: &Executor, id: &Id) -> Result<User> {
-    let sql = &format!("SELECT {} FROM users WHERE id = $1", User::fields().join(", "));
+    let sql = &format!("SELECT {} FROM users WHERE id = $1 AND deleted_at IS NULL", User::fields().join(", "));
     let result = query_as(sql).bind(id).fetch_one(executor).await;
     match result {
         Ok(user) => Ok(user),
@@ -56,15 +91,144 @@ pub async fn get_user_by_id(executor: &Executor, id: &Id) -> Result<User> {
 }
 
 
-/// This function deletes a user by id.
-pub async fn delete_user_by_id(executor: &Executor, id: &Id) -> Result<()> {
-    query("DELETE FROM users WHERE id = $1;").bind(id).execute(executor).await?;
+/// This function gets a particular user by the canonical form of his email address.
+pub async fn get_user_by_email(executor: &Executor, canonical_email: &str) -> Result<User> {
+    let sql = &format!("SELECT {} FROM users WHERE email_canonical = $1 AND deleted_at IS NULL", User::fields().join(", "));
+    let result = query_as(sql).bind(canonical_email).fetch_one(executor).await;
+    match result {
+        Ok(user) => Ok(user),
+        Err(err) => {
+            match err {
+                SqlxError::RowNotFound => Err(Error::UserNotFound),
+                _ => Err(err)?
+            }
+        }
+    }
+}
+
+
+/// This function gets a user by canonical email address along with their password hash, for checking a login.
+pub async fn get_user_with_password_by_email(executor: &Executor, canonical_email: &str) -> Result<User> {
+    let sql = &format!("SELECT {} FROM users WHERE email_canonical = $1 AND deleted_at IS NULL", FIELDS.join(", "));
+    match query_as(sql).bind(canonical_email).fetch_one(executor).await {
+        Ok(user) => Ok(user),
+        Err(SqlxError::RowNotFound) => Err(Error::UserNotFound),
+        Err(err) => Err(err)?
+    }
+}
+
+
+/// This function gets a user by id along with their password hash, for checking a password change.
+pub async fn get_user_with_password_by_id(executor: &Executor, id: &Id) -> Result<User> {
+    let sql = &format!("SELECT {} FROM users WHERE id = $1 AND deleted_at IS NULL", FIELDS.join(", "));
+    match query_as(sql).bind(id).fetch_one(executor).await {
+        Ok(user) => Ok(user),
+        Err(SqlxError::RowNotFound) => Err(Error::UserNotFound),
+        Err(err) => Err(err)?
+    }
+}
+
+
+/// This function replaces the password hash of a user.
+pub async fn set_password(executor: &Executor, id: &Id, password_hash: &str) -> Result<()> {
+    let result = query("UPDATE users SET password = $1 WHERE id = $2 AND deleted_at IS NULL;").bind(password_hash).bind(id).execute(executor).await?;
+    match result.rows_affected() {
+        0 => Err(Error::UserNotFound),
+        _ => Ok(())
+    }
+}
+
+
+/// This function counts the users with a password and those whose hash does not start with the given prefix.
+pub async fn count_password_hashes(executor: &Executor, current_prefix: &str) -> Result<PasswordHashReport> {
+    let (total, outdated) = query_as(r#"
+    SELECT COUNT(*), COUNT(*) FILTER (WHERE LEFT(password, LENGTH($1)) <> $1)
+    FROM users WHERE password <> '' AND deleted_at IS NULL;"#)
+    .bind(current_prefix)
+    .fetch_one(executor).await?;
+    Ok(PasswordHashReport{total, outdated})
+}
+
+
+/// This function marks a user as deleted. The user can be restored until they are purged after the given time.
+pub async fn delete_user_by_id(executor: &Executor, id: &Id, purge_after: DateTime<Utc>) -> Result<()> {
+    let result = query("UPDATE users SET deleted_at = NOW(), purge_after = $1 WHERE id = $2 AND deleted_at IS NULL;")
+        .bind(purge_after).bind(id).execute(executor).await?;
+    match result.rows_affected() {
+        0 => Err(Error::UserNotFound),
+        _ => Ok(())
+    }
+}
+
+
+/// This function brings back a deleted user who has not been purged yet.
+pub async fn restore_user(executor: &Executor, id: &Id) -> Result<User> {
+    let sql = &format!(r#"
+    UPDATE users SET deleted_at = NULL, purge_after = NULL
+    WHERE id = $1 AND deleted_at IS NOT NULL AND purge_after > NOW() RETURNING {};"#, User::fields().join(", "));
+    match query_as(sql).bind(id).fetch_one(executor).await {
+        Ok(user) => Ok(user),
+        Err(SqlxError::RowNotFound) => Err(Error::UserNotFound),
+        Err(err) => Err(err)?
+    }
+}
+
+
+/// This function deletes for good the users whose grace period has ended, with everything of theirs.
+/// The organizations they solely own are handed over or deleted first, so a restored user still owns theirs.
+/// Returns how many were purged.
+pub async fn purge_deleted_users(executor: &Executor) -> Result<u64> {
+    let mut transaction = executor.begin().await?;
+    let ids: Vec<Id> = query_scalar("SELECT id FROM users WHERE purge_after <= NOW() FOR UPDATE;").fetch_all(&mut *transaction).await?;
+    for id in &ids {
+        super::organization::release_sole_ownerships(&mut transaction, id).await?;
+        query("DELETE FROM users WHERE id = $1;").bind(id).execute(&mut *transaction).await?;
+    }
+    transaction.commit().await?;
+    Ok(ids.len() as u64)
+}
+
+
+/// This function returns the id, email and canonical email of every user, oldest first.
+/// The deleted users are included, their emails stay taken until they are purged.
+pub async fn get_canonical_emails(executor: &Executor) -> Result<Vec<(Id, String, String)>> {
+    Ok(query_as("SELECT id, email, email_canonical FROM users ORDER BY created_at, id;").fetch_all(executor).await?)
+}
+
+
+/// This function sets the canonical emails of users, all of them or none.
+/// They are cleared first, so a user may take the canonical email another one gives up.
+pub async fn set_canonical_emails(executor: &Executor, changes: &[(Id, String)]) -> Result<()> {
+    let mut transaction = executor.begin().await?;
+    for (id, _) in changes {
+        query("UPDATE users SET email_canonical = '#' || encode(id, 'hex') WHERE id = $1;").bind(id).execute(&mut *transaction).await?;
+    }
+    for (id, canonical_email) in changes {
+        query("UPDATE users SET email_canonical = $1 WHERE id = $2;").bind(canonical_email).bind(id).execute(&mut *transaction).await?;
+    }
+    transaction.commit().await?;
     Ok(())
 }
 
 
+/// This function overwrites the email, names and profile picture of a user with the values in the given user.
+pub async fn update_user(executor: &Executor, user: &User, canonical_email: &str) -> Result<User> {
+    let sql = &format!(r#"
+    UPDATE users SET email = $1, email_canonical = $2, email_verified_at = $3, user_name = $4, first_name = $5, last_name = $6, profile_picture = $7
+    WHERE id = $8 AND deleted_at IS NULL RETURNING {};"#, User::fields().join(", "));
+    let result = query_as(sql)
+        .bind(user.email.address().to_string()).bind(canonical_email).bind(user.verified_at()).bind(&user.user_name).bind(&user.first_name).bind(&user.last_name).bind(&user.profile_picture).bind(&user.id)
+        .fetch_one(executor).await;
+    match result {
+        Ok(user) => Ok(user),
+        Err(SqlxError::RowNotFound) => Err(Error::UserNotFound),
+        Err(err) => Err(email_taken(err))
+    }
+}
+
+
 /// update user by id
-pub async fn update_user_by_id<'a>(executor: &Executor, id: &Id, map: &HashMap<&'a str, Value>) -> Result<User> {
+pub async fn update_user_by_id(executor: &Executor, id: &Id, map: &HashMap<&str, Value>) -> Result<User> {
     let mut index = 1usize;
     let mut updates = Vec::new();
     let mut values = Vec::new();
@@ -73,23 +237,26 @@ pub async fn update_user_by_id<'a>(executor: &Executor, id: &Id, map: &HashMap<&
         values.push(value);
         index+=1;
     }
-    if updates.len() == 0 {
+    if updates.is_empty() {
         return  Err(Error::Custom(StatusCode::BAD_REQUEST, "No Data to Update. Please provide fields and values to be updated".into()));
     }
     // let statement = format!("WITH updated AS (UPDATE users SET {} WHERE id = ${} RETURNING id) SELECT * FROM users_view WHERE id IN (SELECT id FROM updated);", updates.join(", "), index);
-    let statement = format!("UPDATE users SET {} WHERE id = ${} RETURNING {};", updates.join(", "), index, User::fields().join(", "));
+    let statement = format!("UPDATE users SET {} WHERE id = ${} AND deleted_at IS NULL RETURNING {};", updates.join(", "), index, User::fields().join(", "));
     let mut query = query_as::<Postgres, User>(&statement);
     for value in values {
         query = query.bind(value);
     }
     query = query.bind(id);
-    let user = query.fetch_one(executor).await?;
-    Ok(user)
+    match query.fetch_one(executor).await {
+        Ok(user) => Ok(user),
+        Err(SqlxError::RowNotFound) => Err(Error::UserNotFound),
+        Err(err) => Err(err)?
+    }
 }
 
 
 pub async fn verify_user(executor: &Executor, user_id: &Id) -> Result<User> {
-    let sql = &format!("UPDATE users SET email = jsonb_set(email, '{{verified}}', 'true'::jsonb) WHERE id = $1 RETURNING {};", User::fields().join(", "));
+    let sql = &format!("UPDATE users SET email_verified_at = COALESCE(email_verified_at, NOW()) WHERE id = $1 AND deleted_at IS NULL RETURNING {};", User::fields().join(", "));
     let result = query_as(sql)
         .bind(user_id)
         .fetch_one(executor)
@@ -99,4 +266,99 @@ pub async fn verify_user(executor: &Executor, user_id: &Id) -> Result<User> {
         Err(sqlx::Error::RowNotFound) => Err(Error::Custom(StatusCode::NOT_FOUND, "the user you are trying to validate seems to be deleted".into())),
         Err(err) => Err(Error::Custom(StatusCode::INTERNAL_SERVER_ERROR, "Internal Server error".into())),
     }
-}
\ No newline at end of file
+}
+
+
+/// This function streams every user, oldest first, row by row as they are read from the database.
+pub fn stream_users(executor: &Executor, with_password: bool) -> BoxStream<'_, std::result::Result<User, SqlxError>> {
+    let sql = match with_password {
+        true => SELECT_ALL_USERS_WITH_PASSWORD.as_str(),
+        false => SELECT_ALL_USERS.as_str(),
+    };
+    query_as(sql).fetch(executor)
+}
+
+
+///Escapes the wildcards of a `LIKE` pattern so the value is matched literally.
+fn escape_like(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_")
+}
+
+
+impl UserQuery {
+    fn build(&self) -> QueryBuilder<'_, Postgres> {
+        let mut builder = QueryBuilder::new(format!("SELECT {} FROM users WHERE deleted_at IS NULL", User::fields().join(", ")));
+        if let Some(verified) = self.verified {
+            builder.push(" AND (email_verified_at IS NOT NULL) = ").push_bind(verified);
+        }
+        if let Some(created_after) = self.created_after {
+            builder.push(" AND created_at >= ").push_bind(created_after);
+        }
+        if let Some(created_before) = self.created_before {
+            builder.push(" AND created_at < ").push_bind(created_before);
+        }
+        if let Some(ref role) = self.role {
+            builder.push(" AND id IN (SELECT user_id FROM user_roles WHERE role = ").push_bind(role).push(")");
+        }
+        if let Some(ref search) = self.search {
+            let pattern = format!("%{}%", escape_like(search));
+            builder.push(" AND (");
+            for (index, column) in ["email", "user_name", "first_name", "last_name"].into_iter().enumerate() {
+                if index > 0 {
+                    builder.push(" OR ");
+                }
+                builder.push(column).push(" ILIKE ").push_bind(pattern.clone()).push(" ESCAPE '\\'");
+            }
+            builder.push(")");
+        }
+        let (comparison, direction) = match self.order {
+            SortOrder::Asc => (">", "ASC"),
+            SortOrder::Desc => ("<", "DESC"),
+        };
+        if let Some(ref cursor) = self.cursor {
+            builder.push(format!(" AND (created_at, id) {} (", comparison))
+                .push_bind(cursor.created_at).push(", ").push_bind(&cursor.id).push(")");
+        }
+        builder.push(format!(" ORDER BY created_at {0}, id {0} LIMIT ", direction)).push_bind(self.limit);
+        builder
+    }
+}
+
+
+/// This function lists the users matching the query, one page at a time.
+pub async fn get_users(executor: &Executor, user_query: &UserQuery) -> Result<Page<User>> {
+    // One more user than asked for is fetched to tell whether there is a next page.
+    let user_query = UserQuery{limit: user_query.limit + 1, ..user_query.clone()};
+    let mut users: Vec<User> = user_query.build().build_query_as().fetch_all(executor).await?;
+    let mut next_cursor = None;
+    if users.len() as i64 == user_query.limit {
+        users.pop();
+        next_cursor = users.last().map(|user| Cursor{created_at: user.created_at, id: user.id.clone()}.encode());
+    }
+    Ok(Page{items: users, next_cursor})
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_user_query_binds_every_value() {
+        let user_query = UserQuery {
+            verified: Some(true),
+            role: Some("admin' OR '1'='1".into()),
+            search: Some("100%_jane".into()),
+            cursor: Some(Cursor{created_at: Utc::now(), id: Default::default()}),
+            limit: 10,
+            ..Default::default()
+        };
+        let builder = user_query.build();
+        let sql = builder.sql();
+        assert!(!sql.contains("admin"));
+        assert!(!sql.contains("jane"));
+        assert!(sql.contains("(created_at, id) < ($7, $8)"));
+        assert!(sql.ends_with("ORDER BY created_at DESC, id DESC LIMIT $9"));
+        assert_eq!(escape_like("100%_jane\\"), "100\\%\\_jane\\\\");
+    }
+}
@@ -10,10 +10,10 @@ pub async fn create_verification_code(executor: &Executor, verification: &Verifi
     query(r#"
     INSERT INTO verification_codes (id, user_id, code, created_at)
     VALUES ($1, $2, $3, $4);"#)
-    .bind(&verification.id)
+    .bind(verification.id)
     .bind(&verification.user_id)
     .bind(&verification.code)
-    .bind(&verification.created_at)
+    .bind(verification.created_at)
     .execute(executor)
     .await?;
     Ok(())
@@ -1,7 +1,9 @@
 mod services;
 mod types;
 mod db;
+mod store;
 
 
 pub use services::*;
-pub use types::*;
\ No newline at end of file
+pub use types::*;
+pub use store::*;
\ No newline at end of file
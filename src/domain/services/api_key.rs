@@ -0,0 +1,127 @@
+use rand::{distributions::Alphanumeric, Rng};
+use super::{ApiKey, ApiKeyStore, Error, Id, UserStore};
+use actix_web::http::StatusCode;
+use chrono::{DateTime, Utc};
+use sqlx::types::Uuid;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+type Result<T> = std::result::Result<T, Error>;
+
+
+///Tells API keys apart from JWTs in the `Authorization` header.
+pub const KEY_PREFIX: &str = "ak_";
+const PREFIX_LENGTH: usize = 8;
+const SECRET_LENGTH: usize = 32;
+
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct NewApiKey {
+    pub name: String,
+    #[serde(default)]
+    pub scopes: Vec<String>,
+    #[serde(default)]
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+
+fn random_string(length: usize) -> String {
+    rand::thread_rng().sample_iter(&Alphanumeric).take(length).map(char::from).collect()
+}
+
+
+fn hash_secret(secret: &str) -> String {
+    Sha256::digest(secret.as_bytes()).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+
+///Compares two strings in time independent of where they differ.
+pub fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len() && a.bytes().zip(b.bytes()).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+
+///Splits a key of the form `ak_<prefix>_<secret>` into its prefix and secret.
+fn parse_key(key: &str) -> Option<(&str, &str)> {
+    let (prefix, secret) = key.strip_prefix(KEY_PREFIX)?.split_once('_')?;
+    match prefix.len() == PREFIX_LENGTH && secret.len() == SECRET_LENGTH {
+        true => Some((prefix, secret)),
+        false => None
+    }
+}
+
+
+///Creates a key for the user and returns it along with the key itself, which is not stored.
+pub async fn create_api_key(api_keys: &dyn ApiKeyStore, user_id: &Id, new_key: NewApiKey) -> Result<(ApiKey, String)> {
+    let name = new_key.name.trim();
+    if name.is_empty() {
+        return Err(Error::Custom(StatusCode::BAD_REQUEST, "the api key needs a name".into()));
+    }
+    let now = Utc::now();
+    if new_key.expires_at.is_some_and(|expires_at| expires_at <= now) {
+        return Err(Error::Custom(StatusCode::BAD_REQUEST, "the expiry of the api key has to be in the future".into()));
+    }
+    let prefix = random_string(PREFIX_LENGTH);
+    let secret = random_string(SECRET_LENGTH);
+    let api_key = ApiKey {
+        id: Uuid::new_v4(),
+        user_id: user_id.clone(),
+        name: name.to_string(),
+        prefix: prefix.clone(),
+        secret_hash: hash_secret(&secret),
+        scopes: new_key.scopes,
+        expires_at: new_key.expires_at,
+        last_used_at: None,
+        revoked_at: None,
+        created_at: now,
+    };
+    api_keys.create_api_key(&api_key).await?;
+    Ok((api_key, format!("{}{}_{}", KEY_PREFIX, prefix, secret)))
+}
+
+
+pub async fn get_api_keys(users: &dyn UserStore, api_keys: &dyn ApiKeyStore, user_id: &Id) -> Result<Vec<ApiKey>> {
+    users.get_user_by_id(user_id).await?;
+    api_keys.get_api_keys_by_user_id(user_id).await
+}
+
+
+pub async fn revoke_api_key(api_keys: &dyn ApiKeyStore, user_id: &Id, id: &Uuid) -> Result<()> {
+    api_keys.revoke_api_key(user_id, id).await
+}
+
+
+///Returns the active key matching the given one and records its use.
+pub async fn authenticate(api_keys: &dyn ApiKeyStore, key: &str) -> Result<ApiKey> {
+    let (prefix, secret) = parse_key(key).ok_or(Error::Unauthorized)?;
+    let api_key = api_keys.get_api_key_by_prefix(prefix).await?.ok_or(Error::Unauthorized)?;
+    let now = Utc::now();
+    if !constant_time_eq(&api_key.secret_hash, &hash_secret(secret)) || !api_key.is_active(now) {
+        return Err(Error::Unauthorized);
+    }
+    api_keys.touch_api_key(&api_key.id, now).await?;
+    Ok(api_key)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_key() {
+        let prefix = random_string(PREFIX_LENGTH);
+        let secret = random_string(SECRET_LENGTH);
+        let key = format!("{}{}_{}", KEY_PREFIX, prefix, secret);
+        assert_eq!(parse_key(&key), Some((prefix.as_str(), secret.as_str())));
+        assert!(parse_key(&format!("{}_{}", prefix, secret)).is_none());
+        assert!(parse_key(&format!("{}{}_{}", KEY_PREFIX, prefix, &secret[1..])).is_none());
+    }
+
+    #[test]
+    fn test_hash_secret() {
+        assert_eq!(hash_secret("secret"), "2bb80d537b1da3e38bd30361aa855686bde0eacd7162fef6a25fe97bf527a25b");
+        assert!(constant_time_eq(&hash_secret("secret"), &hash_secret("secret")));
+        assert!(!constant_time_eq(&hash_secret("secret"), &hash_secret("secreT")));
+    }
+}
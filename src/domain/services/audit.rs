@@ -0,0 +1,308 @@
+use super::{AuditCheckpoint, AuditEvent, AuditQuery, AuditStore, Cursor, Error, Id, Page, Value, AUDIT_GENESIS_HASH};
+use chrono::{DateTime, Utc};
+use actix_web::http::StatusCode;
+use crate::config::{AuditLog, Config, Jwt};
+use hmac::Hmac;
+use sha2::Sha256;
+use log::{error, warn};
+use std::fmt::{self, Display, Formatter};
+use std::collections::HashMap;
+use serde::Deserialize;
+use hmac::Mac;
+
+type Result<T> = std::result::Result<T, Error>;
+
+
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 100;
+
+
+///Where a request came from, as recorded with the events it leads to.
+#[derive(Clone, Debug, Default)]
+pub struct Origin {
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+
+///Records that the actor did something, to the target user if there is one.
+pub async fn record(audit: &dyn AuditStore, origin: &Origin, actor_id: Option<&Id>, action: &str, target_id: Option<&Id>, details: Value) -> Result<()> {
+    // Kept to the microsecond like the cursors, so paging through events of the same microsecond skips none.
+    let now = Utc::now();
+    let created_at = DateTime::from_timestamp_micros(now.timestamp_micros()).unwrap_or(now);
+    let event = AuditEvent {
+        id: Id::default(),
+        sequence: 0,
+        hash: None,
+        action: action.to_string(),
+        actor_id: actor_id.cloned(),
+        target_id: target_id.cloned(),
+        ip: origin.ip.clone(),
+        user_agent: origin.user_agent.clone(),
+        details,
+        created_at,
+    };
+    audit.record_event(&event).await
+}
+
+
+///The parameters of an audit event listing as given by a client.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct AuditListing {
+    pub limit: Option<i64>,
+    pub cursor: Option<String>,
+    pub actor_id: Option<String>,
+    pub target_id: Option<String>,
+    pub action: Option<String>,
+    pub created_after: Option<DateTime<Utc>>,
+    pub created_before: Option<DateTime<Utc>>,
+}
+
+
+fn parse_id(id: Option<&str>) -> Result<Option<Id>> {
+    id.map(|id| id.parse().map_err(|_| Error::Custom(StatusCode::BAD_REQUEST, "invalid id".into()))).transpose()
+}
+
+
+///Lists the audit events one page at a time, newest first.
+pub async fn get_events(audit: &dyn AuditStore, listing: AuditListing) -> Result<Page<AuditEvent>> {
+    let cursor = match listing.cursor {
+        Some(ref cursor) => Some(Cursor::decode(cursor).ok_or_else(|| Error::Custom(StatusCode::BAD_REQUEST, "invalid cursor".into()))?),
+        None => None,
+    };
+    let limit = listing.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+    let audit_query = AuditQuery {
+        actor_id: parse_id(listing.actor_id.as_deref())?,
+        target_id: parse_id(listing.target_id.as_deref())?,
+        action: listing.action,
+        created_after: listing.created_after,
+        created_before: listing.created_before,
+        cursor,
+        // One more event than asked for is fetched to tell whether there is a next page.
+        limit: limit + 1,
+    };
+    let mut events = audit.get_events(&audit_query).await?;
+    let mut next_cursor = None;
+    if events.len() as i64 > limit {
+        events.truncate(limit as usize);
+        next_cursor = events.last().map(|event| Cursor{created_at: event.created_at, id: event.id.clone()}.encode());
+    }
+    Ok(Page{items: events, next_cursor})
+}
+
+
+///Lists what happened to the account of a user, newest first.
+pub async fn get_activity(audit: &dyn AuditStore, user_id: &Id, listing: AuditListing) -> Result<Page<AuditEvent>> {
+    get_events(audit, AuditListing{target_id: Some(user_id.to_hex()), ..listing}).await
+}
+
+
+fn checkpoint_message(sequence: i64, hash: &str) -> String {
+    format!("{}:{}", sequence, hash)
+}
+
+
+///Signs the latest event as a checkpoint with the current checkpoint key,
+/// unless there is no key, it already is a checkpoint or it is not chained.
+pub async fn sign_checkpoint(audit: &dyn AuditStore, audit_log: &AuditLog) -> Result<Option<AuditCheckpoint>> {
+    let Some(version) = audit_log.current_key() else {
+        return Ok(None);
+    };
+    let mut key = audit_log.key(version).ok_or("the checkpoint key is not loaded")?;
+    let (sequence, hash) = match audit.get_chain_head().await? {
+        Some((sequence, Some(hash))) => (sequence, hash),
+        _ => return Ok(None),
+    };
+    if audit.get_checkpoints().await?.last().is_some_and(|checkpoint| checkpoint.sequence >= sequence) {
+        return Ok(None);
+    }
+    key.update(checkpoint_message(sequence, &hash).as_bytes());
+    let signature = key.finalize().into_bytes().iter().map(|byte| format!("{:02x}", byte)).collect();
+    let checkpoint = AuditCheckpoint{sequence, hash, signature, key_id: Some(version.into()), created_at: Utc::now()};
+    audit.record_checkpoint(&checkpoint).await?;
+    Ok(Some(checkpoint))
+}
+
+
+///Signs a checkpoint every checkpoint interval, for as long as the server runs.
+pub async fn sign_checkpoints(audit: &dyn AuditStore, config: &Config) {
+    if config.audit_log.current_key().is_none() {
+        warn!("no audit checkpoint key is configured, the audit log is chained but no checkpoints are signed");
+        return;
+    }
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(config.audit_log.checkpoint_interval.max(1)));
+    loop {
+        interval.tick().await;
+        // A checkpoint that could not be signed is tried again at the next interval.
+        if let Err(err) = sign_checkpoint(audit, &config.audit_log).await {
+            error!("could not sign an audit checkpoint: {}", err);
+        }
+    }
+}
+
+
+///The key the checkpoint was signed with. The checkpoints without a key id were signed with the JWT secret.
+fn checkpoint_key(audit_log: &AuditLog, jwt: &Jwt, checkpoint: &AuditCheckpoint) -> Option<Hmac<Sha256>> {
+    match checkpoint.key_id {
+        Some(key_id) => u32::try_from(key_id).ok().and_then(|version| audit_log.key(version)),
+        None => Some(jwt.key()),
+    }
+}
+
+
+fn signature_is_valid(mut key: Hmac<Sha256>, checkpoint: &AuditCheckpoint) -> bool {
+    let signature: Option<Vec<u8>> = (0..checkpoint.signature.len()).step_by(2)
+        .map(|index| checkpoint.signature.get(index..index + 2).and_then(|byte| u8::from_str_radix(byte, 16).ok()))
+        .collect();
+    key.update(checkpoint_message(checkpoint.sequence, &checkpoint.hash).as_bytes());
+    signature.is_some_and(|signature| key.verify_slice(&signature).is_ok())
+}
+
+
+///Where the audit log was found to be altered.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ChainBreak {
+    ///The events from one sequence to another were removed.
+    Missing{from: i64, to: i64},
+    ///The event, or the one before it, was changed.
+    Modified{sequence: i64},
+    ///The checkpoint was not signed with the checkpoint key it names.
+    ForgedCheckpoint{sequence: i64},
+    ///The checkpoint was signed with a key that is not configured, so it can not be checked.
+    UnknownCheckpointKey{sequence: i64, key_id: i64},
+    ///The event the checkpoint was signed for has changed since.
+    CheckpointMismatch{sequence: i64},
+}
+
+
+impl Display for ChainBreak {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ChainBreak::Missing{from, to} if from == to => write!(f, "event {} is missing", from),
+            ChainBreak::Missing{from, to} => write!(f, "events {} to {} are missing", from, to),
+            ChainBreak::Modified{sequence} => write!(f, "event {} or the one before it was modified", sequence),
+            ChainBreak::ForgedCheckpoint{sequence} => write!(f, "the checkpoint of event {} has an invalid signature", sequence),
+            ChainBreak::UnknownCheckpointKey{sequence, key_id} => write!(f, "the checkpoint of event {} was signed with the unknown key {}", sequence, key_id),
+            ChainBreak::CheckpointMismatch{sequence} => write!(f, "event {} no longer matches its checkpoint", sequence),
+        }
+    }
+}
+
+
+///What verifying the audit log found.
+#[derive(Clone, Debug, Default)]
+pub struct ChainReport {
+    pub events: usize,
+    ///The events recorded before the log was chained, which can not be verified.
+    pub unchained: usize,
+    pub checkpoints: usize,
+    pub breaks: Vec<ChainBreak>,
+}
+
+
+///Walks the chain of audit events and checks it against the signed checkpoints.
+/// Removed events show as gaps in the sequence, or past the end of the chain when a checkpoint was signed for them.
+/// Changed events no longer hash to what is stored, or no longer match the checkpoint signed for them.
+/// The checkpoints are checked with the checkpoint keys, and the oldest ones, which have no key id, with the JWT secret.
+pub async fn verify(audit: &dyn AuditStore, audit_log: &AuditLog, jwt: &Jwt) -> Result<ChainReport> {
+    let events = audit.get_chain().await?;
+    let checkpoints = audit.get_checkpoints().await?;
+    let mut report = ChainReport{events: events.len(), checkpoints: checkpoints.len(), ..Default::default()};
+    let mut hashes = HashMap::new();
+    let mut previous: Option<&AuditEvent> = None;
+    for event in &events {
+        let expected = previous.map_or(1, |previous| previous.sequence + 1);
+        if event.sequence > expected {
+            // The event after a gap can not be checked against the one before it, which is gone.
+            report.breaks.push(ChainBreak::Missing{from: expected, to: event.sequence - 1});
+        }else if event.sequence < expected {
+            report.breaks.push(ChainBreak::Modified{sequence: event.sequence});
+        }else {
+            let previous_hash = previous.map(|previous| previous.hash.as_deref());
+            match (&event.hash, previous_hash) {
+                // Only the events before the first chained one may have no hash.
+                (None, None | Some(None)) => report.unchained += 1,
+                (None, Some(Some(_))) => report.breaks.push(ChainBreak::Modified{sequence: event.sequence}),
+                (Some(hash), previous_hash) => {
+                    if *hash != event.chain_hash(previous_hash.flatten().unwrap_or(AUDIT_GENESIS_HASH)) {
+                        report.breaks.push(ChainBreak::Modified{sequence: event.sequence});
+                    }
+                }
+            }
+        }
+        hashes.insert(event.sequence, event.hash.as_deref());
+        previous = Some(event);
+    }
+
+    let last = previous.map_or(0, |previous| previous.sequence);
+    let mut signed_up_to = None;
+    for checkpoint in &checkpoints {
+        match checkpoint_key(audit_log, jwt, checkpoint).map(|key| signature_is_valid(key, checkpoint)) {
+            None => {
+                report.breaks.push(ChainBreak::UnknownCheckpointKey{sequence: checkpoint.sequence, key_id: checkpoint.key_id.unwrap_or_default()});
+                continue;
+            },
+            Some(false) => {
+                report.breaks.push(ChainBreak::ForgedCheckpoint{sequence: checkpoint.sequence});
+                continue;
+            },
+            Some(true) => signed_up_to = signed_up_to.max(Some(checkpoint.sequence)),
+        }
+        match hashes.get(&checkpoint.sequence) {
+            Some(hash) if *hash != Some(checkpoint.hash.as_str()) => report.breaks.push(ChainBreak::CheckpointMismatch{sequence: checkpoint.sequence}),
+            // Events missing within the chain were reported as a gap already.
+            _ => (),
+        }
+    }
+    if let Some(signed_up_to) = signed_up_to.filter(|signed_up_to| *signed_up_to > last) {
+        report.breaks.push(ChainBreak::Missing{from: last + 1, to: signed_up_to});
+    }
+    Ok(report)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MemoryStore;
+    use crate::config::{CheckpointKey, SecretSource};
+
+    #[actix_web::test]
+    async fn test_verify_detects_changes() {
+        let store = MemoryStore::default();
+        let jwt = Jwt::default();
+        let audit_log = |secret: &str| {
+            let mut audit_log = AuditLog{checkpoint_keys: vec![CheckpointKey{version: 1, source: SecretSource::Value(secret.into())}], ..Default::default()};
+            audit_log.load_checkpoint_keys().unwrap();
+            audit_log
+        };
+        let keys = audit_log("first");
+        for action in ["user.signup", "login.succeeded", "login.failed", "user.deleted"] {
+            let details = Value::Map(HashMap::from([("a".to_string(), Value::Number(1)), ("b".to_string(), Value::String(action.into()))]));
+            record(&store, &Origin::default(), None, action, None, details).await.unwrap();
+        }
+        assert_eq!(sign_checkpoint(&store, &keys).await.unwrap().map(|checkpoint| checkpoint.sequence), Some(4));
+        assert!(sign_checkpoint(&store, &keys).await.unwrap().is_none());
+        let report = verify(&store, &keys, &jwt).await.unwrap();
+        assert_eq!((report.events, report.checkpoints), (4, 1));
+        assert!(report.breaks.is_empty());
+
+        let mut events = store.get_chain().await.unwrap();
+        events[1].action = "login.failed".into();
+        events.remove(2);
+        events.pop();
+        let tampered = MemoryStore::default();
+        for event in &events {
+            tampered.record_event(event).await.unwrap();
+        }
+        let report = verify(&tampered, &keys, &jwt).await.unwrap();
+        assert!(report.breaks.is_empty(), "a rewritten chain is only caught by the checkpoints");
+        tampered.record_checkpoint(&store.get_checkpoints().await.unwrap()[0]).await.unwrap();
+        let report = verify(&tampered, &keys, &jwt).await.unwrap();
+        assert_eq!(report.breaks, [ChainBreak::Missing{from: 3, to: 4}]);
+        let report = verify(&store, &audit_log("other"), &jwt).await.unwrap();
+        assert_eq!(report.breaks, [ChainBreak::ForgedCheckpoint{sequence: 4}]);
+        let report = verify(&store, &AuditLog::default(), &jwt).await.unwrap();
+        assert_eq!(report.breaks, [ChainBreak::UnknownCheckpointKey{sequence: 4, key_id: 1}]);
+    }
+}
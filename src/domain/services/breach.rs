@@ -0,0 +1,238 @@
+use crate::config::{BreachAction, BreachedPasswords};
+use std::io::{self, BufRead, BufReader, ErrorKind};
+use std::path::{Path, PathBuf};
+use sha1::{Digest, Sha1};
+use std::fs::File;
+use super::Error;
+
+type Result<T> = std::result::Result<T, Error>;
+type Hash = [u8; 20];
+
+const FILTER_MAGIC: &[u8; 4] = b"BPF1";
+///The magic, the number of hashes as a `u32` and the number of bits as a `u64`.
+const FILTER_HEADER_LENGTH: usize = 16;
+
+
+fn sha1(password: &str) -> Hash {
+    Sha1::digest(password.as_bytes()).into()
+}
+
+
+fn parse_hash(hex: &str) -> Option<Hash> {
+    if hex.len() != 40 || !hex.is_ascii() {
+        return None;
+    }
+    let mut hash = [0u8; 20];
+    for (index, byte) in hash.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[index * 2..index * 2 + 2], 16).ok()?;
+    }
+    Some(hash)
+}
+
+
+///Splits a `SUFFIX:COUNT` line of a range file. Lines with a count of zero are padding and are skipped.
+fn parse_line(line: &str) -> Option<&str> {
+    let (hash, count) = line.trim().split_once(':')?;
+    match count.trim().parse::<u64>() {
+        Ok(0) | Err(_) => None,
+        Ok(_) => Some(hash)
+    }
+}
+
+
+///A bloom filter of the SHA-1 hashes of breached passwords.
+/// It never misses a breached password, and wrongly reports others at the rate it was built for.
+pub struct BreachFilter {
+    hashes: u32,
+    bits: u64,
+    data: Vec<u8>,
+}
+
+
+impl BreachFilter {
+    ///An empty filter sized for the given number of hashes.
+    pub fn new(entries: u64, false_positive_rate: f64) -> Self {
+        let entries = entries.max(1) as f64;
+        let ln2 = std::f64::consts::LN_2;
+        let bits = (-entries * false_positive_rate.ln() / (ln2 * ln2)).ceil().max(64.0) as u64;
+        let hashes = (bits as f64 / entries * ln2).round().clamp(1.0, 32.0) as u32;
+        Self{hashes, bits, data: vec![0; bits.div_ceil(8) as usize]}
+    }
+
+    ///The bits of a hash, derived from the hash itself by double hashing since SHA-1 is already uniform.
+    fn positions(hashes: u32, bits: u64, hash: &Hash) -> impl Iterator<Item = u64> {
+        let first = u64::from_le_bytes(hash[0..8].try_into().unwrap());
+        let second = u64::from_le_bytes(hash[8..16].try_into().unwrap()) | 1;
+        (0..hashes as u64).map(move |index| first.wrapping_add(index.wrapping_mul(second)) % bits)
+    }
+
+    pub fn insert(&mut self, hash: &Hash) {
+        for position in Self::positions(self.hashes, self.bits, hash) {
+            self.data[(position / 8) as usize] |= 1 << (position % 8);
+        }
+    }
+
+    pub fn contains(&self, hash: &Hash) -> bool {
+        Self::positions(self.hashes, self.bits, hash).all(|position| self.data[(position / 8) as usize] & (1 << (position % 8)) != 0)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(FILTER_HEADER_LENGTH + self.data.len());
+        bytes.extend_from_slice(FILTER_MAGIC);
+        bytes.extend_from_slice(&self.hashes.to_le_bytes());
+        bytes.extend_from_slice(&self.bits.to_le_bytes());
+        bytes.extend_from_slice(&self.data);
+        bytes
+    }
+
+    pub fn from_bytes(mut bytes: Vec<u8>) -> Option<Self> {
+        if bytes.len() < FILTER_HEADER_LENGTH || &bytes[..4] != FILTER_MAGIC {
+            return None;
+        }
+        let hashes = u32::from_le_bytes(bytes[4..8].try_into().ok()?);
+        let bits = u64::from_le_bytes(bytes[8..16].try_into().ok()?);
+        let data = bytes.split_off(FILTER_HEADER_LENGTH);
+        match hashes > 0 && bits > 0 && data.len() as u64 == bits.div_ceil(8) {
+            true => Some(Self{hashes, bits, data}),
+            false => None
+        }
+    }
+}
+
+
+///The range files of a directory with the prefix each of them covers.
+fn range_files(directory: &Path) -> io::Result<Vec<(String, PathBuf)>> {
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(directory)? {
+        let path = entry?.path();
+        let prefix = path.file_stem().and_then(|stem| stem.to_str()).unwrap_or_default().to_uppercase();
+        if path.is_file() && prefix.len() == 5 && prefix.chars().all(|c| c.is_ascii_hexdigit()) {
+            files.push((prefix, path));
+        }
+    }
+    Ok(files)
+}
+
+
+///Calls `f` with every hash of a directory of range files, or of a single file of `HASH:COUNT` lines.
+fn for_each_hash(source: &Path, mut f: impl FnMut(&Hash)) -> io::Result<()> {
+    let files = match source.is_dir() {
+        true => range_files(source)?,
+        false => vec![(String::new(), source.to_path_buf())]
+    };
+    for (prefix, path) in files {
+        for line in BufReader::new(File::open(path)?).lines() {
+            let line = line?;
+            if let Some(hash) = parse_line(&line).and_then(|suffix| parse_hash(&format!("{}{}", prefix, suffix))) {
+                f(&hash);
+            }
+        }
+    }
+    Ok(())
+}
+
+
+///Builds a filter of every hash of the source with the given false positive rate.
+/// The source is read twice, once to size the filter and once to fill it.
+pub fn build_filter(source: &Path, false_positive_rate: f64) -> io::Result<BreachFilter> {
+    let mut entries = 0u64;
+    for_each_hash(source, |_| entries += 1)?;
+    let mut filter = BreachFilter::new(entries, false_positive_rate);
+    for_each_hash(source, |hash| filter.insert(hash))?;
+    Ok(filter)
+}
+
+
+enum Corpus {
+    Disabled,
+    Ranges(PathBuf),
+    Filter(BreachFilter),
+}
+
+
+///The breached passwords new passwords are checked against, and what to do with a match.
+pub struct BreachCorpus {
+    corpus: Corpus,
+    pub action: BreachAction,
+}
+
+
+impl BreachCorpus {
+    ///Opens the configured corpus. A directory is read as range files, any other file as a filter.
+    pub fn load(config: &BreachedPasswords) -> io::Result<Self> {
+        let corpus = match config.path {
+            None => Corpus::Disabled,
+            Some(ref path) if Path::new(path).is_dir() => Corpus::Ranges(path.into()),
+            Some(ref path) => {
+                let filter = BreachFilter::from_bytes(std::fs::read(path)?)
+                    .ok_or_else(|| io::Error::new(ErrorKind::InvalidData, format!("{} is not a breached password filter", path)))?;
+                Corpus::Filter(filter)
+            }
+        };
+        Ok(Self{corpus, action: config.action})
+    }
+
+    ///Tells whether the password is in the corpus.
+    /// A prefix without a range file is taken as having no breached passwords.
+    pub async fn contains(&self, password: &str) -> Result<bool> {
+        let hash = sha1(password);
+        match self.corpus {
+            Corpus::Disabled => Ok(false),
+            Corpus::Filter(ref filter) => Ok(filter.contains(&hash)),
+            Corpus::Ranges(ref directory) => {
+                let hex: String = hash.iter().map(|byte| format!("{:02X}", byte)).collect();
+                let (prefix, suffix) = hex.split_at(5);
+                for name in [format!("{}.txt", prefix), prefix.to_string()] {
+                    match tokio::fs::read_to_string(directory.join(name)).await {
+                        Ok(range) => return Ok(range.lines().filter_map(parse_line).any(|line| line.eq_ignore_ascii_case(suffix))),
+                        Err(err) if err.kind() == ErrorKind::NotFound => continue,
+                        Err(err) => return Err(Error::InternalServerError(Some(err.into())))
+                    }
+                }
+                Ok(false)
+            }
+        }
+    }
+}
+
+
+impl Default for BreachCorpus {
+    fn default() -> Self {
+        Self{corpus: Corpus::Disabled, action: Default::default()}
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_ranges_and_filter_find_breached_passwords() {
+        let directory = std::env::temp_dir().join(format!("breach-{}", bson::oid::ObjectId::new()));
+        std::fs::create_dir_all(&directory).unwrap();
+        // The SHA-1 of `password` is 5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8.
+        std::fs::write(directory.join("5BAA6.txt"), "003D68EB55068C33ACE09247EE4C639306B:3\r\n1E4C9B93F3F0682250B6CF8331B7EE68FD8:9545824\r\n1E4C9B93F3F0682250B6CF8331B7EE68FD9:0\r\n").unwrap();
+        let ranges = BreachCorpus::load(&BreachedPasswords{path: Some(directory.to_string_lossy().into()), ..Default::default()}).unwrap();
+        assert!(ranges.contains("password").await.unwrap());
+        assert!(!ranges.contains("correct horse battery staple").await.unwrap());
+
+        let filter = build_filter(&directory, 0.001).unwrap();
+        let path = directory.join("breached.filter");
+        std::fs::write(&path, filter.to_bytes()).unwrap();
+        let filter = BreachCorpus::load(&BreachedPasswords{path: Some(path.to_string_lossy().into()), ..Default::default()}).unwrap();
+        assert!(filter.contains("password").await.unwrap());
+        assert!(!filter.contains("correct horse battery staple").await.unwrap());
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn test_filter_rejects_malformed_bytes() {
+        let mut filter = BreachFilter::new(100, 0.01);
+        filter.insert(&sha1("password"));
+        let bytes = filter.to_bytes();
+        assert!(BreachFilter::from_bytes(bytes.clone()).unwrap().contains(&sha1("password")));
+        assert!(BreachFilter::from_bytes(bytes[..bytes.len() - 1].to_vec()).is_none());
+        assert!(BreachFilter::from_bytes(b"not a filter at all".to_vec()).is_none());
+    }
+}
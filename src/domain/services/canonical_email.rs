@@ -0,0 +1,87 @@
+use super::{EmailNormalization, Error, Id, UserStore};
+use std::collections::{BTreeMap, HashSet};
+
+type Result<T> = std::result::Result<T, Error>;
+
+
+///What recomputing the canonical emails with the configured normalization changes.
+#[derive(Debug, Default, PartialEq)]
+pub struct Recanonicalization {
+    ///The users whose canonical email changes, with the new one.
+    pub changes: Vec<(Id, String)>,
+    ///The canonical emails several users would share, with the ids and emails of those users.
+    /// Their canonical emails are left as they are until all but one of them are merged or changed.
+    pub collisions: Vec<(String, Vec<(Id, String)>)>,
+    ///The users whose email has no canonical form, with their email.
+    pub invalid: Vec<(Id, String)>,
+}
+
+
+///Works out the canonical email of every user, given as their id, email and current canonical email.
+/// The users who would share a canonical email keep theirs, and so does anyone they would clash with afterwards.
+pub fn recanonicalize(users: &[(Id, String, String)], normalization: &EmailNormalization) -> Recanonicalization {
+    let mut report = Recanonicalization::default();
+    let canonical: Vec<Option<String>> = users.iter().map(|(_, email, _)| normalization.canonicalize(email)).collect();
+    for ((id, email, _), canonical) in users.iter().zip(&canonical) {
+        if canonical.is_none() {
+            report.invalid.push((id.clone(), email.clone()));
+        }
+    }
+    let mut held = HashSet::new();
+    loop {
+        let mut by_canonical: BTreeMap<&str, Vec<usize>> = BTreeMap::new();
+        for (index, (_, _, current)) in users.iter().enumerate() {
+            let canonical = canonical[index].as_deref().filter(|_| !held.contains(&index)).unwrap_or(current);
+            by_canonical.entry(canonical).or_default().push(index);
+        }
+        let mut clashed = false;
+        for (canonical, indexes) in by_canonical.into_iter().filter(|(_, indexes)| indexes.len() > 1) {
+            report.collisions.push((canonical.to_string(), indexes.iter().map(|&index| (users[index].0.clone(), users[index].1.clone())).collect()));
+            for index in indexes {
+                clashed |= held.insert(index);
+            }
+        }
+        if !clashed {
+            break;
+        }
+    }
+    report.changes = users.iter().zip(&canonical).enumerate()
+        .filter(|(index, _)| !held.contains(index))
+        .filter_map(|(_, ((id, _, current), canonical))| canonical.as_ref().filter(|canonical| *canonical != current).map(|canonical| (id.clone(), canonical.clone())))
+        .collect();
+    report
+}
+
+
+///Recomputes the canonical emails of every user with the normalization, after it was changed.
+/// Applies the changes unless it is a dry run, and returns them with the collisions left to resolve.
+pub async fn recanonicalize_users(users: &dyn UserStore, normalization: &EmailNormalization, dry_run: bool) -> Result<Recanonicalization> {
+    let emails = users.get_canonical_emails().await?;
+    let report = recanonicalize(&emails, normalization);
+    if !dry_run && !report.changes.is_empty() {
+        users.set_canonical_emails(&report.changes).await?;
+    }
+    Ok(report)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recanonicalize_holds_back_collisions() {
+        let ids: Vec<Id> = (0..5).map(|_| Id::default()).collect();
+        let users = vec![
+            (ids[0].clone(), "Jane@Example.com".to_string(), "Jane@example.com".to_string()),
+            (ids[1].clone(), "jane@example.com".to_string(), "jane@example.com".to_string()),
+            (ids[2].clone(), "John@Example.com".to_string(), "John@example.com".to_string()),
+            (ids[3].clone(), "Mary@example.com".to_string(), "Mary@example.com".to_string()),
+            (ids[4].clone(), "not an email".to_string(), "not an email".to_string()),
+        ];
+        let report = recanonicalize(&users, &EmailNormalization::default());
+        assert_eq!(report.changes, vec![(ids[2].clone(), "john@example.com".to_string()), (ids[3].clone(), "mary@example.com".to_string())]);
+        assert_eq!(report.collisions, vec![("jane@example.com".to_string(), vec![(ids[0].clone(), "Jane@Example.com".to_string()), (ids[1].clone(), "jane@example.com".to_string())])]);
+        assert_eq!(report.invalid, vec![(ids[4].clone(), "not an email".to_string())]);
+    }
+}
@@ -0,0 +1,148 @@
+use crate::config::EmailDomains;
+use std::collections::HashSet;
+use std::io;
+use lettre::Address;
+use super::{DomainRejection, Error};
+
+type Result<T> = std::result::Result<T, Error>;
+
+
+///The domains of the disposable email services known when the server was built.
+const DISPOSABLE_DOMAINS: &str = include_str!("disposable_domains.txt");
+
+
+///The domains of a list, one per line, lower-cased and in punycode. Blank lines and `#` comments are skipped.
+fn parse_domains<'a>(lines: impl IntoIterator<Item = &'a str>) -> HashSet<String> {
+    lines.into_iter()
+        .map(|line| line.split('#').next().unwrap_or_default().trim().trim_end_matches('.'))
+        .filter(|line| !line.is_empty())
+        .filter_map(|domain| idna::domain_to_ascii(domain).ok())
+        .collect()
+}
+
+
+///Whether a domain is in the set, or is a subdomain of one that is.
+fn matches(domains: &HashSet<String>, domain: &str) -> bool {
+    let mut domain = domain;
+    loop {
+        if domains.contains(domain) {
+            return true;
+        }
+        match domain.split_once('.') {
+            Some((_, parent)) => domain = parent,
+            None => return false,
+        }
+    }
+}
+
+
+///Whether a domain could have a mail server: a host name of letters, digits and hyphens
+/// with at least two labels and an alphabetic top level domain.
+fn is_mail_domain(domain: &str) -> bool {
+    let labels: Vec<&str> = domain.split('.').collect();
+    let valid_label = |label: &&str| {
+        !label.is_empty() && label.len() <= 63 && !label.starts_with('-') && !label.ends_with('-')
+            && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+    };
+    let top_level = labels.last().copied().unwrap_or_default();
+    let valid_top_level = top_level.len() >= 2 && (top_level.chars().all(|c| c.is_ascii_alphabetic()) || top_level.starts_with("xn--"));
+    domain.len() <= 253 && labels.len() >= 2 && labels.iter().all(valid_label) && valid_top_level
+}
+
+
+///The domain policy of the configuration, with its lists loaded.
+#[derive(Debug, Default)]
+pub struct DomainPolicy {
+    allow: HashSet<String>,
+    block: HashSet<String>,
+    disposable: HashSet<String>,
+    check_mx_syntax: bool,
+}
+
+
+impl DomainPolicy {
+    pub fn load(config: &EmailDomains) -> io::Result<Self> {
+        let disposable = match (config.block_disposable, &config.disposable_list) {
+            (false, _) => HashSet::new(),
+            (true, Some(path)) => parse_domains(std::fs::read_to_string(path)?.lines()),
+            (true, None) => parse_domains(DISPOSABLE_DOMAINS.lines()),
+        };
+        Ok(Self {
+            allow: parse_domains(config.allow.iter().map(String::as_str)),
+            block: parse_domains(config.block.iter().map(String::as_str)),
+            disposable,
+            check_mx_syntax: config.check_mx_syntax,
+        })
+    }
+
+    ///Fails with `Error::EmailDomainRejected` when the domain of the address is refused.
+    /// Blocked domains are refused first, then those not allowed. Allowed domains are never taken as disposable.
+    pub fn check(&self, address: &Address) -> Result<()> {
+        let rejected = |rejection| Err(Error::EmailDomainRejected(rejection));
+        let domain = match idna::domain_to_ascii(address.domain()) {
+            Ok(domain) => domain,
+            Err(_) => return rejected(DomainRejection::InvalidDomain),
+        };
+        let domain = domain.trim_end_matches('.');
+        if self.check_mx_syntax && !is_mail_domain(domain) {
+            return rejected(DomainRejection::InvalidDomain);
+        }
+        if matches(&self.block, domain) {
+            return rejected(DomainRejection::Blocked);
+        }
+        match (self.allow.is_empty(), matches(&self.allow, domain)) {
+            (false, false) => rejected(DomainRejection::NotAllowed),
+            (false, true) => Ok(()),
+            (true, _) if matches(&self.disposable, domain) => rejected(DomainRejection::Disposable),
+            (true, _) => Ok(()),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rejection(policy: &DomainPolicy, email: &str) -> Option<DomainRejection> {
+        match policy.check(&email.parse().unwrap()) {
+            Ok(()) => None,
+            Err(Error::EmailDomainRejected(rejection)) => Some(rejection),
+            Err(err) => panic!("unexpected error {}", err),
+        }
+    }
+
+    #[test]
+    fn test_domain_policy() {
+        let policy = DomainPolicy::load(&EmailDomains::default()).unwrap();
+        assert_eq!(rejection(&policy, "jane@example.com"), None);
+        assert_eq!(rejection(&policy, "jane@Mailinator.com"), Some(DomainRejection::Disposable));
+        assert_eq!(rejection(&policy, "jane@eu.yopmail.com"), Some(DomainRejection::Disposable));
+        assert_eq!(rejection(&policy, "jane@localhost"), None);
+
+        let config = EmailDomains {
+            allow: vec!["example.com".into(), "yopmail.com".into()],
+            block: vec!["contractors.example.com".into()],
+            check_mx_syntax: true,
+            ..Default::default()
+        };
+        let policy = DomainPolicy::load(&config).unwrap();
+        assert_eq!(rejection(&policy, "jane@mail.example.com"), None);
+        assert_eq!(rejection(&policy, "jane@yopmail.com"), None);
+        assert_eq!(rejection(&policy, "jane@contractors.example.com"), Some(DomainRejection::Blocked));
+        assert_eq!(rejection(&policy, "jane@example.org"), Some(DomainRejection::NotAllowed));
+        assert_eq!(rejection(&policy, "jane@localhost"), Some(DomainRejection::InvalidDomain));
+    }
+
+    #[test]
+    fn test_mx_syntax() {
+        assert!(is_mail_domain("mail.example.com"));
+        assert!(is_mail_domain("xn--bcher-kva.de"));
+        assert!(is_mail_domain("example.xn--p1ai"));
+        assert!(!is_mail_domain("localhost"));
+        assert!(!is_mail_domain("example.123"));
+        assert!(!is_mail_domain("-example.com"));
+        assert!(!is_mail_domain("exa_mple.com"));
+        assert!(!is_mail_domain("example..com"));
+    }
+}
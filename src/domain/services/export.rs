@@ -0,0 +1,162 @@
+use super::{Error, User, UserStore};
+use futures_util::{stream, Stream, StreamExt};
+use actix_web::http::StatusCode;
+use serde_json::{json, Map, Value as JsonValue};
+use super::import::FileFormat;
+use std::sync::Arc;
+
+type Result<T> = std::result::Result<T, Error>;
+
+///The field of the password hash, exported only when asked for.
+const PASSWORD_FIELD: &str = "password";
+///How many encoded users wait for a slow reader before the store is read further.
+const BUFFERED_USERS: usize = 64;
+
+
+///Picks the exported fields out of a comma separated list, or all of them without one.
+/// The fields are those of `User::fields()`, with the password hash when hashes are included.
+pub fn export_fields(fields: Option<&str>, include_hashes: bool) -> Result<Vec<&'static str>> {
+    let available: Vec<&'static str> = User::fields().into_iter().chain(include_hashes.then_some(PASSWORD_FIELD)).collect();
+    let fields = match fields {
+        Some(fields) => fields,
+        None => return Ok(available)
+    };
+    fields.split(',').map(str::trim).filter(|field| !field.is_empty()).map(|field| {
+        available.iter().find(|available| **available == field).copied()
+            .ok_or_else(|| Error::Custom(StatusCode::BAD_REQUEST, format!("{} is not a field that can be exported", field).into()))
+    }).collect()
+}
+
+
+///The columns of a field. They are named so an export can be imported again.
+fn columns(field: &'static str) -> &'static [&'static str] {
+    match field {
+        "id" => &["id"],
+        "email" => &["email", "verified"],
+        "email_verified_at" => &["email_verified_at"],
+        "user_name" => &["user_name"],
+        "first_name" => &["first_name"],
+        "last_name" => &["last_name"],
+        "created_at" => &["created_at"],
+        "profile_picture" => &["profile_picture"],
+        PASSWORD_FIELD => &["password_hash"],
+        _ => &[]
+    }
+}
+
+
+fn values(user: &User, field: &'static str) -> Vec<JsonValue> {
+    match field {
+        "id" => vec![json!(user.id.to_hex())],
+        "email" => {
+            let verified = matches!(user.email, crate::EmailAddress::Verified(_));
+            let address: lettre::Address = user.email.clone().into();
+            vec![json!(address.to_string()), json!(verified)]
+        },
+        "email_verified_at" => vec![json!(user.email_verified_at)],
+        "user_name" => vec![json!(user.user_name)],
+        "first_name" => vec![json!(user.first_name)],
+        "last_name" => vec![json!(user.last_name)],
+        "created_at" => vec![json!(user.created_at)],
+        "profile_picture" => vec![json!(user.profile_picture)],
+        PASSWORD_FIELD => vec![json!(user.password)],
+        _ => vec![]
+    }
+}
+
+
+fn csv_row(cells: impl IntoIterator<Item = String>) -> Vec<u8> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    // Writing to memory does not fail.
+    let _ = writer.write_record(cells);
+    writer.into_inner().unwrap_or_default()
+}
+
+
+///The first line of an export, the header row of a CSV file.
+pub fn header(format: FileFormat, fields: &[&'static str]) -> Option<Vec<u8>> {
+    match format {
+        FileFormat::JsonLines => None,
+        FileFormat::Csv => Some(csv_row(fields.iter().flat_map(|field| columns(field)).map(|column| column.to_string()))),
+    }
+}
+
+
+///A user as a line of an export.
+pub fn encode(format: FileFormat, fields: &[&'static str], user: &User) -> Vec<u8> {
+    let cells = fields.iter().flat_map(|field| columns(field).iter().copied().zip(values(user, field)));
+    match format {
+        FileFormat::JsonLines => {
+            let mut line = serde_json::to_vec(&cells.map(|(column, value)| (column.to_string(), value)).collect::<Map<_, _>>()).unwrap_or_default();
+            line.push(b'\n');
+            line
+        },
+        FileFormat::Csv => csv_row(cells.map(|(_, value)| match value {
+            JsonValue::String(value) => value,
+            JsonValue::Null => String::new(),
+            value => value.to_string(),
+        })),
+    }
+}
+
+
+///Streams every user, oldest first, as the lines of an export.
+/// The users are read from the store only as fast as the lines are taken, so memory stays flat however many users there are.
+/// The reading runs on the current thread, so it has to be called from within a `LocalSet`, as the actix workers are.
+pub fn export_users(users: Arc<dyn UserStore>, format: FileFormat, fields: Vec<&'static str>) -> impl Stream<Item = Result<Vec<u8>>> + 'static {
+    let (sender, receiver) = tokio::sync::mpsc::channel(BUFFERED_USERS);
+    tokio::task::spawn_local(async move {
+        if let Some(header) = header(format, &fields) {
+            if sender.send(Ok(header)).await.is_err() {
+                return;
+            }
+        }
+        let mut stream = users.stream_users(fields.contains(&PASSWORD_FIELD));
+        while let Some(user) = stream.next().await {
+            let line = user.map(|user| encode(format, &fields, &user));
+            let failed = line.is_err();
+            // The reader is gone, or an error ends the export.
+            if sender.send(line).await.is_err() || failed {
+                return;
+            }
+        }
+    });
+    stream::unfold(receiver, |mut receiver| async move {
+        let line = receiver.recv().await?;
+        Some((line, receiver))
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_user;
+
+    #[test]
+    fn test_encode_round_trips_through_import() {
+        let user = User {
+            id: "65f1c0a2e4b0a1b2c3d4e5f6".parse().unwrap(),
+            email: crate::EmailAddress::Verified("jane@example.com".parse().unwrap()),
+            user_name: "jane, \"the\" doe".into(),
+            password: "$2a$05$CCCCCCCCCCCCCCCCCCCCC.E5YPO9kmyuRGyh0XouQYb4YMJKvyOeW".into(),
+            ..test_user("jane@example.com")
+        };
+        assert!(export_fields(Some("id,password"), false).is_err());
+        let fields = export_fields(None, true).unwrap();
+        for format in [FileFormat::JsonLines, FileFormat::Csv] {
+            let mut file = header(format, &fields).unwrap_or_default();
+            file.extend(encode(format, &fields, &user));
+            let (_, imported) = crate::import::read_users(format, file.as_slice()).next().unwrap();
+            let imported = imported.unwrap();
+            assert_eq!(imported.id, "65f1c0a2e4b0a1b2c3d4e5f6");
+            assert_eq!(imported.user_name, user.user_name);
+            assert!(imported.verified);
+            assert_eq!(imported.password_hash, user.password);
+            assert_eq!(imported.created_at, Some(user.created_at));
+        }
+        let fields = export_fields(Some("id, email"), false).unwrap();
+        assert_eq!(header(FileFormat::Csv, &fields).unwrap(), b"id,email,verified\n");
+        assert_eq!(encode(FileFormat::JsonLines, &fields, &user), b"{\"id\":\"65f1c0a2e4b0a1b2c3d4e5f6\",\"email\":\"jane@example.com\",\"verified\":true}\n");
+    }
+}
@@ -0,0 +1,101 @@
+use argon2::password_hash::{PasswordHash, PasswordVerifier};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use super::api_key::constant_time_eq;
+use pbkdf2::Pbkdf2;
+use scrypt::Scrypt;
+use sha2::Sha256;
+
+
+///A password hash format of another system, kept for the users imported from it
+/// until their next login replaces it with an Argon2 hash.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ForeignHash {
+    ///`$2a$`, `$2b$` or `$2y$` bcrypt hashes.
+    Bcrypt,
+    ///`$scrypt$` PHC strings.
+    Scrypt,
+    ///`$pbkdf2-sha256$` PHC strings.
+    Pbkdf2Sha256,
+    ///`pbkdf2_sha256$<iterations>$<salt>$<hash>` hashes, as made by Django.
+    DjangoPbkdf2Sha256,
+}
+
+
+impl ForeignHash {
+    pub fn detect(hash: &str) -> Option<Self> {
+        use ForeignHash::*;
+        match hash {
+            _ if ["$2a$", "$2b$", "$2y$"].iter().any(|prefix| hash.starts_with(prefix)) => Some(Bcrypt),
+            _ if hash.starts_with("$scrypt$") => Some(Scrypt),
+            _ if hash.starts_with("$pbkdf2-sha256$") => Some(Pbkdf2Sha256),
+            _ if hash.starts_with("pbkdf2_sha256$") => Some(DjangoPbkdf2Sha256),
+            _ => None
+        }
+    }
+
+    pub fn verify(&self, hash: &str, password: &str) -> bool {
+        match self {
+            ForeignHash::Bcrypt => bcrypt::verify(password, hash).unwrap_or(false),
+            ForeignHash::Scrypt => verify_phc(&Scrypt, hash, password),
+            ForeignHash::Pbkdf2Sha256 => verify_phc(&Pbkdf2, hash, password),
+            ForeignHash::DjangoPbkdf2Sha256 => verify_django(hash, password),
+        }
+    }
+}
+
+
+fn verify_phc(verifier: &impl PasswordVerifier, hash: &str, password: &str) -> bool {
+    match PasswordHash::new(hash) {
+        Ok(hash) => verifier.verify_password(password.as_bytes(), &hash).is_ok(),
+        Err(_) => false
+    }
+}
+
+
+fn verify_django(hash: &str, password: &str) -> bool {
+    let mut parts = hash.splitn(4, '$').skip(1);
+    let (Some(iterations), Some(salt), Some(expected)) = (parts.next(), parts.next(), parts.next()) else {
+        return false;
+    };
+    let (Ok(iterations), Ok(decoded)) = (iterations.parse::<u32>(), STANDARD.decode(expected)) else {
+        return false;
+    };
+    if iterations == 0 || decoded.is_empty() {
+        return false;
+    }
+    let mut output = vec![0u8; decoded.len()];
+    pbkdf2::pbkdf2_hmac::<Sha256>(password.as_bytes(), salt.as_bytes(), iterations, &mut output);
+    constant_time_eq(&STANDARD.encode(output), expected)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use argon2::password_hash::{PasswordHasher, SaltString};
+
+    #[test]
+    fn test_verify_foreign_hashes() {
+        let cases = [
+            ("$2a$05$CCCCCCCCCCCCCCCCCCCCC.E5YPO9kmyuRGyh0XouQYb4YMJKvyOeW", "U*U", ForeignHash::Bcrypt),
+            ("pbkdf2_sha256$1000$seasalt$mQnueSakb748zqBAC1tmWVZsZbi2zPGZarEzTGdfmso=", "correct horse", ForeignHash::DjangoPbkdf2Sha256),
+        ];
+        for (hash, password, format) in cases {
+            assert_eq!(ForeignHash::detect(hash), Some(format));
+            assert!(format.verify(hash, password));
+            assert!(!format.verify(hash, "wrong"));
+        }
+
+        let salt = SaltString::from_b64("c2Vhc2FsdHNlYXNhbHQ").unwrap();
+        let params = scrypt::Params::new(4, 8, 1, 32).unwrap();
+        let scrypt = Scrypt.hash_password_customized(b"correct horse", None, None, params, &salt).unwrap().to_string();
+        let pbkdf2 = Pbkdf2.hash_password_customized(b"correct horse", None, None, pbkdf2::Params{rounds: 1000, output_length: 32}, &salt).unwrap().to_string();
+        for hash in [scrypt, pbkdf2] {
+            let format = ForeignHash::detect(&hash).unwrap();
+            assert!(format.verify(&hash, "correct horse"));
+            assert!(!format.verify(&hash, "wrong"));
+        }
+        assert_eq!(ForeignHash::detect("$argon2id$v=19$m=19456,t=2,p=1$c2FsdA$aGFzaA"), None);
+        assert!(!ForeignHash::DjangoPbkdf2Sha256.verify("pbkdf2_sha256$0$salt$", "correct horse"));
+    }
+}
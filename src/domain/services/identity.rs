@@ -0,0 +1,169 @@
+use super::{EmailAddress, EmailNormalization, Error, Id, Identity, IdentityStore, User, UserStore};
+use super::api_key::constant_time_eq;
+use super::email_domain::DomainPolicy;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use rand::{distributions::Alphanumeric, Rng};
+use serde::{Serialize, Deserialize};
+use actix_web::http::StatusCode;
+use sha2::{Digest, Sha256};
+use lettre::Address;
+use chrono::Utc;
+
+type Result<T> = std::result::Result<T, Error>;
+
+
+///How long the secret of a pending link is kept by the browser, in seconds. As long as the logins at the providers take.
+pub const LINK_EXPIRES_IN: i64 = 10 * 60;
+const LINK_SECRET_LENGTH: usize = 43;
+
+
+///A user as described by an external identity provider or directory.
+#[derive(Clone, Debug)]
+pub struct ExternalUser {
+    ///The stable id of the user at the provider.
+    pub subject: String,
+    ///An email address the provider has verified.
+    pub email: Address,
+    pub user_name: String,
+    pub first_name: String,
+    pub last_name: String,
+    pub profile_picture: Option<String>,
+}
+
+
+impl From<ExternalUser> for User {
+    fn from(external: ExternalUser) -> Self {
+        User {
+            id: Default::default(),
+            email: EmailAddress::Verified(external.email),
+            email_verified_at: Some(Utc::now()),
+            user_name: external.user_name,
+            first_name: external.first_name,
+            last_name: external.last_name,
+            password: Default::default(),
+            created_at: Utc::now(),
+            profile_picture: external.profile_picture,
+        }
+    }
+}
+
+
+///A link of an identity to a signed in user, carried through the provider in the signed state of the login.
+/// The provider sends the browser back without the user's token, so the link is bound to a secret
+/// the browser that started it keeps in a cookie. A state leaked to someone else is of no use without it.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PendingLink {
+    user_id: Id,
+    ///The hash of the secret.
+    binding: String,
+}
+
+
+fn hash_link_secret(secret: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(secret.as_bytes()))
+}
+
+
+impl PendingLink {
+    ///Starts a link to the user. Returns it along with the secret the browser has to keep.
+    pub fn new(user_id: &Id) -> (Self, String) {
+        let secret: String = rand::thread_rng().sample_iter(&Alphanumeric).take(LINK_SECRET_LENGTH).map(char::from).collect();
+        (Self{user_id: user_id.clone(), binding: hash_link_secret(&secret)}, secret)
+    }
+
+    ///Returns the user to link the identity to, given the secret kept by the browser completing the link.
+    pub fn verify(self, secret: Option<&str>) -> Result<Id> {
+        match secret {
+            Some(secret) if constant_time_eq(&hash_link_secret(secret), &self.binding) => Ok(self.user_id),
+            _ => Err(Error::Custom(StatusCode::FORBIDDEN, "the link was started in another browser".into())),
+        }
+    }
+}
+
+
+///Whether an identity with the email may be linked to the existing user with the same email on its first sign in.
+/// The user has to have verified the email, and the provider has to be trusted for its domain.
+pub fn may_link(user: &User, trusted_domains: &[String], email: &Address) -> bool {
+    user.verified_at().is_some() && trusted_domains.iter().any(|domain| domain.eq_ignore_ascii_case(email.domain()))
+}
+
+
+///Returns the user linked to the external user's identity at the provider.
+/// If the identity has not been linked yet and there is no user with the same email, a new one is created.
+/// It is only linked to an existing user with the same email when that user has verified it,
+/// and the provider is trusted for the domain of the email. Otherwise the user has to sign in and link it themselves,
+/// so nobody can take over an account by signing up with its address or asserting it at a provider.
+/// New users are subject to the domain policy, like the ones who sign up.
+pub async fn link_or_create_user(users: &dyn UserStore, identities: &dyn IdentityStore, normalization: &EmailNormalization, domains: &DomainPolicy, provider: &str, trusted_domains: &[String], external: ExternalUser) -> Result<User> {
+    if let Some(identity) = identities.get_identity(provider, &external.subject).await? {
+        return users.get_user_by_id(&identity.user_id).await;
+    }
+    let canonical_email = normalization.canonicalize(external.email.as_ref())
+        .ok_or_else(|| Error::Custom(StatusCode::BAD_REQUEST, "the email address of the identity has an invalid domain".into()))?;
+    let user = match users.get_user_by_email(&canonical_email).await {
+        Ok(user) if may_link(&user, trusted_domains, &external.email) => user,
+        Ok(_) => return Err(Error::Custom(StatusCode::CONFLICT, "an account with this email address already exists, sign in to it to link this identity".into())),
+        Err(Error::UserNotFound) => {
+            domains.check(&external.email)?;
+            let user = User::from(external.clone());
+            users.create_user(&user, &canonical_email).await?;
+            user
+        },
+        Err(err) => return Err(err),
+    };
+    create_identity(identities, provider, &user.id, external).await?;
+    Ok(user)
+}
+
+
+///Links the external user's identity at the provider to a user who is signed in.
+/// An identity that is already linked to another user stays linked to them.
+pub async fn link_identity(users: &dyn UserStore, identities: &dyn IdentityStore, provider: &str, user_id: &Id, external: ExternalUser) -> Result<User> {
+    match identities.get_identity(provider, &external.subject).await? {
+        Some(identity) if identity.user_id == *user_id => (),
+        Some(_) => return Err(Error::Custom(StatusCode::CONFLICT, "the identity is linked to another account".into())),
+        None => create_identity(identities, provider, user_id, external).await?,
+    }
+    users.get_user_by_id(user_id).await
+}
+
+
+async fn create_identity(identities: &dyn IdentityStore, provider: &str, user_id: &Id, external: ExternalUser) -> Result<()> {
+    let identity = Identity {
+        provider: provider.to_string(),
+        subject: external.subject,
+        user_id: user_id.clone(),
+        email: external.email.to_string(),
+        created_at: Utc::now(),
+    };
+    identities.create_identity(&identity).await
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_user;
+
+    #[test]
+    fn test_may_link_verified_users_of_trusted_domains_only() {
+        let email: Address = "jane@Example.com".parse().unwrap();
+        let trusted = vec!["example.com".to_string()];
+        let unverified = test_user("jane@example.com");
+        let verified = User{email: EmailAddress::Verified("jane@example.com".parse().unwrap()), ..unverified.clone()};
+        assert!(may_link(&verified, &trusted, &email));
+        assert!(!may_link(&unverified, &trusted, &email));
+        assert!(!may_link(&verified, &[], &email));
+        assert!(!may_link(&verified, &["other.com".to_string()], &email));
+    }
+
+    #[test]
+    fn test_pending_link_needs_its_secret() {
+        let user_id = Id::default();
+        let (link, secret) = PendingLink::new(&user_id);
+        assert_eq!(link.clone().verify(Some(&secret)).unwrap(), user_id);
+        assert!(link.clone().verify(None).is_err());
+        let (_, other_secret) = PendingLink::new(&user_id);
+        assert!(link.verify(Some(&other_secret)).is_err());
+    }
+}
@@ -0,0 +1,202 @@
+use super::{EmailAddress, EmailNormalization, Error, User, UserStore};
+use super::foreign_hash::ForeignHash;
+use super::email_domain::DomainPolicy;
+use argon2::password_hash::PasswordHash;
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use std::io::{BufRead, BufReader, Read};
+
+type Result<T> = std::result::Result<T, Error>;
+
+
+///The formats users are imported from and exported to.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FileFormat {
+    ///One JSON object per line.
+    #[default]
+    #[serde(rename = "jsonl")]
+    JsonLines,
+    ///Comma separated values with a header row naming the fields.
+    Csv,
+}
+
+
+impl FileFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            FileFormat::JsonLines => "jsonl",
+            FileFormat::Csv => "csv",
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            FileFormat::JsonLines => "application/x-ndjson",
+            FileFormat::Csv => "text/csv",
+        }
+    }
+}
+
+
+///A user exported from another system, with their password hash as it was kept there.
+#[derive(Debug, Deserialize)]
+pub struct ImportedUser {
+    pub id: String,
+    pub email: String,
+    #[serde(default)]
+    pub verified: bool,
+    ///When the email was verified. The creation time of a verified user when not given.
+    #[serde(default)]
+    pub email_verified_at: Option<DateTime<Utc>>,
+    pub user_name: String,
+    #[serde(default)]
+    pub first_name: String,
+    #[serde(default)]
+    pub last_name: String,
+    ///An Argon2 PHC string, a hash in one of the formats of [`ForeignHash`], or nothing for a user without a password.
+    #[serde(default)]
+    pub password_hash: String,
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+
+impl ImportedUser {
+    fn into_user(self) -> std::result::Result<User, String> {
+        let id = self.id.parse().map_err(|_| format!("invalid id {}", self.id))?;
+        let address = self.email.trim().parse().map_err(|_| format!("invalid email {}", self.email))?;
+        let email = match self.verified {
+            true => EmailAddress::Verified(address),
+            false => EmailAddress::New(address),
+        };
+        let hash = &self.password_hash;
+        let supported = hash.is_empty() || ForeignHash::detect(hash).is_some() || (hash.starts_with("$argon2") && PasswordHash::new(hash).is_ok());
+        if !supported {
+            return Err("the password hash is in an unsupported format".into());
+        }
+        Ok(User {
+            id,
+            email,
+            email_verified_at: self.email_verified_at.filter(|_| self.verified),
+            user_name: self.user_name,
+            first_name: self.first_name,
+            last_name: self.last_name,
+            password: self.password_hash,
+            created_at: self.created_at.unwrap_or_else(Utc::now),
+            profile_picture: None,
+        })
+    }
+}
+
+
+///The records of a file of users, with the line each of them starts on.
+pub fn read_users<'a>(format: FileFormat, reader: impl Read + 'a) -> Box<dyn Iterator<Item = (u64, std::result::Result<ImportedUser, String>)> + 'a> {
+    match format {
+        FileFormat::JsonLines => Box::new(BufReader::new(reader).lines().enumerate()
+            .map(|(index, line)| (index as u64 + 1, line))
+            .filter(|(_, line)| line.as_ref().map_or(true, |line| !line.trim().is_empty()))
+            .map(|(number, line)| (number, line.map_err(|err| err.to_string()).and_then(|line| serde_json::from_str(&line).map_err(|err| err.to_string()))))),
+        FileFormat::Csv => {
+            let mut reader = csv::Reader::from_reader(reader);
+            let headers = match reader.headers() {
+                Ok(headers) => headers.clone(),
+                Err(err) => return Box::new(std::iter::once((1, Err(err.to_string()))))
+            };
+            let mut record = csv::StringRecord::new();
+            let mut done = false;
+            Box::new(std::iter::from_fn(move || {
+                let line = reader.position().line();
+                match (done, reader.read_record(&mut record)) {
+                    (true, _) | (_, Ok(false)) => None,
+                    (_, Ok(true)) => Some((line, record.deserialize(Some(&headers)).map_err(|err| err.to_string()))),
+                    (_, Err(err)) => {
+                        // A malformed record is skipped, but reading goes no further once the file itself fails.
+                        done = err.is_io_error();
+                        Some((line, Err(err.to_string())))
+                    }
+                }
+            }))
+        }
+    }
+}
+
+
+#[derive(Debug, Default)]
+pub struct ImportReport {
+    pub imported: usize,
+    ///The line of every record that was not imported and why.
+    pub failures: Vec<(u64, String)>,
+}
+
+
+///Creates the users of the records, keeping their ids, email verification and password hashes.
+/// Records that are invalid, clash with an existing user or have an email the domain policy refuses are skipped and reported.
+pub async fn import_users(users: &dyn UserStore, normalization: &EmailNormalization, domains: &DomainPolicy, records: impl Iterator<Item = (u64, std::result::Result<ImportedUser, String>)>) -> Result<ImportReport> {
+    let mut report = ImportReport::default();
+    for (line, record) in records {
+        let user = match record.and_then(ImportedUser::into_user) {
+            Ok(user) => user,
+            Err(message) => {
+                report.failures.push((line, message));
+                continue;
+            }
+        };
+        match users.get_user_by_id(&user.id).await {
+            Ok(_) => {
+                report.failures.push((line, format!("a user with the id {} exists", user.id.to_hex())));
+                continue;
+            },
+            Err(Error::UserNotFound) => (),
+            Err(err) => return Err(err),
+        }
+        if let Err(err) = domains.check(user.email.address()) {
+            report.failures.push((line, err.to_string()));
+            continue;
+        }
+        let canonical_email = match user.email.canonical(normalization) {
+            Some(canonical_email) => canonical_email,
+            None => {
+                report.failures.push((line, format!("invalid email {}", user.email.address())));
+                continue;
+            }
+        };
+        match users.create_user(&user, &canonical_email).await {
+            Ok(()) => report.imported += 1,
+            Err(Error::UserWithEmailExists) => report.failures.push((line, "a user with the same email exists".into())),
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(report)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_users() {
+        let jsonl = concat!(
+            r#"{"id": "65f1c0a2e4b0a1b2c3d4e5f6", "email": "jane@example.com", "verified": true, "user_name": "jane", "password_hash": "$2a$05$CCCCCCCCCCCCCCCCCCCCC.E5YPO9kmyuRGyh0XouQYb4YMJKvyOeW"}"#, "\n",
+            "\n",
+            r#"{"id": "not an id", "email": "john@example.com", "user_name": "john"}"#, "\n",
+        );
+        let records: Vec<_> = read_users(FileFormat::JsonLines, jsonl.as_bytes()).collect();
+        assert_eq!(records.len(), 2);
+        let (line, record) = &records[0];
+        assert_eq!(*line, 1);
+        let user = record.as_ref().unwrap();
+        assert!(user.verified);
+        assert_eq!(records[1].0, 3);
+
+        let csv = "id,email,verified,user_name,first_name,last_name,password_hash,created_at\n\
+            65f1c0a2e4b0a1b2c3d4e5f6,jane@example.com,false,jane,Jane,Doe,pbkdf2_sha256$1000$seasalt$mQnueSakb748zqBAC1tmWVZsZbi2zPGZarEzTGdfmso=,\n\
+            65f1c0a2e4b0a1b2c3d4e5f7,john@example.com,false,john,John,Doe,md5:5f4dcc3b5aa765d61d8327deb882cf99,2020-01-01T00:00:00Z\n";
+        let records: Vec<_> = read_users(FileFormat::Csv, csv.as_bytes()).collect();
+        assert_eq!(records.iter().map(|(line, _)| *line).collect::<Vec<_>>(), vec![2, 3]);
+        let mut users = records.into_iter().map(|(_, record)| record.unwrap().into_user());
+        let user = users.next().unwrap().unwrap();
+        assert_eq!(user.email, EmailAddress::New("jane@example.com".parse().unwrap()));
+        assert_eq!(users.next().unwrap().unwrap_err(), "the password hash is in an unsupported format");
+    }
+}
@@ -0,0 +1,278 @@
+use ldap3::{ldap_escape, Ldap as Connection, LdapConnAsync, Scope, SearchEntry};
+use super::identity::{self, ExternalUser};
+use super::email_domain::DomainPolicy;
+use super::{EmailAddress, EmailNormalization, Error, Id, IdentityStore, RoleStore, User, UserStore};
+use actix_web::http::StatusCode;
+use std::collections::HashMap;
+use crate::config::Ldap;
+use lettre::Address;
+
+type Result<T> = std::result::Result<T, Error>;
+
+
+///The provider name LDAP identities are linked with.
+pub const PROVIDER: &str = "ldap";
+const RESULT_CODE_SUCCESS: u32 = 0;
+const RESULT_CODE_INVALID_CREDENTIALS: u32 = 49;
+
+
+///An entry returned by a directory search.
+#[derive(Clone, Debug)]
+pub struct DirectoryEntry {
+    pub dn: String,
+    pub attributes: HashMap<String, Vec<String>>,
+}
+
+
+impl DirectoryEntry {
+    ///Returns the values of an attribute. Attribute names are case insensitive.
+    fn values(&self, name: &str) -> &[String] {
+        self.attributes.iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, values)| values.as_slice())
+            .unwrap_or_default()
+    }
+
+    fn first(&self, name: &str) -> Option<String> {
+        self.values(name).first().cloned()
+    }
+}
+
+
+///The operations authentication needs from a directory server.
+pub trait Directory {
+    ///Binds as the given DN. Returns false when the credentials are invalid.
+    async fn bind(&mut self, dn: &str, password: &str) -> Result<bool>;
+    async fn search(&mut self, base_dn: &str, filter: &str, attributes: &[&str]) -> Result<Vec<DirectoryEntry>>;
+}
+
+
+///A directory server reached over the LDAP protocol.
+pub struct LdapDirectory(Connection);
+
+
+impl LdapDirectory {
+    pub async fn connect(config: &Ldap) -> Result<Self> {
+        let (connection, ldap) = LdapConnAsync::new(&config.url).await.map_err(bad_gateway)?;
+        ldap3::drive!(connection);
+        Ok(Self(ldap))
+    }
+}
+
+
+impl Directory for LdapDirectory {
+    async fn bind(&mut self, dn: &str, password: &str) -> Result<bool> {
+        let result = self.0.simple_bind(dn, password).await.map_err(bad_gateway)?;
+        match result.rc {
+            RESULT_CODE_SUCCESS => Ok(true),
+            RESULT_CODE_INVALID_CREDENTIALS => Ok(false),
+            _ => Err(bad_gateway(result)),
+        }
+    }
+
+    async fn search(&mut self, base_dn: &str, filter: &str, attributes: &[&str]) -> Result<Vec<DirectoryEntry>> {
+        let (entries, _) = self.0.search(base_dn, Scope::Subtree, filter, attributes).await
+            .and_then(|result| result.success())
+            .map_err(bad_gateway)?;
+        let entries = entries.into_iter()
+            .map(SearchEntry::construct)
+            .map(|entry| DirectoryEntry{dn: entry.dn, attributes: entry.attrs})
+            .collect();
+        Ok(entries)
+    }
+}
+
+
+///A user who signed in with their directory credentials.
+#[derive(Clone, Debug)]
+pub struct DirectoryUser {
+    pub external: ExternalUser,
+    ///The roles mapped from the groups the user is a member of.
+    pub roles: Vec<String>,
+}
+
+
+fn invalid_credentials() -> Error {
+    Error::Custom(StatusCode::UNAUTHORIZED, "invalid user name or password".into())
+}
+
+
+fn bad_gateway<E: std::fmt::Display>(err: E) -> Error {
+    Error::Custom(StatusCode::BAD_GATEWAY, format!("directory: {}", err).into())
+}
+
+
+///Finds the user in the directory and checks the password by binding as them.
+pub async fn authenticate<D: Directory>(directory: &mut D, config: &Ldap, username: &str, password: &str) -> Result<DirectoryUser> {
+    // An empty password would make the bind an unauthenticated one which always succeeds.
+    if username.is_empty() || password.is_empty() {
+        return Err(invalid_credentials());
+    }
+    if let Some(ref dn) = config.bind_dn {
+        let bind_password = config.bind_password.as_deref().unwrap_or_default();
+        if !directory.bind(dn, bind_password).await? {
+            return Err(bad_gateway("the service account credentials were rejected"));
+        }
+    }
+    let mapping = &config.attributes;
+    let filter = config.user_filter.replace("{username}", &ldap_escape(username));
+    let mut attributes = vec![mapping.email.as_str(), &mapping.user_name, &mapping.first_name, &mapping.last_name, &mapping.groups];
+    if let Some(ref id) = mapping.id {
+        attributes.push(id);
+    }
+    let mut entries = directory.search(&config.base_dn, &filter, &attributes).await?;
+    if entries.len() != 1 {
+        return Err(invalid_credentials());
+    }
+    let entry = entries.remove(0);
+    if !directory.bind(&entry.dn, password).await? {
+        return Err(invalid_credentials());
+    }
+    directory_user(config, entry, username)
+}
+
+
+fn directory_user(config: &Ldap, entry: DirectoryEntry, username: &str) -> Result<DirectoryUser> {
+    let mapping = &config.attributes;
+    let email = entry.first(&mapping.email)
+        .and_then(|email| email.parse::<Address>().ok())
+        .ok_or_else(|| Error::Custom(StatusCode::UNPROCESSABLE_ENTITY, "the directory entry has no valid email address".into()))?;
+    let subject = match mapping.id {
+        Some(ref id) => entry.first(id).ok_or_else(|| bad_gateway("the directory entry has no id attribute"))?,
+        None => entry.dn.clone(),
+    };
+    let mut roles: Vec<String> = Vec::new();
+    for group in entry.values(&mapping.groups) {
+        let mapped = config.group_roles.iter().find(|(dn, _)| dn.eq_ignore_ascii_case(group));
+        for role in mapped.map(|(_, roles)| roles.as_slice()).unwrap_or_default() {
+            if !roles.contains(role) {
+                roles.push(role.clone());
+            }
+        }
+    }
+    let external = ExternalUser {
+        subject,
+        email,
+        user_name: entry.first(&mapping.user_name).unwrap_or_else(|| username.to_string()),
+        first_name: entry.first(&mapping.first_name).unwrap_or_default(),
+        last_name: entry.first(&mapping.last_name).unwrap_or_default(),
+        profile_picture: None,
+    };
+    Ok(DirectoryUser{external, roles})
+}
+
+
+///Creates the local user of a directory user on their first sign in,
+/// and keeps its email and names in sync with the directory afterwards.
+/// The roles mapped from the directory groups are assigned to the user, and the ones no longer mapped are taken away.
+#[allow(clippy::too_many_arguments)]
+pub async fn provision(users: &dyn UserStore, identities: &dyn IdentityStore, roles: &dyn RoleStore, normalization: &EmailNormalization, domains: &DomainPolicy, config: &Ldap, directory_user: &DirectoryUser) -> Result<User> {
+    let external = directory_user.external.clone();
+    let user = identity::link_or_create_user(users, identities, normalization, domains, PROVIDER, &config.trusted_domains, external.clone()).await?;
+    roles.sync_source_roles(&user.id, PROVIDER, &directory_user.roles).await?;
+    let email = EmailAddress::Verified(external.email);
+    if user.email == email && user.user_name == external.user_name && user.first_name == external.first_name && user.last_name == external.last_name {
+        return Ok(user);
+    }
+    if user.email.address() != email.address() {
+        domains.check(email.address())?;
+    }
+    let user = User {email, user_name: external.user_name, first_name: external.first_name, last_name: external.last_name, ..user};
+    let canonical_email = user.email.canonical(normalization)
+        .ok_or_else(|| Error::Custom(StatusCode::BAD_REQUEST, "the email address in the directory has an invalid domain".into()))?;
+    users.update_user(&user, &canonical_email).await
+}
+
+
+
+///Links the directory user to a user who is signed in. They are provisioned from the directory from their next sign in on.
+pub async fn link(users: &dyn UserStore, identities: &dyn IdentityStore, user_id: &Id, directory_user: &DirectoryUser) -> Result<User> {
+    identity::link_identity(users, identities, PROVIDER, user_id, directory_user.external.clone()).await
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    ///An in-process stand-in for a directory server.
+    /// It understands equality filters of the form `(attribute=value)` only.
+    struct MemoryDirectory {
+        entries: Vec<(DirectoryEntry, &'static str)>,
+    }
+
+    impl Directory for MemoryDirectory {
+        async fn bind(&mut self, dn: &str, password: &str) -> Result<bool> {
+            Ok(self.entries.iter().any(|(entry, secret)| entry.dn == dn && *secret == password))
+        }
+
+        async fn search(&mut self, base_dn: &str, filter: &str, _attributes: &[&str]) -> Result<Vec<DirectoryEntry>> {
+            let (name, value) = filter.trim_start_matches('(').trim_end_matches(')').split_once('=').unwrap();
+            let entries = self.entries.iter()
+                .map(|(entry, _)| entry)
+                .filter(|entry| entry.dn.ends_with(base_dn) && entry.values(name).iter().any(|v| v == value))
+                .cloned()
+                .collect();
+            Ok(entries)
+        }
+    }
+
+    fn entry(dn: &str, attributes: &[(&str, &[&str])]) -> DirectoryEntry {
+        let attributes = attributes.iter()
+            .map(|(name, values)| (name.to_string(), values.iter().map(|v| v.to_string()).collect()))
+            .collect();
+        DirectoryEntry{dn: dn.into(), attributes}
+    }
+
+    fn directory() -> MemoryDirectory {
+        let service = entry("cn=service,dc=example,dc=com", &[]);
+        let alice = entry("uid=alice,ou=people,dc=example,dc=com", &[
+            ("uid", &["alice"]),
+            ("mail", &["alice@example.com"]),
+            ("givenName", &["Alice"]),
+            ("SN", &["Doe"]),
+            ("memberOf", &["cn=admins,ou=groups,dc=example,dc=com", "cn=staff,ou=groups,dc=example,dc=com"]),
+        ]);
+        MemoryDirectory{entries: vec![(service, "service password"), (alice, "alice password")]}
+    }
+
+    fn config() -> Ldap {
+        let group_roles = [
+            ("CN=Admins,OU=Groups,DC=example,DC=com".to_string(), vec!["admin".to_string()]),
+            ("cn=staff,ou=groups,dc=example,dc=com".to_string(), vec!["staff".to_string(), "admin".to_string()]),
+        ].into_iter().collect();
+        Ldap {
+            url: "ldap://localhost".into(),
+            bind_dn: Some("cn=service,dc=example,dc=com".into()),
+            bind_password: Some("service password".into()),
+            base_dn: "ou=people,dc=example,dc=com".into(),
+            user_filter: "(uid={username})".into(),
+            attributes: Default::default(),
+            group_roles,
+            trusted_domains: vec![],
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_authenticate_maps_attributes_and_groups() {
+        let user = authenticate(&mut directory(), &config(), "alice", "alice password").await.unwrap();
+        assert_eq!(user.external.subject, "uid=alice,ou=people,dc=example,dc=com");
+        assert_eq!(user.external.email.to_string(), "alice@example.com");
+        assert_eq!(user.external.user_name, "alice");
+        assert_eq!(user.external.first_name, "Alice");
+        assert_eq!(user.external.last_name, "Doe");
+        assert_eq!(user.roles, vec!["admin".to_string(), "staff".to_string()]);
+    }
+
+    #[actix_web::test]
+    async fn test_authenticate_rejects_invalid_credentials() {
+        let config = config();
+        assert!(authenticate(&mut directory(), &config, "alice", "wrong password").await.is_err());
+        assert!(authenticate(&mut directory(), &config, "alice", "").await.is_err());
+        assert!(authenticate(&mut directory(), &config, "bob", "alice password").await.is_err());
+        assert!(authenticate(&mut directory(), &config, "*", "alice password").await.is_err());
+
+        let config = Ldap{bind_password: Some("wrong password".into()), ..config};
+        assert!(authenticate(&mut directory(), &config, "alice", "alice password").await.is_err());
+    }
+}
@@ -0,0 +1,85 @@
+use crate::domain::services::mail::send_html_email;
+use super::{Error, LoginFailureStore, Mailer, User};
+use crate::config::{Lockout, Mail};
+use lettre::message::Mailbox;
+use chrono::{Duration, Utc};
+use std::net::IpAddr;
+
+type Result<T> = std::result::Result<T, Error>;
+
+
+///The subject the failed logins for an email address are counted under.
+/// Unknown addresses are counted too, so a lock does not tell whether an account exists.
+pub fn account_subject(email: &str) -> String {
+    format!("account:{}", email.trim().to_lowercase())
+}
+
+
+pub fn ip_subject(ip: &IpAddr) -> String {
+    format!("ip:{}", ip)
+}
+
+
+///The delay of the response to a failed login. It doubles with every failure up to the configured maximum.
+pub fn delay(config: &Lockout, failures: u32) -> std::time::Duration {
+    let exponent = failures.saturating_sub(1).min(31);
+    let delay = config.base_delay.saturating_mul(1 << exponent).min(config.max_delay);
+    std::time::Duration::from_millis(delay)
+}
+
+
+///Fails with `Error::Locked` while any of the subjects is locked.
+pub async fn check(login_failures: &dyn LoginFailureStore, subjects: &[String]) -> Result<()> {
+    match login_failures.get_locked_until(subjects).await? {
+        Some(locked_until) => {
+            let seconds = (locked_until - Utc::now()).num_milliseconds().max(0);
+            Err(Error::Locked((seconds + 999) / 1000))
+        },
+        None => Ok(())
+    }
+}
+
+
+///Counts a failed login of the subject, locks it once the threshold is reached and returns its failures.
+/// The second value tells whether this failure locked the subject.
+pub async fn record_failure(login_failures: &dyn LoginFailureStore, config: &Lockout, subject: &str, threshold: u32) -> Result<(u32, bool)> {
+    let failures = login_failures.record_failure(subject, config.window).await?.max(0) as u32;
+    let locked = failures == threshold;
+    if failures >= threshold {
+        login_failures.lock(subject, Utc::now() + Duration::seconds(config.lock_duration)).await?;
+    }
+    Ok((failures, locked))
+}
+
+
+pub async fn clear(login_failures: &dyn LoginFailureStore, subject: &str) -> Result<()> {
+    login_failures.clear(subject).await
+}
+
+
+///Tells the owner of an account that it has been locked.
+pub async fn notify_locked(mailer: &Mailer, mail_config: &Mail, config: &Lockout, user: &User) -> Result<()> {
+    const HTML_TEMPLATE: &str = include_str!("account_locked.html");
+    let locked_until = Utc::now() + Duration::seconds(config.lock_duration);
+    let message = HTML_TEMPLATE.replace("{{locked_until}}", &locked_until.format("%Y-%m-%d %H:%M UTC").to_string());
+    let receiver = Mailbox{name: Some(user.user_name.clone()), email: user.email.clone().into()};
+    send_html_email(mailer, mail_config.sender.clone(), receiver, "Your account was locked", message).await
+        .map_err(|_| "could not send the account locked email")?;
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_delay_doubles_up_to_the_maximum() {
+        let config = Lockout::default();
+        assert_eq!(delay(&config, 1).as_millis(), 250);
+        assert_eq!(delay(&config, 2).as_millis(), 500);
+        assert_eq!(delay(&config, 4).as_millis(), 2_000);
+        assert_eq!(delay(&config, 10).as_millis(), 8_000);
+        assert_eq!(delay(&config, u32::MAX).as_millis(), 8_000);
+    }
+}
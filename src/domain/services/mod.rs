@@ -1,5 +1,23 @@
 pub mod verification;
 pub mod user;
 pub mod mail;
+pub mod token;
+pub mod identity;
+pub mod oidc;
+pub mod ldap;
+pub mod saml;
+pub mod rbac;
+pub mod organization;
+pub mod api_key;
+pub mod password;
+pub mod breach;
+pub mod email_domain;
+pub mod foreign_hash;
+pub mod import;
+pub mod canonical_email;
+pub mod export;
+pub mod lockout;
+pub mod rate_limit;
+pub mod audit;
 
 use super::*;
\ No newline at end of file
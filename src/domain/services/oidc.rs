@@ -0,0 +1,313 @@
+use jsonwebtoken::{decode, decode_header, jwk::JwkSet, DecodingKey, Validation};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use rand::{distributions::Alphanumeric, Rng};
+use super::identity::{self, ExternalUser, PendingLink};
+use super::email_domain::DomainPolicy;
+use super::{EmailNormalization, Error, Id, IdentityStore, User, UserStore};
+use crate::config::{Jwt, OidcProvider};
+use serde::{Serialize, Deserialize};
+use actix_web::http::StatusCode;
+use sha2::{Digest, Sha256};
+use lettre::Address;
+use chrono::Utc;
+use url::Url;
+
+type Result<T> = std::result::Result<T, Error>;
+type Client = reqwest::Client;
+
+
+const DISCOVERY_PATH: &str = ".well-known/openid-configuration";
+const RANDOM_LENGTH: usize = 43;
+///How long a user has to complete the login at the provider, in seconds.
+const STATE_EXPIRES_IN: i64 = 10 * 60;
+
+
+///The parts of the provider metadata needed for the authorization code flow.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ProviderMetadata {
+    pub issuer: String,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub jwks_uri: String,
+}
+
+
+///The pending login carried through the provider in the `state` parameter.
+/// It is signed with the server's key so no storage is needed between the redirect and the callback.
+#[derive(Debug, Serialize, Deserialize)]
+struct AuthorizationState {
+    provider: String,
+    nonce: String,
+    verifier: String,
+    redirect_uri: String,
+    ///The link to a signed in user, when the login was started to link the identity.
+    #[serde(default)]
+    link: Option<PendingLink>,
+    exp: i64,
+}
+
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+
+///The claims of a validated ID token.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct IdTokenClaims {
+    pub iss: String,
+    pub sub: String,
+    pub nonce: Option<String>,
+    pub email: Option<String>,
+    #[serde(default)]
+    pub email_verified: bool,
+    pub preferred_username: Option<String>,
+    pub given_name: Option<String>,
+    pub family_name: Option<String>,
+    pub picture: Option<String>,
+}
+
+
+fn unauthorized(message: &'static str) -> Error {
+    Error::Custom(StatusCode::UNAUTHORIZED, message.into())
+}
+
+
+fn bad_gateway<E: std::fmt::Display>(err: E) -> Error {
+    Error::Custom(StatusCode::BAD_GATEWAY, format!("identity provider: {}", err).into())
+}
+
+
+fn random_string() -> String {
+    rand::thread_rng().sample_iter(&Alphanumeric).take(RANDOM_LENGTH).map(char::from).collect()
+}
+
+
+///Fetches the provider metadata from its discovery document.
+pub async fn discover(client: &Client, provider: &OidcProvider) -> Result<ProviderMetadata> {
+    let url = format!("{}/{}", provider.issuer.trim_end_matches('/'), DISCOVERY_PATH);
+    let response = client.get(url).send().await.map_err(bad_gateway)?;
+    let metadata: ProviderMetadata = response.error_for_status().map_err(bad_gateway)?.json().await.map_err(bad_gateway)?;
+    if metadata.issuer.trim_end_matches('/') != provider.issuer.trim_end_matches('/') {
+        return Err(bad_gateway("issuer in the discovery document does not match the configured issuer"));
+    }
+    Ok(metadata)
+}
+
+
+///Builds the url the user has to be redirected to in order to sign in at the provider.
+/// With a pending link, the identity is linked to its user once they have signed in, instead of signing them in.
+pub async fn authorization_url(client: &Client, provider: &OidcProvider, jwt: &Jwt, redirect_uri: &str, link: Option<PendingLink>) -> Result<Url> {
+    let metadata = discover(client, provider).await?;
+    let state = AuthorizationState {
+        provider: provider.name.clone(),
+        nonce: random_string(),
+        verifier: random_string(),
+        redirect_uri: redirect_uri.to_string(),
+        link,
+        exp: Utc::now().timestamp() + STATE_EXPIRES_IN,
+    };
+    let challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(state.verifier.as_bytes()));
+    let mut url = Url::parse(&metadata.authorization_endpoint).map_err(bad_gateway)?;
+    url.query_pairs_mut()
+        .append_pair("response_type", "code")
+        .append_pair("client_id", &provider.client_id)
+        .append_pair("redirect_uri", redirect_uri)
+        .append_pair("scope", &provider.scopes.join(" "))
+        .append_pair("state", &super::token::sign(jwt, &state)?)
+        .append_pair("nonce", &state.nonce)
+        .append_pair("code_challenge", &challenge)
+        .append_pair("code_challenge_method", "S256");
+    Ok(url)
+}
+
+
+///Exchanges the authorization code from the callback for an ID token and validates it.
+/// Returns its claims along with the link the login was started for.
+pub async fn authenticate(client: &Client, provider: &OidcProvider, jwt: &Jwt, code: &str, state: &str) -> Result<(IdTokenClaims, Option<PendingLink>)> {
+    let state: AuthorizationState = super::token::verify(jwt, state)?;
+    if state.provider != provider.name {
+        return Err(unauthorized("the login was started with a different provider"));
+    }
+    if state.exp <= Utc::now().timestamp() {
+        return Err(unauthorized("the login has expired, please try again"));
+    }
+    let metadata = discover(client, provider).await?;
+    let params = [
+        ("grant_type", "authorization_code"),
+        ("code", code),
+        ("redirect_uri", &state.redirect_uri),
+        ("code_verifier", &state.verifier),
+    ];
+    let response = client.post(&metadata.token_endpoint)
+        .basic_auth(&provider.client_id, Some(&provider.client_secret))
+        .form(&params)
+        .send()
+        .await
+        .map_err(bad_gateway)?;
+    let response: TokenResponse = response.error_for_status().map_err(bad_gateway)?.json().await.map_err(bad_gateway)?;
+    let jwks: JwkSet = client.get(&metadata.jwks_uri).send().await.map_err(bad_gateway)?.json().await.map_err(bad_gateway)?;
+    let claims = validate_id_token(&response.id_token, &jwks, provider, &metadata.issuer, &state.nonce)?;
+    Ok((claims, state.link))
+}
+
+
+///Validates the signature of an ID token against the provider's keys and checks its issuer, audience, expiry and nonce.
+pub fn validate_id_token(id_token: &str, jwks: &JwkSet, provider: &OidcProvider, issuer: &str, nonce: &str) -> Result<IdTokenClaims> {
+    let header = decode_header(id_token).map_err(|_| unauthorized("invalid id token"))?;
+    let jwk = match header.kid {
+        Some(ref kid) => jwks.find(kid),
+        None => jwks.keys.first(),
+    }.ok_or_else(|| unauthorized("the id token was signed with an unknown key"))?;
+    let key = DecodingKey::from_jwk(jwk).map_err(|_| unauthorized("the id token was signed with an unsupported key"))?;
+    let mut validation = Validation::new(header.alg);
+    validation.set_audience(&[&provider.client_id]);
+    validation.set_issuer(&[issuer]);
+    let claims = decode::<IdTokenClaims>(id_token, &key, &validation).map_err(|_| unauthorized("invalid id token"))?.claims;
+    if claims.nonce.as_deref() != Some(nonce) {
+        return Err(unauthorized("invalid id token nonce"));
+    }
+    Ok(claims)
+}
+
+
+///Returns the user linked to the identity in the claims, linking or creating one when needed.
+/// The provider has to have verified the email in the claims.
+#[allow(clippy::too_many_arguments)]
+pub async fn link_or_create_user(users: &dyn UserStore, identities: &dyn IdentityStore, normalization: &EmailNormalization, domains: &DomainPolicy, provider: &OidcProvider, claims: IdTokenClaims, link_to: Option<&Id>) -> Result<User> {
+    let email = match (&claims.email, claims.email_verified) {
+        (Some(email), true) => email.parse::<Address>().map_err(|_| unauthorized("the identity provider returned an invalid email"))?,
+        _ => return Err(unauthorized("the identity provider did not return a verified email")),
+    };
+    let external = ExternalUser {
+        subject: claims.sub,
+        user_name: claims.preferred_username.unwrap_or_else(|| email.user().to_string()),
+        email,
+        first_name: claims.given_name.unwrap_or_default(),
+        last_name: claims.family_name.unwrap_or_default(),
+        profile_picture: claims.picture,
+    };
+    match link_to {
+        Some(user_id) => identity::link_identity(users, identities, &provider.name, user_id, external).await,
+        None => identity::link_or_create_user(users, identities, normalization, domains, &provider.name, &provider.trusted_domains, external).await,
+    }
+}
+
+
+///An identity provider serving the discovery document, keys and token endpoint of the authorization code flow, for tests.
+#[cfg(test)]
+pub mod mock {
+    use super::*;
+    use actix_web::{get, post, web, App, HttpResponse, HttpServer, Responder};
+    use jsonwebtoken::{encode, EncodingKey, Header, Algorithm};
+    use std::collections::HashMap;
+
+    const PRIVATE_KEY: &str = include_str!("fixtures/oidc_key.pem");
+    pub const JWKS: &str = include_str!("fixtures/oidc_jwks.json");
+    pub const CLIENT_ID: &str = "client";
+
+    ///Signs an ID token the way the mock provider does.
+    /// The mock provider has no login page, so the authorization code it accepts is the nonce itself.
+    pub fn id_token(issuer: &str, nonce: &str, audience: &str) -> String {
+        let claims = serde_json::json!({
+            "iss": issuer,
+            "sub": "subject",
+            "aud": audience,
+            "exp": Utc::now().timestamp() + 60,
+            "nonce": nonce,
+            "email": "user@domain.com",
+            "email_verified": true,
+        });
+        let mut header = Header::new(Algorithm::RS256);
+        header.kid = Some("test-key".into());
+        encode(&header, &claims, &EncodingKey::from_rsa_pem(PRIVATE_KEY.as_bytes()).unwrap()).unwrap()
+    }
+
+    #[get("/.well-known/openid-configuration")]
+    async fn discovery_endpoint(issuer: web::Data<String>) -> impl Responder {
+        let issuer = issuer.as_str();
+        HttpResponse::Ok().json(serde_json::json!({
+            "issuer": issuer,
+            "authorization_endpoint": format!("{}/authorize", issuer),
+            "token_endpoint": format!("{}/token", issuer),
+            "jwks_uri": format!("{}/jwks", issuer),
+        }))
+    }
+
+    #[get("/jwks")]
+    async fn jwks_endpoint() -> impl Responder {
+        HttpResponse::Ok().content_type("application/json").body(JWKS)
+    }
+
+    #[post("/token")]
+    async fn token_endpoint(issuer: web::Data<String>, form: web::Form<HashMap<String, String>>) -> impl Responder {
+        let nonce = &form["code"];
+        HttpResponse::Ok().json(serde_json::json!({"id_token": id_token(&issuer, nonce, CLIENT_ID), "token_type": "Bearer"}))
+    }
+
+    pub async fn mock_provider() -> OidcProvider {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let issuer = format!("http://{}", listener.local_addr().unwrap());
+        let data = web::Data::new(issuer.clone());
+        let server = HttpServer::new(move || App::new().app_data(data.clone()).service(discovery_endpoint).service(jwks_endpoint).service(token_endpoint))
+            .listen(listener).unwrap()
+            .run();
+        actix_web::rt::spawn(server);
+        OidcProvider {
+            name: "mock".into(),
+            issuer,
+            client_id: CLIENT_ID.into(),
+            client_secret: "secret".into(),
+            scopes: vec!["openid".into(), "email".into()],
+            trusted_domains: vec![],
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::mock::{id_token, mock_provider, CLIENT_ID, JWKS};
+    use std::collections::HashMap;
+
+    #[actix_web::test]
+    async fn test_login_with_mock_provider() {
+        let provider = mock_provider().await;
+        let client = Client::new();
+        let jwt = Jwt::default();
+        let user_id = Id::default();
+        let (link, secret) = PendingLink::new(&user_id);
+        let url = authorization_url(&client, &provider, &jwt, "http://localhost/oidc/mock/callback", Some(link)).await.unwrap();
+        let query: HashMap<_, _> = url.query_pairs().into_owned().collect();
+        assert_eq!(query["client_id"], CLIENT_ID);
+        assert_eq!(query["code_challenge_method"], "S256");
+
+        let (claims, link) = authenticate(&client, &provider, &jwt, &query["nonce"], &query["state"]).await.unwrap();
+        assert_eq!(link.unwrap().verify(Some(&secret)).unwrap(), user_id);
+        assert_eq!(claims.sub, "subject");
+        assert_eq!(claims.email.as_deref(), Some("user@domain.com"));
+        assert!(claims.email_verified);
+
+        assert!(authenticate(&client, &provider, &jwt, "another nonce", &query["state"]).await.is_err());
+        assert!(authenticate(&client, &provider, &Jwt::default(), &query["nonce"], &query["state"]).await.is_err());
+    }
+
+    #[test]
+    fn test_validate_id_token_checks_audience() {
+        let jwks: JwkSet = serde_json::from_str(JWKS).unwrap();
+        let provider = OidcProvider {
+            name: "mock".into(),
+            issuer: "http://issuer".into(),
+            client_id: CLIENT_ID.into(),
+            client_secret: "secret".into(),
+            scopes: vec![],
+            trusted_domains: vec![],
+        };
+        let token = id_token("http://issuer", "nonce", CLIENT_ID);
+        assert!(validate_id_token(&token, &jwks, &provider, "http://issuer", "nonce").is_ok());
+        let token = id_token("http://issuer", "nonce", "another client");
+        assert!(validate_id_token(&token, &jwks, &provider, "http://issuer", "nonce").is_err());
+    }
+}
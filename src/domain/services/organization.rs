@@ -0,0 +1,246 @@
+use super::{EmailAddress, Error, Id, Invitation, Mailer, Membership, Organization, OrganizationRole, OrganizationStore, RoleStore, User, UserStore};
+use crate::domain::services::mail::send_html_email;
+use actix_web::http::StatusCode;
+use chrono::{Duration, Utc};
+use lettre::message::Mailbox;
+use sqlx::types::Uuid;
+use lettre::Address;
+use serde::Deserialize;
+use crate::config::{Config, Jwt};
+use super::token;
+
+type Result<T> = std::result::Result<T, Error>;
+
+
+const INVITATION_LIFETIME_DAYS: i64 = 7;
+
+
+///Fails unless the user is a member of the organization with at least the given role.
+/// Organizations the user is not a member of are reported as not found.
+pub async fn require_role(organizations: &dyn OrganizationStore, organization_id: &Id, user_id: &Id, role: OrganizationRole) -> Result<Membership> {
+    let membership = organizations.get_membership(organization_id, user_id).await?
+        .ok_or(Error::OrganizationNotFound)?;
+    match membership.role >= role {
+        true => Ok(membership),
+        false => Err(Error::Forbidden)
+    }
+}
+
+
+pub async fn create_organization(organizations: &dyn OrganizationStore, owner: &Id, name: &str) -> Result<Organization> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(Error::Custom(StatusCode::BAD_REQUEST, "the organization needs a name".into()));
+    }
+    let organization = Organization{id: Default::default(), name: name.to_string(), created_at: Utc::now()};
+    organizations.create_organization(&organization, owner).await?;
+    Ok(organization)
+}
+
+
+pub async fn get_organizations(organizations: &dyn OrganizationStore, user_id: &Id) -> Result<Vec<Organization>> {
+    organizations.get_user_organizations(user_id).await
+}
+
+
+pub async fn get_organization(organizations: &dyn OrganizationStore, user_id: &Id, id: &Id) -> Result<Organization> {
+    require_role(organizations, id, user_id, OrganizationRole::Member).await?;
+    organizations.get_organization(id).await
+}
+
+
+pub async fn update_organization(organizations: &dyn OrganizationStore, user_id: &Id, id: &Id, name: &str) -> Result<Organization> {
+    require_role(organizations, id, user_id, OrganizationRole::Admin).await?;
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(Error::Custom(StatusCode::BAD_REQUEST, "the organization needs a name".into()));
+    }
+    organizations.update_organization(id, name).await
+}
+
+
+pub async fn delete_organization(organizations: &dyn OrganizationStore, user_id: &Id, id: &Id) -> Result<()> {
+    require_role(organizations, id, user_id, OrganizationRole::Owner).await?;
+    organizations.delete_organization(id).await
+}
+
+
+pub async fn get_members(organizations: &dyn OrganizationStore, user_id: &Id, id: &Id) -> Result<Vec<Membership>> {
+    require_role(organizations, id, user_id, OrganizationRole::Member).await?;
+    organizations.get_memberships(id).await
+}
+
+
+///Fails when the change would leave the organization without an owner.
+async fn keep_an_owner(organizations: &dyn OrganizationStore, organization_id: &Id, member: &Membership) -> Result<()> {
+    if member.role == OrganizationRole::Owner && organizations.count_owners(organization_id).await? <= 1 {
+        return Err(Error::Custom(StatusCode::CONFLICT, "an organization needs at least one owner".into()));
+    }
+    Ok(())
+}
+
+
+///Changes the role of a member. Admins manage members and admins, only owners grant or revoke ownership.
+pub async fn update_member_role(organizations: &dyn OrganizationStore, user_id: &Id, id: &Id, member_id: &Id, role: OrganizationRole) -> Result<Membership> {
+    let caller = require_role(organizations, id, user_id, OrganizationRole::Admin).await?;
+    let member = organizations.get_membership(id, member_id).await?
+        .ok_or_else(|| Error::Custom(StatusCode::NOT_FOUND, "membership not found".into()))?;
+    if (member.role == OrganizationRole::Owner || role == OrganizationRole::Owner) && caller.role != OrganizationRole::Owner {
+        return Err(Error::Forbidden);
+    }
+    if role != OrganizationRole::Owner {
+        keep_an_owner(organizations, id, &member).await?;
+    }
+    organizations.update_membership_role(id, member_id, role).await
+}
+
+
+///Removes a member from an organization. Members may leave by removing themselves.
+pub async fn remove_member(organizations: &dyn OrganizationStore, user_id: &Id, id: &Id, member_id: &Id) -> Result<()> {
+    let caller = require_role(organizations, id, user_id, OrganizationRole::Member).await?;
+    let member = organizations.get_membership(id, member_id).await?
+        .ok_or_else(|| Error::Custom(StatusCode::NOT_FOUND, "membership not found".into()))?;
+    if user_id != member_id && (caller.role < OrganizationRole::Admin || member.role > caller.role) {
+        return Err(Error::Forbidden);
+    }
+    keep_an_owner(organizations, id, &member).await?;
+    organizations.delete_membership(id, member_id).await
+}
+
+
+///The email address to invite and the role it will join the organization with.
+#[derive(Clone, Debug, Deserialize)]
+pub struct InvitationRequest {
+    pub email: Address,
+    #[serde(default = "default_invitation_role")]
+    pub role: OrganizationRole,
+}
+
+
+fn default_invitation_role() -> OrganizationRole {
+    OrganizationRole::Member
+}
+
+
+fn escape_html(value: &str) -> String {
+    value.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;").replace('\'', "&#39;")
+}
+
+
+///Invites an email address to an organization and emails the invitation to it.
+/// Only owners may invite owners.
+pub async fn invite(users: &dyn UserStore, organizations: &dyn OrganizationStore, mailer: &Mailer, config: &Config, user_id: &Id, id: &Id, request: InvitationRequest) -> Result<Invitation> {
+    let InvitationRequest{email, role} = request;
+    let caller = require_role(organizations, id, user_id, OrganizationRole::Admin).await?;
+    if role > caller.role {
+        return Err(Error::Forbidden);
+    }
+    let organization = organizations.get_organization(id).await?;
+    let inviter = users.get_user_by_id(user_id).await?;
+    let now = Utc::now();
+    let invitation = Invitation {
+        id: Uuid::new_v4(),
+        organization_id: id.clone(),
+        email: email.to_string(),
+        role,
+        invited_by: user_id.clone(),
+        created_at: now,
+        expires_at: now + Duration::days(INVITATION_LIFETIME_DAYS),
+    };
+    organizations.create_invitation(&invitation).await?;
+
+    const HTML_TEMPLATE: &str = include_str!("invitation.html");
+    let invitation_link = config.link(&format!("/invitations/{}", invitation.id.simple()));
+    let message = HTML_TEMPLATE
+        .replace("{{inviter}}", &escape_html(&inviter.user_name))
+        .replace("{{organization}}", &escape_html(&organization.name))
+        .replace("{{role}}", role.as_str())
+        .replace("{{invitation_link}}", &invitation_link)
+        .replace("{{expires_at}}", &invitation.expires_at.format("%Y-%m-%d").to_string());
+    let subject = format!("Invitation to {}", organization.name);
+    let receiver = Mailbox{name: None, email};
+    if send_html_email(mailer, config.mail.sender.clone(), receiver, &subject, message).await.is_err() {
+        organizations.delete_invitation(&invitation.id).await?;
+        return Err(Error::Custom(StatusCode::BAD_GATEWAY, "could not send the invitation email".into()));
+    }
+    Ok(invitation)
+}
+
+
+pub async fn get_organization_invitations(organizations: &dyn OrganizationStore, user_id: &Id, id: &Id) -> Result<Vec<Invitation>> {
+    require_role(organizations, id, user_id, OrganizationRole::Admin).await?;
+    organizations.get_invitations_by_organization(id).await
+}
+
+
+pub async fn revoke_invitation(organizations: &dyn OrganizationStore, user_id: &Id, id: &Id, invitation_id: &Uuid) -> Result<()> {
+    require_role(organizations, id, user_id, OrganizationRole::Admin).await?;
+    let invitation = organizations.get_invitation(invitation_id).await?;
+    if invitation.organization_id != *id {
+        return Err(Error::Custom(StatusCode::NOT_FOUND, "invitation not found".into()));
+    }
+    organizations.delete_invitation(invitation_id).await
+}
+
+
+///Returns the pending invitations sent to the verified email address of the user.
+pub async fn get_user_invitations(users: &dyn UserStore, organizations: &dyn OrganizationStore, user_id: &Id) -> Result<Vec<Invitation>> {
+    let user = users.get_user_by_id(user_id).await?;
+    match user.email {
+        EmailAddress::Verified(email) => organizations.get_invitations_by_email(email.as_ref()).await,
+        EmailAddress::New(_) => Ok(Vec::new())
+    }
+}
+
+
+///Returns an invitation if it was sent to the verified email address of the user.
+async fn get_user_invitation(organizations: &dyn OrganizationStore, user: &User, id: &Uuid) -> Result<Invitation> {
+    let invitation = organizations.get_invitation(id).await?;
+    let email = match user.email {
+        EmailAddress::Verified(ref email) => email.to_string(),
+        EmailAddress::New(_) => return Err(Error::Custom(StatusCode::FORBIDDEN, "verify your email address to answer invitations".into())),
+    };
+    match invitation.email.eq_ignore_ascii_case(&email) {
+        true => Ok(invitation),
+        false => Err(Error::Custom(StatusCode::NOT_FOUND, "invitation not found".into()))
+    }
+}
+
+
+pub async fn accept_invitation(users: &dyn UserStore, organizations: &dyn OrganizationStore, user_id: &Id, id: &Uuid) -> Result<Membership> {
+    let user = users.get_user_by_id(user_id).await?;
+    let invitation = get_user_invitation(organizations, &user, id).await?;
+    organizations.accept_invitation(&invitation, user_id).await
+}
+
+
+pub async fn decline_invitation(users: &dyn UserStore, organizations: &dyn OrganizationStore, user_id: &Id, id: &Uuid) -> Result<()> {
+    let user = users.get_user_by_id(user_id).await?;
+    let invitation = get_user_invitation(organizations, &user, id).await?;
+    organizations.delete_invitation(&invitation.id).await
+}
+
+
+///Issues a token scoped to the given organization, or to no organization.
+/// The user has to be a member of the organization.
+pub async fn switch_organization(users: &dyn UserStore, organizations: &dyn OrganizationStore, roles: &dyn RoleStore, config: &Jwt, user_id: &Id, organization_id: Option<&Id>) -> Result<String> {
+    if let Some(id) = organization_id {
+        require_role(organizations, id, user_id, OrganizationRole::Member).await?;
+    }
+    let user = users.get_user_by_id(user_id).await?;
+    token::issue_user_token(roles, config, &user, organization_id).await
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_organization_roles_are_ordered_by_privilege() {
+        assert!(OrganizationRole::Owner > OrganizationRole::Admin);
+        assert!(OrganizationRole::Admin > OrganizationRole::Member);
+        assert_eq!("owner".parse::<OrganizationRole>().unwrap(), OrganizationRole::Owner);
+        assert!("superuser".parse::<OrganizationRole>().is_err());
+    }
+}
@@ -0,0 +1,244 @@
+use argon2::password_hash::{rand_core::OsRng, ParamsString, PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use crate::config::{Argon2Config, BreachAction, PasswordPolicy};
+use super::{Error, PasswordViolation, User};
+use super::breach::BreachCorpus;
+use super::foreign_hash::ForeignHash;
+use std::collections::HashSet;
+use std::sync::OnceLock;
+use argon2::{Argon2, Params};
+
+type Result<T> = std::result::Result<T, Error>;
+
+
+///Hashes a password into a PHC string with a random salt.
+pub fn hash_password(argon2: &Argon2, password: &str) -> Result<String> {
+    let salt = SaltString::generate(&mut OsRng);
+    let hash = argon2.hash_password(password.as_bytes(), &salt).map_err(|err| Error::InternalServerError(Some(err.to_string().into())))?;
+    Ok(hash.to_string())
+}
+
+
+///A hash made with the current parameters, checked when a login has no hash to check the password against.
+static DUMMY_HASH: OnceLock<String> = OnceLock::new();
+
+
+///Checks the password against a hash made with the current parameters, the result aside.
+/// Done when there is no user or no password to check, so telling that takes as long as a wrong password does.
+pub fn verify_dummy_password(config: &Argon2Config, argon2: &Argon2, password: &str) {
+    let hash = DUMMY_HASH.get_or_init(|| hash_password(argon2, "no password").unwrap_or_default());
+    verify_password(config, hash, password);
+}
+
+
+///Checks a password against a PHC string, with the pepper recorded in it, or against a hash imported from another system.
+/// Users without a password never match, nor do hashes made with a pepper that was removed.
+pub fn verify_password(config: &Argon2Config, hash: &str, password: &str) -> bool {
+    if let Some(foreign) = ForeignHash::detect(hash) {
+        return foreign.verify(hash, password);
+    }
+    let hash = match PasswordHash::new(hash) {
+        Ok(hash) => hash,
+        Err(_) => return false
+    };
+    let keyid = Params::try_from(&hash).map(|params| params.keyid().to_vec()).unwrap_or_default();
+    match config.argon2_for(&keyid) {
+        Some(argon2) => argon2.verify_password(password.as_bytes(), &hash).is_ok(),
+        None => false
+    }
+}
+
+
+///Tells whether a PHC string was made with other parameters, or another pepper, than new hashes are.
+/// Anything that is not an Argon2 hash needs to be rehashed too.
+pub fn needs_rehash(config: &Argon2Config, hash: &str) -> bool {
+    let hash = match PasswordHash::new(hash) {
+        Ok(hash) => hash,
+        Err(_) => return true
+    };
+    let params = match Params::try_from(&hash) {
+        Ok(params) => params,
+        Err(_) => return true
+    };
+    let current = config.params();
+    hash.algorithm != config.algorithm.ident()
+        || hash.version != Some(config.version as u32)
+        || params.m_cost() != current.m_cost()
+        || params.t_cost() != current.t_cost()
+        || params.p_cost() != current.p_cost()
+        || params.keyid() != current.keyid()
+}
+
+
+///The start of the PHC strings of the hashes made with the current parameters, up to the salt.
+pub fn current_hash_prefix(config: &Argon2Config) -> String {
+    let params = ParamsString::try_from(&config.params()).expect("the argon2 parameters fit a PHC string");
+    format!("${}$v={}${}$", config.algorithm.ident(), config.version as u32, params)
+}
+
+
+///Fields shorter than this are not compared with the password.
+const MIN_SIMILAR_LENGTH: usize = 3;
+
+
+fn common_passwords() -> &'static HashSet<&'static str> {
+    static COMMON_PASSWORDS: OnceLock<HashSet<&'static str>> = OnceLock::new();
+    COMMON_PASSWORDS.get_or_init(|| include_str!("common_passwords.txt").lines().map(str::trim).filter(|line| !line.is_empty()).collect())
+}
+
+
+///Lowercases a value and keeps its letters and digits only.
+fn normalize(value: &str) -> String {
+    value.chars().filter(|c| c.is_alphanumeric()).flat_map(char::to_lowercase).collect()
+}
+
+
+fn is_common(password: &str) -> bool {
+    let password = password.to_lowercase();
+    // A common password with digits or symbols tacked on, like `Sunshine2024!`, is as easy to guess.
+    let stem = password.trim_end_matches(|c: char| !c.is_alphabetic());
+    common_passwords().contains(password.as_str()) || (!stem.is_empty() && common_passwords().contains(stem))
+}
+
+
+///Returns every rule of the policy the password of the user breaks.
+pub fn check_policy(policy: &PasswordPolicy, password: &str, user: &User) -> Vec<PasswordViolation> {
+    let mut violations = Vec::new();
+    let length = password.chars().count();
+    if length < policy.min_length {
+        violations.push(PasswordViolation::TooShort{min_length: policy.min_length});
+    }
+    if length > policy.max_length {
+        violations.push(PasswordViolation::TooLong{max_length: policy.max_length});
+    }
+    let classes = [
+        (policy.require_lowercase, password.chars().any(char::is_lowercase), PasswordViolation::MissingLowercase),
+        (policy.require_uppercase, password.chars().any(char::is_uppercase), PasswordViolation::MissingUppercase),
+        (policy.require_digit, password.chars().any(|c| c.is_ascii_digit()), PasswordViolation::MissingDigit),
+        (policy.require_symbol, password.chars().any(|c| !c.is_alphanumeric()), PasswordViolation::MissingSymbol),
+    ];
+    for (required, present, violation) in classes {
+        if required && !present {
+            violations.push(violation);
+        }
+    }
+    if policy.reject_user_data {
+        let email: lettre::Address = user.email.clone().into();
+        let normalized = normalize(password);
+        let fields = [
+            ("email", email.user()),
+            ("user_name", user.user_name.as_str()),
+            ("first_name", user.first_name.as_str()),
+            ("last_name", user.last_name.as_str()),
+        ];
+        for (field, value) in fields {
+            let value = normalize(value);
+            if value.len() >= MIN_SIMILAR_LENGTH && !normalized.is_empty() && (normalized.contains(&value) || value.contains(&normalized)) {
+                violations.push(PasswordViolation::SimilarToUserData{field});
+            }
+        }
+    }
+    if policy.reject_common && is_common(password) {
+        violations.push(PasswordViolation::Common);
+    }
+    violations
+}
+
+
+///Fails with `Error::InvalidPassword` listing every rule of the policy the password breaks.
+/// A breached password is one of them, unless the corpus is set to warn about it
+/// in which case it is returned for the client to be told.
+pub async fn validate_password(policy: &PasswordPolicy, breached: &BreachCorpus, password: &str, user: &User) -> Result<Vec<PasswordViolation>> {
+    let mut violations = check_policy(policy, password, user);
+    let mut warnings = Vec::new();
+    if breached.contains(password).await? {
+        match breached.action {
+            BreachAction::Reject => violations.push(PasswordViolation::Breached),
+            BreachAction::Warn => warnings.push(PasswordViolation::Breached),
+        }
+    }
+    match violations.is_empty() {
+        true => Ok(warnings),
+        false => Err(Error::InvalidPassword(violations))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_user;
+
+    #[test]
+    fn test_hash_and_verify_password() {
+        let config = Argon2Config::default();
+        let hash = hash_password(&config.initialize_argon2(), "correct horse battery staple").unwrap();
+        assert!(hash.starts_with("$argon2id$"));
+        assert!(verify_password(&config, &hash, "correct horse battery staple"));
+        assert!(!verify_password(&config, &hash, "correct horse battery"));
+        assert!(!verify_password(&config, "", ""));
+    }
+
+    #[test]
+    fn test_verify_password_with_a_rotated_pepper() {
+        use crate::config::{Pepper, SecretSource};
+        let pepper = |version: u32, value: &str| Pepper{version, source: SecretSource::Value(value.into())};
+        let mut old = Argon2Config{peppers: vec![pepper(1, "first")], ..Default::default()};
+        old.load_peppers().unwrap();
+        let hash = hash_password(&old.initialize_argon2(), "a password").unwrap();
+
+        let mut rotated = Argon2Config{peppers: vec![pepper(1, "first"), pepper(2, "second")], ..Default::default()};
+        rotated.load_peppers().unwrap();
+        assert!(verify_password(&rotated, &hash, "a password"));
+        assert!(needs_rehash(&rotated, &hash));
+        let rehashed = hash_password(&rotated.initialize_argon2(), "a password").unwrap();
+        assert!(verify_password(&rotated, &rehashed, "a password"));
+        assert!(!needs_rehash(&rotated, &rehashed));
+
+        let mut retired = Argon2Config{peppers: vec![pepper(2, "second")], ..Default::default()};
+        retired.load_peppers().unwrap();
+        assert!(!verify_password(&retired, &hash, "a password"));
+        assert!(verify_password(&retired, &rehashed, "a password"));
+    }
+
+    #[test]
+    fn test_dummy_password_is_hashed_with_the_current_parameters() {
+        let config = Argon2Config::default();
+        verify_dummy_password(&config, &config.initialize_argon2(), "a password");
+        assert!(DUMMY_HASH.get().unwrap().starts_with(&current_hash_prefix(&config)));
+    }
+
+    #[test]
+    fn test_needs_rehash_when_the_parameters_change() {
+        let config = Argon2Config::default();
+        let hash = hash_password(&config.initialize_argon2(), "a password").unwrap();
+        assert!(hash.starts_with(&current_hash_prefix(&config)));
+        assert!(!needs_rehash(&config, &hash));
+        assert!(needs_rehash(&Argon2Config{time_cost: config.time_cost + 1, ..config.clone()}, &hash));
+        let mut peppered = Argon2Config{pepper: Some("pepper".into()), ..config.clone()};
+        peppered.load_peppers().unwrap();
+        assert!(needs_rehash(&peppered, &hash));
+        assert!(needs_rehash(&config, "$2b$12$R9h/cIPz0gi.URNNX3kh2OPST9/PgBkqquzi.Ss7KIUgO2t0jWMUW"));
+        let hash = hash_password(&peppered.initialize_argon2(), "a password").unwrap();
+        assert!(hash.starts_with(&current_hash_prefix(&peppered)));
+        assert!(!needs_rehash(&peppered, &hash));
+    }
+
+    #[test]
+    fn test_check_policy_lists_every_violation() {
+        let policy = PasswordPolicy{require_uppercase: true, require_digit: true, require_symbol: true, ..Default::default()};
+        assert_eq!(check_policy(&policy, "", &test_user("jane.doe@example.com")), vec![
+            PasswordViolation::TooShort{min_length: 8},
+            PasswordViolation::MissingUppercase,
+            PasswordViolation::MissingDigit,
+            PasswordViolation::MissingSymbol,
+        ]);
+        assert_eq!(check_policy(&policy, "Sunshine2024!", &test_user("jane.doe@example.com")), vec![PasswordViolation::Common]);
+        assert_eq!(check_policy(&policy, "Jane.Doe#1984", &test_user("jane.doe@example.com")), vec![
+            PasswordViolation::SimilarToUserData{field: "email"},
+            PasswordViolation::SimilarToUserData{field: "user_name"},
+            PasswordViolation::SimilarToUserData{field: "first_name"},
+            PasswordViolation::SimilarToUserData{field: "last_name"},
+        ]);
+        assert!(check_policy(&policy, "Tr0ub4dor&3-horse", &test_user("jane.doe@example.com")).is_empty());
+    }
+}
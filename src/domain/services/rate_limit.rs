@@ -0,0 +1,165 @@
+use crate::config::{RateLimitBackend, RateLimitPolicy};
+use chrono::{DateTime, Duration, Utc};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use super::{Error, RateLimitStore};
+use rand::Rng;
+
+type Result<T> = std::result::Result<T, Error>;
+
+
+///The most buckets kept in memory. Beyond it the full ones are dropped, then the least recently used ones.
+const MAX_MEMORY_BUCKETS: usize = 10_000;
+///One in this many requests deletes the full buckets from the store.
+const CLEANUP_ONE_IN: u32 = 100;
+
+
+///A token bucket as of its last request.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Bucket {
+    pub tokens: f64,
+    pub updated_at: DateTime<Utc>,
+}
+
+
+///Whether a request may go through, and what to tell the client about the limit.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Decision {
+    pub allowed: bool,
+    pub limit: u32,
+    pub remaining: u32,
+    ///Seconds until the bucket is full again.
+    pub reset: i64,
+    ///Seconds until the next request is allowed. Zero when this one was.
+    pub retry_after: i64,
+}
+
+
+fn seconds(duration: f64) -> i64 {
+    duration.max(0.0).ceil() as i64
+}
+
+
+///Refills the bucket for the time passed since its last request and takes a token from it if it has one.
+/// Returns the bucket after the request, when it will be full again and the decision.
+pub fn take(bucket: Option<Bucket>, policy: &RateLimitPolicy, now: DateTime<Utc>) -> (Bucket, DateTime<Utc>, Decision) {
+    let capacity = policy.capacity as f64;
+    let rate = capacity / policy.period.max(1) as f64;
+    let available = match bucket {
+        Some(bucket) => {
+            let elapsed = (now - bucket.updated_at).num_milliseconds().max(0) as f64 / 1000.0;
+            (bucket.tokens + elapsed * rate).min(capacity)
+        },
+        None => capacity,
+    };
+    let allowed = available >= 1.0;
+    let tokens = if allowed { available - 1.0 } else { available };
+    let reset = seconds((capacity - tokens) / rate);
+    let decision = Decision {
+        allowed,
+        limit: policy.capacity,
+        remaining: tokens.floor() as u32,
+        reset,
+        retry_after: if allowed { 0 } else { seconds((1.0 - tokens) / rate) },
+    };
+    (Bucket{tokens, updated_at: now}, now + Duration::seconds(reset), decision)
+}
+
+
+///The buckets by their key, with when they will be full again.
+type MemoryBuckets = HashMap<String, (Bucket, DateTime<Utc>)>;
+
+
+///Makes room for the bucket of a new key when there are as many buckets as can be kept.
+/// The keys come from the requests, so the buckets in use are dropped too when there are that many,
+/// the least recently used tenth of them, rather than letting the map grow.
+fn make_room(buckets: &mut MemoryBuckets, key: &str, now: DateTime<Utc>) {
+    if buckets.len() < MAX_MEMORY_BUCKETS || buckets.contains_key(key) {
+        return;
+    }
+    buckets.retain(|_, (_, full_at)| *full_at > now);
+    if buckets.len() >= MAX_MEMORY_BUCKETS {
+        let mut updated_at: Vec<DateTime<Utc>> = buckets.values().map(|(bucket, _)| bucket.updated_at).collect();
+        let (_, cutoff, _) = updated_at.select_nth_unstable(MAX_MEMORY_BUCKETS / 10);
+        let cutoff = *cutoff;
+        buckets.retain(|_, (bucket, _)| bucket.updated_at > cutoff);
+    }
+}
+
+
+///Keeps the token buckets of the rate limiter.
+pub enum RateLimitBuckets {
+    Memory(Mutex<MemoryBuckets>),
+    ///The buckets are kept in the store, so they are shared by every replica.
+    Shared(Arc<dyn RateLimitStore>),
+}
+
+
+impl RateLimitBuckets {
+    pub fn new(backend: RateLimitBackend, store: Arc<dyn RateLimitStore>) -> Self {
+        match backend {
+            RateLimitBackend::Memory => Self::Memory(Default::default()),
+            RateLimitBackend::Postgres => Self::Shared(store),
+        }
+    }
+
+    ///Takes a token from the bucket of the key for the policy.
+    pub async fn take(&self, key: &str, policy: &RateLimitPolicy) -> Result<Decision> {
+        let now = Utc::now();
+        match self {
+            Self::Memory(buckets) => {
+                let mut buckets = buckets.lock().map_err(|_| "the rate limiter is poisoned")?;
+                make_room(&mut buckets, key, now);
+                let (bucket, full_at, decision) = take(buckets.get(key).map(|(bucket, _)| *bucket), policy, now);
+                buckets.insert(key.to_string(), (bucket, full_at));
+                Ok(decision)
+            },
+            Self::Shared(store) => {
+                if rand::thread_rng().gen_ratio(1, CLEANUP_ONE_IN) {
+                    store.delete_full().await?;
+                }
+                store.take(key, Box::new(|bucket| take(bucket, policy, now))).await
+            },
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::RateLimitKey;
+
+    #[test]
+    fn test_take_empties_and_refills_the_bucket() {
+        let policy = RateLimitPolicy{method: "POST".into(), path: "/signup".into(), key: RateLimitKey::Ip, capacity: 2, period: 60};
+        let now = Utc::now();
+        let (bucket, _, decision) = take(None, &policy, now);
+        assert_eq!(decision, Decision{allowed: true, limit: 2, remaining: 1, reset: 30, retry_after: 0});
+        let (bucket, _, decision) = take(Some(bucket), &policy, now);
+        assert!(decision.allowed);
+        assert_eq!(decision.remaining, 0);
+        let (bucket, full_at, decision) = take(Some(bucket), &policy, now);
+        assert!(!decision.allowed);
+        assert_eq!(decision.retry_after, 30);
+        assert_eq!(full_at, now + Duration::seconds(60));
+
+        let (_, _, decision) = take(Some(bucket), &policy, now + Duration::seconds(30));
+        assert!(decision.allowed);
+        assert_eq!(decision.remaining, 0);
+    }
+
+    #[test]
+    fn test_memory_buckets_are_capped() {
+        let now = Utc::now();
+        let mut buckets: MemoryBuckets = (0..MAX_MEMORY_BUCKETS as i64)
+            .map(|i| (i.to_string(), (Bucket{tokens: 0.0, updated_at: now + Duration::milliseconds(i)}, now + Duration::hours(1))))
+            .collect();
+        make_room(&mut buckets, "0", now);
+        assert_eq!(buckets.len(), MAX_MEMORY_BUCKETS);
+        make_room(&mut buckets, "new", now);
+        assert!(buckets.len() < MAX_MEMORY_BUCKETS);
+        assert!(!buckets.contains_key("0"));
+        assert!(buckets.contains_key(&(MAX_MEMORY_BUCKETS - 1).to_string()));
+    }
+}
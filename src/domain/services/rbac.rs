@@ -0,0 +1,83 @@
+use super::{EmailNormalization, Error, Id, Role, RoleStore, UserStore, ADMIN_ROLE};
+use actix_web::http::StatusCode;
+
+type Result<T> = std::result::Result<T, Error>;
+
+
+///Fails with `Error::Forbidden` unless one of the roles of the user grants the permission.
+pub async fn require_permission(roles: &dyn RoleStore, user_id: &Id, permission: &str) -> Result<()> {
+    match roles.has_permission(user_id, permission).await? {
+        true => Ok(()),
+        false => Err(Error::Forbidden)
+    }
+}
+
+
+///Users may act on themselves. Acting on other users requires the permission.
+pub async fn require_self_or_permission(roles: &dyn RoleStore, user_id: &Id, target: &Id, permission: &str) -> Result<()> {
+    if user_id == target {
+        return Ok(());
+    }
+    require_permission(roles, user_id, permission).await
+}
+
+
+pub async fn get_roles(roles: &dyn RoleStore) -> Result<Vec<Role>> {
+    roles.get_roles().await
+}
+
+
+pub async fn create_role(roles: &dyn RoleStore, role: Role) -> Result<Role> {
+    let name = role.name.trim();
+    if name.is_empty() {
+        return Err(Error::Custom(StatusCode::BAD_REQUEST, "the role needs a name".into()));
+    }
+    let role = Role{name: name.to_string(), ..role};
+    roles.create_role(&role).await?;
+    roles.get_role(&role.name).await
+}
+
+
+pub async fn update_role(roles: &dyn RoleStore, name: &str, description: Option<&str>, permissions: Option<&[String]>) -> Result<Role> {
+    roles.update_role(name, description, permissions).await
+}
+
+
+///Deletes a role. The admin role can not be deleted.
+pub async fn delete_role(roles: &dyn RoleStore, name: &str) -> Result<()> {
+    if name == ADMIN_ROLE {
+        return Err(Error::Custom(StatusCode::BAD_REQUEST, "the admin role can not be deleted".into()));
+    }
+    roles.delete_role(name).await
+}
+
+
+pub async fn get_user_roles(users: &dyn UserStore, roles: &dyn RoleStore, user_id: &Id) -> Result<Vec<Role>> {
+    users.get_user_by_id(user_id).await?;
+    roles.get_user_roles(user_id).await
+}
+
+
+pub async fn assign_role(users: &dyn UserStore, roles: &dyn RoleStore, user_id: &Id, role: &str) -> Result<()> {
+    roles.get_role(role).await?;
+    users.get_user_by_id(user_id).await?;
+    roles.assign_role(user_id, role).await
+}
+
+
+pub async fn unassign_role(roles: &dyn RoleStore, user_id: &Id, role: &str) -> Result<()> {
+    roles.unassign_role(user_id, role).await
+}
+
+
+///Gives the admin role to the existing users with the given emails.
+pub async fn bootstrap_admins(users: &dyn UserStore, roles: &dyn RoleStore, normalization: &EmailNormalization, emails: &[String]) -> Result<()> {
+    for email in emails.iter().filter_map(|email| normalization.canonicalize(email)) {
+        match users.get_user_by_email(&email).await {
+            Ok(user) => roles.assign_role(&user.id, ADMIN_ROLE).await?,
+            Err(Error::UserNotFound) => continue,
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,409 @@
+use base64::{engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD}, Engine};
+use jsonwebtoken::{crypto, Algorithm, DecodingKey};
+use flate2::{write::DeflateEncoder, Compression};
+use super::identity::{self, ExternalUser, PendingLink};
+use super::email_domain::DomainPolicy;
+use crate::config::{Jwt, SamlProvider};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Serialize, Deserialize};
+use actix_web::http::StatusCode;
+use std::collections::HashMap;
+use sha2::{Digest, Sha256};
+use super::{EmailNormalization, Error, Id, IdentityStore, User, UserStore};
+use lettre::Address;
+use std::io::Write;
+use xml::{escape, Element};
+use url::Url;
+
+mod xml;
+
+type Result<T> = std::result::Result<T, Error>;
+
+
+const PROTOCOL: &str = "urn:oasis:names:tc:SAML:2.0:protocol";
+const ASSERTION: &str = "urn:oasis:names:tc:SAML:2.0:assertion";
+const DSIG: &str = "http://www.w3.org/2000/09/xmldsig#";
+const STATUS_SUCCESS: &str = "urn:oasis:names:tc:SAML:2.0:status:Success";
+const ENVELOPED_SIGNATURE: &str = "http://www.w3.org/2000/09/xmldsig#enveloped-signature";
+const EXCLUSIVE_C14N: &str = "http://www.w3.org/2001/10/xml-exc-c14n#";
+const RSA_SHA256: &str = "http://www.w3.org/2001/04/xmldsig-more#rsa-sha256";
+const SHA256: &str = "http://www.w3.org/2001/04/xmlenc#sha256";
+const BEARER: &str = "urn:oasis:names:tc:SAML:2.0:cm:bearer";
+const NAME_ID_FORMAT_EMAIL: &str = "urn:oasis:names:tc:SAML:1.1:nameid-format:emailAddress";
+const BINDING_POST: &str = "urn:oasis:names:tc:SAML:2.0:bindings:HTTP-POST";
+///How far the clocks of the identity provider and this server may drift apart, in seconds.
+const CLOCK_SKEW: i64 = 2 * 60;
+///How long a user has to complete the login at the identity provider, in seconds.
+const RELAY_STATE_EXPIRES_IN: i64 = 10 * 60;
+
+
+///The urls of this server as a SAML service provider for one identity provider.
+#[derive(Clone, Debug)]
+pub struct ServiceProvider {
+    pub entity_id: String,
+    ///The url of the assertion consumer service. Responses are posted here.
+    pub acs_url: String,
+}
+
+
+///The pending login carried through the identity provider in the `RelayState` parameter.
+#[derive(Debug, Serialize, Deserialize)]
+struct RelayState {
+    provider: String,
+    request_id: String,
+    ///The link to a signed in user, when the login was started to link the identity.
+    #[serde(default)]
+    link: Option<PendingLink>,
+    exp: i64,
+}
+
+
+///The parts of a validated assertion this server uses.
+#[derive(Clone, Debug)]
+pub struct Assertion {
+    pub id: String,
+    pub name_id: String,
+    pub name_id_format: Option<String>,
+    pub attributes: HashMap<String, Vec<String>>,
+    ///The assertion can not be used after this instant, so it only has to be kept in the replay cache until then.
+    pub expires_at: DateTime<Utc>,
+}
+
+
+fn invalid(message: &str) -> Error {
+    Error::Custom(StatusCode::UNAUTHORIZED, format!("invalid saml response: {}", message).into())
+}
+
+
+fn internal<E: std::error::Error + 'static>(err: E) -> Error {
+    Error::InternalServerError(Some(err.into()))
+}
+
+
+///Generates the metadata document describing this service provider to the identity provider.
+pub fn metadata(sp: &ServiceProvider) -> String {
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?><md:EntityDescriptor xmlns:md="urn:oasis:names:tc:SAML:2.0:metadata" entityID="{}"><md:SPSSODescriptor AuthnRequestsSigned="false" WantAssertionsSigned="true" protocolSupportEnumeration="{}"><md:NameIDFormat>{}</md:NameIDFormat><md:AssertionConsumerService Binding="{}" Location="{}" index="0" isDefault="true"/></md:SPSSODescriptor></md:EntityDescriptor>"#,
+        escape(&sp.entity_id), PROTOCOL, NAME_ID_FORMAT_EMAIL, BINDING_POST, escape(&sp.acs_url)
+    )
+}
+
+
+///Builds the url of the identity provider carrying an `AuthnRequest` with the HTTP-Redirect binding.
+/// With a pending link, the identity is linked to its user once they have signed in, instead of signing them in.
+pub fn authn_request_url(provider: &SamlProvider, sp: &ServiceProvider, jwt: &Jwt, link: Option<PendingLink>) -> Result<Url> {
+    let request_id = format!("_{}", sqlx::types::Uuid::new_v4().simple());
+    let request = format!(
+        r#"<samlp:AuthnRequest xmlns:samlp="{}" xmlns:saml="{}" ID="{}" Version="2.0" IssueInstant="{}" Destination="{}" ProtocolBinding="{}" AssertionConsumerServiceURL="{}"><saml:Issuer>{}</saml:Issuer><samlp:NameIDPolicy Format="{}" AllowCreate="true"/></samlp:AuthnRequest>"#,
+        PROTOCOL, ASSERTION, request_id, Utc::now().format("%Y-%m-%dT%H:%M:%SZ"), escape(&provider.sso_url),
+        BINDING_POST, escape(&sp.acs_url), escape(&sp.entity_id), NAME_ID_FORMAT_EMAIL
+    );
+    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(request.as_bytes()).map_err(internal)?;
+    let request = STANDARD.encode(encoder.finish().map_err(internal)?);
+    let relay_state = RelayState {
+        provider: provider.name.clone(),
+        request_id,
+        link,
+        exp: Utc::now().timestamp() + RELAY_STATE_EXPIRES_IN,
+    };
+    let relay_state = super::token::sign(jwt, &relay_state)?;
+    let mut url = Url::parse(&provider.sso_url).map_err(internal)?;
+    url.query_pairs_mut().append_pair("SAMLRequest", &request).append_pair("RelayState", &relay_state);
+    Ok(url)
+}
+
+
+///Returns the id of the `AuthnRequest` the login was started with, and the link it was started for.
+/// Logins started at the identity provider have no relay state.
+pub fn request_id(provider: &SamlProvider, jwt: &Jwt, relay_state: Option<&str>) -> Result<(Option<String>, Option<PendingLink>)> {
+    let relay_state = match relay_state {
+        Some(relay_state) if !relay_state.is_empty() => relay_state,
+        _ => return Ok((None, None)),
+    };
+    let relay_state: RelayState = super::token::verify(jwt, relay_state)?;
+    if relay_state.provider != provider.name {
+        return Err(invalid("the login was started with a different provider"));
+    }
+    if relay_state.exp <= Utc::now().timestamp() {
+        return Err(invalid("the login has expired, please try again"));
+    }
+    Ok((Some(relay_state.request_id), relay_state.link))
+}
+
+
+fn parse_instant(value: Option<&str>) -> Result<Option<DateTime<Utc>>> {
+    match value {
+        None => Ok(None),
+        Some(value) => DateTime::parse_from_rfc3339(value)
+            .map(|instant| Some(instant.with_timezone(&Utc)))
+            .map_err(|_| invalid("malformed instant")),
+    }
+}
+
+
+fn algorithm<'a>(element: &'a Element, name: &str) -> Option<&'a str> {
+    element.child(DSIG, name).and_then(|method| method.attribute("Algorithm"))
+}
+
+
+fn remove_whitespace(value: &str) -> String {
+    value.chars().filter(|c| !c.is_whitespace()).collect()
+}
+
+
+///Verifies the enveloped signature of an element with the identity provider's certificate.
+fn verify_signature(element: &Element, certificate: &DecodingKey) -> Result<()> {
+    let signature = element.child(DSIG, "Signature").ok_or_else(|| invalid("missing signature"))?;
+    let signed_info = signature.child(DSIG, "SignedInfo").ok_or_else(|| invalid("missing signed info"))?;
+    if algorithm(signed_info, "CanonicalizationMethod") != Some(EXCLUSIVE_C14N) {
+        return Err(invalid("unsupported canonicalization method"));
+    }
+    if algorithm(signed_info, "SignatureMethod") != Some(RSA_SHA256) {
+        return Err(invalid("unsupported signature method"));
+    }
+    let mut references = signed_info.children_named(DSIG, "Reference");
+    let reference = match (references.next(), references.next()) {
+        (Some(reference), None) => reference,
+        _ => return Err(invalid("the signature has to have exactly one reference")),
+    };
+    let id = element.attribute("ID").ok_or_else(|| invalid("the signed element has no id"))?;
+    if reference.attribute("URI") != Some(&format!("#{}", id)) {
+        return Err(invalid("the signature does not reference the signed element"));
+    }
+    let transforms = reference.child(DSIG, "Transforms").map(|transforms| transforms.children_named(DSIG, "Transform").collect()).unwrap_or(Vec::new());
+    if transforms.iter().any(|transform| !matches!(transform.attribute("Algorithm"), Some(ENVELOPED_SIGNATURE) | Some(EXCLUSIVE_C14N))) {
+        return Err(invalid("unsupported transform"));
+    }
+    if algorithm(reference, "DigestMethod") != Some(SHA256) {
+        return Err(invalid("unsupported digest method"));
+    }
+    let digest = STANDARD.encode(Sha256::digest(element.canonicalize(Some(signature)).as_bytes()));
+    let expected_digest = reference.child(DSIG, "DigestValue").map(|value| remove_whitespace(&value.text())).unwrap_or_default();
+    if digest != expected_digest {
+        return Err(invalid("the digest of the signed element does not match"));
+    }
+    let signature_value = signature.child(DSIG, "SignatureValue").map(|value| remove_whitespace(&value.text())).unwrap_or_default();
+    let signature_value = STANDARD.decode(signature_value).map_err(|_| invalid("malformed signature value"))?;
+    let canonical_signed_info = signed_info.canonicalize(None);
+    match crypto::verify(&URL_SAFE_NO_PAD.encode(signature_value), canonical_signed_info.as_bytes(), certificate, Algorithm::RS256) {
+        Ok(true) => Ok(()),
+        _ => Err(invalid("bad signature")),
+    }
+}
+
+
+///Decodes a response posted to the assertion consumer service and validates its signature,
+/// issuer, status, audience, recipient and validity period.
+/// `request_id` is the id of the `AuthnRequest` the response has to be in response to, if the login was started here.
+pub fn validate_response(encoded: &str, provider: &SamlProvider, sp: &ServiceProvider, request_id: Option<&str>, now: DateTime<Utc>) -> Result<Assertion> {
+    let xml = STANDARD.decode(remove_whitespace(encoded)).map_err(|_| invalid("malformed encoding"))?;
+    let xml = String::from_utf8(xml).map_err(|_| invalid("malformed encoding"))?;
+    let response = Element::parse(&xml).map_err(|err| invalid(&err))?;
+    if !response.is(PROTOCOL, "Response") {
+        return Err(invalid("not a response"));
+    }
+    if response.attribute("Destination").is_some_and(|destination| destination != sp.acs_url) {
+        return Err(invalid("wrong destination"));
+    }
+    let status = response.child(PROTOCOL, "Status").and_then(|status| status.child(PROTOCOL, "StatusCode")).and_then(|code| code.attribute("Value"));
+    if status != Some(STATUS_SUCCESS) {
+        return Err(invalid("the identity provider did not authenticate the user"));
+    }
+    if response.child(ASSERTION, "EncryptedAssertion").is_some() {
+        return Err(invalid("encrypted assertions are not supported"));
+    }
+    let mut assertions = response.children_named(ASSERTION, "Assertion");
+    let assertion = match (assertions.next(), assertions.next()) {
+        (Some(assertion), None) => assertion,
+        _ => return Err(invalid("the response has to have exactly one assertion")),
+    };
+    let certificate = DecodingKey::from_rsa_pem(provider.certificate.as_bytes())
+        .map_err(|_| Error::InternalServerError(Some("invalid saml identity provider certificate".into())))?;
+    match assertion.child(DSIG, "Signature") {
+        Some(_) => verify_signature(assertion, &certificate)?,
+        None => verify_signature(&response, &certificate)?,
+    }
+
+    // Everything below is read from the signed assertion only.
+    let issuer = assertion.child(ASSERTION, "Issuer").map(|issuer| issuer.text());
+    if issuer.as_deref().map(str::trim) != Some(provider.entity_id.as_str()) {
+        return Err(invalid("wrong issuer"));
+    }
+    let skew = Duration::seconds(CLOCK_SKEW);
+    let conditions = assertion.child(ASSERTION, "Conditions").ok_or_else(|| invalid("missing conditions"))?;
+    if parse_instant(conditions.attribute("NotBefore"))?.is_some_and(|not_before| now + skew < not_before) {
+        return Err(invalid("the assertion is not valid yet"));
+    }
+    let not_on_or_after = parse_instant(conditions.attribute("NotOnOrAfter"))?;
+    if not_on_or_after.is_some_and(|not_on_or_after| now - skew >= not_on_or_after) {
+        return Err(invalid("the assertion has expired"));
+    }
+    let audiences: Vec<String> = conditions.children_named(ASSERTION, "AudienceRestriction")
+        .flat_map(|restriction| restriction.children_named(ASSERTION, "Audience"))
+        .map(|audience| audience.text().trim().to_string())
+        .collect();
+    if !audiences.contains(&sp.entity_id) {
+        return Err(invalid("the assertion is not meant for this service provider"));
+    }
+
+    let subject = assertion.child(ASSERTION, "Subject").ok_or_else(|| invalid("missing subject"))?;
+    let name_id = subject.child(ASSERTION, "NameID").ok_or_else(|| invalid("missing name id"))?;
+    let mut confirmed_until = None;
+    for confirmation in subject.children_named(ASSERTION, "SubjectConfirmation") {
+        let data = confirmation.child(ASSERTION, "SubjectConfirmationData");
+        let until = parse_instant(data.and_then(|data| data.attribute("NotOnOrAfter")))?;
+        let confirmed = confirmation.attribute("Method") == Some(BEARER)
+            && data.and_then(|data| data.attribute("Recipient")) == Some(sp.acs_url.as_str())
+            && until.is_some_and(|until| now - skew < until)
+            && data.and_then(|data| data.attribute("InResponseTo")) == request_id;
+        if confirmed {
+            confirmed_until = until;
+            break;
+        }
+    }
+    let confirmed_until = confirmed_until.ok_or_else(|| invalid("the subject could not be confirmed"))?;
+
+    let mut attributes: HashMap<String, Vec<String>> = HashMap::new();
+    for statement in assertion.children_named(ASSERTION, "AttributeStatement") {
+        for attribute in statement.children_named(ASSERTION, "Attribute") {
+            let name = attribute.attribute("Name").unwrap_or_default().to_string();
+            let values = attribute.children_named(ASSERTION, "AttributeValue").map(|value| value.text().trim().to_string());
+            attributes.entry(name).or_default().extend(values);
+        }
+    }
+    let id = assertion.attribute("ID").unwrap_or_default().to_string();
+    let expires_at = not_on_or_after.map_or(confirmed_until, |not_on_or_after| not_on_or_after.max(confirmed_until)) + skew;
+    Ok(Assertion {
+        id,
+        name_id: name_id.text().trim().to_string(),
+        name_id_format: name_id.attribute("Format").map(String::from),
+        attributes,
+        expires_at,
+    })
+}
+
+
+fn first(assertion: &Assertion, name: &str) -> Option<String> {
+    assertion.attributes.get(name).and_then(|values| values.first()).cloned()
+}
+
+
+///Maps the name id and attributes of an assertion to the fields of a `User`.
+pub fn external_user(provider: &SamlProvider, assertion: &Assertion) -> Result<ExternalUser> {
+    let mapping = &provider.attributes;
+    let email = match mapping.email {
+        Some(ref name) => first(assertion, name),
+        None => Some(assertion.name_id.clone()),
+    };
+    let email = email.and_then(|email| email.parse::<Address>().ok())
+        .ok_or_else(|| Error::Custom(StatusCode::UNPROCESSABLE_ENTITY, "the identity provider did not provide a valid email address".into()))?;
+    let user_name = mapping.user_name.as_ref().and_then(|name| first(assertion, name)).unwrap_or_else(|| email.user().to_string());
+    Ok(ExternalUser {
+        subject: assertion.name_id.clone(),
+        user_name,
+        email,
+        first_name: first(assertion, &mapping.first_name).unwrap_or_default(),
+        last_name: first(assertion, &mapping.last_name).unwrap_or_default(),
+        profile_picture: None,
+    })
+}
+
+
+///Validates a response posted to the assertion consumer service, in response to the request of the relay state if any,
+/// and returns the user it authenticates. The user is created on their first login, unless it links the identity to a user.
+#[allow(clippy::too_many_arguments)]
+pub async fn login(users: &dyn UserStore, identities: &dyn IdentityStore, normalization: &EmailNormalization, domains: &DomainPolicy, provider: &SamlProvider, sp: &ServiceProvider, encoded: &str, request_id: Option<&str>, link_to: Option<&Id>) -> Result<User> {
+    let assertion = validate_response(encoded, provider, sp, request_id, Utc::now())?;
+    if !identities.record_assertion(&assertion.id, &assertion.expires_at).await? {
+        return Err(invalid("the assertion has already been used"));
+    }
+    let external = external_user(provider, &assertion)?;
+    let name = format!("saml:{}", provider.name);
+    match link_to {
+        Some(user_id) => identity::link_identity(users, identities, &name, user_id, external).await,
+        None => identity::link_or_create_user(users, identities, normalization, domains, &name, &provider.trusted_domains, external).await,
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CERTIFICATE: &str = include_str!("../fixtures/saml_certificate.pem");
+    ///A response signed by the fixture identity provider at 2026-01-01T00:00:00Z, in response to `_request`.
+    const RESPONSE: &str = include_str!("../fixtures/saml_response.xml");
+
+    fn provider() -> SamlProvider {
+        SamlProvider {
+            name: "acme".into(),
+            entity_id: "https://idp.example.com/metadata".into(),
+            sso_url: "https://idp.example.com/sso".into(),
+            certificate: CERTIFICATE.into(),
+            attributes: Default::default(),
+            trusted_domains: vec![],
+        }
+    }
+
+    fn sp() -> ServiceProvider {
+        ServiceProvider {
+            entity_id: "https://sp.example.com/saml/acme/metadata".into(),
+            acs_url: "https://sp.example.com/saml/acme/acs".into(),
+        }
+    }
+
+    fn now() -> DateTime<Utc> {
+        "2026-01-01T00:01:00Z".parse().unwrap()
+    }
+
+    fn encode(xml: &str) -> String {
+        STANDARD.encode(xml)
+    }
+
+    #[test]
+    fn test_validate_signed_response() {
+        let assertion = validate_response(&encode(RESPONSE), &provider(), &sp(), Some("_request"), now()).unwrap();
+        assert_eq!(assertion.name_id, "jane@example.com");
+        assert_eq!(assertion.attributes["givenName"], vec!["Jane".to_string()]);
+        let user = external_user(&provider(), &assertion).unwrap();
+        assert_eq!(user.email.to_string(), "jane@example.com");
+        assert_eq!(user.first_name, "Jane");
+        assert_eq!(user.last_name, "Roe & Co");
+        assert_eq!(user.user_name, "jane");
+    }
+
+    #[test]
+    fn test_validate_response_rejects_tampering() {
+        let tampered = RESPONSE.replace("jane@example.com", "admin@example.com");
+        assert!(validate_response(&encode(&tampered), &provider(), &sp(), Some("_request"), now()).is_err());
+        let tampered = RESPONSE.replace(">Jane</saml:AttributeValue>", ">Eve</saml:AttributeValue>");
+        assert!(validate_response(&encode(&tampered), &provider(), &sp(), Some("_request"), now()).is_err());
+    }
+
+    #[test]
+    fn test_validate_response_checks_conditions() {
+        let response = encode(RESPONSE);
+        let expired = "2026-01-01T01:00:00Z".parse().unwrap();
+        assert!(validate_response(&response, &provider(), &sp(), Some("_request"), expired).is_err());
+        assert!(validate_response(&response, &provider(), &sp(), Some("_another_request"), now()).is_err());
+        assert!(validate_response(&response, &provider(), &sp(), None, now()).is_err());
+        let other_sp = ServiceProvider{entity_id: "https://other.example.com".into(), ..sp()};
+        assert!(validate_response(&response, &provider(), &other_sp, Some("_request"), now()).is_err());
+        let other_idp = SamlProvider{entity_id: "https://other.example.com".into(), ..provider()};
+        assert!(validate_response(&response, &other_idp, &sp(), Some("_request"), now()).is_err());
+    }
+
+    #[test]
+    fn test_authn_request_url() {
+        let jwt = Jwt::default();
+        let url = authn_request_url(&provider(), &sp(), &jwt, None).unwrap();
+        let query: HashMap<_, _> = url.query_pairs().into_owned().collect();
+        assert!(query.contains_key("SAMLRequest"));
+        let (request_id, link) = request_id(&provider(), &jwt, Some(&query["RelayState"])).unwrap();
+        let request_id = request_id.unwrap();
+        assert!(link.is_none());
+        assert!(request_id.starts_with('_'));
+        assert!(metadata(&sp()).contains(r#"entityID="https://sp.example.com/saml/acme/metadata""#));
+    }
+}
@@ -0,0 +1,288 @@
+//! A small XML tree with just enough namespace support to read SAML messages
+//! and to canonicalize elements with Exclusive XML Canonicalization (without comments).
+use quick_xml::{events::{BytesStart, Event}, escape::unescape, Reader};
+use std::collections::BTreeMap;
+
+type Result<T> = std::result::Result<T, String>;
+///Maps a namespace prefix to its uri. The default namespace has the empty prefix.
+type Namespaces = BTreeMap<String, String>;
+
+
+const XMLNS: &str = "xmlns";
+const XML_PREFIX: &str = "xml";
+const XML_NAMESPACE: &str = "http://www.w3.org/XML/1998/namespace";
+
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Node {
+    Element(Element),
+    Text(String),
+}
+
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Element {
+    ///The qualified name as written in the document. e.g `saml:Assertion`
+    pub name: String,
+    ///The attributes as written in the document, including namespace declarations.
+    pub attributes: Vec<(String, String)>,
+    pub children: Vec<Node>,
+    ///The namespaces in scope of this element, including the ones it declares.
+    namespaces: Namespaces,
+}
+
+
+fn split_name(name: &str) -> (&str, &str) {
+    name.split_once(':').unwrap_or(("", name))
+}
+
+
+///Normalizes the line endings of raw text the way an XML processor does.
+fn normalize_line_endings(raw: &str) -> String {
+    raw.replace("\r\n", "\n").replace('\r', "\n")
+}
+
+
+///Normalizes the raw value of an attribute the way an XML processor does for CDATA attributes.
+fn normalize_attribute(raw: &str) -> String {
+    normalize_line_endings(raw).replace(['\n', '\t'], " ")
+}
+
+
+impl Element {
+    fn from_start(start: &BytesStart, parent: &Namespaces) -> Result<Self> {
+        let name = String::from_utf8(start.name().as_ref().to_vec()).map_err(|err| err.to_string())?;
+        let mut namespaces = parent.clone();
+        let mut attributes = Vec::new();
+        for attribute in start.attributes() {
+            let attribute = attribute.map_err(|err| err.to_string())?;
+            let key = String::from_utf8(attribute.key.as_ref().to_vec()).map_err(|err| err.to_string())?;
+            let raw = std::str::from_utf8(&attribute.value).map_err(|err| err.to_string())?;
+            let value = unescape(&normalize_attribute(raw)).map_err(|err| err.to_string())?.into_owned();
+            if key == XMLNS {
+                namespaces.insert(String::new(), value.clone());
+            } else if let Some(prefix) = key.strip_prefix("xmlns:") {
+                namespaces.insert(prefix.to_string(), value.clone());
+            }
+            attributes.push((key, value));
+        }
+        Ok(Self{name, attributes, children: Vec::new(), namespaces})
+    }
+
+    ///Parses a document and returns its root element.
+    /// Documents with a DTD are rejected.
+    pub fn parse(xml: &str) -> Result<Self> {
+        let mut reader = Reader::from_str(xml);
+        reader.config_mut().trim_text(false);
+        let mut stack: Vec<Element> = Vec::new();
+        let root_namespaces = Namespaces::from([(XML_PREFIX.to_string(), XML_NAMESPACE.to_string())]);
+        loop {
+            let event = reader.read_event().map_err(|err| err.to_string())?;
+            match event {
+                Event::Start(start) => {
+                    let parent = stack.last().map(|element| &element.namespaces).unwrap_or(&root_namespaces);
+                    let element = Element::from_start(&start, parent)?;
+                    stack.push(element);
+                },
+                Event::Empty(start) => {
+                    let parent = stack.last().map(|element| &element.namespaces).unwrap_or(&root_namespaces);
+                    let element = Element::from_start(&start, parent)?;
+                    match stack.last_mut() {
+                        Some(parent) => parent.children.push(Node::Element(element)),
+                        None => return Ok(element),
+                    }
+                },
+                Event::End(_) => {
+                    let element = stack.pop().ok_or("unexpected end tag")?;
+                    match stack.last_mut() {
+                        Some(parent) => parent.children.push(Node::Element(element)),
+                        None => return Ok(element),
+                    }
+                },
+                Event::Text(text) => {
+                    if let Some(element) = stack.last_mut() {
+                        let raw = std::str::from_utf8(&text).map_err(|err| err.to_string())?;
+                        let text = unescape(&normalize_line_endings(raw)).map_err(|err| err.to_string())?.into_owned();
+                        element.children.push(Node::Text(text));
+                    }
+                },
+                Event::CData(data) => {
+                    if let Some(element) = stack.last_mut() {
+                        let text = std::str::from_utf8(&data).map_err(|err| err.to_string())?;
+                        element.children.push(Node::Text(normalize_line_endings(text)));
+                    }
+                },
+                Event::DocType(_) => return Err("documents with a DTD are not accepted".into()),
+                Event::Eof => return Err("unexpected end of document".into()),
+                _ => (),
+            }
+        }
+    }
+
+    ///The namespace uri of the element.
+    pub fn namespace(&self) -> Option<&str> {
+        let (prefix, _) = split_name(&self.name);
+        self.namespaces.get(prefix).map(String::as_str).filter(|uri| !uri.is_empty())
+    }
+
+    pub fn local_name(&self) -> &str {
+        split_name(&self.name).1
+    }
+
+    pub fn is(&self, namespace: &str, local_name: &str) -> bool {
+        self.local_name() == local_name && self.namespace() == Some(namespace)
+    }
+
+    ///Returns the value of an unqualified attribute.
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes.iter().find(|(key, _)| key == name).map(|(_, value)| value.as_str())
+    }
+
+    pub fn elements(&self) -> impl Iterator<Item = &Element> {
+        self.children.iter().filter_map(|node| match node {
+            Node::Element(element) => Some(element),
+            Node::Text(_) => None,
+        })
+    }
+
+    pub fn child(&self, namespace: &str, local_name: &str) -> Option<&Element> {
+        self.elements().find(|element| element.is(namespace, local_name))
+    }
+
+    pub fn children_named<'a>(&'a self, namespace: &'a str, local_name: &'a str) -> impl Iterator<Item = &'a Element> {
+        self.elements().filter(move |element| element.is(namespace, local_name))
+    }
+
+    ///The concatenated text content of the element and its descendants.
+    pub fn text(&self) -> String {
+        let mut text = String::new();
+        for node in &self.children {
+            match node {
+                Node::Text(value) => text.push_str(value),
+                Node::Element(element) => text.push_str(&element.text()),
+            }
+        }
+        text
+    }
+
+    ///Canonicalizes the element with Exclusive XML Canonicalization without comments.
+    /// `exclude` is left out of the output, which is how the enveloped signature transform is applied.
+    pub fn canonicalize(&self, exclude: Option<&Element>) -> String {
+        let mut output = String::new();
+        self.write_canonical(&Namespaces::new(), exclude, &mut output);
+        output
+    }
+
+    fn write_canonical(&self, rendered: &Namespaces, exclude: Option<&Element>, output: &mut String) {
+        let (prefix, _) = split_name(&self.name);
+        let mut utilized = vec![prefix];
+        for (key, _) in &self.attributes {
+            let (attribute_prefix, _) = split_name(key);
+            if !attribute_prefix.is_empty() && attribute_prefix != XMLNS && key != XMLNS && !utilized.contains(&attribute_prefix) {
+                utilized.push(attribute_prefix);
+            }
+        }
+        let mut rendered = rendered.clone();
+        let mut declarations = Namespaces::new();
+        for prefix in utilized {
+            if prefix == XML_PREFIX {
+                continue;
+            }
+            let uri = self.namespaces.get(prefix).cloned().unwrap_or_default();
+            let current = rendered.get(prefix).cloned().unwrap_or_default();
+            // An empty default namespace only has to be rendered to undo a non empty one.
+            if uri != current || (!prefix.is_empty() && !rendered.contains_key(prefix)) {
+                declarations.insert(prefix.to_string(), uri.clone());
+                rendered.insert(prefix.to_string(), uri);
+            }
+        }
+
+        output.push('<');
+        output.push_str(&self.name);
+        for (prefix, uri) in &declarations {
+            match prefix.is_empty() {
+                true => output.push_str(" xmlns=\""),
+                false => {
+                    output.push_str(" xmlns:");
+                    output.push_str(prefix);
+                    output.push_str("=\"");
+                }
+            }
+            output.push_str(&escape_attribute(uri));
+            output.push('"');
+        }
+        let mut attributes: Vec<_> = self.attributes.iter()
+            .filter(|(key, _)| key != XMLNS && !key.starts_with("xmlns:"))
+            .map(|(key, value)| {
+                let (prefix, local_name) = split_name(key);
+                let namespace = match prefix.is_empty() {
+                    true => "",
+                    false => self.namespaces.get(prefix).map(String::as_str).unwrap_or_default(),
+                };
+                ((namespace, local_name), key, value)
+            })
+            .collect();
+        attributes.sort_by(|a, b| a.0.cmp(&b.0));
+        for (_, key, value) in attributes {
+            output.push(' ');
+            output.push_str(key);
+            output.push_str("=\"");
+            output.push_str(&escape_attribute(value));
+            output.push('"');
+        }
+        output.push('>');
+        for node in &self.children {
+            match node {
+                Node::Text(text) => output.push_str(&escape_text(text)),
+                Node::Element(element) => {
+                    if exclude.is_some_and(|excluded| std::ptr::eq(excluded, element)) {
+                        continue;
+                    }
+                    element.write_canonical(&rendered, exclude, output);
+                },
+            }
+        }
+        output.push_str("</");
+        output.push_str(&self.name);
+        output.push('>');
+    }
+}
+
+
+fn escape_text(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('\r', "&#xD;")
+}
+
+
+fn escape_attribute(value: &str) -> String {
+    value.replace('&', "&amp;").replace('<', "&lt;").replace('"', "&quot;")
+        .replace('\t', "&#x9;").replace('\n', "&#xA;").replace('\r', "&#xD;")
+}
+
+
+///Escapes a value to be put in an xml document as text or as an attribute value.
+pub fn escape(value: &str) -> String {
+    value.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;").replace('\'', "&apos;")
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_canonicalize_renders_only_visibly_utilized_namespaces() {
+        let xml = r#"<root xmlns="urn:default" xmlns:a="urn:a" xmlns:b="urn:b"><a:child b:z="1" y='2 &amp; 3' x="&#xA;"   ><empty/></a:child></root>"#;
+        let root = Element::parse(xml).unwrap();
+        let child = root.child("urn:a", "child").unwrap();
+        assert_eq!(
+            child.canonicalize(None),
+            r#"<a:child xmlns:a="urn:a" xmlns:b="urn:b" x="&#xA;" y="2 &amp; 3" b:z="1"><empty xmlns="urn:default"></empty></a:child>"#
+        );
+    }
+
+    #[test]
+    fn test_parse_rejects_doctype() {
+        assert!(Element::parse(r#"<!DOCTYPE x [<!ENTITY e "e">]><x>&e;</x>"#).is_err());
+    }
+}
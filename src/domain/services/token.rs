@@ -0,0 +1,123 @@
+use jwt::{SignWithKey, VerifyWithKey};
+use serde::{Serialize, Deserialize};
+use actix_web::http::StatusCode;
+use super::{Access, Error, Id, RoleStore, User};
+use crate::config::Jwt;
+use chrono::Utc;
+
+type Result<T> = std::result::Result<T, Error>;
+
+
+///The claims of the access tokens issued by this server.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Claims {
+    ///The hex encoded `Id` of the user the token was issued to.
+    pub sub: String,
+    pub iss: String,
+    pub iat: i64,
+    pub exp: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub roles: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub permissions: Option<Vec<String>>,
+    ///The hex encoded `Id` of the organization the user is acting in.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub org: Option<String>,
+}
+
+
+///Issues a signed access token for the given user, carrying the given roles and permissions
+/// and the active organization if any.
+pub fn issue_token(config: &Jwt, user: &User, access: Option<Access>, organization: Option<&Id>) -> Result<String> {
+    let now = Utc::now().timestamp();
+    let (roles, permissions) = match access {
+        Some(access) => (Some(access.roles), Some(access.permissions)),
+        None => (None, None),
+    };
+    let claims = Claims {
+        sub: user.id.to_hex(),
+        iss: config.issuer.clone(),
+        iat: now,
+        exp: now + config.expires_in,
+        roles,
+        permissions,
+        org: organization.map(|id| id.to_hex()),
+    };
+    sign(config, &claims)
+}
+
+
+///Issues a signed access token for the given user, scoped to the organization if one is given.
+/// The roles and permissions of the user are included when the configuration allows it.
+pub async fn issue_user_token(roles: &dyn RoleStore, config: &Jwt, user: &User, organization: Option<&Id>) -> Result<String> {
+    let access = match config.include_access {
+        true => Some(roles.get_user_access(&user.id).await?),
+        false => None,
+    };
+    issue_token(config, user, access, organization)
+}
+
+
+///Verifies the signature, issuer and expiry of an access token and returns its claims.
+pub fn verify_token(config: &Jwt, token: &str) -> Result<Claims> {
+    let claims: Claims = verify(config, token)?;
+    if claims.iss != config.issuer {
+        return Err(Error::Custom(StatusCode::UNAUTHORIZED, "invalid token issuer".into()));
+    }
+    if claims.exp <= Utc::now().timestamp() {
+        return Err(Error::Custom(StatusCode::UNAUTHORIZED, "token has expired".into()));
+    }
+    Ok(claims)
+}
+
+
+///Signs any serializable claims with the server's key.
+pub fn sign<T: Serialize>(config: &Jwt, claims: &T) -> Result<String> {
+    claims.sign_with_key(&config.key()).map_err(|err| Error::InternalServerError(Some(err.into())))
+}
+
+
+///Verifies the signature of a token signed with [`sign`] and returns its claims.
+/// The claims themselves are not validated.
+pub fn verify<T: for<'de> Deserialize<'de>>(config: &Jwt, token: &str) -> Result<T> {
+    token.verify_with_key(&config.key()).map_err(|_| Error::Custom(StatusCode::UNAUTHORIZED, "invalid token".into()))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_user;
+
+    #[test]
+    fn test_issue_and_verify_token() {
+        let config = Jwt::default();
+        let user = test_user("user@domain.com");
+        let token = issue_token(&config, &user, None, None).unwrap();
+        let claims = verify_token(&config, &token).unwrap();
+        assert_eq!(claims.sub, user.id.to_hex());
+        assert!(claims.roles.is_none());
+
+        let access = Access{roles: vec!["admin".into()], permissions: vec!["*".into()]};
+        let token = issue_token(&config, &user, Some(access), None).unwrap();
+        let claims = verify_token(&config, &token).unwrap();
+        assert_eq!(claims.roles, Some(vec!["admin".to_string()]));
+        assert_eq!(claims.permissions, Some(vec!["*".to_string()]));
+
+        let organization = Id::default();
+        let token = issue_token(&config, &user, None, Some(&organization)).unwrap();
+        let claims = verify_token(&config, &token).unwrap();
+        assert_eq!(claims.org, Some(organization.to_hex()));
+    }
+
+    #[test]
+    fn test_verify_token_rejects_other_keys_and_expired_tokens() {
+        let config = Jwt::default();
+        let token = issue_token(&config, &test_user("user@domain.com"), None, None).unwrap();
+        assert!(verify_token(&Jwt::default(), &token).is_err());
+
+        let config = Jwt{expires_in: -1, ..config};
+        let token = issue_token(&config, &test_user("user@domain.com"), None, None).unwrap();
+        assert!(verify_token(&config, &token).is_err());
+    }
+}
@@ -1,28 +1,57 @@
-use sqlx::{query, query_as, Error as SqlxError, Execute, FromRow, Pool, Postgres};
-use super::{db, EmailAddress, Error, Id, User, Value, Mailer, Verification};
+use super::{AccountRestoration, Cursor, EmailAddress, Error, Id, Page, PasswordHashReport, PasswordReset, PasswordViolation, SortOrder, User, UserQuery, Value, Mailer, Verification, AUDIT_USERS_PURGED};
+use super::{AccountRestorationStore, AuditStore, LoginFailureStore, PasswordResetStore, UserStore, VerificationStore};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
 use crate::domain::services::verification::generate_verification_code;
 use crate::domain::services::mail::send_html_email;
 use actix_web::http::StatusCode;
 use std::collections::HashMap;
-use lettre::message::Mailbox;
-use crate::config::Mail;
+use lettre::{message::Mailbox, Address};
+use crate::config::{Config, Mail};
+use super::{audit, lockout, password};
+use super::breach::BreachCorpus;
+use super::email_domain::DomainPolicy;
+use sqlx::types::Uuid;
+use std::net::IpAddr;
+use argon2::Argon2;
+use log::{error, info};
 
 type Result<T> = std::result::Result<T, Error>;
-type Executor = Pool<Postgres>;
 
 
-pub async fn signup(executor: &Executor, mut user: User, mailer: &Mailer, mail_config: &Mail, scheme: &str, host: &str) -> Result<User> {
-    db::user::create_user(executor, &user).await?;
+///Creates a user and mails them a link to verify their email.
+/// Only the profile and the password are taken from the client, the id is generated and the email is not verified.
+/// Returns the warnings about the password alongside the user.
+#[allow(clippy::too_many_arguments)]
+pub async fn signup(users: &dyn UserStore, verifications: &dyn VerificationStore, mut user: User, mailer: &Mailer, config: &Config, argon2: &Argon2<'_>, breached: &BreachCorpus, domains: &DomainPolicy) -> Result<(User, Vec<PasswordViolation>)> {
+    // Otherwise an account could claim an address it does not own and have identities with it linked to it.
+    user.id = Id::default();
+    user.email = EmailAddress::New(user.email.address().clone());
+    user.email_verified_at = None;
+    user.created_at = Utc::now();
+    domains.check(user.email.address())?;
+    let canonical_email = user.email.canonical(&config.email_normalization)
+        .ok_or_else(|| Error::Custom(StatusCode::BAD_REQUEST, "the email address has an invalid domain".into()))?;
+    let warnings = password::validate_password(&config.password_policy, breached, &user.password, &user).await?;
+    user.password = password::hash_password(argon2, &user.password)?;
+    users.create_user(&user, &canonical_email).await?;
     user.password = Default::default();
 
-    // Generate a verification code for the new user
-    let verification = generate_verification_code(executor, user.id.clone()).await?;
+    send_verification_email(verifications, mailer, config, &user).await?;
+    Ok((user, warnings))
+}
+
+
+///Mails the user a link and a code to verify their email.
+async fn send_verification_email(verifications: &dyn VerificationStore, mailer: &Mailer, config: &Config, user: &User) -> Result<()> {
+    // Generate a verification code for the user
+    let verification = generate_verification_code(verifications, user.id.clone()).await?;
 
     // Include the HTML template
-    const HTML_TEMPLATE: &'static str = include_str!("mail.html");
+    const HTML_TEMPLATE: &str = include_str!("mail.html");
 
     // Prepare the magic link and replace placeholders
-    let magic_link = format!("{}://{}/magic-link/{}", scheme, host, verification.id.simple().to_string());
+    let magic_link = config.link(&format!("/magic-link/{}", verification.id.simple()));
     let message = HTML_TEMPLATE
         .replace("{{magic_link}}", &magic_link)
         .replace("{{code}}", &verification.code);
@@ -34,27 +63,270 @@ pub async fn signup(executor: &Executor, mut user: User, mailer: &Mailer, mail_c
     let receiver = Mailbox{name, email};
     send_html_email(
         mailer,
-        mail_config.sender.clone(),
+        config.mail.sender.clone(),
         receiver,
         subject,
         message,
     ).await.map_err(|_|"error could not send verification email to the provided email address");
 
+    Ok(())
+}
+
+
+///Replaces the email of a user and mails them a link to verify the new one.
+/// The new email is subject to the domain policy, like the emails users sign up with.
+#[allow(clippy::too_many_arguments)]
+pub async fn change_email(users: &dyn UserStore, verifications: &dyn VerificationStore, mailer: &Mailer, config: &Config, domains: &DomainPolicy, id: &Id, email: &str) -> Result<User> {
+    let address: Address = email.trim().parse().map_err(|_| Error::Custom(StatusCode::BAD_REQUEST, "invalid email".into()))?;
+    domains.check(&address)?;
+    let canonical_email = config.email_normalization.canonicalize(address.as_ref())
+        .ok_or_else(|| Error::Custom(StatusCode::BAD_REQUEST, "the email address has an invalid domain".into()))?;
+    let user = users.change_email(id, address.as_ref(), &canonical_email).await?;
+    send_verification_email(verifications, mailer, config, &user).await?;
     Ok(user)
 }
 
 
-pub async fn get_user_by_id(executor: &Executor, id: &Id) -> Result<User> {
-    Ok(db::user::get_user_by_id(executor, id).await?)
+fn invalid_credentials() -> Error {
+    Error::Custom(StatusCode::UNAUTHORIZED, "invalid email or password".into())
 }
 
 
-pub async fn delete_user_by_id(executor: &Executor, id: &Id) -> Result<()> {
-    Ok(db::user::delete_user_by_id(executor, id).await?)
+///The form of an email users are looked up by. What is not an address is looked up as given, and matches nobody.
+fn canonical_email(config: &Config, email: &str) -> String {
+    config.email_normalization.canonicalize(email).unwrap_or_else(|| email.trim().to_string())
 }
 
+
+///Checks the email and password of a user.
+/// Failed attempts are counted per account and per IP address. Every failure is answered
+/// a little slower than the one before, and too many failures lock the account or address for a while.
+#[allow(clippy::too_many_arguments)]
+pub async fn login(users: &dyn UserStore, login_failures: &dyn LoginFailureStore, mailer: &Mailer, config: &Config, argon2: &Argon2<'_>, email: &str, password: &str, ip: Option<IpAddr>) -> Result<User> {
+    let email = canonical_email(config, email);
+    let account = lockout::account_subject(&email);
+    let ip = ip.map(|ip| lockout::ip_subject(&ip));
+    let subjects: Vec<String> = std::iter::once(account.clone()).chain(ip.clone()).collect();
+    lockout::check(login_failures, &subjects).await?;
+
+    let user = match users.get_user_with_password_by_email(&email).await {
+        Ok(user) => Some(user),
+        Err(Error::UserNotFound) => None,
+        Err(err) => return Err(err),
+    };
+    match user {
+        Some(ref user) if !user.password.is_empty() => {
+            if password::verify_password(&config.argon, &user.password, password) {
+                lockout::clear(login_failures, &account).await?;
+                if password::needs_rehash(&config.argon, &user.password) {
+                    // The login goes through whether or not the hash could be upgraded, it is tried again next time.
+                    if let Ok(hash) = password::hash_password(argon2, password) {
+                        let _ = users.set_password(&user.id, &hash).await;
+                    }
+                }
+                return Ok(User{password: Default::default(), ..user.clone()});
+            }
+        },
+        // An unknown email is answered as slowly as a wrong password, so a login does not tell whether an account exists.
+        _ => password::verify_dummy_password(&config.argon, argon2, password),
+    }
+
+    let lockout_config = &config.lockout;
+    let (mut failures, locked) = lockout::record_failure(login_failures, lockout_config, &account, lockout_config.account_threshold).await?;
+    if let Some(ref ip) = ip {
+        let (ip_failures, _) = lockout::record_failure(login_failures, lockout_config, ip, lockout_config.ip_threshold).await?;
+        failures = failures.max(ip_failures);
+    }
+    if let (true, Some(user)) = (locked, user) {
+        // The lock holds whether or not the owner could be told about it.
+        let _ = lockout::notify_locked(mailer, &config.mail, lockout_config, &user).await;
+    }
+    tokio::time::sleep(lockout::delay(lockout_config, failures)).await;
+    Err(invalid_credentials())
+}
+
+
+///Replaces the password of a user who knows their current one.
+/// Returns the warnings about the new password.
+pub async fn change_password(users: &dyn UserStore, config: &Config, argon2: &Argon2<'_>, breached: &BreachCorpus, id: &Id, current_password: &str, new_password: &str) -> Result<Vec<PasswordViolation>> {
+    let user = users.get_user_with_password_by_id(id).await?;
+    if !password::verify_password(&config.argon, &user.password, current_password) {
+        return Err(Error::Custom(StatusCode::FORBIDDEN, "the current password is wrong".into()));
+    }
+    let warnings = password::validate_password(&config.password_policy, breached, new_password, &user).await?;
+    users.set_password(id, &password::hash_password(argon2, new_password)?).await?;
+    Ok(warnings)
+}
+
+
+const PASSWORD_RESET_LIFETIME: i64 = 60 * 60;
+
+
+///Mails a link to reset their password to the user with the given email and returns their id.
+/// Unknown emails are answered the same way, so a reset does not tell whether an account exists.
+pub async fn request_password_reset(users: &dyn UserStore, resets: &dyn PasswordResetStore, mailer: &Mailer, config: &Config, email: &str) -> Result<Option<Id>> {
+    let user = match users.get_user_by_email(&canonical_email(config, email)).await {
+        Ok(user) => user,
+        Err(Error::UserNotFound) => return Ok(None),
+        Err(err) => return Err(err),
+    };
+    let now = Utc::now();
+    let reset = PasswordReset{id: Uuid::new_v4(), user_id: user.id.clone(), created_at: now, expires_at: now + chrono::Duration::seconds(PASSWORD_RESET_LIFETIME)};
+    resets.create_password_reset(&reset).await?;
+
+    const HTML_TEMPLATE: &str = include_str!("password_reset.html");
+    let reset_link = config.link(&format!("/password-reset/{}", reset.id.simple()));
+    let message = HTML_TEMPLATE
+        .replace("{{reset_link}}", &reset_link)
+        .replace("{{expires_at}}", &reset.expires_at.format("%Y-%m-%d %H:%M UTC").to_string());
+    let receiver = Mailbox{name: Some(user.user_name.clone()), email: user.email.into()};
+    send_html_email(mailer, config.mail.sender.clone(), receiver, "Reset your password", message).await
+        .map_err(|_| "could not send the password reset email")?;
+    Ok(Some(user.id))
+}
+
+
+///Sets the password of the user a reset link was sent to and lifts the lock of their account.
+/// Returns the id of the user and the warnings about the new password.
+#[allow(clippy::too_many_arguments)]
+pub async fn reset_password(users: &dyn UserStore, resets: &dyn PasswordResetStore, login_failures: &dyn LoginFailureStore, config: &Config, argon2: &Argon2<'_>, breached: &BreachCorpus, id: &Uuid, new_password: &str) -> Result<(Id, Vec<PasswordViolation>)> {
+    let reset = resets.get_password_reset(id).await?
+        .ok_or_else(|| Error::Custom(StatusCode::NOT_FOUND, "the password reset link is invalid or has expired".into()))?;
+    let user = users.get_user_by_id(&reset.user_id).await?;
+    let warnings = password::validate_password(&config.password_policy, breached, new_password, &user).await?;
+    users.set_password(&user.id, &password::hash_password(argon2, new_password)?).await?;
+    resets.delete_password_resets_by_user_id(&user.id).await?;
+    let email = canonical_email(config, user.email.address().as_ref());
+    lockout::clear(login_failures, &lockout::account_subject(&email)).await?;
+    Ok((user.id, warnings))
+}
+
+
+///Counts the password hashes that are rehashed at the next login of their user.
+pub async fn get_password_hash_report(users: &dyn UserStore, config: &Config) -> Result<PasswordHashReport> {
+    users.count_password_hashes(&password::current_hash_prefix(&config.argon)).await
+}
+
+
+///Lifts the lock of an account and forgets its failed logins.
+pub async fn unlock_user(users: &dyn UserStore, login_failures: &dyn LoginFailureStore, config: &Config, id: &Id) -> Result<()> {
+    let user = users.get_user_by_id(id).await?;
+    let email = canonical_email(config, user.email.address().as_ref());
+    lockout::clear(login_failures, &lockout::account_subject(&email)).await
+}
+
+
+pub async fn get_user_by_id(users: &dyn UserStore, id: &Id) -> Result<User> {
+    users.get_user_by_id(id).await
+}
+
+
+pub async fn get_user_by_email(users: &dyn UserStore, config: &Config, email: &str) -> Result<User> {
+    users.get_user_by_email(&canonical_email(config, email)).await
+}
+
+
+///Deletes a user and mails them a link to restore their account within the grace period, after which they are purged.
+/// The organizations the user solely owns stay theirs until they are purged, and are then handed over to another member,
+/// or deleted when nobody else is left in them.
+pub async fn delete_user_by_id(users: &dyn UserStore, restorations: &dyn AccountRestorationStore, mailer: &Mailer, config: &Config, id: &Id) -> Result<()> {
+    let user = users.get_user_by_id(id).await?;
+    let now = Utc::now();
+    let purge_after = now + chrono::Duration::seconds(config.deletion.grace_period);
+    users.delete_user_by_id(id, purge_after).await?;
+    let restoration = AccountRestoration{id: Uuid::new_v4(), user_id: user.id.clone(), created_at: now, expires_at: purge_after};
+    restorations.create_account_restoration(&restoration).await?;
+
+    const HTML_TEMPLATE: &str = include_str!("account_deleted.html");
+    let restore_link = config.link(&format!("/users/restore/{}", restoration.id.simple()));
+    let message = HTML_TEMPLATE
+        .replace("{{restore_link}}", &restore_link)
+        .replace("{{purge_after}}", &purge_after.format("%Y-%m-%d %H:%M UTC").to_string());
+    let receiver = Mailbox{name: Some(user.user_name.clone()), email: user.email.into()};
+    // The deletion holds whether or not the user could be told about it, an admin can still restore them.
+    let _ = send_html_email(mailer, config.mail.sender.clone(), receiver, "Your account was deleted", message).await;
+    Ok(())
+}
+
+
+///Restores the account of a deleted user through the link mailed to them.
+pub async fn restore_account(users: &dyn UserStore, restorations: &dyn AccountRestorationStore, id: &Uuid) -> Result<User> {
+    let restoration = restorations.get_account_restoration(id).await?
+        .ok_or_else(|| Error::Custom(StatusCode::NOT_FOUND, "the restore link is invalid or has expired".into()))?;
+    restore_user(users, restorations, &restoration.user_id).await
+}
+
+
+///Restores a deleted user whose grace period has not ended.
+pub async fn restore_user(users: &dyn UserStore, restorations: &dyn AccountRestorationStore, id: &Id) -> Result<User> {
+    let user = users.restore_user(id).await?;
+    restorations.delete_account_restorations_by_user_id(id).await?;
+    Ok(user)
+}
+
+
+///Purges the deleted users past their grace period every purge interval, for as long as the server runs.
+/// Each purge that removes users is recorded as an audit event of the server.
+pub async fn purge_deleted_users(users: &dyn UserStore, audit: &dyn AuditStore, config: &Config) {
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(config.deletion.purge_interval.max(1)));
+    loop {
+        interval.tick().await;
+        match users.purge_deleted_users().await {
+            Ok(0) => (),
+            Ok(purged) => {
+                info!("purged {} deleted users", purged);
+                let details = Value::Map(HashMap::from([("count".to_string(), Value::Number(purged as i64))]));
+                if let Err(err) = audit::record(audit, &Default::default(), None, AUDIT_USERS_PURGED, None, details).await {
+                    error!("could not record the purge of the deleted users: {}", err);
+                }
+            },
+            // A failed purge is tried again at the next interval.
+            Err(err) => error!("could not purge the deleted users: {}", err),
+        }
+    }
+}
+
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 100;
+
+
+///The parameters of a user listing as given by a client.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct UserListing {
+    pub limit: Option<i64>,
+    pub cursor: Option<String>,
+    #[serde(default)]
+    pub order: SortOrder,
+    pub verified: Option<bool>,
+    pub created_after: Option<DateTime<Utc>>,
+    pub created_before: Option<DateTime<Utc>>,
+    pub role: Option<String>,
+    pub search: Option<String>,
+}
+
+
+/// Lists the users one page at a time, newest first unless asked otherwise.
+pub async fn get_users(users: &dyn UserStore, listing: UserListing) -> Result<Page<User>> {
+    let cursor = match listing.cursor {
+        Some(ref cursor) => Some(Cursor::decode(cursor).ok_or_else(|| Error::Custom(StatusCode::BAD_REQUEST, "invalid cursor".into()))?),
+        None => None,
+    };
+    let user_query = UserQuery {
+        verified: listing.verified,
+        created_after: listing.created_after,
+        created_before: listing.created_before,
+        role: listing.role,
+        search: listing.search.map(|search| search.trim().to_string()).filter(|search| !search.is_empty()),
+        order: listing.order,
+        cursor,
+        limit: listing.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE),
+    };
+    users.get_users(&user_query).await
+}
+
+
 /// update the `user_name`, `first_name` and `last_name` of a user with the given Id.
-pub async fn update_user_by_id(executor: &Executor, id: &Id, mut map: HashMap<String, Value>) -> Result<User> {
+pub async fn update_user_by_id(users: &dyn UserStore, id: &Id, mut map: HashMap<String, Value>) -> Result<User> {
     let fields = ["user_name", "first_name", "last_name"];
     let mut new_map = HashMap::new();
     for field in fields {
@@ -62,5 +334,5 @@ pub async fn update_user_by_id(executor: &Executor, id: &Id, mut map: HashMap<St
             new_map.insert(field, value);
         }
     }
-    Ok(db::user::update_user_by_id(executor, id, &new_map).await?)
+    users.update_user_by_id(id, &new_map).await
 }
@@ -1,16 +1,13 @@
-use crate::domain::db::{verification::*, user::verify_user};
+use crate::{Verification, Error, UserStore, VerificationStore};
 use actix_web::http::StatusCode;
-use sqlx::{Postgres, types::Uuid};
-use crate::{Verification, Error};
+use sqlx::types::Uuid;
 use super::{Id, User};
 use chrono::Utc;
-use sqlx::Pool;
 use rand::Rng;
 
-type Executor = Pool<Postgres>;
 type Result<T> = std::result::Result<T, Error>;
 
-pub async fn generate_verification_code(executor: &Executor, user_id: Id) -> Result<Verification> {
+pub async fn generate_verification_code(verifications: &dyn VerificationStore, user_id: Id) -> Result<Verification> {
     let code = rand::thread_rng().gen_range(100_000..1_000_000).to_string();
     let verification = Verification {
         id: Uuid::new_v4(),
@@ -18,28 +15,28 @@ pub async fn generate_verification_code(executor: &Executor, user_id: Id) -> Res
         code: code.clone(),
         created_at: Utc::now(),
     };
-    create_verification_code(executor, &verification).await?;
+    verifications.create_verification_code(&verification).await?;
     Ok(verification)
 }
 
 
-pub async fn verify_magic_link(executor: &Executor, verification_id: &Uuid) -> Result<User> {
+pub async fn verify_magic_link(users: &dyn UserStore, verifications: &dyn VerificationStore, verification_id: &Uuid) -> Result<User> {
     // Retrieve the verification information by ID
-    let verification = get_verification_by_id(executor, verification_id).await?;
+    let verification = verifications.get_verification_by_id(verification_id).await?;
 
     // Delete the verification code
-    delete_verification_by_id(executor, &verification.id).await?;
+    verifications.delete_verification_by_id(&verification.id).await?;
 
     // Verify the user's email and return the updated user
-    let updated_user = verify_user(executor, &verification.user_id).await?;
+    let updated_user = users.verify_user(&verification.user_id).await?;
 
     Ok(updated_user)
 }
 
 
-pub async fn verify_code_and_update_user(executor: &Executor, user_id: Id, code: &str) -> Result<User> {
+pub async fn verify_code_and_update_user(users: &dyn UserStore, verifications: &dyn VerificationStore, user_id: Id, code: &str) -> Result<User> {
     // Retrieve the latest verification code for the user
-    let verification = get_latest_verification_by_user_id(executor, &user_id).await?;
+    let verification = verifications.get_latest_verification_by_user_id(&user_id).await?;
 
     // Check if the code matches
     if verification.code != code {
@@ -47,10 +44,10 @@ pub async fn verify_code_and_update_user(executor: &Executor, user_id: Id, code:
     }
 
     // Delete the verification code
-    delete_verification_by_id(executor, &verification.id).await?;
+    verifications.delete_verification_by_id(&verification.id).await?;
 
     // Verify the user's email and return the updated user
-    let updated_user = verify_user(executor, &user_id).await?;
+    let updated_user = users.verify_user(&user_id).await?;
 
     Ok(updated_user)
-}
\ No newline at end of file
+}
@@ -0,0 +1,921 @@
+use super::{AuditCheckpoint, AuditEvent, AuditQuery, AuditStore, ChainHead, Error, Id, LocalBoxFuture, LocalBoxStream, LoginFailureStore, Result, User, UserQuery, UserStore, Uuid, Value, Verification, VerificationStore};
+use super::{Access, AccountRestoration, AccountRestorationStore, ApiKey, ApiKeyStore, Bucket, Decision, Identity, IdentityStore, Invitation, Membership, Organization, OrganizationRole, OrganizationStore};
+use super::{Page, PasswordHashReport, PasswordReset, PasswordResetStore, RateLimitStore, Role, RoleStore, TakeToken};
+use crate::{EmailAddress, ADMIN_ROLE, PERMISSION_ALL};
+use futures_util::{stream, StreamExt};
+use chrono::{DateTime, Duration, Utc};
+use actix_web::http::StatusCode;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+
+///Keeps everything in memory, for running the server without a database in tests.
+/// It starts out with the admin role, like a migrated database.
+#[derive(Debug)]
+pub struct MemoryStore {
+    users: Mutex<Vec<StoredUser>>,
+    verifications: Mutex<Vec<Verification>>,
+    login_failures: Mutex<HashMap<String, LoginFailures>>,
+    audit_events: Mutex<Vec<AuditEvent>>,
+    audit_checkpoints: Mutex<Vec<AuditCheckpoint>>,
+    roles: Mutex<Vec<Role>>,
+    user_roles: Mutex<Vec<UserRole>>,
+    organizations: Mutex<Vec<Organization>>,
+    memberships: Mutex<Vec<Membership>>,
+    invitations: Mutex<Vec<Invitation>>,
+    api_keys: Mutex<Vec<ApiKey>>,
+    identities: Mutex<Vec<Identity>>,
+    saml_assertions: Mutex<HashMap<String, DateTime<Utc>>>,
+    password_resets: Mutex<Vec<PasswordReset>>,
+    account_restorations: Mutex<Vec<AccountRestoration>>,
+    rate_limits: Mutex<HashMap<String, (Bucket, DateTime<Utc>)>>,
+}
+
+
+impl Default for MemoryStore {
+    fn default() -> Self {
+        let admin = Role{name: ADMIN_ROLE.into(), description: "Has every permission.".into(), permissions: vec![PERMISSION_ALL.into()], created_at: Utc::now()};
+        Self {
+            users: Default::default(),
+            verifications: Default::default(),
+            login_failures: Default::default(),
+            audit_events: Default::default(),
+            audit_checkpoints: Default::default(),
+            roles: Mutex::new(vec![admin]),
+            user_roles: Default::default(),
+            organizations: Default::default(),
+            memberships: Default::default(),
+            invitations: Default::default(),
+            api_keys: Default::default(),
+            identities: Default::default(),
+            saml_assertions: Default::default(),
+            password_resets: Default::default(),
+            account_restorations: Default::default(),
+            rate_limits: Default::default(),
+        }
+    }
+}
+
+
+#[derive(Debug)]
+struct StoredUser {
+    canonical_email: String,
+    user: User,
+    ///When a deleted user is purged. Nothing while the user is not deleted.
+    purge_after: Option<DateTime<Utc>>,
+}
+
+
+#[derive(Debug)]
+struct UserRole {
+    user_id: Id,
+    role: String,
+    ///The directory the role is mapped from. Nothing for the roles assigned by hand.
+    source: Option<String>,
+}
+
+
+#[derive(Debug)]
+struct LoginFailures {
+    failures: i32,
+    last_failed_at: DateTime<Utc>,
+    locked_until: Option<DateTime<Utc>>,
+}
+
+
+fn without_password(user: &User) -> User {
+    User{password: Default::default(), ..user.clone()}
+}
+
+
+///Sorts the permissions and drops the repeated ones, as they are kept in a database.
+fn with_permissions(role: Role, permissions: &[String]) -> Role {
+    let mut permissions = permissions.to_vec();
+    permissions.sort();
+    permissions.dedup();
+    Role{permissions, ..role}
+}
+
+
+fn membership_not_found() -> Error {
+    Error::Custom(StatusCode::NOT_FOUND, "membership not found".into())
+}
+
+
+impl MemoryStore {
+    ///Returns the first user who is not deleted for whom `f` holds.
+    fn find_user(&self, f: impl Fn(&StoredUser) -> bool) -> Result<User> {
+        self.users.lock().unwrap().iter().find(|stored| stored.purge_after.is_none() && f(stored)).map(|stored| stored.user.clone()).ok_or(Error::UserNotFound)
+    }
+
+    fn update_user(&self, id: &Id, f: impl FnOnce(&mut User) -> Result<()>) -> Result<User> {
+        let mut users = self.users.lock().unwrap();
+        let user = users.iter_mut().find(|stored| stored.purge_after.is_none() && stored.user.id == *id).map(|stored| &mut stored.user).ok_or(Error::UserNotFound)?;
+        let mut updated = user.clone();
+        f(&mut updated)?;
+        *user = updated;
+        Ok(without_password(user))
+    }
+
+    fn is_deleted(&self, id: &Id) -> bool {
+        !self.users.lock().unwrap().iter().any(|stored| stored.purge_after.is_none() && stored.user.id == *id)
+    }
+
+    ///The names of the roles of a user, whether they are deleted or not.
+    fn role_names(&self, user_id: &Id) -> Vec<String> {
+        self.user_roles.lock().unwrap().iter().filter(|user_role| user_role.user_id == *user_id).map(|user_role| user_role.role.clone()).collect()
+    }
+
+    fn roles_named(&self, names: &[String]) -> Vec<Role> {
+        let mut roles: Vec<Role> = self.roles.lock().unwrap().iter().filter(|role| names.contains(&role.name)).cloned().collect();
+        roles.sort_by(|a, b| a.name.cmp(&b.name));
+        roles
+    }
+
+    fn delete_organizations(&self, ids: &[Id]) {
+        self.organizations.lock().unwrap().retain(|organization| !ids.contains(&organization.id));
+        self.memberships.lock().unwrap().retain(|membership| !ids.contains(&membership.organization_id));
+        self.invitations.lock().unwrap().retain(|invitation| !ids.contains(&invitation.organization_id));
+    }
+
+    ///Deletes the organizations the user is the only member of, and hands over the ones they are the only owner of.
+    fn release_sole_ownerships(&self, user_id: &Id) {
+        let mut memberships = self.memberships.lock().unwrap();
+        let organization_ids: Vec<Id> = memberships.iter().filter(|membership| membership.user_id == *user_id).map(|membership| membership.organization_id.clone()).collect();
+        let mut deleted = Vec::new();
+        for organization_id in organization_ids {
+            let others = || memberships.iter().filter(|membership| membership.organization_id == organization_id && membership.user_id != *user_id);
+            if others().next().is_none() {
+                deleted.push(organization_id);
+                continue;
+            }
+            let sole_owner = memberships.iter().any(|membership| membership.organization_id == organization_id && membership.user_id == *user_id && membership.role == OrganizationRole::Owner)
+                && !others().any(|membership| membership.role == OrganizationRole::Owner);
+            if !sole_owner {
+                continue;
+            }
+            let heir = others().min_by_key(|membership| (membership.role != OrganizationRole::Admin, membership.created_at)).map(|membership| membership.user_id.clone());
+            if let Some(membership) = memberships.iter_mut().find(|membership| membership.organization_id == organization_id && Some(&membership.user_id) == heir.as_ref()) {
+                membership.role = OrganizationRole::Owner;
+            }
+        }
+        drop(memberships);
+        self.delete_organizations(&deleted);
+    }
+}
+
+
+impl UserStore for MemoryStore {
+    fn create_user<'a>(&'a self, user: &'a User, canonical_email: &'a str) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let mut users = self.users.lock().unwrap();
+            if users.iter().any(|stored| stored.canonical_email == canonical_email) {
+                return Err(Error::UserWithEmailExists);
+            }
+            let user = User{email_verified_at: user.verified_at(), ..user.clone()};
+            users.push(StoredUser{canonical_email: canonical_email.to_string(), user, purge_after: None});
+            Ok(())
+        })
+    }
+
+    fn get_user_by_id<'a>(&'a self, id: &'a Id) -> LocalBoxFuture<'a, Result<User>> {
+        Box::pin(async move { self.find_user(|stored| stored.user.id == *id).map(|user| without_password(&user)) })
+    }
+
+    fn get_user_by_email<'a>(&'a self, email: &'a str) -> LocalBoxFuture<'a, Result<User>> {
+        Box::pin(async move { self.find_user(|stored| stored.canonical_email == email).map(|user| without_password(&user)) })
+    }
+
+    fn get_user_with_password_by_id<'a>(&'a self, id: &'a Id) -> LocalBoxFuture<'a, Result<User>> {
+        Box::pin(async move { self.find_user(|stored| stored.user.id == *id) })
+    }
+
+    fn get_user_with_password_by_email<'a>(&'a self, email: &'a str) -> LocalBoxFuture<'a, Result<User>> {
+        Box::pin(async move { self.find_user(|stored| stored.canonical_email == email) })
+    }
+
+    fn set_password<'a>(&'a self, id: &'a Id, password_hash: &'a str) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            self.update_user(id, |user| {
+                user.password = password_hash.into();
+                Ok(())
+            })?;
+            Ok(())
+        })
+    }
+
+    fn update_user_by_id<'a>(&'a self, id: &'a Id, map: &'a HashMap<&'a str, Value>) -> LocalBoxFuture<'a, Result<User>> {
+        Box::pin(async move {
+            if map.is_empty() {
+                return Err(Error::Custom(StatusCode::BAD_REQUEST, "No Data to Update. Please provide fields and values to be updated".into()));
+            }
+            self.update_user(id, |user| {
+                for (key, value) in map {
+                    let field = match *key {
+                        "user_name" => &mut user.user_name,
+                        "first_name" => &mut user.first_name,
+                        "last_name" => &mut user.last_name,
+                        _ => return Err(Error::Custom(StatusCode::BAD_REQUEST, format!("{} can not be updated", key).into())),
+                    };
+                    match value {
+                        Value::String(value) => *field = value.clone(),
+                        _ => return Err(Error::Custom(StatusCode::BAD_REQUEST, format!("{} has to be a string", key).into())),
+                    }
+                }
+                Ok(())
+            })
+        })
+    }
+
+    fn change_email<'a>(&'a self, id: &'a Id, email: &'a str, canonical_email: &'a str) -> LocalBoxFuture<'a, Result<User>> {
+        Box::pin(async move {
+            let address = email.parse().map_err(|_| Error::Custom(StatusCode::BAD_REQUEST, "invalid email".into()))?;
+            let mut users = self.users.lock().unwrap();
+            if users.iter().any(|stored| stored.canonical_email == canonical_email && stored.user.id != *id) {
+                return Err(Error::UserWithEmailExists);
+            }
+            let stored = users.iter_mut().find(|stored| stored.purge_after.is_none() && stored.user.id == *id).ok_or(Error::UserNotFound)?;
+            stored.canonical_email = canonical_email.to_string();
+            stored.user.email = EmailAddress::New(address);
+            stored.user.email_verified_at = None;
+            Ok(without_password(&stored.user))
+        })
+    }
+
+    fn verify_user<'a>(&'a self, id: &'a Id) -> LocalBoxFuture<'a, Result<User>> {
+        Box::pin(async move {
+            let result = self.update_user(id, |user| {
+                user.email_verified_at = user.email_verified_at.or(Some(Utc::now()));
+                user.email = EmailAddress::Verified(user.email.address().clone());
+                Ok(())
+            });
+            match result {
+                Err(Error::UserNotFound) => Err(Error::Custom(StatusCode::NOT_FOUND, "the user you are trying to validate seems to be deleted".into())),
+                result => result
+            }
+        })
+    }
+
+    fn delete_user_by_id<'a>(&'a self, id: &'a Id, purge_after: DateTime<Utc>) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let mut users = self.users.lock().unwrap();
+            let stored = users.iter_mut().find(|stored| stored.purge_after.is_none() && stored.user.id == *id).ok_or(Error::UserNotFound)?;
+            stored.purge_after = Some(purge_after);
+            Ok(())
+        })
+    }
+
+    fn restore_user<'a>(&'a self, id: &'a Id) -> LocalBoxFuture<'a, Result<User>> {
+        Box::pin(async move {
+            let now = Utc::now();
+            let mut users = self.users.lock().unwrap();
+            let stored = users.iter_mut().find(|stored| stored.purge_after.is_some_and(|purge_after| purge_after > now) && stored.user.id == *id)
+                .ok_or(Error::UserNotFound)?;
+            stored.purge_after = None;
+            Ok(without_password(&stored.user))
+        })
+    }
+
+    fn purge_deleted_users<'a>(&'a self) -> LocalBoxFuture<'a, Result<u64>> {
+        Box::pin(async move {
+            let now = Utc::now();
+            let mut users = self.users.lock().unwrap();
+            let purged: Vec<Id> = users.iter().filter(|stored| stored.purge_after.is_some_and(|purge_after| purge_after <= now)).map(|stored| stored.user.id.clone()).collect();
+            users.retain(|stored| !purged.contains(&stored.user.id));
+            drop(users);
+            for id in &purged {
+                self.release_sole_ownerships(id);
+            }
+            self.verifications.lock().unwrap().retain(|verification| !purged.contains(&verification.user_id));
+            self.user_roles.lock().unwrap().retain(|user_role| !purged.contains(&user_role.user_id));
+            self.memberships.lock().unwrap().retain(|membership| !purged.contains(&membership.user_id));
+            self.invitations.lock().unwrap().retain(|invitation| !purged.contains(&invitation.invited_by));
+            self.api_keys.lock().unwrap().retain(|api_key| !purged.contains(&api_key.user_id));
+            self.identities.lock().unwrap().retain(|identity| !purged.contains(&identity.user_id));
+            self.password_resets.lock().unwrap().retain(|reset| !purged.contains(&reset.user_id));
+            self.account_restorations.lock().unwrap().retain(|restoration| !purged.contains(&restoration.user_id));
+            Ok(purged.len() as u64)
+        })
+    }
+
+    fn count_password_hashes<'a>(&'a self, current_prefix: &'a str) -> LocalBoxFuture<'a, Result<PasswordHashReport>> {
+        Box::pin(async move {
+            let users = self.users.lock().unwrap();
+            let passwords: Vec<&str> = users.iter().filter(|stored| stored.purge_after.is_none() && !stored.user.password.is_empty()).map(|stored| stored.user.password.as_str()).collect();
+            let outdated = passwords.iter().filter(|password| !password.starts_with(current_prefix)).count();
+            Ok(PasswordHashReport{total: passwords.len() as i64, outdated: outdated as i64})
+        })
+    }
+
+    fn get_users<'a>(&'a self, user_query: &'a UserQuery) -> LocalBoxFuture<'a, Result<Page<User>>> {
+        Box::pin(async move {
+            let users: Vec<User> = self.users.lock().unwrap().iter().filter(|stored| stored.purge_after.is_none()).map(|stored| without_password(&stored.user)).collect();
+            let users = users.into_iter().filter(|user| user_query.role.as_ref().is_none_or(|role| self.role_names(&user.id).contains(role)));
+            Ok(user_query.select(users))
+        })
+    }
+
+    fn stream_users<'a>(&'a self, with_password: bool) -> LocalBoxStream<'a, Result<User>> {
+        let mut users: Vec<User> = self.users.lock().unwrap().iter()
+            .filter(|stored| stored.purge_after.is_none())
+            .map(|stored| match with_password {
+                true => stored.user.clone(),
+                false => without_password(&stored.user),
+            })
+            .collect();
+        users.sort_by_key(|user| (user.created_at, user.id.bytes()));
+        stream::iter(users.into_iter().map(Ok)).boxed_local()
+    }
+
+    fn get_canonical_emails<'a>(&'a self) -> LocalBoxFuture<'a, Result<Vec<(Id, String, String)>>> {
+        Box::pin(async move {
+            let users = self.users.lock().unwrap();
+            let mut users: Vec<&StoredUser> = users.iter().collect();
+            users.sort_by_key(|stored| (stored.user.created_at, stored.user.id.bytes()));
+            Ok(users.into_iter().map(|stored| (stored.user.id.clone(), stored.user.email.address().to_string(), stored.canonical_email.clone())).collect())
+        })
+    }
+
+    fn set_canonical_emails<'a>(&'a self, changes: &'a [(Id, String)]) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let mut users = self.users.lock().unwrap();
+            let mut canonical_emails: Vec<String> = users.iter().map(|stored| stored.canonical_email.clone()).collect();
+            for (id, canonical_email) in changes {
+                if let Some(index) = users.iter().position(|stored| stored.user.id == *id) {
+                    canonical_emails[index] = canonical_email.clone();
+                }
+            }
+            let mut sorted = canonical_emails.clone();
+            sorted.sort();
+            if sorted.windows(2).any(|pair| pair[0] == pair[1]) {
+                return Err(Error::UserWithEmailExists);
+            }
+            for (stored, canonical_email) in users.iter_mut().zip(canonical_emails) {
+                stored.canonical_email = canonical_email;
+            }
+            Ok(())
+        })
+    }
+
+    fn update_user<'a>(&'a self, user: &'a User, canonical_email: &'a str) -> LocalBoxFuture<'a, Result<User>> {
+        Box::pin(async move {
+            let mut users = self.users.lock().unwrap();
+            if users.iter().any(|stored| stored.canonical_email == canonical_email && stored.user.id != user.id) {
+                return Err(Error::UserWithEmailExists);
+            }
+            let stored = users.iter_mut().find(|stored| stored.purge_after.is_none() && stored.user.id == user.id).ok_or(Error::UserNotFound)?;
+            stored.canonical_email = canonical_email.to_string();
+            stored.user = User {
+                email: user.email.clone(),
+                email_verified_at: user.verified_at(),
+                user_name: user.user_name.clone(),
+                first_name: user.first_name.clone(),
+                last_name: user.last_name.clone(),
+                profile_picture: user.profile_picture.clone(),
+                ..stored.user.clone()
+            };
+            Ok(without_password(&stored.user))
+        })
+    }
+}
+
+
+impl VerificationStore for MemoryStore {
+    fn create_verification_code<'a>(&'a self, verification: &'a Verification) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            self.verifications.lock().unwrap().push(verification.clone());
+            Ok(())
+        })
+    }
+
+    fn get_verification_by_id<'a>(&'a self, id: &'a Uuid) -> LocalBoxFuture<'a, Result<Verification>> {
+        Box::pin(async move {
+            self.verifications.lock().unwrap().iter().find(|verification| verification.id == *id).cloned()
+                .ok_or_else(|| Error::Custom(StatusCode::NOT_FOUND, "Verification code not found".into()))
+        })
+    }
+
+    fn get_latest_verification_by_user_id<'a>(&'a self, user_id: &'a Id) -> LocalBoxFuture<'a, Result<Verification>> {
+        Box::pin(async move {
+            self.verifications.lock().unwrap().iter().filter(|verification| verification.user_id == *user_id)
+                .max_by_key(|verification| verification.created_at).cloned()
+                .ok_or_else(|| Error::Custom(StatusCode::NOT_FOUND, "No verification code found for the user".into()))
+        })
+    }
+
+    fn delete_verification_by_id<'a>(&'a self, id: &'a Uuid) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            self.verifications.lock().unwrap().retain(|verification| verification.id != *id);
+            Ok(())
+        })
+    }
+}
+
+
+impl LoginFailureStore for MemoryStore {
+    fn get_locked_until<'a>(&'a self, subjects: &'a [String]) -> LocalBoxFuture<'a, Result<Option<DateTime<Utc>>>> {
+        Box::pin(async move {
+            let now = Utc::now();
+            let login_failures = self.login_failures.lock().unwrap();
+            Ok(subjects.iter().filter_map(|subject| login_failures.get(subject)?.locked_until).filter(|until| *until > now).max())
+        })
+    }
+
+    fn record_failure<'a>(&'a self, subject: &'a str, window: i64) -> LocalBoxFuture<'a, Result<i32>> {
+        Box::pin(async move {
+            let now = Utc::now();
+            let mut login_failures = self.login_failures.lock().unwrap();
+            let entry = login_failures.entry(subject.to_string()).or_insert(LoginFailures{failures: 0, last_failed_at: now, locked_until: None});
+            let lock_ended = entry.locked_until.is_some_and(|until| until <= now);
+            entry.failures = match entry.last_failed_at < now - Duration::seconds(window) || lock_ended {
+                true => 1,
+                false => entry.failures + 1,
+            };
+            entry.last_failed_at = now;
+            entry.locked_until = entry.locked_until.filter(|until| *until > now);
+            Ok(entry.failures)
+        })
+    }
+
+    fn lock<'a>(&'a self, subject: &'a str, until: DateTime<Utc>) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            if let Some(entry) = self.login_failures.lock().unwrap().get_mut(subject) {
+                entry.locked_until = Some(until);
+            }
+            Ok(())
+        })
+    }
+
+    fn clear<'a>(&'a self, subject: &'a str) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            self.login_failures.lock().unwrap().remove(subject);
+            Ok(())
+        })
+    }
+}
+
+
+impl AuditStore for MemoryStore {
+    fn record_event<'a>(&'a self, event: &'a AuditEvent) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let mut events = self.audit_events.lock().unwrap();
+            let previous = events.last().map(|last| (last.sequence, last.hash.clone()));
+            events.push(event.clone().chained(previous));
+            Ok(())
+        })
+    }
+
+    fn get_events<'a>(&'a self, query: &'a AuditQuery) -> LocalBoxFuture<'a, Result<Vec<AuditEvent>>> {
+        Box::pin(async move {
+            Ok(query.select(self.audit_events.lock().unwrap().iter().cloned()))
+        })
+    }
+
+    fn get_chain_head<'a>(&'a self) -> LocalBoxFuture<'a, Result<Option<ChainHead>>> {
+        Box::pin(async move {
+            Ok(self.audit_events.lock().unwrap().last().map(|last| (last.sequence, last.hash.clone())))
+        })
+    }
+
+    fn get_chain<'a>(&'a self) -> LocalBoxFuture<'a, Result<Vec<AuditEvent>>> {
+        Box::pin(async move {
+            Ok(self.audit_events.lock().unwrap().clone())
+        })
+    }
+
+    fn record_checkpoint<'a>(&'a self, checkpoint: &'a AuditCheckpoint) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            self.audit_checkpoints.lock().unwrap().push(checkpoint.clone());
+            Ok(())
+        })
+    }
+
+    fn get_checkpoints<'a>(&'a self) -> LocalBoxFuture<'a, Result<Vec<AuditCheckpoint>>> {
+        Box::pin(async move {
+            Ok(self.audit_checkpoints.lock().unwrap().clone())
+        })
+    }
+}
+
+
+impl RoleStore for MemoryStore {
+    fn get_roles<'a>(&'a self) -> LocalBoxFuture<'a, Result<Vec<Role>>> {
+        Box::pin(async move {
+            let mut roles = self.roles.lock().unwrap().clone();
+            roles.sort_by(|a, b| a.name.cmp(&b.name));
+            Ok(roles)
+        })
+    }
+
+    fn get_role<'a>(&'a self, name: &'a str) -> LocalBoxFuture<'a, Result<Role>> {
+        Box::pin(async move {
+            self.roles.lock().unwrap().iter().find(|role| role.name == name).cloned().ok_or(Error::RoleNotFound)
+        })
+    }
+
+    fn create_role<'a>(&'a self, role: &'a Role) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let mut roles = self.roles.lock().unwrap();
+            if roles.iter().any(|existing| existing.name == role.name) {
+                return Err(Error::Custom(StatusCode::CONFLICT, "a role with the same name already exists".into()));
+            }
+            roles.push(with_permissions(role.clone(), &role.permissions));
+            Ok(())
+        })
+    }
+
+    fn update_role<'a>(&'a self, name: &'a str, description: Option<&'a str>, permissions: Option<&'a [String]>) -> LocalBoxFuture<'a, Result<Role>> {
+        Box::pin(async move {
+            let mut roles = self.roles.lock().unwrap();
+            let role = roles.iter_mut().find(|role| role.name == name).ok_or(Error::RoleNotFound)?;
+            if let Some(description) = description {
+                role.description = description.to_string();
+            }
+            if let Some(permissions) = permissions {
+                *role = with_permissions(role.clone(), permissions);
+            }
+            Ok(role.clone())
+        })
+    }
+
+    fn delete_role<'a>(&'a self, name: &'a str) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let mut roles = self.roles.lock().unwrap();
+            let count = roles.len();
+            roles.retain(|role| role.name != name);
+            if roles.len() == count {
+                return Err(Error::RoleNotFound);
+            }
+            self.user_roles.lock().unwrap().retain(|user_role| user_role.role != name);
+            Ok(())
+        })
+    }
+
+    fn get_user_roles<'a>(&'a self, user_id: &'a Id) -> LocalBoxFuture<'a, Result<Vec<Role>>> {
+        Box::pin(async move { Ok(self.roles_named(&self.role_names(user_id))) })
+    }
+
+    fn assign_role<'a>(&'a self, user_id: &'a Id, role: &'a str) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let user_exists = self.users.lock().unwrap().iter().any(|stored| stored.user.id == *user_id);
+            let role_exists = self.roles.lock().unwrap().iter().any(|existing| existing.name == role);
+            if !user_exists || !role_exists {
+                return Err(Error::Custom(StatusCode::NOT_FOUND, "user or role not found".into()));
+            }
+            let mut user_roles = self.user_roles.lock().unwrap();
+            match user_roles.iter_mut().find(|user_role| user_role.user_id == *user_id && user_role.role == role) {
+                Some(user_role) => user_role.source = None,
+                None => user_roles.push(UserRole{user_id: user_id.clone(), role: role.to_string(), source: None}),
+            }
+            Ok(())
+        })
+    }
+
+    fn sync_source_roles<'a>(&'a self, user_id: &'a Id, source: &'a str, roles: &'a [String]) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let existing: Vec<String> = self.roles.lock().unwrap().iter().filter(|role| roles.contains(&role.name)).map(|role| role.name.clone()).collect();
+            let mut user_roles = self.user_roles.lock().unwrap();
+            user_roles.retain(|user_role| user_role.user_id != *user_id || user_role.source.as_deref() != Some(source) || roles.contains(&user_role.role));
+            for role in existing {
+                if !user_roles.iter().any(|user_role| user_role.user_id == *user_id && user_role.role == role) {
+                    user_roles.push(UserRole{user_id: user_id.clone(), role, source: Some(source.to_string())});
+                }
+            }
+            Ok(())
+        })
+    }
+
+    fn unassign_role<'a>(&'a self, user_id: &'a Id, role: &'a str) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            self.user_roles.lock().unwrap().retain(|user_role| user_role.user_id != *user_id || user_role.role != role);
+            Ok(())
+        })
+    }
+
+    fn has_permission<'a>(&'a self, user_id: &'a Id, permission: &'a str) -> LocalBoxFuture<'a, Result<bool>> {
+        Box::pin(async move {
+            let access = self.get_user_access(user_id).await?;
+            Ok(access.permissions.iter().any(|granted| granted == permission || granted == PERMISSION_ALL))
+        })
+    }
+
+    fn get_user_access<'a>(&'a self, user_id: &'a Id) -> LocalBoxFuture<'a, Result<Access>> {
+        Box::pin(async move {
+            let mut access = Access::default();
+            if self.is_deleted(user_id) {
+                return Ok(access);
+            }
+            for role in self.roles_named(&self.role_names(user_id)) {
+                for permission in role.permissions {
+                    if !access.permissions.contains(&permission) {
+                        access.permissions.push(permission);
+                    }
+                }
+                access.roles.push(role.name);
+            }
+            Ok(access)
+        })
+    }
+}
+
+
+impl OrganizationStore for MemoryStore {
+    fn create_organization<'a>(&'a self, organization: &'a Organization, owner: &'a Id) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            self.organizations.lock().unwrap().push(organization.clone());
+            self.memberships.lock().unwrap().push(Membership{organization_id: organization.id.clone(), user_id: owner.clone(), role: OrganizationRole::Owner, created_at: Utc::now()});
+            Ok(())
+        })
+    }
+
+    fn get_organization<'a>(&'a self, id: &'a Id) -> LocalBoxFuture<'a, Result<Organization>> {
+        Box::pin(async move {
+            self.organizations.lock().unwrap().iter().find(|organization| organization.id == *id).cloned().ok_or(Error::OrganizationNotFound)
+        })
+    }
+
+    fn get_user_organizations<'a>(&'a self, user_id: &'a Id) -> LocalBoxFuture<'a, Result<Vec<Organization>>> {
+        Box::pin(async move {
+            let ids: Vec<Id> = self.memberships.lock().unwrap().iter().filter(|membership| membership.user_id == *user_id).map(|membership| membership.organization_id.clone()).collect();
+            let mut organizations: Vec<Organization> = self.organizations.lock().unwrap().iter().filter(|organization| ids.contains(&organization.id)).cloned().collect();
+            organizations.sort_by_key(|organization| organization.created_at);
+            Ok(organizations)
+        })
+    }
+
+    fn update_organization<'a>(&'a self, id: &'a Id, name: &'a str) -> LocalBoxFuture<'a, Result<Organization>> {
+        Box::pin(async move {
+            let mut organizations = self.organizations.lock().unwrap();
+            let organization = organizations.iter_mut().find(|organization| organization.id == *id).ok_or(Error::OrganizationNotFound)?;
+            organization.name = name.to_string();
+            Ok(organization.clone())
+        })
+    }
+
+    fn delete_organization<'a>(&'a self, id: &'a Id) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            if !self.organizations.lock().unwrap().iter().any(|organization| organization.id == *id) {
+                return Err(Error::OrganizationNotFound);
+            }
+            self.delete_organizations(std::slice::from_ref(id));
+            Ok(())
+        })
+    }
+
+    fn get_membership<'a>(&'a self, organization_id: &'a Id, user_id: &'a Id) -> LocalBoxFuture<'a, Result<Option<Membership>>> {
+        Box::pin(async move {
+            Ok(self.memberships.lock().unwrap().iter().find(|membership| membership.organization_id == *organization_id && membership.user_id == *user_id).cloned())
+        })
+    }
+
+    fn get_memberships<'a>(&'a self, organization_id: &'a Id) -> LocalBoxFuture<'a, Result<Vec<Membership>>> {
+        Box::pin(async move {
+            let mut memberships: Vec<Membership> = self.memberships.lock().unwrap().iter().filter(|membership| membership.organization_id == *organization_id).cloned().collect();
+            memberships.sort_by_key(|membership| membership.created_at);
+            Ok(memberships)
+        })
+    }
+
+    fn update_membership_role<'a>(&'a self, organization_id: &'a Id, user_id: &'a Id, role: OrganizationRole) -> LocalBoxFuture<'a, Result<Membership>> {
+        Box::pin(async move {
+            let mut memberships = self.memberships.lock().unwrap();
+            let membership = memberships.iter_mut().find(|membership| membership.organization_id == *organization_id && membership.user_id == *user_id).ok_or_else(membership_not_found)?;
+            membership.role = role;
+            Ok(membership.clone())
+        })
+    }
+
+    fn delete_membership<'a>(&'a self, organization_id: &'a Id, user_id: &'a Id) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let mut memberships = self.memberships.lock().unwrap();
+            let count = memberships.len();
+            memberships.retain(|membership| membership.organization_id != *organization_id || membership.user_id != *user_id);
+            match memberships.len() == count {
+                true => Err(membership_not_found()),
+                false => Ok(())
+            }
+        })
+    }
+
+    fn count_owners<'a>(&'a self, organization_id: &'a Id) -> LocalBoxFuture<'a, Result<i64>> {
+        Box::pin(async move {
+            Ok(self.memberships.lock().unwrap().iter().filter(|membership| membership.organization_id == *organization_id && membership.role == OrganizationRole::Owner).count() as i64)
+        })
+    }
+
+    fn create_invitation<'a>(&'a self, invitation: &'a Invitation) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            self.invitations.lock().unwrap().push(invitation.clone());
+            Ok(())
+        })
+    }
+
+    fn get_invitation<'a>(&'a self, id: &'a Uuid) -> LocalBoxFuture<'a, Result<Invitation>> {
+        Box::pin(async move {
+            let now = Utc::now();
+            self.invitations.lock().unwrap().iter().find(|invitation| invitation.id == *id && invitation.expires_at > now).cloned()
+                .ok_or_else(|| Error::Custom(StatusCode::NOT_FOUND, "invitation not found".into()))
+        })
+    }
+
+    fn get_invitations_by_email<'a>(&'a self, email: &'a str) -> LocalBoxFuture<'a, Result<Vec<Invitation>>> {
+        Box::pin(async move {
+            let now = Utc::now();
+            let mut invitations: Vec<Invitation> = self.invitations.lock().unwrap().iter()
+                .filter(|invitation| invitation.email.to_lowercase() == email.to_lowercase() && invitation.expires_at > now).cloned().collect();
+            invitations.sort_by_key(|invitation| invitation.created_at);
+            Ok(invitations)
+        })
+    }
+
+    fn get_invitations_by_organization<'a>(&'a self, organization_id: &'a Id) -> LocalBoxFuture<'a, Result<Vec<Invitation>>> {
+        Box::pin(async move {
+            let now = Utc::now();
+            let mut invitations: Vec<Invitation> = self.invitations.lock().unwrap().iter()
+                .filter(|invitation| invitation.organization_id == *organization_id && invitation.expires_at > now).cloned().collect();
+            invitations.sort_by_key(|invitation| invitation.created_at);
+            Ok(invitations)
+        })
+    }
+
+    fn delete_invitation<'a>(&'a self, id: &'a Uuid) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            self.invitations.lock().unwrap().retain(|invitation| invitation.id != *id);
+            Ok(())
+        })
+    }
+
+    fn accept_invitation<'a>(&'a self, invitation: &'a Invitation, user_id: &'a Id) -> LocalBoxFuture<'a, Result<Membership>> {
+        Box::pin(async move {
+            let mut memberships = self.memberships.lock().unwrap();
+            let membership = match memberships.iter_mut().find(|membership| membership.organization_id == invitation.organization_id && membership.user_id == *user_id) {
+                Some(membership) => {
+                    membership.role = membership.role.max(invitation.role);
+                    membership.clone()
+                },
+                None => {
+                    let membership = Membership{organization_id: invitation.organization_id.clone(), user_id: user_id.clone(), role: invitation.role, created_at: Utc::now()};
+                    memberships.push(membership.clone());
+                    membership
+                },
+            };
+            self.invitations.lock().unwrap().retain(|existing| existing.id != invitation.id);
+            Ok(membership)
+        })
+    }
+}
+
+
+impl ApiKeyStore for MemoryStore {
+    fn create_api_key<'a>(&'a self, api_key: &'a ApiKey) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            self.api_keys.lock().unwrap().push(api_key.clone());
+            Ok(())
+        })
+    }
+
+    fn get_api_key_by_prefix<'a>(&'a self, prefix: &'a str) -> LocalBoxFuture<'a, Result<Option<ApiKey>>> {
+        Box::pin(async move {
+            let api_key = self.api_keys.lock().unwrap().iter().find(|api_key| api_key.prefix == prefix).cloned();
+            Ok(api_key.filter(|api_key| !self.is_deleted(&api_key.user_id)))
+        })
+    }
+
+    fn get_api_keys_by_user_id<'a>(&'a self, user_id: &'a Id) -> LocalBoxFuture<'a, Result<Vec<ApiKey>>> {
+        Box::pin(async move {
+            let mut api_keys: Vec<ApiKey> = self.api_keys.lock().unwrap().iter().filter(|api_key| api_key.user_id == *user_id).cloned().collect();
+            api_keys.sort_by_key(|api_key| api_key.created_at);
+            Ok(api_keys)
+        })
+    }
+
+    fn revoke_api_key<'a>(&'a self, user_id: &'a Id, id: &'a Uuid) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let mut api_keys = self.api_keys.lock().unwrap();
+            let api_key = api_keys.iter_mut().find(|api_key| api_key.id == *id && api_key.user_id == *user_id)
+                .ok_or_else(|| Error::Custom(StatusCode::NOT_FOUND, "api key not found".into()))?;
+            api_key.revoked_at = api_key.revoked_at.or(Some(Utc::now()));
+            Ok(())
+        })
+    }
+
+    fn touch_api_key<'a>(&'a self, id: &'a Uuid, used_at: DateTime<Utc>) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            if let Some(api_key) = self.api_keys.lock().unwrap().iter_mut().find(|api_key| api_key.id == *id) {
+                api_key.last_used_at = Some(used_at);
+            }
+            Ok(())
+        })
+    }
+}
+
+
+impl IdentityStore for MemoryStore {
+    fn create_identity<'a>(&'a self, identity: &'a Identity) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let mut identities = self.identities.lock().unwrap();
+            if identities.iter().any(|existing| existing.provider == identity.provider && existing.subject == identity.subject) {
+                return Err(Error::Custom(StatusCode::CONFLICT, "the identity is linked to another account".into()));
+            }
+            identities.push(identity.clone());
+            Ok(())
+        })
+    }
+
+    fn get_identity<'a>(&'a self, provider: &'a str, subject: &'a str) -> LocalBoxFuture<'a, Result<Option<Identity>>> {
+        Box::pin(async move {
+            Ok(self.identities.lock().unwrap().iter().find(|identity| identity.provider == provider && identity.subject == subject).cloned())
+        })
+    }
+
+    fn get_identities_by_user_id<'a>(&'a self, user_id: &'a Id) -> LocalBoxFuture<'a, Result<Vec<Identity>>> {
+        Box::pin(async move {
+            let mut identities: Vec<Identity> = self.identities.lock().unwrap().iter().filter(|identity| identity.user_id == *user_id).cloned().collect();
+            identities.sort_by_key(|identity| identity.created_at);
+            Ok(identities)
+        })
+    }
+
+    fn record_assertion<'a>(&'a self, id: &'a str, expires_at: &'a DateTime<Utc>) -> LocalBoxFuture<'a, Result<bool>> {
+        Box::pin(async move {
+            let now = Utc::now();
+            let mut assertions = self.saml_assertions.lock().unwrap();
+            assertions.retain(|_, expires_at| *expires_at >= now);
+            match assertions.contains_key(id) {
+                true => Ok(false),
+                false => {
+                    assertions.insert(id.to_string(), *expires_at);
+                    Ok(true)
+                }
+            }
+        })
+    }
+}
+
+
+impl PasswordResetStore for MemoryStore {
+    fn create_password_reset<'a>(&'a self, reset: &'a PasswordReset) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            self.password_resets.lock().unwrap().push(reset.clone());
+            Ok(())
+        })
+    }
+
+    fn get_password_reset<'a>(&'a self, id: &'a Uuid) -> LocalBoxFuture<'a, Result<Option<PasswordReset>>> {
+        Box::pin(async move {
+            let now = Utc::now();
+            Ok(self.password_resets.lock().unwrap().iter().find(|reset| reset.id == *id && reset.expires_at > now).cloned())
+        })
+    }
+
+    fn delete_password_resets_by_user_id<'a>(&'a self, user_id: &'a Id) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            self.password_resets.lock().unwrap().retain(|reset| reset.user_id != *user_id);
+            Ok(())
+        })
+    }
+}
+
+
+impl AccountRestorationStore for MemoryStore {
+    fn create_account_restoration<'a>(&'a self, restoration: &'a AccountRestoration) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            self.account_restorations.lock().unwrap().push(restoration.clone());
+            Ok(())
+        })
+    }
+
+    fn get_account_restoration<'a>(&'a self, id: &'a Uuid) -> LocalBoxFuture<'a, Result<Option<AccountRestoration>>> {
+        Box::pin(async move {
+            let now = Utc::now();
+            Ok(self.account_restorations.lock().unwrap().iter().find(|restoration| restoration.id == *id && restoration.expires_at > now).cloned())
+        })
+    }
+
+    fn delete_account_restorations_by_user_id<'a>(&'a self, user_id: &'a Id) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            self.account_restorations.lock().unwrap().retain(|restoration| restoration.user_id != *user_id);
+            Ok(())
+        })
+    }
+}
+
+
+impl RateLimitStore for MemoryStore {
+    fn take<'a>(&'a self, key: &'a str, take: TakeToken<'a>) -> LocalBoxFuture<'a, Result<Decision>> {
+        Box::pin(async move {
+            let mut rate_limits = self.rate_limits.lock().unwrap();
+            let (bucket, full_at, decision) = take(rate_limits.get(key).map(|(bucket, _)| *bucket));
+            rate_limits.insert(key.to_string(), (bucket, full_at));
+            Ok(decision)
+        })
+    }
+
+    fn delete_full<'a>(&'a self) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let now = Utc::now();
+            self.rate_limits.lock().unwrap().retain(|_, (_, full_at)| *full_at > now);
+            Ok(())
+        })
+    }
+}
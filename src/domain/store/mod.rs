@@ -0,0 +1,552 @@
+use futures_util::{future::LocalBoxFuture, stream::LocalBoxStream};
+use chrono::{DateTime, Utc};
+use super::{AuditCheckpoint, AuditEvent, ChainHead, Cursor, Error, Id, Page, PasswordHashReport, SortOrder, User, Value, Verification};
+use super::{Access, AccountRestoration, ApiKey, Identity, Invitation, Membership, Organization, OrganizationRole, PasswordReset, Role};
+use super::rate_limit::{Bucket, Decision};
+use std::collections::HashMap;
+use sqlx::types::Uuid;
+
+mod postgres;
+mod memory;
+mod sqlite;
+
+pub use memory::MemoryStore;
+
+type Result<T> = std::result::Result<T, Error>;
+
+
+///Where the accounts of the users are kept.
+/// Users are returned without their password hash, except by the `with_password` lookups.
+/// Users are found by the canonical form of their email, see `EmailNormalization`.
+/// Deleted users are hidden from every method but `restore_user` and `purge_deleted_users`.
+pub trait UserStore: Send + Sync {
+    ///Fails with `Error::UserWithEmailExists` when another user has the canonical email.
+    fn create_user<'a>(&'a self, user: &'a User, canonical_email: &'a str) -> LocalBoxFuture<'a, Result<()>>;
+
+    fn get_user_by_id<'a>(&'a self, id: &'a Id) -> LocalBoxFuture<'a, Result<User>>;
+
+    fn get_user_by_email<'a>(&'a self, email: &'a str) -> LocalBoxFuture<'a, Result<User>>;
+
+    fn get_user_with_password_by_id<'a>(&'a self, id: &'a Id) -> LocalBoxFuture<'a, Result<User>>;
+
+    fn get_user_with_password_by_email<'a>(&'a self, email: &'a str) -> LocalBoxFuture<'a, Result<User>>;
+
+    fn set_password<'a>(&'a self, id: &'a Id, password_hash: &'a str) -> LocalBoxFuture<'a, Result<()>>;
+
+    ///Sets the given columns of a user and returns the user as updated.
+    fn update_user_by_id<'a>(&'a self, id: &'a Id, map: &'a HashMap<&'a str, Value>) -> LocalBoxFuture<'a, Result<User>>;
+
+    ///Replaces the email of a user with one that has not been verified yet.
+    /// Fails with `Error::UserWithEmailExists` when another user has the canonical email.
+    fn change_email<'a>(&'a self, id: &'a Id, email: &'a str, canonical_email: &'a str) -> LocalBoxFuture<'a, Result<User>>;
+
+    ///Marks the email of a user as verified.
+    fn verify_user<'a>(&'a self, id: &'a Id) -> LocalBoxFuture<'a, Result<User>>;
+
+    ///Marks a user as deleted. They can be restored until they are purged after the given time.
+    fn delete_user_by_id<'a>(&'a self, id: &'a Id, purge_after: DateTime<Utc>) -> LocalBoxFuture<'a, Result<()>>;
+
+    ///Brings back a deleted user who has not been purged yet.
+    fn restore_user<'a>(&'a self, id: &'a Id) -> LocalBoxFuture<'a, Result<User>>;
+
+    ///Deletes for good the users past their grace period, along with everything of theirs.
+    /// The organizations they solely own are handed over or deleted first, see `OrganizationStore`.
+    /// Returns how many were purged.
+    fn purge_deleted_users<'a>(&'a self) -> LocalBoxFuture<'a, Result<u64>>;
+
+    ///Counts the users with a password and those whose hash does not start with the given prefix.
+    fn count_password_hashes<'a>(&'a self, current_prefix: &'a str) -> LocalBoxFuture<'a, Result<PasswordHashReport>>;
+
+    ///Lists the users matching the query, one page at a time.
+    fn get_users<'a>(&'a self, user_query: &'a UserQuery) -> LocalBoxFuture<'a, Result<Page<User>>>;
+
+    ///Streams every user, oldest first, as they are read.
+    fn stream_users<'a>(&'a self, with_password: bool) -> LocalBoxStream<'a, Result<User>>;
+
+    ///Returns the id, email and canonical email of every user, oldest first.
+    /// The deleted users are included, their emails stay taken until they are purged.
+    fn get_canonical_emails<'a>(&'a self) -> LocalBoxFuture<'a, Result<Vec<(Id, String, String)>>>;
+
+    ///Sets the canonical emails of users, all of them or none.
+    /// A user may take the canonical email another one gives up in the same changes.
+    fn set_canonical_emails<'a>(&'a self, changes: &'a [(Id, String)]) -> LocalBoxFuture<'a, Result<()>>;
+
+    ///Overwrites the email, names and profile picture of a user with the values in the given user.
+    /// Fails with `Error::UserWithEmailExists` when another user has the canonical email.
+    fn update_user<'a>(&'a self, user: &'a User, canonical_email: &'a str) -> LocalBoxFuture<'a, Result<User>>;
+}
+
+
+///The filters, search and position of a user listing.
+#[derive(Clone, Debug, Default)]
+pub struct UserQuery {
+    pub verified: Option<bool>,
+    pub created_after: Option<DateTime<Utc>>,
+    pub created_before: Option<DateTime<Utc>>,
+    pub role: Option<String>,
+    ///Matched case insensitively against the email, user name, first name and last name.
+    pub search: Option<String>,
+    pub order: SortOrder,
+    pub cursor: Option<Cursor>,
+    pub limit: i64,
+}
+
+
+impl UserQuery {
+    ///Whether the user matches the query, but for its role, which the stores check on their own.
+    fn matches(&self, user: &User) -> bool {
+        let position = (user.created_at, user.id.bytes());
+        let search = self.search.as_ref().map(|search| search.to_lowercase());
+        let after_cursor = |cursor: &Cursor| match self.order {
+            SortOrder::Asc => position > (cursor.created_at, cursor.id.bytes()),
+            SortOrder::Desc => position < (cursor.created_at, cursor.id.bytes()),
+        };
+        self.verified.is_none_or(|verified| user.verified_at().is_some() == verified)
+            && self.created_after.is_none_or(|created_after| user.created_at >= created_after)
+            && self.created_before.is_none_or(|created_before| user.created_at < created_before)
+            && search.as_ref().is_none_or(|search| {
+                let email = user.email.address().to_string();
+                [&email, &user.user_name, &user.first_name, &user.last_name].iter().any(|value| value.to_lowercase().contains(search))
+            })
+            && self.cursor.as_ref().is_none_or(after_cursor)
+    }
+
+    ///Picks the page of users matching the query among the given ones, for the stores that can not filter them on their own.
+    fn select(&self, users: impl IntoIterator<Item = User>) -> Page<User> {
+        let mut users: Vec<User> = users.into_iter().filter(|user| self.matches(user)).collect();
+        users.sort_by_key(|user| (user.created_at, user.id.bytes()));
+        if self.order == SortOrder::Desc {
+            users.reverse();
+        }
+        users.truncate(self.limit.max(0) as usize + 1);
+        user_page(users, self.limit)
+    }
+}
+
+
+///Makes a page of the first `limit` users, given one more than that when there is a next page.
+fn user_page(mut users: Vec<User>, limit: i64) -> Page<User> {
+    let mut next_cursor = None;
+    if users.len() as i64 > limit {
+        users.truncate(limit.max(0) as usize);
+        next_cursor = users.last().map(|user| Cursor{created_at: user.created_at, id: user.id.clone()}.encode());
+    }
+    Page{items: users, next_cursor}
+}
+
+
+///Where the codes sent to verify emails are kept until they are used.
+pub trait VerificationStore: Send + Sync {
+    fn create_verification_code<'a>(&'a self, verification: &'a Verification) -> LocalBoxFuture<'a, Result<()>>;
+
+    fn get_verification_by_id<'a>(&'a self, id: &'a Uuid) -> LocalBoxFuture<'a, Result<Verification>>;
+
+    fn get_latest_verification_by_user_id<'a>(&'a self, user_id: &'a Id) -> LocalBoxFuture<'a, Result<Verification>>;
+
+    fn delete_verification_by_id<'a>(&'a self, id: &'a Uuid) -> LocalBoxFuture<'a, Result<()>>;
+}
+
+
+///Where the failed logins of accounts and addresses are counted, and their locks kept.
+pub trait LoginFailureStore: Send + Sync {
+    ///Returns the latest time any of the subjects is locked until, if one of them is locked.
+    fn get_locked_until<'a>(&'a self, subjects: &'a [String]) -> LocalBoxFuture<'a, Result<Option<DateTime<Utc>>>>;
+
+    ///Counts a failed login of the subject and returns its failures within the window of seconds.
+    /// The count starts over once the window has passed since the last failure or once a lock has ended.
+    fn record_failure<'a>(&'a self, subject: &'a str, window: i64) -> LocalBoxFuture<'a, Result<i32>>;
+
+    fn lock<'a>(&'a self, subject: &'a str, until: DateTime<Utc>) -> LocalBoxFuture<'a, Result<()>>;
+
+    ///Forgets the failures and the lock of the subject.
+    fn clear<'a>(&'a self, subject: &'a str) -> LocalBoxFuture<'a, Result<()>>;
+}
+
+
+///Where the roles, the permissions they grant and the roles of the users are kept.
+/// Deleted users keep their roles, but have no permission until they are restored.
+pub trait RoleStore: Send + Sync {
+    fn get_roles<'a>(&'a self) -> LocalBoxFuture<'a, Result<Vec<Role>>>;
+
+    fn get_role<'a>(&'a self, name: &'a str) -> LocalBoxFuture<'a, Result<Role>>;
+
+    ///Creates a role with its permissions. Fails with a conflict when a role with the same name exists.
+    fn create_role<'a>(&'a self, role: &'a Role) -> LocalBoxFuture<'a, Result<()>>;
+
+    ///Updates the description of a role and replaces its permissions with the given ones.
+    fn update_role<'a>(&'a self, name: &'a str, description: Option<&'a str>, permissions: Option<&'a [String]>) -> LocalBoxFuture<'a, Result<Role>>;
+
+    ///Deletes a role, and takes it away from the users who have it.
+    fn delete_role<'a>(&'a self, name: &'a str) -> LocalBoxFuture<'a, Result<()>>;
+
+    fn get_user_roles<'a>(&'a self, user_id: &'a Id) -> LocalBoxFuture<'a, Result<Vec<Role>>>;
+
+    ///Assigns a role by hand. A role the user already has from a directory stays theirs when the directory stops mapping it.
+    fn assign_role<'a>(&'a self, user_id: &'a Id, role: &'a str) -> LocalBoxFuture<'a, Result<()>>;
+
+    ///Makes the roles the source maps to the user the given ones which exist. Unknown roles are ignored.
+    /// The roles the source no longer maps are taken away, and the roles assigned by hand are left as they are.
+    fn sync_source_roles<'a>(&'a self, user_id: &'a Id, source: &'a str, roles: &'a [String]) -> LocalBoxFuture<'a, Result<()>>;
+
+    fn unassign_role<'a>(&'a self, user_id: &'a Id, role: &'a str) -> LocalBoxFuture<'a, Result<()>>;
+
+    ///Checks whether one of the roles of a user grants the permission, or every permission.
+    fn has_permission<'a>(&'a self, user_id: &'a Id, permission: &'a str) -> LocalBoxFuture<'a, Result<bool>>;
+
+    ///The roles of a user and the permissions they grant.
+    fn get_user_access<'a>(&'a self, user_id: &'a Id) -> LocalBoxFuture<'a, Result<Access>>;
+}
+
+
+///Where the organizations, their members and the invitations to join them are kept.
+/// When a user is purged, the organizations they were the only member of are deleted, and in the others
+/// they were the only owner of, the longest standing admin, or member when there is no admin, becomes the owner.
+pub trait OrganizationStore: Send + Sync {
+    ///Creates an organization with the given user as its owner.
+    fn create_organization<'a>(&'a self, organization: &'a Organization, owner: &'a Id) -> LocalBoxFuture<'a, Result<()>>;
+
+    fn get_organization<'a>(&'a self, id: &'a Id) -> LocalBoxFuture<'a, Result<Organization>>;
+
+    ///Returns the organizations the user is a member of, oldest first.
+    fn get_user_organizations<'a>(&'a self, user_id: &'a Id) -> LocalBoxFuture<'a, Result<Vec<Organization>>>;
+
+    fn update_organization<'a>(&'a self, id: &'a Id, name: &'a str) -> LocalBoxFuture<'a, Result<Organization>>;
+
+    ///Deletes an organization along with its memberships and invitations.
+    fn delete_organization<'a>(&'a self, id: &'a Id) -> LocalBoxFuture<'a, Result<()>>;
+
+    fn get_membership<'a>(&'a self, organization_id: &'a Id, user_id: &'a Id) -> LocalBoxFuture<'a, Result<Option<Membership>>>;
+
+    fn get_memberships<'a>(&'a self, organization_id: &'a Id) -> LocalBoxFuture<'a, Result<Vec<Membership>>>;
+
+    fn update_membership_role<'a>(&'a self, organization_id: &'a Id, user_id: &'a Id, role: OrganizationRole) -> LocalBoxFuture<'a, Result<Membership>>;
+
+    fn delete_membership<'a>(&'a self, organization_id: &'a Id, user_id: &'a Id) -> LocalBoxFuture<'a, Result<()>>;
+
+    fn count_owners<'a>(&'a self, organization_id: &'a Id) -> LocalBoxFuture<'a, Result<i64>>;
+
+    fn create_invitation<'a>(&'a self, invitation: &'a Invitation) -> LocalBoxFuture<'a, Result<()>>;
+
+    ///Returns the invitation with the given id unless it has expired.
+    fn get_invitation<'a>(&'a self, id: &'a Uuid) -> LocalBoxFuture<'a, Result<Invitation>>;
+
+    ///Returns the pending invitations sent to the email address, whatever its case.
+    fn get_invitations_by_email<'a>(&'a self, email: &'a str) -> LocalBoxFuture<'a, Result<Vec<Invitation>>>;
+
+    fn get_invitations_by_organization<'a>(&'a self, organization_id: &'a Id) -> LocalBoxFuture<'a, Result<Vec<Invitation>>>;
+
+    fn delete_invitation<'a>(&'a self, id: &'a Uuid) -> LocalBoxFuture<'a, Result<()>>;
+
+    ///Adds the member of an accepted invitation and deletes the invitation.
+    /// A user who is already a member keeps the higher of the two roles.
+    fn accept_invitation<'a>(&'a self, invitation: &'a Invitation, user_id: &'a Id) -> LocalBoxFuture<'a, Result<Membership>>;
+}
+
+
+///Where the API keys are kept, with the hash of their secret only.
+pub trait ApiKeyStore: Send + Sync {
+    fn create_api_key<'a>(&'a self, api_key: &'a ApiKey) -> LocalBoxFuture<'a, Result<()>>;
+
+    ///Returns the key with the prefix unless its user is deleted.
+    fn get_api_key_by_prefix<'a>(&'a self, prefix: &'a str) -> LocalBoxFuture<'a, Result<Option<ApiKey>>>;
+
+    ///Returns every key of the user, the revoked ones included, oldest first.
+    fn get_api_keys_by_user_id<'a>(&'a self, user_id: &'a Id) -> LocalBoxFuture<'a, Result<Vec<ApiKey>>>;
+
+    ///Revokes a key of the user. Revoking a revoked key does nothing.
+    fn revoke_api_key<'a>(&'a self, user_id: &'a Id, id: &'a Uuid) -> LocalBoxFuture<'a, Result<()>>;
+
+    fn touch_api_key<'a>(&'a self, id: &'a Uuid, used_at: DateTime<Utc>) -> LocalBoxFuture<'a, Result<()>>;
+}
+
+
+///Where the identities of the users at external providers are kept,
+/// along with the SAML assertions already consumed.
+pub trait IdentityStore: Send + Sync {
+    fn create_identity<'a>(&'a self, identity: &'a Identity) -> LocalBoxFuture<'a, Result<()>>;
+
+    ///Returns the identity with the given provider and subject if it has been linked to a user.
+    fn get_identity<'a>(&'a self, provider: &'a str, subject: &'a str) -> LocalBoxFuture<'a, Result<Option<Identity>>>;
+
+    fn get_identities_by_user_id<'a>(&'a self, user_id: &'a Id) -> LocalBoxFuture<'a, Result<Vec<Identity>>>;
+
+    ///Records that an assertion has been consumed until it expires.
+    /// Returns false when the assertion had already been recorded, meaning it is being replayed.
+    fn record_assertion<'a>(&'a self, id: &'a str, expires_at: &'a DateTime<Utc>) -> LocalBoxFuture<'a, Result<bool>>;
+}
+
+
+///Where the links sent to reset forgotten passwords are kept until they are used or expire.
+pub trait PasswordResetStore: Send + Sync {
+    fn create_password_reset<'a>(&'a self, reset: &'a PasswordReset) -> LocalBoxFuture<'a, Result<()>>;
+
+    ///Returns the password reset with the given id unless it has expired.
+    fn get_password_reset<'a>(&'a self, id: &'a Uuid) -> LocalBoxFuture<'a, Result<Option<PasswordReset>>>;
+
+    ///Deletes every password reset of a user, so a reset link works only once.
+    fn delete_password_resets_by_user_id<'a>(&'a self, user_id: &'a Id) -> LocalBoxFuture<'a, Result<()>>;
+}
+
+
+///Where the links sent to deleted users to restore their account are kept until they are used or expire.
+pub trait AccountRestorationStore: Send + Sync {
+    fn create_account_restoration<'a>(&'a self, restoration: &'a AccountRestoration) -> LocalBoxFuture<'a, Result<()>>;
+
+    ///Returns the account restoration with the given id unless it has expired.
+    fn get_account_restoration<'a>(&'a self, id: &'a Uuid) -> LocalBoxFuture<'a, Result<Option<AccountRestoration>>>;
+
+    ///Deletes every account restoration of a user, once they are restored.
+    fn delete_account_restorations_by_user_id<'a>(&'a self, user_id: &'a Id) -> LocalBoxFuture<'a, Result<()>>;
+}
+
+
+///Decides what becomes of a token bucket: returns the bucket after taking a token, when it will be full again and the decision.
+pub type TakeToken<'a> = Box<dyn FnOnce(Option<Bucket>) -> (Bucket, DateTime<Utc>, Decision) + 'a>;
+
+
+///Where the token buckets of the rate limiter are kept when the replicas of the server share them.
+pub trait RateLimitStore: Send + Sync {
+    ///Replaces the bucket of the key with the one `take` makes of it, and returns the decision of `take`.
+    /// Concurrent takes of the same bucket wait their turn, so two requests can not both take its last token.
+    fn take<'a>(&'a self, key: &'a str, take: TakeToken<'a>) -> LocalBoxFuture<'a, Result<Decision>>;
+
+    ///Deletes the buckets which have filled up again. A missing bucket is a full one.
+    fn delete_full<'a>(&'a self) -> LocalBoxFuture<'a, Result<()>>;
+}
+
+
+///Every store, for the backends which keep all of them.
+pub trait Store: UserStore + VerificationStore + LoginFailureStore + AuditStore + RoleStore + OrganizationStore + ApiKeyStore
+    + IdentityStore + PasswordResetStore + AccountRestorationStore + RateLimitStore {}
+
+
+impl<T> Store for T
+where
+    T: UserStore + VerificationStore + LoginFailureStore + AuditStore + RoleStore + OrganizationStore + ApiKeyStore
+        + IdentityStore + PasswordResetStore + AccountRestorationStore + RateLimitStore {}
+
+
+///Which audit events to list, newest first.
+#[derive(Clone, Debug, Default)]
+pub struct AuditQuery {
+    pub actor_id: Option<Id>,
+    pub target_id: Option<Id>,
+    pub action: Option<String>,
+    pub created_after: Option<DateTime<Utc>>,
+    pub created_before: Option<DateTime<Utc>>,
+    ///Lists the events older than the last one of the previous page.
+    pub cursor: Option<Cursor>,
+    pub limit: i64,
+}
+
+
+impl AuditQuery {
+    fn matches(&self, event: &AuditEvent) -> bool {
+        let position = (event.created_at, event.id.bytes());
+        self.actor_id.as_ref().is_none_or(|actor_id| event.actor_id.as_ref() == Some(actor_id))
+            && self.target_id.as_ref().is_none_or(|target_id| event.target_id.as_ref() == Some(target_id))
+            && self.action.as_ref().is_none_or(|action| event.action == *action)
+            && self.created_after.is_none_or(|created_after| event.created_at >= created_after)
+            && self.created_before.is_none_or(|created_before| event.created_at < created_before)
+            && self.cursor.as_ref().is_none_or(|cursor| position < (cursor.created_at, cursor.id.bytes()))
+    }
+
+    ///Picks the events matching the query among the given ones, for the stores that can not filter them on their own.
+    fn select(&self, events: impl IntoIterator<Item = AuditEvent>) -> Vec<AuditEvent> {
+        let mut events: Vec<AuditEvent> = events.into_iter().filter(|event| self.matches(event)).collect();
+        events.sort_by_key(|event| std::cmp::Reverse((event.created_at, event.id.bytes())));
+        events.truncate(self.limit.max(0) as usize);
+        events
+    }
+}
+
+
+///Where the audit events and their checkpoints are kept. They are only ever added, never changed or removed.
+pub trait AuditStore: Send + Sync {
+    ///Numbers the event after the latest one and chains it to it, whatever sequence and hash it is given.
+    fn record_event<'a>(&'a self, event: &'a AuditEvent) -> LocalBoxFuture<'a, Result<()>>;
+
+    ///Returns at most `limit` of the events matching the query, newest first.
+    fn get_events<'a>(&'a self, query: &'a AuditQuery) -> LocalBoxFuture<'a, Result<Vec<AuditEvent>>>;
+
+    ///Returns the sequence and hash of the latest event, if there is one.
+    fn get_chain_head<'a>(&'a self) -> LocalBoxFuture<'a, Result<Option<ChainHead>>>;
+
+    ///Returns every event in the order of the chain.
+    fn get_chain<'a>(&'a self) -> LocalBoxFuture<'a, Result<Vec<AuditEvent>>>;
+
+    fn record_checkpoint<'a>(&'a self, checkpoint: &'a AuditCheckpoint) -> LocalBoxFuture<'a, Result<()>>;
+
+    ///Returns every checkpoint, oldest first.
+    fn get_checkpoints<'a>(&'a self) -> LocalBoxFuture<'a, Result<Vec<AuditCheckpoint>>>;
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_user;
+
+    ///Deletes a user, restores them, then deletes them with a grace period already over and purges them.
+    async fn test_soft_delete<S: UserStore>(store: S) {
+        let user = test_user("jane@example.com");
+        store.create_user(&user, "jane@example.com").await.unwrap();
+
+        store.delete_user_by_id(&user.id, Utc::now() + chrono::Duration::days(1)).await.unwrap();
+        assert!(matches!(store.get_user_by_id(&user.id).await, Err(Error::UserNotFound)));
+        assert!(matches!(store.get_user_with_password_by_email("jane@example.com").await, Err(Error::UserNotFound)));
+        assert!(matches!(store.create_user(&User{id: Default::default(), ..user.clone()}, "jane@example.com").await, Err(Error::UserWithEmailExists)));
+        assert_eq!(store.purge_deleted_users().await.unwrap(), 0);
+        assert_eq!(store.restore_user(&user.id).await.unwrap().id, user.id);
+        assert!(store.get_user_by_email("jane@example.com").await.is_ok());
+        assert!(matches!(store.restore_user(&user.id).await, Err(Error::UserNotFound)));
+
+        store.delete_user_by_id(&user.id, Utc::now() - chrono::Duration::seconds(1)).await.unwrap();
+        assert!(matches!(store.restore_user(&user.id).await, Err(Error::UserNotFound)));
+        assert_eq!(store.purge_deleted_users().await.unwrap(), 1);
+        store.create_user(&user, "jane@example.com").await.unwrap();
+    }
+
+    #[actix_web::test]
+    async fn test_soft_delete_in_memory() {
+        test_soft_delete(MemoryStore::default()).await;
+    }
+
+    #[actix_web::test]
+    async fn test_soft_delete_on_sqlite() {
+        let database = crate::config::Database{url: "sqlite::memory:".into(), ..Default::default()};
+        test_soft_delete(database.init_sqlite().await.unwrap()).await;
+    }
+
+    ///Records events a second apart and pages through them, filtered by time.
+    async fn test_audit_events_are_paged<S: AuditStore>(store: S) {
+        let start = Utc::now();
+        for seconds in 0..5 {
+            let event = AuditEvent {
+                id: Default::default(), sequence: 0, hash: None, action: "login.succeeded".into(), actor_id: None, target_id: None,
+                ip: None, user_agent: None, details: Value::Map(HashMap::new()), created_at: start + chrono::Duration::seconds(seconds),
+            };
+            store.record_event(&event).await.unwrap();
+        }
+        let times = |events: &[AuditEvent]| events.iter().map(|event| (event.created_at - start).num_seconds()).collect::<Vec<_>>();
+        let mut audit_query = AuditQuery{created_after: Some(start + chrono::Duration::seconds(1)), created_before: Some(start + chrono::Duration::seconds(4)), limit: 2, ..Default::default()};
+        let events = store.get_events(&audit_query).await.unwrap();
+        assert_eq!(times(&events), [3, 2]);
+        audit_query.cursor = Some(Cursor{created_at: events[1].created_at, id: events[1].id.clone()});
+        assert_eq!(times(&store.get_events(&audit_query).await.unwrap()), [1]);
+    }
+
+    #[actix_web::test]
+    async fn test_audit_events_are_paged_in_memory() {
+        test_audit_events_are_paged(MemoryStore::default()).await;
+    }
+
+    #[actix_web::test]
+    async fn test_audit_events_are_paged_on_sqlite() {
+        let database = crate::config::Database{url: "sqlite::memory:".into(), ..Default::default()};
+        test_audit_events_are_paged(database.init_sqlite().await.unwrap()).await;
+    }
+
+    ///Purges the owner of two organizations: the one they are alone in goes away,
+    /// the other one is handed over to its admin rather than to the member who joined first.
+    async fn test_purge_releases_ownerships<S: UserStore + OrganizationStore>(store: S) {
+        let (owner, member, admin) = (test_user("owner@example.com"), test_user("member@example.com"), test_user("admin@example.com"));
+        for user in [&owner, &member, &admin] {
+            store.create_user(user, user.email.address().as_ref()).await.unwrap();
+        }
+        let (alone, shared) = (Organization{id: Default::default(), name: "alone".into(), created_at: Utc::now()}, Organization{id: Default::default(), name: "shared".into(), created_at: Utc::now()});
+        store.create_organization(&alone, &owner.id).await.unwrap();
+        store.create_organization(&shared, &owner.id).await.unwrap();
+        for (user, role) in [(&member, OrganizationRole::Member), (&admin, OrganizationRole::Admin)] {
+            let invitation = Invitation {
+                id: Uuid::new_v4(), organization_id: shared.id.clone(), email: user.email.address().to_string(), role,
+                invited_by: owner.id.clone(), created_at: Utc::now(), expires_at: Utc::now() + chrono::Duration::days(1),
+            };
+            store.create_invitation(&invitation).await.unwrap();
+            assert_eq!(store.get_invitations_by_email(&invitation.email.to_uppercase()).await.unwrap().len(), 1);
+            assert_eq!(store.accept_invitation(&invitation, &user.id).await.unwrap().role, role);
+        }
+
+        store.delete_user_by_id(&owner.id, Utc::now() - chrono::Duration::seconds(1)).await.unwrap();
+        assert_eq!(store.purge_deleted_users().await.unwrap(), 1);
+        assert!(matches!(store.get_organization(&alone.id).await, Err(Error::OrganizationNotFound)));
+        assert_eq!(store.get_membership(&shared.id, &admin.id).await.unwrap().unwrap().role, OrganizationRole::Owner);
+        assert_eq!(store.get_membership(&shared.id, &member.id).await.unwrap().unwrap().role, OrganizationRole::Member);
+        assert_eq!(store.count_owners(&shared.id).await.unwrap(), 1);
+    }
+
+    #[actix_web::test]
+    async fn test_purge_releases_ownerships_in_memory() {
+        test_purge_releases_ownerships(MemoryStore::default()).await;
+    }
+
+    #[actix_web::test]
+    async fn test_purge_releases_ownerships_on_sqlite() {
+        let database = crate::config::Database{url: "sqlite::memory:".into(), ..Default::default()};
+        test_purge_releases_ownerships(database.init_sqlite().await.unwrap()).await;
+    }
+
+    ///Finds an API key by its prefix with its scopes until its user is deleted, and revokes it once.
+    async fn test_api_keys<S: UserStore + ApiKeyStore>(store: S) {
+        let user = test_user("jane@example.com");
+        store.create_user(&user, "jane@example.com").await.unwrap();
+        let api_key = ApiKey {
+            id: Uuid::new_v4(), user_id: user.id.clone(), name: "ci".into(), prefix: "prefix".into(), secret_hash: "hash".into(),
+            scopes: vec![crate::PERMISSION_USERS_READ.into()], expires_at: None, last_used_at: None, revoked_at: None, created_at: Utc::now(),
+        };
+        store.create_api_key(&api_key).await.unwrap();
+        assert_eq!(store.get_api_key_by_prefix("prefix").await.unwrap().unwrap().scopes, api_key.scopes);
+
+        store.revoke_api_key(&user.id, &api_key.id).await.unwrap();
+        let revoked_at = store.get_api_keys_by_user_id(&user.id).await.unwrap()[0].revoked_at;
+        assert!(revoked_at.is_some());
+        store.revoke_api_key(&user.id, &api_key.id).await.unwrap();
+        assert_eq!(store.get_api_keys_by_user_id(&user.id).await.unwrap()[0].revoked_at, revoked_at);
+        assert!(store.revoke_api_key(&Id::default(), &api_key.id).await.is_err());
+
+        store.delete_user_by_id(&user.id, Utc::now() + chrono::Duration::days(1)).await.unwrap();
+        assert!(store.get_api_key_by_prefix("prefix").await.unwrap().is_none());
+    }
+
+    #[actix_web::test]
+    async fn test_api_keys_in_memory() {
+        test_api_keys(MemoryStore::default()).await;
+    }
+
+    #[actix_web::test]
+    async fn test_api_keys_on_sqlite() {
+        let database = crate::config::Database{url: "sqlite::memory:".into(), ..Default::default()};
+        test_api_keys(database.init_sqlite().await.unwrap()).await;
+    }
+
+    ///Takes the two tokens of a bucket and is turned away, then finds the bucket gone once it is full again.
+    async fn test_rate_limit_buckets<S: RateLimitStore>(store: S) {
+        let policy = crate::config::RateLimitPolicy{method: "POST".into(), path: "/login".into(), key: crate::config::RateLimitKey::Ip, capacity: 2, period: 60};
+        let now = Utc::now();
+        for remaining in [1, 0] {
+            let decision = store.take("ip", Box::new(|bucket| crate::rate_limit::take(bucket, &policy, now))).await.unwrap();
+            assert_eq!((decision.allowed, decision.remaining), (true, remaining));
+        }
+        let (bucket, _, decision) = crate::rate_limit::take(None, &policy, now);
+        assert!(!store.take("ip", Box::new(|bucket| crate::rate_limit::take(bucket, &policy, now))).await.unwrap().allowed);
+        store.delete_full().await.unwrap();
+
+        store.take("ip", Box::new(|_| (bucket, now - chrono::Duration::seconds(1), decision))).await.unwrap();
+        store.delete_full().await.unwrap();
+        store.take("ip", Box::new(|found| {
+            assert_eq!(found, None);
+            (bucket, now, decision)
+        })).await.unwrap();
+    }
+
+    #[actix_web::test]
+    async fn test_rate_limit_buckets_in_memory() {
+        test_rate_limit_buckets(MemoryStore::default()).await;
+    }
+
+    #[actix_web::test]
+    async fn test_rate_limit_buckets_on_sqlite() {
+        let database = crate::config::Database{url: "sqlite::memory:".into(), ..Default::default()};
+        test_rate_limit_buckets(database.init_sqlite().await.unwrap()).await;
+    }
+}
@@ -0,0 +1,346 @@
+use super::{AuditCheckpoint, AuditEvent, AuditQuery, AuditStore, ChainHead, Error, Id, LocalBoxFuture, LocalBoxStream, LoginFailureStore, Result, User, UserQuery, UserStore, Uuid, Value, Verification, VerificationStore};
+use super::{Access, AccountRestoration, AccountRestorationStore, ApiKey, ApiKeyStore, Decision, Identity, IdentityStore, Invitation, Membership, Organization, OrganizationRole, OrganizationStore};
+use super::{Page, PasswordHashReport, PasswordReset, PasswordResetStore, RateLimitStore, Role, RoleStore, TakeToken};
+use crate::domain::db::{account_restoration, api_key, audit, identity, login_failure, organization, password_reset, rate_limit, role, saml, user, verification};
+use futures_util::StreamExt;
+use chrono::{DateTime, Utc};
+use sqlx::{Pool, Postgres};
+use std::collections::HashMap;
+
+
+impl UserStore for Pool<Postgres> {
+    fn create_user<'a>(&'a self, user: &'a User, canonical_email: &'a str) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(user::create_user(self, user, canonical_email))
+    }
+
+    fn get_user_by_id<'a>(&'a self, id: &'a Id) -> LocalBoxFuture<'a, Result<User>> {
+        Box::pin(user::get_user_by_id(self, id))
+    }
+
+    fn get_user_by_email<'a>(&'a self, email: &'a str) -> LocalBoxFuture<'a, Result<User>> {
+        Box::pin(user::get_user_by_email(self, email))
+    }
+
+    fn get_user_with_password_by_id<'a>(&'a self, id: &'a Id) -> LocalBoxFuture<'a, Result<User>> {
+        Box::pin(user::get_user_with_password_by_id(self, id))
+    }
+
+    fn get_user_with_password_by_email<'a>(&'a self, email: &'a str) -> LocalBoxFuture<'a, Result<User>> {
+        Box::pin(user::get_user_with_password_by_email(self, email))
+    }
+
+    fn set_password<'a>(&'a self, id: &'a Id, password_hash: &'a str) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(user::set_password(self, id, password_hash))
+    }
+
+    fn update_user_by_id<'a>(&'a self, id: &'a Id, map: &'a HashMap<&'a str, Value>) -> LocalBoxFuture<'a, Result<User>> {
+        Box::pin(user::update_user_by_id(self, id, map))
+    }
+
+    fn change_email<'a>(&'a self, id: &'a Id, email: &'a str, canonical_email: &'a str) -> LocalBoxFuture<'a, Result<User>> {
+        Box::pin(user::change_email(self, id, email, canonical_email))
+    }
+
+    fn verify_user<'a>(&'a self, id: &'a Id) -> LocalBoxFuture<'a, Result<User>> {
+        Box::pin(user::verify_user(self, id))
+    }
+
+    fn delete_user_by_id<'a>(&'a self, id: &'a Id, purge_after: DateTime<Utc>) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(user::delete_user_by_id(self, id, purge_after))
+    }
+
+    fn restore_user<'a>(&'a self, id: &'a Id) -> LocalBoxFuture<'a, Result<User>> {
+        Box::pin(user::restore_user(self, id))
+    }
+
+    fn purge_deleted_users<'a>(&'a self) -> LocalBoxFuture<'a, Result<u64>> {
+        // The verification codes of the users go with them through the foreign key.
+        Box::pin(user::purge_deleted_users(self))
+    }
+
+    fn count_password_hashes<'a>(&'a self, current_prefix: &'a str) -> LocalBoxFuture<'a, Result<PasswordHashReport>> {
+        Box::pin(user::count_password_hashes(self, current_prefix))
+    }
+
+    fn get_users<'a>(&'a self, user_query: &'a UserQuery) -> LocalBoxFuture<'a, Result<Page<User>>> {
+        Box::pin(user::get_users(self, user_query))
+    }
+
+    fn stream_users<'a>(&'a self, with_password: bool) -> LocalBoxStream<'a, Result<User>> {
+        user::stream_users(self, with_password).map(|user| user.map_err(Error::from)).boxed_local()
+    }
+
+    fn get_canonical_emails<'a>(&'a self) -> LocalBoxFuture<'a, Result<Vec<(Id, String, String)>>> {
+        Box::pin(user::get_canonical_emails(self))
+    }
+
+    fn set_canonical_emails<'a>(&'a self, changes: &'a [(Id, String)]) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(user::set_canonical_emails(self, changes))
+    }
+
+    fn update_user<'a>(&'a self, user: &'a User, canonical_email: &'a str) -> LocalBoxFuture<'a, Result<User>> {
+        Box::pin(user::update_user(self, user, canonical_email))
+    }
+}
+
+
+impl VerificationStore for Pool<Postgres> {
+    fn create_verification_code<'a>(&'a self, verification: &'a Verification) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(verification::create_verification_code(self, verification))
+    }
+
+    fn get_verification_by_id<'a>(&'a self, id: &'a Uuid) -> LocalBoxFuture<'a, Result<Verification>> {
+        Box::pin(verification::get_verification_by_id(self, id))
+    }
+
+    fn get_latest_verification_by_user_id<'a>(&'a self, user_id: &'a Id) -> LocalBoxFuture<'a, Result<Verification>> {
+        Box::pin(verification::get_latest_verification_by_user_id(self, user_id))
+    }
+
+    fn delete_verification_by_id<'a>(&'a self, id: &'a Uuid) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(verification::delete_verification_by_id(self, id))
+    }
+}
+
+
+impl LoginFailureStore for Pool<Postgres> {
+    fn get_locked_until<'a>(&'a self, subjects: &'a [String]) -> LocalBoxFuture<'a, Result<Option<DateTime<Utc>>>> {
+        Box::pin(login_failure::get_locked_until(self, subjects))
+    }
+
+    fn record_failure<'a>(&'a self, subject: &'a str, window: i64) -> LocalBoxFuture<'a, Result<i32>> {
+        Box::pin(login_failure::record_failure(self, subject, window))
+    }
+
+    fn lock<'a>(&'a self, subject: &'a str, until: DateTime<Utc>) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(login_failure::lock(self, subject, until))
+    }
+
+    fn clear<'a>(&'a self, subject: &'a str) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(login_failure::clear(self, subject))
+    }
+}
+
+
+impl AuditStore for Pool<Postgres> {
+    fn record_event<'a>(&'a self, event: &'a AuditEvent) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(audit::record_event(self, event))
+    }
+
+    fn get_events<'a>(&'a self, query: &'a AuditQuery) -> LocalBoxFuture<'a, Result<Vec<AuditEvent>>> {
+        Box::pin(audit::get_events(self, query))
+    }
+
+    fn get_chain_head<'a>(&'a self) -> LocalBoxFuture<'a, Result<Option<ChainHead>>> {
+        Box::pin(audit::get_chain_head(self))
+    }
+
+    fn get_chain<'a>(&'a self) -> LocalBoxFuture<'a, Result<Vec<AuditEvent>>> {
+        Box::pin(audit::get_chain(self))
+    }
+
+    fn record_checkpoint<'a>(&'a self, checkpoint: &'a AuditCheckpoint) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(audit::record_checkpoint(self, checkpoint))
+    }
+
+    fn get_checkpoints<'a>(&'a self) -> LocalBoxFuture<'a, Result<Vec<AuditCheckpoint>>> {
+        Box::pin(audit::get_checkpoints(self))
+    }
+}
+
+
+impl RoleStore for Pool<Postgres> {
+    fn get_roles<'a>(&'a self) -> LocalBoxFuture<'a, Result<Vec<Role>>> {
+        Box::pin(role::get_roles(self))
+    }
+
+    fn get_role<'a>(&'a self, name: &'a str) -> LocalBoxFuture<'a, Result<Role>> {
+        Box::pin(role::get_role(self, name))
+    }
+
+    fn create_role<'a>(&'a self, role: &'a Role) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(role::create_role(self, role))
+    }
+
+    fn update_role<'a>(&'a self, name: &'a str, description: Option<&'a str>, permissions: Option<&'a [String]>) -> LocalBoxFuture<'a, Result<Role>> {
+        Box::pin(role::update_role(self, name, description, permissions))
+    }
+
+    fn delete_role<'a>(&'a self, name: &'a str) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(role::delete_role(self, name))
+    }
+
+    fn get_user_roles<'a>(&'a self, user_id: &'a Id) -> LocalBoxFuture<'a, Result<Vec<Role>>> {
+        Box::pin(role::get_user_roles(self, user_id))
+    }
+
+    fn assign_role<'a>(&'a self, user_id: &'a Id, role: &'a str) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(role::assign_role(self, user_id, role))
+    }
+
+    fn sync_source_roles<'a>(&'a self, user_id: &'a Id, source: &'a str, roles: &'a [String]) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(role::sync_source_roles(self, user_id, source, roles))
+    }
+
+    fn unassign_role<'a>(&'a self, user_id: &'a Id, role: &'a str) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(role::unassign_role(self, user_id, role))
+    }
+
+    fn has_permission<'a>(&'a self, user_id: &'a Id, permission: &'a str) -> LocalBoxFuture<'a, Result<bool>> {
+        Box::pin(role::has_permission(self, user_id, permission))
+    }
+
+    fn get_user_access<'a>(&'a self, user_id: &'a Id) -> LocalBoxFuture<'a, Result<Access>> {
+        Box::pin(role::get_user_access(self, user_id))
+    }
+}
+
+
+impl OrganizationStore for Pool<Postgres> {
+    fn create_organization<'a>(&'a self, organization: &'a Organization, owner: &'a Id) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(organization::create_organization(self, organization, owner))
+    }
+
+    fn get_organization<'a>(&'a self, id: &'a Id) -> LocalBoxFuture<'a, Result<Organization>> {
+        Box::pin(organization::get_organization(self, id))
+    }
+
+    fn get_user_organizations<'a>(&'a self, user_id: &'a Id) -> LocalBoxFuture<'a, Result<Vec<Organization>>> {
+        Box::pin(organization::get_user_organizations(self, user_id))
+    }
+
+    fn update_organization<'a>(&'a self, id: &'a Id, name: &'a str) -> LocalBoxFuture<'a, Result<Organization>> {
+        Box::pin(organization::update_organization(self, id, name))
+    }
+
+    fn delete_organization<'a>(&'a self, id: &'a Id) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(organization::delete_organization(self, id))
+    }
+
+    fn get_membership<'a>(&'a self, organization_id: &'a Id, user_id: &'a Id) -> LocalBoxFuture<'a, Result<Option<Membership>>> {
+        Box::pin(organization::get_membership(self, organization_id, user_id))
+    }
+
+    fn get_memberships<'a>(&'a self, organization_id: &'a Id) -> LocalBoxFuture<'a, Result<Vec<Membership>>> {
+        Box::pin(organization::get_memberships(self, organization_id))
+    }
+
+    fn update_membership_role<'a>(&'a self, organization_id: &'a Id, user_id: &'a Id, role: OrganizationRole) -> LocalBoxFuture<'a, Result<Membership>> {
+        Box::pin(organization::update_membership_role(self, organization_id, user_id, role))
+    }
+
+    fn delete_membership<'a>(&'a self, organization_id: &'a Id, user_id: &'a Id) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(organization::delete_membership(self, organization_id, user_id))
+    }
+
+    fn count_owners<'a>(&'a self, organization_id: &'a Id) -> LocalBoxFuture<'a, Result<i64>> {
+        Box::pin(organization::count_owners(self, organization_id))
+    }
+
+    fn create_invitation<'a>(&'a self, invitation: &'a Invitation) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(organization::create_invitation(self, invitation))
+    }
+
+    fn get_invitation<'a>(&'a self, id: &'a Uuid) -> LocalBoxFuture<'a, Result<Invitation>> {
+        Box::pin(organization::get_invitation(self, id))
+    }
+
+    fn get_invitations_by_email<'a>(&'a self, email: &'a str) -> LocalBoxFuture<'a, Result<Vec<Invitation>>> {
+        Box::pin(organization::get_invitations_by_email(self, email))
+    }
+
+    fn get_invitations_by_organization<'a>(&'a self, organization_id: &'a Id) -> LocalBoxFuture<'a, Result<Vec<Invitation>>> {
+        Box::pin(organization::get_invitations_by_organization(self, organization_id))
+    }
+
+    fn delete_invitation<'a>(&'a self, id: &'a Uuid) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(organization::delete_invitation(self, id))
+    }
+
+    fn accept_invitation<'a>(&'a self, invitation: &'a Invitation, user_id: &'a Id) -> LocalBoxFuture<'a, Result<Membership>> {
+        Box::pin(organization::accept_invitation(self, invitation, user_id))
+    }
+}
+
+
+impl ApiKeyStore for Pool<Postgres> {
+    fn create_api_key<'a>(&'a self, api_key: &'a ApiKey) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(api_key::create_api_key(self, api_key))
+    }
+
+    fn get_api_key_by_prefix<'a>(&'a self, prefix: &'a str) -> LocalBoxFuture<'a, Result<Option<ApiKey>>> {
+        Box::pin(api_key::get_api_key_by_prefix(self, prefix))
+    }
+
+    fn get_api_keys_by_user_id<'a>(&'a self, user_id: &'a Id) -> LocalBoxFuture<'a, Result<Vec<ApiKey>>> {
+        Box::pin(api_key::get_api_keys_by_user_id(self, user_id))
+    }
+
+    fn revoke_api_key<'a>(&'a self, user_id: &'a Id, id: &'a Uuid) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(api_key::revoke_api_key(self, user_id, id))
+    }
+
+    fn touch_api_key<'a>(&'a self, id: &'a Uuid, used_at: DateTime<Utc>) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(api_key::touch_api_key(self, id, used_at))
+    }
+}
+
+
+impl IdentityStore for Pool<Postgres> {
+    fn create_identity<'a>(&'a self, identity: &'a Identity) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(identity::create_identity(self, identity))
+    }
+
+    fn get_identity<'a>(&'a self, provider: &'a str, subject: &'a str) -> LocalBoxFuture<'a, Result<Option<Identity>>> {
+        Box::pin(identity::get_identity(self, provider, subject))
+    }
+
+    fn get_identities_by_user_id<'a>(&'a self, user_id: &'a Id) -> LocalBoxFuture<'a, Result<Vec<Identity>>> {
+        Box::pin(identity::get_identities_by_user_id(self, user_id))
+    }
+
+    fn record_assertion<'a>(&'a self, id: &'a str, expires_at: &'a DateTime<Utc>) -> LocalBoxFuture<'a, Result<bool>> {
+        Box::pin(saml::record_assertion(self, id, expires_at))
+    }
+}
+
+
+impl PasswordResetStore for Pool<Postgres> {
+    fn create_password_reset<'a>(&'a self, reset: &'a PasswordReset) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(password_reset::create_password_reset(self, reset))
+    }
+
+    fn get_password_reset<'a>(&'a self, id: &'a Uuid) -> LocalBoxFuture<'a, Result<Option<PasswordReset>>> {
+        Box::pin(password_reset::get_password_reset(self, id))
+    }
+
+    fn delete_password_resets_by_user_id<'a>(&'a self, user_id: &'a Id) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(password_reset::delete_password_resets_by_user_id(self, user_id))
+    }
+}
+
+
+impl AccountRestorationStore for Pool<Postgres> {
+    fn create_account_restoration<'a>(&'a self, restoration: &'a AccountRestoration) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(account_restoration::create_account_restoration(self, restoration))
+    }
+
+    fn get_account_restoration<'a>(&'a self, id: &'a Uuid) -> LocalBoxFuture<'a, Result<Option<AccountRestoration>>> {
+        Box::pin(account_restoration::get_account_restoration(self, id))
+    }
+
+    fn delete_account_restorations_by_user_id<'a>(&'a self, user_id: &'a Id) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(account_restoration::delete_account_restorations_by_user_id(self, user_id))
+    }
+}
+
+
+impl RateLimitStore for Pool<Postgres> {
+    fn take<'a>(&'a self, key: &'a str, take: TakeToken<'a>) -> LocalBoxFuture<'a, Result<Decision>> {
+        Box::pin(rate_limit::take(self, key, take))
+    }
+
+    fn delete_full<'a>(&'a self) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(rate_limit::delete_full(self))
+    }
+}
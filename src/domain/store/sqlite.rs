@@ -0,0 +1,993 @@
+use super::{AuditCheckpoint, AuditEvent, AuditQuery, AuditStore, ChainHead, Error, Id, LocalBoxFuture, LocalBoxStream, LoginFailureStore, Result, User, UserQuery, UserStore, Uuid, Value, Verification, VerificationStore};
+use super::{Access, AccountRestoration, AccountRestorationStore, ApiKey, ApiKeyStore, Bucket, Decision, Identity, IdentityStore, Invitation, Membership, Organization, OrganizationRole, OrganizationStore};
+use super::{user_page, Page, PasswordHashReport, PasswordReset, PasswordResetStore, RateLimitStore, Role, RoleStore, SortOrder, TakeToken};
+use sqlx::{query, query_as, query_scalar, types::Json, Error as SqlxError, FromRow, Pool, QueryBuilder, Sqlite, SqliteConnection};
+use futures_util::StreamExt;
+use chrono::{DateTime, Duration, Utc};
+use actix_web::http::StatusCode;
+use static_init::dynamic;
+use std::collections::HashMap;
+use crate::{FIELDS, PERMISSION_ALL};
+
+#[dynamic]
+static USER_FIELDS: String = User::fields().join(", ");
+#[dynamic]
+static USER_FIELDS_WITH_PASSWORD: String = FIELDS.join(", ");
+#[dynamic]
+static SELECT_ALL_USERS: String = format!("SELECT {} FROM users WHERE deleted_at IS NULL ORDER BY created_at, id", *USER_FIELDS);
+#[dynamic]
+static SELECT_ALL_USERS_WITH_PASSWORD: String = format!("SELECT {} FROM users WHERE deleted_at IS NULL ORDER BY created_at, id", *USER_FIELDS_WITH_PASSWORD);
+
+const AUDIT_EVENT_FIELDS: &str = "id, sequence, hash, action, actor_id, target_id, ip, user_agent, details, created_at";
+const SELECT_ROLES: &str = r#"
+    SELECT r.name, r.description, r.created_at,
+    (SELECT json_group_array(permission) FROM (SELECT permission FROM permissions WHERE role = r.name ORDER BY permission)) AS permissions
+    FROM roles r
+"#;
+
+
+///A role as it is read, with its permissions gathered in a JSON array since SQLite has no arrays.
+#[derive(FromRow)]
+struct RoleRow {
+    name: String,
+    description: String,
+    created_at: DateTime<Utc>,
+    permissions: Json<Vec<String>>,
+}
+
+
+impl From<RoleRow> for Role {
+    fn from(row: RoleRow) -> Self {
+        Role{name: row.name, description: row.description, permissions: row.permissions.0, created_at: row.created_at}
+    }
+}
+
+
+///An API key as it is read, with its scopes kept in a JSON array.
+#[derive(FromRow)]
+struct ApiKeyRow {
+    id: Uuid,
+    user_id: Id,
+    name: String,
+    prefix: String,
+    secret_hash: String,
+    scopes: Json<Vec<String>>,
+    expires_at: Option<DateTime<Utc>>,
+    last_used_at: Option<DateTime<Utc>>,
+    revoked_at: Option<DateTime<Utc>>,
+    created_at: DateTime<Utc>,
+}
+
+
+impl From<ApiKeyRow> for ApiKey {
+    fn from(row: ApiKeyRow) -> Self {
+        ApiKey {
+            id: row.id, user_id: row.user_id, name: row.name, prefix: row.prefix, secret_hash: row.secret_hash, scopes: row.scopes.0,
+            expires_at: row.expires_at, last_used_at: row.last_used_at, revoked_at: row.revoked_at, created_at: row.created_at,
+        }
+    }
+}
+
+
+fn user_not_found(err: SqlxError) -> Error {
+    match err {
+        SqlxError::RowNotFound => Error::UserNotFound,
+        err => err.into()
+    }
+}
+
+
+///Maps the violation of the unique index on the canonical emails to `Error::UserWithEmailExists`.
+/// Another user can take the email between the check and the insert or update.
+fn email_taken(err: SqlxError) -> Error {
+    match err.as_database_error().is_some_and(|err| err.is_unique_violation()) {
+        true => Error::UserWithEmailExists,
+        false => err.into()
+    }
+}
+
+
+///Escapes the wildcards of a `LIKE` pattern so the value is matched literally.
+fn escape_like(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_")
+}
+
+
+fn membership_not_found() -> Error {
+    Error::Custom(StatusCode::NOT_FOUND, "membership not found".into())
+}
+
+
+///Returns the roles of a user, none when the filter on the users leaves them out.
+async fn get_user_roles(pool: &Pool<Sqlite>, user_id: &Id, users: &str) -> Result<Vec<Role>> {
+    let sql = format!(r#"{} WHERE r.name IN (
+        SELECT ur.role FROM user_roles ur JOIN users u ON u.id = ur.user_id WHERE ur.user_id = $1 {}
+    ) ORDER BY r.name"#, SELECT_ROLES, users);
+    let rows: Vec<RoleRow> = query_as(&sql).bind(user_id).fetch_all(pool).await?;
+    Ok(rows.into_iter().map(Role::from).collect())
+}
+
+
+///Deletes the organizations the user is the only member of, and hands over the ones they are the only owner of
+/// to the longest standing admin, or the longest standing member when there is no admin.
+async fn release_sole_ownerships(connection: &mut SqliteConnection, user_id: &Id) -> Result<()> {
+    query(r#"
+        DELETE FROM organizations WHERE id IN (
+            SELECT organization_id FROM memberships WHERE user_id = $1
+        ) AND NOT EXISTS (
+            SELECT 1 FROM memberships m WHERE m.organization_id = organizations.id AND m.user_id <> $1
+        );
+    "#).bind(user_id).execute(&mut *connection).await?;
+    query(r#"
+        UPDATE memberships SET role = 'owner' WHERE user_id = (
+            SELECT m.user_id FROM memberships m WHERE m.organization_id = memberships.organization_id AND m.user_id <> $1
+            ORDER BY m.role = 'admin' DESC, m.created_at LIMIT 1
+        ) AND organization_id IN (
+            SELECT organization_id FROM memberships WHERE user_id = $1 AND role = 'owner'
+        ) AND NOT EXISTS (
+            SELECT 1 FROM memberships o WHERE o.organization_id = memberships.organization_id AND o.user_id <> $1 AND o.role = 'owner'
+        );
+    "#).bind(user_id).execute(&mut *connection).await?;
+    Ok(())
+}
+
+
+async fn get_user(pool: &Pool<Sqlite>, fields: &str, filter: &str, value: &str) -> Result<User> {
+    let sql = format!("SELECT {} FROM users WHERE {} = $1 AND deleted_at IS NULL", fields, filter);
+    query_as(&sql).bind(value).fetch_one(pool).await.map_err(user_not_found)
+}
+
+
+async fn get_user_by_id(pool: &Pool<Sqlite>, fields: &str, id: &Id) -> Result<User> {
+    let sql = format!("SELECT {} FROM users WHERE id = $1 AND deleted_at IS NULL", fields);
+    query_as(&sql).bind(id).fetch_one(pool).await.map_err(user_not_found)
+}
+
+
+impl UserStore for Pool<Sqlite> {
+    fn create_user<'a>(&'a self, user: &'a User, canonical_email: &'a str) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let exists: bool = query_scalar("SELECT EXISTS (SELECT 1 FROM users WHERE email_canonical = $1)").bind(canonical_email).fetch_one(self).await?;
+            if exists {
+                return Err(Error::UserWithEmailExists);
+            }
+            query(r#"
+            INSERT INTO users
+            (id, email, email_canonical, email_verified_at, user_name, first_name, last_name, password, created_at, profile_picture)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10);"#)
+            .bind(&user.id).bind(user.email.address().to_string()).bind(canonical_email).bind(user.verified_at()).bind(&user.user_name).bind(&user.first_name).bind(&user.last_name).bind(&user.password).bind(user.created_at).bind(&user.profile_picture)
+            .execute(self).await.map_err(email_taken)?;
+            Ok(())
+        })
+    }
+
+    fn get_user_by_id<'a>(&'a self, id: &'a Id) -> LocalBoxFuture<'a, Result<User>> {
+        Box::pin(get_user_by_id(self, &USER_FIELDS, id))
+    }
+
+    fn get_user_by_email<'a>(&'a self, email: &'a str) -> LocalBoxFuture<'a, Result<User>> {
+        Box::pin(get_user(self, &USER_FIELDS, "email_canonical", email))
+    }
+
+    fn get_user_with_password_by_id<'a>(&'a self, id: &'a Id) -> LocalBoxFuture<'a, Result<User>> {
+        Box::pin(get_user_by_id(self, &USER_FIELDS_WITH_PASSWORD, id))
+    }
+
+    fn get_user_with_password_by_email<'a>(&'a self, email: &'a str) -> LocalBoxFuture<'a, Result<User>> {
+        Box::pin(get_user(self, &USER_FIELDS_WITH_PASSWORD, "email_canonical", email))
+    }
+
+    fn set_password<'a>(&'a self, id: &'a Id, password_hash: &'a str) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let result = query("UPDATE users SET password = $1 WHERE id = $2 AND deleted_at IS NULL;").bind(password_hash).bind(id).execute(self).await?;
+            match result.rows_affected() {
+                0 => Err(Error::UserNotFound),
+                _ => Ok(())
+            }
+        })
+    }
+
+    fn update_user_by_id<'a>(&'a self, id: &'a Id, map: &'a HashMap<&'a str, Value>) -> LocalBoxFuture<'a, Result<User>> {
+        Box::pin(async move {
+            if map.is_empty() {
+                return Err(Error::Custom(StatusCode::BAD_REQUEST, "No Data to Update. Please provide fields and values to be updated".into()));
+            }
+            let updates: Vec<String> = map.keys().enumerate().map(|(index, key)| format!("{} = ${}", key, index + 1)).collect();
+            let sql = format!("UPDATE users SET {} WHERE id = ${} AND deleted_at IS NULL RETURNING {};", updates.join(", "), map.len() + 1, *USER_FIELDS);
+            let mut query = query_as::<Sqlite, User>(&sql);
+            for value in map.values() {
+                query = query.bind(value);
+            }
+            query.bind(id).fetch_one(self).await.map_err(user_not_found)
+        })
+    }
+
+    fn change_email<'a>(&'a self, id: &'a Id, email: &'a str, canonical_email: &'a str) -> LocalBoxFuture<'a, Result<User>> {
+        Box::pin(async move {
+            let taken: bool = query_scalar("SELECT EXISTS (SELECT 1 FROM users WHERE email_canonical = $1 AND id <> $2)").bind(canonical_email).bind(id).fetch_one(self).await?;
+            if taken {
+                return Err(Error::UserWithEmailExists);
+            }
+            let sql = format!("UPDATE users SET email = $1, email_canonical = $2, email_verified_at = NULL WHERE id = $3 AND deleted_at IS NULL RETURNING {};", *USER_FIELDS);
+            match query_as(&sql).bind(email).bind(canonical_email).bind(id).fetch_one(self).await {
+                Ok(user) => Ok(user),
+                Err(SqlxError::RowNotFound) => Err(Error::UserNotFound),
+                Err(err) => Err(email_taken(err)),
+            }
+        })
+    }
+
+    fn verify_user<'a>(&'a self, id: &'a Id) -> LocalBoxFuture<'a, Result<User>> {
+        Box::pin(async move {
+            let sql = format!("UPDATE users SET email_verified_at = COALESCE(email_verified_at, $1) WHERE id = $2 AND deleted_at IS NULL RETURNING {};", *USER_FIELDS);
+            match query_as(&sql).bind(Utc::now()).bind(id).fetch_one(self).await {
+                Ok(user) => Ok(user),
+                Err(SqlxError::RowNotFound) => Err(Error::Custom(StatusCode::NOT_FOUND, "the user you are trying to validate seems to be deleted".into())),
+                Err(err) => Err(err.into()),
+            }
+        })
+    }
+
+    fn delete_user_by_id<'a>(&'a self, id: &'a Id, purge_after: DateTime<Utc>) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let result = query("UPDATE users SET deleted_at = $1, purge_after = $2 WHERE id = $3 AND deleted_at IS NULL;")
+                .bind(Utc::now()).bind(purge_after).bind(id).execute(self).await?;
+            match result.rows_affected() {
+                0 => Err(Error::UserNotFound),
+                _ => Ok(())
+            }
+        })
+    }
+
+    fn restore_user<'a>(&'a self, id: &'a Id) -> LocalBoxFuture<'a, Result<User>> {
+        Box::pin(async move {
+            let purge_after: Option<DateTime<Utc>> = query_scalar("SELECT purge_after FROM users WHERE id = $1 AND deleted_at IS NOT NULL")
+                .bind(id).fetch_optional(self).await?.flatten();
+            if purge_after.is_none_or(|purge_after| purge_after <= Utc::now()) {
+                return Err(Error::UserNotFound);
+            }
+            let sql = format!("UPDATE users SET deleted_at = NULL, purge_after = NULL WHERE id = $1 RETURNING {};", *USER_FIELDS);
+            query_as(&sql).bind(id).fetch_one(self).await.map_err(user_not_found)
+        })
+    }
+
+    fn purge_deleted_users<'a>(&'a self) -> LocalBoxFuture<'a, Result<u64>> {
+        Box::pin(async move {
+            // The times are text, so the ones past are picked in Rust like those of the login failures.
+            let now = Utc::now();
+            let mut transaction = self.begin().await?;
+            let deleted: Vec<(Id, DateTime<Utc>)> = query_as("SELECT id, purge_after FROM users WHERE purge_after IS NOT NULL").fetch_all(&mut *transaction).await?;
+            let mut purged = 0;
+            for (id, _) in deleted.iter().filter(|(_, purge_after)| *purge_after <= now) {
+                // The organizations they solely own are handed over or deleted first, so none is left without an owner.
+                release_sole_ownerships(&mut transaction, id).await?;
+                purged += query("DELETE FROM users WHERE id = $1;").bind(id).execute(&mut *transaction).await?.rows_affected();
+            }
+            transaction.commit().await?;
+            Ok(purged)
+        })
+    }
+
+    fn count_password_hashes<'a>(&'a self, current_prefix: &'a str) -> LocalBoxFuture<'a, Result<PasswordHashReport>> {
+        Box::pin(async move {
+            let (total, outdated) = query_as(r#"
+                SELECT COUNT(*), COALESCE(SUM(substr(password, 1, length($1)) <> $1), 0)
+                FROM users WHERE password <> '' AND deleted_at IS NULL;"#)
+                .bind(current_prefix).fetch_one(self).await?;
+            Ok(PasswordHashReport{total, outdated})
+        })
+    }
+
+    ///`LIKE` ignores the case of ASCII letters only, unlike the `ILIKE` of Postgres.
+    fn get_users<'a>(&'a self, user_query: &'a UserQuery) -> LocalBoxFuture<'a, Result<Page<User>>> {
+        Box::pin(async move {
+            let mut builder = QueryBuilder::new(format!("SELECT {} FROM users WHERE deleted_at IS NULL", *USER_FIELDS));
+            if let Some(verified) = user_query.verified {
+                builder.push(" AND (email_verified_at IS NOT NULL) = ").push_bind(verified);
+            }
+            // The times are kept as RFC 3339 text in UTC, which sorts like the times do.
+            if let Some(created_after) = user_query.created_after {
+                builder.push(" AND created_at >= ").push_bind(created_after);
+            }
+            if let Some(created_before) = user_query.created_before {
+                builder.push(" AND created_at < ").push_bind(created_before);
+            }
+            if let Some(ref role) = user_query.role {
+                builder.push(" AND id IN (SELECT user_id FROM user_roles WHERE role = ").push_bind(role).push(")");
+            }
+            if let Some(ref search) = user_query.search {
+                let pattern = format!("%{}%", escape_like(search));
+                builder.push(" AND (");
+                for (index, column) in ["email", "user_name", "first_name", "last_name"].into_iter().enumerate() {
+                    if index > 0 {
+                        builder.push(" OR ");
+                    }
+                    builder.push(column).push(" LIKE ").push_bind(pattern.clone()).push(" ESCAPE '\\'");
+                }
+                builder.push(")");
+            }
+            let (comparison, direction) = match user_query.order {
+                SortOrder::Asc => (">", "ASC"),
+                SortOrder::Desc => ("<", "DESC"),
+            };
+            if let Some(ref cursor) = user_query.cursor {
+                builder.push(format!(" AND (created_at, id) {} (", comparison))
+                    .push_bind(cursor.created_at).push(", ").push_bind(&cursor.id).push(")");
+            }
+            // One more user than asked for is fetched to tell whether there is a next page.
+            builder.push(format!(" ORDER BY created_at {0}, id {0} LIMIT ", direction)).push_bind(user_query.limit + 1);
+            let users = builder.build_query_as().fetch_all(self).await?;
+            Ok(user_page(users, user_query.limit))
+        })
+    }
+
+    fn stream_users<'a>(&'a self, with_password: bool) -> LocalBoxStream<'a, Result<User>> {
+        let sql = match with_password {
+            true => SELECT_ALL_USERS_WITH_PASSWORD.as_str(),
+            false => SELECT_ALL_USERS.as_str(),
+        };
+        query_as(sql).fetch(self).map(|user| user.map_err(Error::from)).boxed_local()
+    }
+
+    fn get_canonical_emails<'a>(&'a self) -> LocalBoxFuture<'a, Result<Vec<(Id, String, String)>>> {
+        Box::pin(async move {
+            Ok(query_as("SELECT id, email, email_canonical FROM users ORDER BY created_at, id;").fetch_all(self).await?)
+        })
+    }
+
+    fn set_canonical_emails<'a>(&'a self, changes: &'a [(Id, String)]) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let mut transaction = self.begin().await?;
+            for (id, _) in changes {
+                query("UPDATE users SET email_canonical = '#' || hex(id) WHERE id = $1;").bind(id).execute(&mut *transaction).await?;
+            }
+            for (id, canonical_email) in changes {
+                query("UPDATE users SET email_canonical = $1 WHERE id = $2;").bind(canonical_email).bind(id).execute(&mut *transaction).await.map_err(email_taken)?;
+            }
+            transaction.commit().await?;
+            Ok(())
+        })
+    }
+
+    fn update_user<'a>(&'a self, user: &'a User, canonical_email: &'a str) -> LocalBoxFuture<'a, Result<User>> {
+        Box::pin(async move {
+            let sql = format!(r#"
+                UPDATE users SET email = $1, email_canonical = $2, email_verified_at = $3, user_name = $4, first_name = $5, last_name = $6, profile_picture = $7
+                WHERE id = $8 AND deleted_at IS NULL RETURNING {};"#, *USER_FIELDS);
+            let result = query_as(&sql)
+                .bind(user.email.address().to_string()).bind(canonical_email).bind(user.verified_at()).bind(&user.user_name).bind(&user.first_name).bind(&user.last_name).bind(&user.profile_picture).bind(&user.id)
+                .fetch_one(self).await;
+            match result {
+                Ok(user) => Ok(user),
+                Err(SqlxError::RowNotFound) => Err(Error::UserNotFound),
+                Err(err) => Err(email_taken(err)),
+            }
+        })
+    }
+}
+
+
+impl VerificationStore for Pool<Sqlite> {
+    fn create_verification_code<'a>(&'a self, verification: &'a Verification) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            query("INSERT INTO verification_codes (id, user_id, code, created_at) VALUES ($1, $2, $3, $4);")
+                .bind(verification.id).bind(&verification.user_id).bind(&verification.code).bind(verification.created_at)
+                .execute(self).await?;
+            Ok(())
+        })
+    }
+
+    fn get_verification_by_id<'a>(&'a self, id: &'a Uuid) -> LocalBoxFuture<'a, Result<Verification>> {
+        Box::pin(async move {
+            query_as("SELECT id, user_id, code, created_at FROM verification_codes WHERE id = $1").bind(id).fetch_optional(self).await?
+                .ok_or_else(|| Error::Custom(StatusCode::NOT_FOUND, "Verification code not found".into()))
+        })
+    }
+
+    fn get_latest_verification_by_user_id<'a>(&'a self, user_id: &'a Id) -> LocalBoxFuture<'a, Result<Verification>> {
+        Box::pin(async move {
+            query_as("SELECT id, user_id, code, created_at FROM verification_codes WHERE user_id = $1 ORDER BY created_at DESC LIMIT 1")
+                .bind(user_id).fetch_optional(self).await?
+                .ok_or_else(|| Error::Custom(StatusCode::NOT_FOUND, "No verification code found for the user".into()))
+        })
+    }
+
+    fn delete_verification_by_id<'a>(&'a self, id: &'a Uuid) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            query("DELETE FROM verification_codes WHERE id = $1").bind(id).execute(self).await?;
+            Ok(())
+        })
+    }
+}
+
+
+///The times are compared in Rust rather than in SQL, since SQLite keeps them as text.
+impl LoginFailureStore for Pool<Sqlite> {
+    fn get_locked_until<'a>(&'a self, subjects: &'a [String]) -> LocalBoxFuture<'a, Result<Option<DateTime<Utc>>>> {
+        Box::pin(async move {
+            let now = Utc::now();
+            let mut locked_until = None;
+            for subject in subjects {
+                let until: Option<DateTime<Utc>> = query_scalar("SELECT locked_until FROM login_failures WHERE subject = $1")
+                    .bind(subject).fetch_optional(self).await?.flatten();
+                locked_until = locked_until.max(until.filter(|until| *until > now));
+            }
+            Ok(locked_until)
+        })
+    }
+
+    fn record_failure<'a>(&'a self, subject: &'a str, window: i64) -> LocalBoxFuture<'a, Result<i32>> {
+        Box::pin(async move {
+            let now = Utc::now();
+            let mut transaction = self.begin().await?;
+            let row: Option<(i32, DateTime<Utc>, Option<DateTime<Utc>>)> = query_as("SELECT failures, last_failed_at, locked_until FROM login_failures WHERE subject = $1")
+                .bind(subject).fetch_optional(&mut *transaction).await?;
+            let (failures, locked_until) = match row {
+                Some((failures, last_failed_at, locked_until)) => {
+                    let lock_ended = locked_until.is_some_and(|until| until <= now);
+                    match last_failed_at < now - Duration::seconds(window) || lock_ended {
+                        true => (1, None),
+                        false => (failures + 1, locked_until.filter(|until| *until > now)),
+                    }
+                },
+                None => (1, None),
+            };
+            query(r#"
+                INSERT INTO login_failures (subject, failures, last_failed_at, locked_until) VALUES ($1, $2, $3, $4)
+                ON CONFLICT (subject) DO UPDATE SET failures = excluded.failures, last_failed_at = excluded.last_failed_at, locked_until = excluded.locked_until;
+            "#)
+                .bind(subject).bind(failures).bind(now).bind(locked_until)
+                .execute(&mut *transaction).await?;
+            transaction.commit().await?;
+            Ok(failures)
+        })
+    }
+
+    fn lock<'a>(&'a self, subject: &'a str, until: DateTime<Utc>) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            query("UPDATE login_failures SET locked_until = $1 WHERE subject = $2;").bind(until).bind(subject).execute(self).await?;
+            Ok(())
+        })
+    }
+
+    fn clear<'a>(&'a self, subject: &'a str) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            query("DELETE FROM login_failures WHERE subject = $1;").bind(subject).execute(self).await?;
+            Ok(())
+        })
+    }
+}
+
+
+impl AuditStore for Pool<Sqlite> {
+    fn record_event<'a>(&'a self, event: &'a AuditEvent) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let mut transaction = self.begin().await?;
+            let previous = query_as("SELECT sequence, hash FROM audit_events ORDER BY sequence DESC LIMIT 1")
+                .fetch_optional(&mut *transaction).await?;
+            let event = event.clone().chained(previous);
+            query(r#"
+                INSERT INTO audit_events (id, sequence, hash, action, actor_id, target_id, ip, user_agent, details, created_at)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10);
+            "#)
+                .bind(&event.id).bind(event.sequence).bind(&event.hash).bind(&event.action).bind(&event.actor_id).bind(&event.target_id)
+                .bind(&event.ip).bind(&event.user_agent).bind(Json(&event.details)).bind(event.created_at)
+                .execute(&mut *transaction).await?;
+            transaction.commit().await?;
+            Ok(())
+        })
+    }
+
+    ///Only the ids and the action are filtered in SQL, the times are compared in Rust.
+    fn get_events<'a>(&'a self, audit_query: &'a AuditQuery) -> LocalBoxFuture<'a, Result<Vec<AuditEvent>>> {
+        Box::pin(async move {
+            let mut builder = QueryBuilder::new(format!("SELECT {} FROM audit_events WHERE TRUE", AUDIT_EVENT_FIELDS));
+            if let Some(ref actor_id) = audit_query.actor_id {
+                builder.push(" AND actor_id = ").push_bind(actor_id);
+            }
+            if let Some(ref target_id) = audit_query.target_id {
+                builder.push(" AND target_id = ").push_bind(target_id);
+            }
+            if let Some(ref action) = audit_query.action {
+                builder.push(" AND action = ").push_bind(action);
+            }
+            // The times are kept as RFC 3339 text in UTC, which sorts like the times do.
+            if let Some(created_after) = audit_query.created_after {
+                builder.push(" AND created_at >= ").push_bind(created_after);
+            }
+            if let Some(created_before) = audit_query.created_before {
+                builder.push(" AND created_at < ").push_bind(created_before);
+            }
+            if let Some(ref cursor) = audit_query.cursor {
+                builder.push(" AND (created_at, id) < (").push_bind(cursor.created_at).push(", ").push_bind(&cursor.id).push(")");
+            }
+            builder.push(" ORDER BY created_at DESC, id DESC LIMIT ").push_bind(audit_query.limit);
+            Ok(builder.build_query_as().fetch_all(self).await?)
+        })
+    }
+
+    fn get_chain_head<'a>(&'a self) -> LocalBoxFuture<'a, Result<Option<ChainHead>>> {
+        Box::pin(async move {
+            Ok(query_as("SELECT sequence, hash FROM audit_events ORDER BY sequence DESC LIMIT 1").fetch_optional(self).await?)
+        })
+    }
+
+    fn get_chain<'a>(&'a self) -> LocalBoxFuture<'a, Result<Vec<AuditEvent>>> {
+        Box::pin(async move {
+            let sql = format!("SELECT {} FROM audit_events ORDER BY sequence", AUDIT_EVENT_FIELDS);
+            Ok(query_as(&sql).fetch_all(self).await?)
+        })
+    }
+
+    fn record_checkpoint<'a>(&'a self, checkpoint: &'a AuditCheckpoint) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            query("INSERT INTO audit_checkpoints (sequence, hash, signature, key_id, created_at) VALUES ($1, $2, $3, $4, $5);")
+                .bind(checkpoint.sequence).bind(&checkpoint.hash).bind(&checkpoint.signature).bind(checkpoint.key_id).bind(checkpoint.created_at)
+                .execute(self).await?;
+            Ok(())
+        })
+    }
+
+    fn get_checkpoints<'a>(&'a self) -> LocalBoxFuture<'a, Result<Vec<AuditCheckpoint>>> {
+        Box::pin(async move {
+            Ok(query_as("SELECT sequence, hash, signature, key_id, created_at FROM audit_checkpoints ORDER BY sequence").fetch_all(self).await?)
+        })
+    }
+}
+
+
+impl RoleStore for Pool<Sqlite> {
+    fn get_roles<'a>(&'a self) -> LocalBoxFuture<'a, Result<Vec<Role>>> {
+        Box::pin(async move {
+            let sql = format!("{} ORDER BY r.name", SELECT_ROLES);
+            let rows: Vec<RoleRow> = query_as(&sql).fetch_all(self).await?;
+            Ok(rows.into_iter().map(Role::from).collect())
+        })
+    }
+
+    fn get_role<'a>(&'a self, name: &'a str) -> LocalBoxFuture<'a, Result<Role>> {
+        Box::pin(async move {
+            let sql = format!("{} WHERE r.name = $1", SELECT_ROLES);
+            let row: Option<RoleRow> = query_as(&sql).bind(name).fetch_optional(self).await?;
+            row.map(Role::from).ok_or(Error::RoleNotFound)
+        })
+    }
+
+    fn create_role<'a>(&'a self, role: &'a Role) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let mut transaction = self.begin().await?;
+            let result = query("INSERT INTO roles (name, description, created_at) VALUES ($1, $2, $3);")
+                .bind(&role.name).bind(&role.description).bind(role.created_at)
+                .execute(&mut *transaction).await;
+            if let Err(err) = result {
+                return match err.as_database_error().is_some_and(|err| err.is_unique_violation()) {
+                    true => Err(Error::Custom(StatusCode::CONFLICT, "a role with the same name already exists".into())),
+                    false => Err(err.into())
+                };
+            }
+            query("INSERT INTO permissions (role, permission) SELECT $1, value FROM json_each($2) WHERE TRUE ON CONFLICT DO NOTHING;")
+                .bind(&role.name).bind(Json(&role.permissions))
+                .execute(&mut *transaction).await?;
+            transaction.commit().await?;
+            Ok(())
+        })
+    }
+
+    fn update_role<'a>(&'a self, name: &'a str, description: Option<&'a str>, permissions: Option<&'a [String]>) -> LocalBoxFuture<'a, Result<Role>> {
+        Box::pin(async move {
+            let mut transaction = self.begin().await?;
+            let result = query("UPDATE roles SET description = COALESCE($1, description) WHERE name = $2;")
+                .bind(description).bind(name)
+                .execute(&mut *transaction).await?;
+            if result.rows_affected() == 0 {
+                return Err(Error::RoleNotFound);
+            }
+            if let Some(permissions) = permissions {
+                query("DELETE FROM permissions WHERE role = $1;").bind(name).execute(&mut *transaction).await?;
+                query("INSERT INTO permissions (role, permission) SELECT $1, value FROM json_each($2) WHERE TRUE ON CONFLICT DO NOTHING;")
+                    .bind(name).bind(Json(permissions))
+                    .execute(&mut *transaction).await?;
+            }
+            transaction.commit().await?;
+            self.get_role(name).await
+        })
+    }
+
+    fn delete_role<'a>(&'a self, name: &'a str) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let result = query("DELETE FROM roles WHERE name = $1;").bind(name).execute(self).await?;
+            match result.rows_affected() {
+                0 => Err(Error::RoleNotFound),
+                _ => Ok(())
+            }
+        })
+    }
+
+    fn get_user_roles<'a>(&'a self, user_id: &'a Id) -> LocalBoxFuture<'a, Result<Vec<Role>>> {
+        Box::pin(get_user_roles(self, user_id, ""))
+    }
+
+    fn assign_role<'a>(&'a self, user_id: &'a Id, role: &'a str) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let result = query("INSERT INTO user_roles (user_id, role, created_at) VALUES ($1, $2, $3) ON CONFLICT (user_id, role) DO UPDATE SET source = NULL;")
+                .bind(user_id).bind(role).bind(Utc::now())
+                .execute(self).await;
+            match result {
+                Ok(_) => Ok(()),
+                Err(err) => match err.as_database_error().is_some_and(|err| err.is_foreign_key_violation()) {
+                    true => Err(Error::Custom(StatusCode::NOT_FOUND, "user or role not found".into())),
+                    false => Err(err.into())
+                }
+            }
+        })
+    }
+
+    fn sync_source_roles<'a>(&'a self, user_id: &'a Id, source: &'a str, roles: &'a [String]) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let mut transaction = self.begin().await?;
+            query("DELETE FROM user_roles WHERE user_id = $1 AND source = $2 AND role NOT IN (SELECT value FROM json_each($3));")
+                .bind(user_id).bind(source).bind(Json(roles))
+                .execute(&mut *transaction).await?;
+            query("INSERT INTO user_roles (user_id, role, source, created_at) SELECT $1, name, $2, $3 FROM roles WHERE name IN (SELECT value FROM json_each($4)) ON CONFLICT DO NOTHING;")
+                .bind(user_id).bind(source).bind(Utc::now()).bind(Json(roles))
+                .execute(&mut *transaction).await?;
+            transaction.commit().await?;
+            Ok(())
+        })
+    }
+
+    fn unassign_role<'a>(&'a self, user_id: &'a Id, role: &'a str) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            query("DELETE FROM user_roles WHERE user_id = $1 AND role = $2;").bind(user_id).bind(role).execute(self).await?;
+            Ok(())
+        })
+    }
+
+    fn has_permission<'a>(&'a self, user_id: &'a Id, permission: &'a str) -> LocalBoxFuture<'a, Result<bool>> {
+        Box::pin(async move {
+            let sql = r#"
+                SELECT EXISTS (
+                    SELECT 1 FROM user_roles ur JOIN permissions p ON p.role = ur.role JOIN users u ON u.id = ur.user_id
+                    WHERE ur.user_id = $1 AND (p.permission = $2 OR p.permission = $3) AND u.deleted_at IS NULL
+                );
+            "#;
+            Ok(query_scalar(sql).bind(user_id).bind(permission).bind(PERMISSION_ALL).fetch_one(self).await?)
+        })
+    }
+
+    fn get_user_access<'a>(&'a self, user_id: &'a Id) -> LocalBoxFuture<'a, Result<Access>> {
+        Box::pin(async move {
+            let mut access = Access::default();
+            for role in get_user_roles(self, user_id, "AND u.deleted_at IS NULL").await? {
+                for permission in role.permissions {
+                    if !access.permissions.contains(&permission) {
+                        access.permissions.push(permission);
+                    }
+                }
+                access.roles.push(role.name);
+            }
+            Ok(access)
+        })
+    }
+}
+
+
+impl OrganizationStore for Pool<Sqlite> {
+    fn create_organization<'a>(&'a self, organization: &'a Organization, owner: &'a Id) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let mut transaction = self.begin().await?;
+            query("INSERT INTO organizations (id, name, created_at) VALUES ($1, $2, $3);")
+                .bind(&organization.id).bind(&organization.name).bind(organization.created_at)
+                .execute(&mut *transaction).await?;
+            query("INSERT INTO memberships (organization_id, user_id, role, created_at) VALUES ($1, $2, $3, $4);")
+                .bind(&organization.id).bind(owner).bind(OrganizationRole::Owner).bind(Utc::now())
+                .execute(&mut *transaction).await?;
+            transaction.commit().await?;
+            Ok(())
+        })
+    }
+
+    fn get_organization<'a>(&'a self, id: &'a Id) -> LocalBoxFuture<'a, Result<Organization>> {
+        Box::pin(async move {
+            query_as("SELECT * FROM organizations WHERE id = $1").bind(id).fetch_optional(self).await?.ok_or(Error::OrganizationNotFound)
+        })
+    }
+
+    fn get_user_organizations<'a>(&'a self, user_id: &'a Id) -> LocalBoxFuture<'a, Result<Vec<Organization>>> {
+        Box::pin(async move {
+            let sql = r#"
+                SELECT o.* FROM organizations o JOIN memberships m ON m.organization_id = o.id
+                WHERE m.user_id = $1 ORDER BY o.created_at
+            "#;
+            Ok(query_as(sql).bind(user_id).fetch_all(self).await?)
+        })
+    }
+
+    fn update_organization<'a>(&'a self, id: &'a Id, name: &'a str) -> LocalBoxFuture<'a, Result<Organization>> {
+        Box::pin(async move {
+            query_as("UPDATE organizations SET name = $1 WHERE id = $2 RETURNING *;").bind(name).bind(id).fetch_optional(self).await?
+                .ok_or(Error::OrganizationNotFound)
+        })
+    }
+
+    fn delete_organization<'a>(&'a self, id: &'a Id) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let result = query("DELETE FROM organizations WHERE id = $1;").bind(id).execute(self).await?;
+            match result.rows_affected() {
+                0 => Err(Error::OrganizationNotFound),
+                _ => Ok(())
+            }
+        })
+    }
+
+    fn get_membership<'a>(&'a self, organization_id: &'a Id, user_id: &'a Id) -> LocalBoxFuture<'a, Result<Option<Membership>>> {
+        Box::pin(async move {
+            Ok(query_as("SELECT * FROM memberships WHERE organization_id = $1 AND user_id = $2").bind(organization_id).bind(user_id).fetch_optional(self).await?)
+        })
+    }
+
+    fn get_memberships<'a>(&'a self, organization_id: &'a Id) -> LocalBoxFuture<'a, Result<Vec<Membership>>> {
+        Box::pin(async move {
+            Ok(query_as("SELECT * FROM memberships WHERE organization_id = $1 ORDER BY created_at").bind(organization_id).fetch_all(self).await?)
+        })
+    }
+
+    fn update_membership_role<'a>(&'a self, organization_id: &'a Id, user_id: &'a Id, role: OrganizationRole) -> LocalBoxFuture<'a, Result<Membership>> {
+        Box::pin(async move {
+            query_as("UPDATE memberships SET role = $1 WHERE organization_id = $2 AND user_id = $3 RETURNING *;")
+                .bind(role).bind(organization_id).bind(user_id)
+                .fetch_optional(self).await?
+                .ok_or_else(membership_not_found)
+        })
+    }
+
+    fn delete_membership<'a>(&'a self, organization_id: &'a Id, user_id: &'a Id) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let result = query("DELETE FROM memberships WHERE organization_id = $1 AND user_id = $2;")
+                .bind(organization_id).bind(user_id)
+                .execute(self).await?;
+            match result.rows_affected() {
+                0 => Err(membership_not_found()),
+                _ => Ok(())
+            }
+        })
+    }
+
+    fn count_owners<'a>(&'a self, organization_id: &'a Id) -> LocalBoxFuture<'a, Result<i64>> {
+        Box::pin(async move {
+            Ok(query_scalar("SELECT COUNT(*) FROM memberships WHERE organization_id = $1 AND role = $2").bind(organization_id).bind(OrganizationRole::Owner).fetch_one(self).await?)
+        })
+    }
+
+    fn create_invitation<'a>(&'a self, invitation: &'a Invitation) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            query("INSERT INTO invitations (id, organization_id, email, role, invited_by, created_at, expires_at) VALUES ($1, $2, $3, $4, $5, $6, $7);")
+                .bind(invitation.id).bind(&invitation.organization_id).bind(&invitation.email).bind(invitation.role)
+                .bind(&invitation.invited_by).bind(invitation.created_at).bind(invitation.expires_at)
+                .execute(self).await?;
+            Ok(())
+        })
+    }
+
+    fn get_invitation<'a>(&'a self, id: &'a Uuid) -> LocalBoxFuture<'a, Result<Invitation>> {
+        Box::pin(async move {
+            // The times are kept as RFC 3339 text in UTC, which sorts like the times do.
+            query_as("SELECT * FROM invitations WHERE id = $1 AND expires_at > $2").bind(id).bind(Utc::now()).fetch_optional(self).await?
+                .ok_or_else(|| Error::Custom(StatusCode::NOT_FOUND, "invitation not found".into()))
+        })
+    }
+
+    ///`lower` changes the case of ASCII letters only, unlike the one of Postgres.
+    fn get_invitations_by_email<'a>(&'a self, email: &'a str) -> LocalBoxFuture<'a, Result<Vec<Invitation>>> {
+        Box::pin(async move {
+            let sql = "SELECT * FROM invitations WHERE lower(email) = lower($1) AND expires_at > $2 ORDER BY created_at";
+            Ok(query_as(sql).bind(email).bind(Utc::now()).fetch_all(self).await?)
+        })
+    }
+
+    fn get_invitations_by_organization<'a>(&'a self, organization_id: &'a Id) -> LocalBoxFuture<'a, Result<Vec<Invitation>>> {
+        Box::pin(async move {
+            let sql = "SELECT * FROM invitations WHERE organization_id = $1 AND expires_at > $2 ORDER BY created_at";
+            Ok(query_as(sql).bind(organization_id).bind(Utc::now()).fetch_all(self).await?)
+        })
+    }
+
+    fn delete_invitation<'a>(&'a self, id: &'a Uuid) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            query("DELETE FROM invitations WHERE id = $1;").bind(id).execute(self).await?;
+            Ok(())
+        })
+    }
+
+    fn accept_invitation<'a>(&'a self, invitation: &'a Invitation, user_id: &'a Id) -> LocalBoxFuture<'a, Result<Membership>> {
+        Box::pin(async move {
+            let mut transaction = self.begin().await?;
+            let membership = query_as(r#"
+                INSERT INTO memberships (organization_id, user_id, role, created_at) VALUES ($1, $2, $3, $4)
+                ON CONFLICT (organization_id, user_id) DO UPDATE SET role = CASE
+                    WHEN memberships.role = 'owner' OR excluded.role = 'owner' THEN 'owner'
+                    WHEN memberships.role = 'admin' OR excluded.role = 'admin' THEN 'admin'
+                    ELSE 'member'
+                END
+                RETURNING *;
+            "#)
+                .bind(&invitation.organization_id).bind(user_id).bind(invitation.role).bind(Utc::now())
+                .fetch_one(&mut *transaction).await?;
+            query("DELETE FROM invitations WHERE id = $1;").bind(invitation.id).execute(&mut *transaction).await?;
+            transaction.commit().await?;
+            Ok(membership)
+        })
+    }
+}
+
+
+impl ApiKeyStore for Pool<Sqlite> {
+    fn create_api_key<'a>(&'a self, api_key: &'a ApiKey) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            query(r#"
+                INSERT INTO api_keys (id, user_id, name, prefix, secret_hash, scopes, expires_at, created_at)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8);
+            "#)
+                .bind(api_key.id).bind(&api_key.user_id).bind(&api_key.name).bind(&api_key.prefix).bind(&api_key.secret_hash)
+                .bind(Json(&api_key.scopes)).bind(api_key.expires_at).bind(api_key.created_at)
+                .execute(self).await?;
+            Ok(())
+        })
+    }
+
+    fn get_api_key_by_prefix<'a>(&'a self, prefix: &'a str) -> LocalBoxFuture<'a, Result<Option<ApiKey>>> {
+        Box::pin(async move {
+            let row: Option<ApiKeyRow> = query_as("SELECT * FROM api_keys WHERE prefix = $1 AND user_id IN (SELECT id FROM users WHERE deleted_at IS NULL)")
+                .bind(prefix).fetch_optional(self).await?;
+            Ok(row.map(ApiKey::from))
+        })
+    }
+
+    fn get_api_keys_by_user_id<'a>(&'a self, user_id: &'a Id) -> LocalBoxFuture<'a, Result<Vec<ApiKey>>> {
+        Box::pin(async move {
+            let rows: Vec<ApiKeyRow> = query_as("SELECT * FROM api_keys WHERE user_id = $1 ORDER BY created_at").bind(user_id).fetch_all(self).await?;
+            Ok(rows.into_iter().map(ApiKey::from).collect())
+        })
+    }
+
+    fn revoke_api_key<'a>(&'a self, user_id: &'a Id, id: &'a Uuid) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let result = query("UPDATE api_keys SET revoked_at = COALESCE(revoked_at, $1) WHERE id = $2 AND user_id = $3;")
+                .bind(Utc::now()).bind(id).bind(user_id)
+                .execute(self).await?;
+            match result.rows_affected() {
+                0 => Err(Error::Custom(StatusCode::NOT_FOUND, "api key not found".into())),
+                _ => Ok(())
+            }
+        })
+    }
+
+    fn touch_api_key<'a>(&'a self, id: &'a Uuid, used_at: DateTime<Utc>) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            query("UPDATE api_keys SET last_used_at = $1 WHERE id = $2;").bind(used_at).bind(id).execute(self).await?;
+            Ok(())
+        })
+    }
+}
+
+
+impl IdentityStore for Pool<Sqlite> {
+    fn create_identity<'a>(&'a self, identity: &'a Identity) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let result = query("INSERT INTO identities (provider, subject, user_id, email, created_at) VALUES ($1, $2, $3, $4, $5);")
+                .bind(&identity.provider).bind(&identity.subject).bind(&identity.user_id).bind(&identity.email).bind(identity.created_at)
+                .execute(self).await;
+            match result {
+                Ok(_) => Ok(()),
+                Err(err) => match err.as_database_error().is_some_and(|err| err.is_unique_violation()) {
+                    true => Err(Error::Custom(StatusCode::CONFLICT, "the identity is linked to another account".into())),
+                    false => Err(err.into())
+                }
+            }
+        })
+    }
+
+    fn get_identity<'a>(&'a self, provider: &'a str, subject: &'a str) -> LocalBoxFuture<'a, Result<Option<Identity>>> {
+        Box::pin(async move {
+            Ok(query_as("SELECT * FROM identities WHERE provider = $1 AND subject = $2").bind(provider).bind(subject).fetch_optional(self).await?)
+        })
+    }
+
+    fn get_identities_by_user_id<'a>(&'a self, user_id: &'a Id) -> LocalBoxFuture<'a, Result<Vec<Identity>>> {
+        Box::pin(async move {
+            Ok(query_as("SELECT * FROM identities WHERE user_id = $1 ORDER BY created_at").bind(user_id).fetch_all(self).await?)
+        })
+    }
+
+    fn record_assertion<'a>(&'a self, id: &'a str, expires_at: &'a DateTime<Utc>) -> LocalBoxFuture<'a, Result<bool>> {
+        Box::pin(async move {
+            query("DELETE FROM saml_assertions WHERE expires_at < $1;").bind(Utc::now()).execute(self).await?;
+            let result = query("INSERT INTO saml_assertions (id, expires_at) VALUES ($1, $2) ON CONFLICT (id) DO NOTHING;")
+                .bind(id).bind(expires_at)
+                .execute(self).await?;
+            Ok(result.rows_affected() == 1)
+        })
+    }
+}
+
+
+impl PasswordResetStore for Pool<Sqlite> {
+    fn create_password_reset<'a>(&'a self, reset: &'a PasswordReset) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            query("INSERT INTO password_resets (id, user_id, created_at, expires_at) VALUES ($1, $2, $3, $4);")
+                .bind(reset.id).bind(&reset.user_id).bind(reset.created_at).bind(reset.expires_at)
+                .execute(self).await?;
+            Ok(())
+        })
+    }
+
+    fn get_password_reset<'a>(&'a self, id: &'a Uuid) -> LocalBoxFuture<'a, Result<Option<PasswordReset>>> {
+        Box::pin(async move {
+            Ok(query_as("SELECT * FROM password_resets WHERE id = $1 AND expires_at > $2").bind(id).bind(Utc::now()).fetch_optional(self).await?)
+        })
+    }
+
+    fn delete_password_resets_by_user_id<'a>(&'a self, user_id: &'a Id) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            query("DELETE FROM password_resets WHERE user_id = $1;").bind(user_id).execute(self).await?;
+            Ok(())
+        })
+    }
+}
+
+
+impl AccountRestorationStore for Pool<Sqlite> {
+    fn create_account_restoration<'a>(&'a self, restoration: &'a AccountRestoration) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            query("INSERT INTO account_restorations (id, user_id, created_at, expires_at) VALUES ($1, $2, $3, $4);")
+                .bind(restoration.id).bind(&restoration.user_id).bind(restoration.created_at).bind(restoration.expires_at)
+                .execute(self).await?;
+            Ok(())
+        })
+    }
+
+    fn get_account_restoration<'a>(&'a self, id: &'a Uuid) -> LocalBoxFuture<'a, Result<Option<AccountRestoration>>> {
+        Box::pin(async move {
+            Ok(query_as("SELECT * FROM account_restorations WHERE id = $1 AND expires_at > $2").bind(id).bind(Utc::now()).fetch_optional(self).await?)
+        })
+    }
+
+    fn delete_account_restorations_by_user_id<'a>(&'a self, user_id: &'a Id) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            query("DELETE FROM account_restorations WHERE user_id = $1;").bind(user_id).execute(self).await?;
+            Ok(())
+        })
+    }
+}
+
+
+impl RateLimitStore for Pool<Sqlite> {
+    ///SQLite has no row locks. The first insert takes the write lock of the database instead,
+    /// which concurrent takes wait for until the transaction ends.
+    fn take<'a>(&'a self, key: &'a str, take: TakeToken<'a>) -> LocalBoxFuture<'a, Result<Decision>> {
+        Box::pin(async move {
+            let now = Utc::now();
+            let mut transaction = self.begin().await?;
+            let inserted = query("INSERT INTO rate_limits (key, tokens, updated_at, full_at) VALUES ($1, 0, $2, $2) ON CONFLICT (key) DO NOTHING RETURNING key;")
+                .bind(key).bind(now)
+                .fetch_optional(&mut *transaction).await?;
+            let bucket: Option<(f64, DateTime<Utc>)> = match inserted {
+                Some(_) => None,
+                None => query_as("SELECT tokens, updated_at FROM rate_limits WHERE key = $1").bind(key).fetch_optional(&mut *transaction).await?,
+            };
+            let (bucket, full_at, decision) = take(bucket.map(|(tokens, updated_at)| Bucket{tokens, updated_at}));
+            query("UPDATE rate_limits SET tokens = $1, updated_at = $2, full_at = $3 WHERE key = $4;")
+                .bind(bucket.tokens).bind(bucket.updated_at).bind(full_at).bind(key)
+                .execute(&mut *transaction).await?;
+            transaction.commit().await?;
+            Ok(decision)
+        })
+    }
+
+    fn delete_full<'a>(&'a self) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            query("DELETE FROM rate_limits WHERE full_at <= $1;").bind(Utc::now()).execute(self).await?;
+            Ok(())
+        })
+    }
+}
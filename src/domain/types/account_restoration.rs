@@ -0,0 +1,13 @@
+use sqlx::{types::Uuid, FromRow};
+use chrono::{DateTime, Utc};
+use super::Id;
+
+
+///A link mailed to a deleted user to restore their account, valid until they are purged.
+#[derive(Clone, Debug, FromRow)]
+pub struct AccountRestoration {
+    pub id: Uuid,
+    pub user_id: Id,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
@@ -0,0 +1,36 @@
+use serde::{Serialize, Deserialize};
+use sqlx::{types::Uuid, FromRow};
+use chrono::{DateTime, Utc};
+use super::{Id, PERMISSION_ALL};
+
+
+///A long lived credential a user hands to scripts and CI instead of their password.
+/// Only a hash of the secret is stored, the key itself is shown once when it is created.
+#[derive(Clone, Debug, Serialize, Deserialize, FromRow)]
+pub struct ApiKey {
+    pub id: Uuid,
+    pub user_id: Id,
+    pub name: String,
+    ///The public part of the key, used to find it and to tell keys apart.
+    pub prefix: String,
+    #[serde(skip)]
+    pub secret_hash: String,
+    ///The permissions the key may use. The user still needs to have them.
+    pub scopes: Vec<String>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+
+impl ApiKey {
+    ///Checks whether the scopes of the key cover the permission.
+    pub fn allows(&self, permission: &str) -> bool {
+        self.scopes.iter().any(|scope| scope == permission || scope == PERMISSION_ALL)
+    }
+
+    pub fn is_active(&self, now: DateTime<Utc>) -> bool {
+        self.revoked_at.is_none() && self.expires_at.is_none_or(|expires_at| expires_at > now)
+    }
+}
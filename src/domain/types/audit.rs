@@ -0,0 +1,120 @@
+use serde::{Serialize, Deserialize};
+use chrono::{DateTime, Utc};
+use sha2::{Digest, Sha256};
+use serde_json::json;
+use sqlx::FromRow;
+use super::{Id, Value};
+
+
+pub const AUDIT_SIGNUP: &str = "user.signup";
+pub const AUDIT_EMAIL_VERIFIED: &str = "user.email_verified";
+pub const AUDIT_EMAIL_CHANGED: &str = "user.email_changed";
+pub const AUDIT_LOGIN_SUCCEEDED: &str = "login.succeeded";
+pub const AUDIT_LOGIN_FAILED: &str = "login.failed";
+pub const AUDIT_PASSWORD_CHANGED: &str = "user.password_changed";
+pub const AUDIT_PASSWORD_RESET_REQUESTED: &str = "user.password_reset_requested";
+pub const AUDIT_PASSWORD_RESET: &str = "user.password_reset";
+pub const AUDIT_USER_UPDATED: &str = "user.updated";
+pub const AUDIT_USER_UNLOCKED: &str = "user.unlocked";
+pub const AUDIT_USER_DELETED: &str = "user.deleted";
+pub const AUDIT_USER_RESTORED: &str = "user.restored";
+pub const AUDIT_USERS_PURGED: &str = "users.purged";
+pub const AUDIT_USERS_EXPORTED: &str = "users.exported";
+pub const AUDIT_ROLE_CREATED: &str = "role.created";
+pub const AUDIT_ROLE_UPDATED: &str = "role.updated";
+pub const AUDIT_ROLE_DELETED: &str = "role.deleted";
+pub const AUDIT_ROLE_ASSIGNED: &str = "role.assigned";
+pub const AUDIT_ROLE_UNASSIGNED: &str = "role.unassigned";
+pub const AUDIT_API_KEY_CREATED: &str = "api_key.created";
+pub const AUDIT_API_KEY_REVOKED: &str = "api_key.revoked";
+pub const AUDIT_IDENTITY_LINKED: &str = "identity.linked";
+///What the first event of the chain is chained to.
+pub const AUDIT_GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+
+///The sequence and hash of the latest audit event, which the next one is chained to.
+pub type ChainHead = (i64, Option<String>);
+
+
+///Something security relevant that happened, who did it, to whom and from where.
+/// Events are only ever added, never changed. Each one is chained to the one before it by its hash,
+/// so changing or removing one breaks the chain.
+#[derive(Clone, Debug, Serialize, Deserialize, FromRow)]
+pub struct AuditEvent {
+    pub id: Id,
+    ///The position of the event in the chain, starting at 1 with no gaps.
+    pub sequence: i64,
+    ///The hash of the hash of the previous event and the content of this one.
+    /// Nothing for the events recorded before the log was chained.
+    pub hash: Option<String>,
+    ///What happened, one of the `AUDIT_` constants.
+    pub action: String,
+    ///Nobody for what the server does on its own and for what anonymous callers do.
+    pub actor_id: Option<Id>,
+    ///The user the event is about, if it is about one.
+    pub target_id: Option<Id>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    #[sqlx(json)]
+    pub details: Value,
+    pub created_at: DateTime<Utc>,
+}
+
+
+///Sorts the keys of the objects in the value, which otherwise come in the order of the maps they were made from.
+fn sort_keys(value: serde_json::Value) -> serde_json::Value {
+    match value {
+        serde_json::Value::Object(object) => {
+            let mut entries: Vec<(String, serde_json::Value)> = object.into_iter().collect();
+            entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+            serde_json::Value::Object(entries.into_iter().map(|(key, value)| (key, sort_keys(value))).collect())
+        },
+        serde_json::Value::Array(array) => serde_json::Value::Array(array.into_iter().map(sort_keys).collect()),
+        value => value,
+    }
+}
+
+
+impl AuditEvent {
+    ///Hashes the event after the hash of the previous one.
+    /// The content is hashed as JSON with its object keys sorted, and times to the microsecond as they are stored.
+    pub fn chain_hash(&self, previous_hash: &str) -> String {
+        let content = json!({
+            "sequence": self.sequence,
+            "id": self.id.to_hex(),
+            "action": self.action,
+            "actor_id": self.actor_id.as_ref().map(|id| id.to_hex()),
+            "target_id": self.target_id.as_ref().map(|id| id.to_hex()),
+            "ip": self.ip,
+            "user_agent": self.user_agent,
+            "details": self.details,
+            "created_at": self.created_at.timestamp_micros(),
+        });
+        let mut hasher = Sha256::new();
+        hasher.update(previous_hash.as_bytes());
+        hasher.update(sort_keys(content).to_string().as_bytes());
+        hasher.finalize().iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    ///Numbers the event after the previous one and chains it to it.
+    pub fn chained(self, previous: Option<ChainHead>) -> Self {
+        let (sequence, previous_hash) = previous.unwrap_or((0, None));
+        let event = Self{sequence: sequence + 1, ..self};
+        let hash = event.chain_hash(previous_hash.as_deref().unwrap_or(AUDIT_GENESIS_HASH));
+        Self{hash: Some(hash), ..event}
+    }
+}
+
+
+///The hash of the latest audit event at some point, signed so the events up to it can not be rewritten unnoticed.
+#[derive(Clone, Debug, Serialize, Deserialize, FromRow)]
+pub struct AuditCheckpoint {
+    pub sequence: i64,
+    pub hash: String,
+    ///The hex encoded HMAC of the sequence and hash.
+    pub signature: String,
+    ///The version of the checkpoint key the signature was made with.
+    /// Nothing for the checkpoints signed with the JWT secret, before there were checkpoint keys.
+    pub key_id: Option<i64>,
+    pub created_at: DateTime<Utc>,
+}
@@ -0,0 +1,29 @@
+use serde::Serialize;
+use std::fmt;
+
+
+///Why the domain of an email address is refused by the domain policy.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DomainRejection {
+    ///The domain could not have a mail server.
+    InvalidDomain,
+    Blocked,
+    ///Only other domains are allowed.
+    NotAllowed,
+    ///The domain belongs to a disposable email service.
+    Disposable,
+}
+
+
+impl fmt::Display for DomainRejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use DomainRejection::*;
+        match self {
+            InvalidDomain => write!(f, "the email domain can not receive mail"),
+            Blocked => write!(f, "the email domain is blocked"),
+            NotAllowed => write!(f, "the email domain is not allowed"),
+            Disposable => write!(f, "disposable email addresses are not allowed"),
+        }
+    }
+}
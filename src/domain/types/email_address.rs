@@ -1,8 +1,10 @@
-use sqlx::{Encode, Decode, Postgres, Type, ValueRef, types::Json};
+use sqlx::{ColumnIndex, Encode, Decode, FromRow, Postgres, Row, Type, ValueRef, types::Json};
 use sqlx::postgres::{PgTypeInfo, PgValueRef, PgArgumentBuffer};
+use sqlx::{Sqlite, sqlite::{SqliteArgumentValue, SqliteTypeInfo, SqliteValueRef}};
 use serde::{Serialize, Deserialize, Serializer, Deserializer};
 use serde::de::{self, Visitor, MapAccess};
 use serde::ser::SerializeStruct;
+use chrono::{DateTime, Utc};
 use std::str::FromStr;
 use lettre::Address;
 use std::fmt;
@@ -13,6 +15,93 @@ pub enum EmailAddress {
     Verified(Address),
 }
 
+impl EmailAddress {
+    pub fn address(&self) -> &Address {
+        match self {
+            EmailAddress::New(address) | EmailAddress::Verified(address) => address
+        }
+    }
+
+    ///The form of the address two users may not share. The address itself is kept as it was given, for display.
+    pub fn canonical(&self, normalization: &EmailNormalization) -> Option<String> {
+        normalization.canonicalize(self.address().as_ref())
+    }
+}
+
+
+const GMAIL_DOMAINS: &[&str] = &["gmail.com", "googlemail.com"];
+
+
+///How addresses are folded before they are compared, so one mailbox can not sign up twice.
+/// Changing it only affects the addresses stored or looked up afterwards.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EmailNormalization {
+    ///Lower-cases the local part. Almost every provider ignores its case, though the standard lets them not to.
+    pub fold_local_part: bool,
+    ///Drops everything from the first `+` of the local part, which many providers use to tag mail to the same mailbox.
+    pub strip_plus_tags: bool,
+    ///Drops the dots from the local part of Gmail addresses, which Gmail ignores, and treats googlemail.com as gmail.com.
+    pub strip_gmail_dots: bool,
+}
+
+
+impl Default for EmailNormalization {
+    fn default() -> Self {
+        Self {
+            fold_local_part: true,
+            strip_plus_tags: false,
+            strip_gmail_dots: false,
+        }
+    }
+}
+
+
+impl EmailNormalization {
+    ///Returns the canonical form of an address, with the domain lower-cased and in punycode.
+    /// Returns nothing when the address is not one.
+    pub fn canonicalize(&self, email: &str) -> Option<String> {
+        let (local, domain) = email.trim().rsplit_once('@')?;
+        let mut domain = idna::domain_to_ascii(domain).ok()?;
+        if local.is_empty() || domain.is_empty() {
+            return None;
+        }
+        let mut local = match self.fold_local_part {
+            true => local.to_lowercase(),
+            false => local.to_string(),
+        };
+        if self.strip_plus_tags {
+            if let Some((untagged, _)) = local.split_once('+').filter(|(untagged, _)| !untagged.is_empty()) {
+                local = untagged.to_string();
+            }
+        }
+        if self.strip_gmail_dots && GMAIL_DOMAINS.contains(&domain.as_str()) {
+            local.retain(|character| character != '.');
+            domain = GMAIL_DOMAINS[0].to_string();
+        }
+        Some(format!("{}@{}", local, domain))
+    }
+}
+
+
+///Reads the address from the `email` column of a user, verified when `email_verified_at` is set.
+impl<'r, R: Row> FromRow<'r, R> for EmailAddress
+where
+    &'r str: ColumnIndex<R>,
+    String: Decode<'r, R::Database> + Type<R::Database>,
+    Option<DateTime<Utc>>: Decode<'r, R::Database> + Type<R::Database>,
+{
+    fn from_row(row: &'r R) -> Result<Self, sqlx::Error> {
+        let email: String = row.try_get("email")?;
+        let verified_at: Option<DateTime<Utc>> = row.try_get("email_verified_at")?;
+        let address = email.parse::<Address>().map_err(|err| sqlx::Error::ColumnDecode{index: "email".into(), source: Box::new(err)})?;
+        Ok(match verified_at {
+            Some(_) => EmailAddress::Verified(address),
+            None => EmailAddress::New(address)
+        })
+    }
+}
+
 impl Type<Postgres> for EmailAddress {
     fn type_info() -> PgTypeInfo {
         <Json<EmailAddress> as Type<Postgres>>::type_info()
@@ -33,6 +122,25 @@ impl<'r> Decode<'r, Postgres> for EmailAddress {
     }
 }
 
+impl Type<Sqlite> for EmailAddress {
+    fn type_info() -> SqliteTypeInfo {
+        <Json<EmailAddress> as Type<Sqlite>>::type_info()
+    }
+}
+
+impl<'q> Encode<'q, Sqlite> for EmailAddress {
+    fn encode_by_ref(&self, buf: &mut Vec<SqliteArgumentValue<'q>>) -> Result<sqlx::encode::IsNull, Box<dyn std::error::Error + Send + Sync>> {
+        let json = Json::from(self);
+        <Json<&EmailAddress> as Encode<Sqlite>>::encode_by_ref(&json, buf)
+    }
+}
+
+impl<'r> Decode<'r, Sqlite> for EmailAddress {
+    fn decode(value: SqliteValueRef<'r>) -> Result<Self, sqlx::error::BoxDynError> {
+        Ok(<Json<EmailAddress> as Decode<Sqlite>>::decode(value)?.0)
+    }
+}
+
 impl Serialize for EmailAddress {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
@@ -130,7 +238,7 @@ impl<'de> Deserialize<'de> for EmailAddress {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use serde_json;
+    
 
     fn test_serialize(email: EmailAddress, expected_json: &str) {
         let json = serde_json::to_string(&email).unwrap();
@@ -154,6 +262,23 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_canonicalize() {
+        let default = EmailNormalization::default();
+        assert_eq!(default.canonicalize(" Alice@Example.COM").as_deref(), Some("alice@example.com"));
+        assert_eq!(default.canonicalize("jane@bücher.de").as_deref(), Some("jane@xn--bcher-kva.de"));
+        assert_eq!(default.canonicalize("j.doe+news@gmail.com").as_deref(), Some("j.doe+news@gmail.com"));
+        assert_eq!(default.canonicalize("example.com"), None);
+
+        let exact = EmailNormalization{fold_local_part: false, ..Default::default()};
+        assert_eq!(exact.canonicalize("Alice@Example.com").as_deref(), Some("Alice@example.com"));
+
+        let gmail = EmailNormalization{strip_plus_tags: true, strip_gmail_dots: true, ..Default::default()};
+        assert_eq!(gmail.canonicalize("J.Doe+news@GoogleMail.com").as_deref(), Some("jdoe@gmail.com"));
+        assert_eq!(gmail.canonicalize("j.doe+news@example.com").as_deref(), Some("j.doe@example.com"));
+        assert_eq!(gmail.canonicalize("+news@example.com").as_deref(), Some("+news@example.com"));
+    }
+
     #[test]
     fn test_email_address_deserialization() {
         test_deserialize(
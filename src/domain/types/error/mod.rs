@@ -3,6 +3,7 @@ use std::fmt::{Display, Formatter, Result};
 use std::error::Error as StdError;
 use sqlx::Error as SqlxError;
 use serde_json::json;
+use super::{DomainRejection, PasswordViolation};
 
 type DefaultError = Box<dyn StdError>;
 
@@ -10,6 +11,21 @@ type DefaultError = Box<dyn StdError>;
 pub enum Error {
     UserWithEmailExists,
     UserNotFound,
+    RoleNotFound,
+    OrganizationNotFound,
+    ///The request does not carry valid credentials.
+    Unauthorized,
+    ///The caller is authenticated but lacks the permission for the request.
+    Forbidden,
+    ///Too many failed logins. Holds the seconds until another attempt is allowed.
+    Locked(i64),
+    ///The client made too many requests. Holds the seconds until another one is allowed.
+    TooManyRequests(i64),
+    ///The password breaks these rules of the password policy.
+    InvalidPassword(Vec<PasswordViolation>),
+    ///The domain policy refuses the domain of the email.
+    EmailDomainRejected(DomainRejection),
+    #[allow(clippy::enum_variant_names)]
     InternalServerError(Option<DefaultError>),
     Custom(StatusCode, DefaultError)
 }
@@ -21,6 +37,14 @@ impl Display for Error {
         match self {
             UserWithEmailExists => write!(f, "user with the same email already exists."),
             UserNotFound => write!(f, "user not found"),
+            RoleNotFound => write!(f, "role not found"),
+            OrganizationNotFound => write!(f, "organization not found"),
+            Unauthorized => write!(f, "authentication required"),
+            Forbidden => write!(f, "permission denied"),
+            Locked(retry_after) => write!(f, "too many failed logins, retry after {} seconds", retry_after),
+            TooManyRequests(retry_after) => write!(f, "too many requests, retry after {} seconds", retry_after),
+            InvalidPassword(violations) => write!(f, "the password does not meet the password policy: {}", violations.iter().map(|v| v.to_string()).collect::<Vec<_>>().join(", ")),
+            EmailDomainRejected(rejection) => write!(f, "{}", rejection),
             InternalServerError(err) => write!(f, "{}", err.as_ref().unwrap_or(&"internal server error".into())),
             Custom(_, err) => write!(f, "custom: {}", err)
         }
@@ -37,6 +61,21 @@ impl ResponseError for Error {
         match self {
             UserWithEmailExists => HttpResponse::Conflict().json(json!({"message": "user with the same email already exists"})),
             UserNotFound => HttpResponse::NotFound().json(json!({"message": "user not found"})),
+            RoleNotFound => HttpResponse::NotFound().json(json!({"message": "role not found"})),
+            OrganizationNotFound => HttpResponse::NotFound().json(json!({"message": "organization not found"})),
+            Unauthorized => HttpResponse::Unauthorized().insert_header(("WWW-Authenticate", "Bearer")).json(json!({"message": "authentication required"})),
+            Forbidden => HttpResponse::Forbidden().json(json!({"message": "permission denied"})),
+            TooManyRequests(retry_after) => HttpResponse::TooManyRequests().insert_header(("Retry-After", retry_after.to_string())).json(json!({"message": "too many requests"})),
+            InvalidPassword(violations) => {
+                let violations: Vec<_> = violations.iter().map(|violation| {
+                    let mut json = json!(violation);
+                    json["message"] = json!(violation.to_string());
+                    json
+                }).collect();
+                HttpResponse::UnprocessableEntity().json(json!({"message": "the password does not meet the password policy", "violations": violations}))
+            },
+            EmailDomainRejected(rejection) => HttpResponse::UnprocessableEntity().json(json!({"message": rejection.to_string(), "reason": rejection})),
+            Locked(retry_after) => HttpResponse::build(StatusCode::LOCKED).insert_header(("Retry-After", retry_after.to_string())).json(json!({"message": "too many failed logins"})),
             InternalServerError(_) => HttpResponse::InternalServerError().json(json!({"message": "internal server error"})),
             Custom(status, err) => HttpResponse::build(*status).json(json!({"message": format!("{}", err)}))
         }
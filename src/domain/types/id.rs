@@ -1,11 +1,12 @@
 use sqlx::{Encode, Decode, Type, Postgres, postgres::{PgValueRef, PgTypeInfo, PgArgumentBuffer}};
+use sqlx::{Sqlite, sqlite::{SqliteArgumentValue, SqliteTypeInfo, SqliteValueRef}};
 use serde::{Serialize, Deserialize, Serializer};
 use std::ops::{Deref, DerefMut};
 use std::str::FromStr;
 
 use bson::oid::ObjectId;
 
-#[derive(Clone, Debug, Deserialize, Default)]
+#[derive(Clone, Debug, Deserialize, Default, PartialEq, Eq)]
 pub struct Id(ObjectId);
 
 
@@ -32,13 +33,36 @@ impl<'q> Encode<'q, Postgres> for Id {
 impl<'r> Decode<'r, Postgres> for Id {
     fn decode(value: PgValueRef<'r>) -> Result<Self> {
         let bytes: &[u8] = value.as_bytes()?;
-        let byte_array: [u8; 12] = bytes.as_ref().try_into().map_err(|_| "Invalid length")?;
+        let byte_array: [u8; 12] = bytes.try_into().map_err(|_| "Invalid length")?;
         let object_id = ObjectId::from_bytes(byte_array);
         Ok(Id(object_id))
     }
 }
 
 
+impl Type<Sqlite> for Id {
+    fn type_info() -> SqliteTypeInfo {
+        <[u8] as Type<Sqlite>>::type_info()
+    }
+}
+
+
+impl<'q> Encode<'q, Sqlite> for Id {
+    fn encode_by_ref(&self, buf: &mut Vec<SqliteArgumentValue<'q>>) -> Result<sqlx::encode::IsNull> {
+        <Vec<u8> as Encode<Sqlite>>::encode(self.0.bytes().to_vec(), buf)
+    }
+}
+
+
+impl<'r> Decode<'r, Sqlite> for Id {
+    fn decode(value: SqliteValueRef<'r>) -> Result<Self> {
+        let bytes = <&[u8] as Decode<Sqlite>>::decode(value)?;
+        let byte_array: [u8; 12] = bytes.try_into().map_err(|_| "Invalid length")?;
+        Ok(Id(ObjectId::from_bytes(byte_array)))
+    }
+}
+
+
 impl Deref for Id {
     type Target = ObjectId;
     fn deref(&self) -> &Self::Target {
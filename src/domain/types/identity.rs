@@ -0,0 +1,19 @@
+use serde::{Serialize, Deserialize};
+use chrono::{DateTime, Utc};
+use sqlx::FromRow;
+use super::Id;
+
+
+///A link between a `User` and an account at an external identity provider.
+/// One user can have several identities, one per provider.
+#[derive(Clone, Debug, Serialize, Deserialize, FromRow)]
+pub struct Identity {
+    ///The name of the provider as configured in `Config.oidc`
+    pub provider: String,
+    ///The subject (`sub` claim) of the user at the provider.
+    pub subject: String,
+    pub user_id: Id,
+    ///The email the provider had for the user when the identity was linked.
+    pub email: String,
+    pub created_at: DateTime<Utc>,
+}
@@ -1,18 +1,36 @@
 mod email_address;
+mod domain_rejection;
 mod verification;
+mod identity;
+mod api_key;
+mod organization;
+mod role;
 mod number;
+mod page;
+mod password;
 mod value;
 mod error;
 mod user;
 mod id;
+mod audit;
+mod account_restoration;
 
 pub use email_address::*;
+pub use domain_rejection::*;
 pub use verification::*;
+pub use identity::*;
+pub use api_key::*;
+pub use organization::*;
+pub use role::*;
 pub use number::*;
+pub use page::*;
+pub use password::*;
 pub use value::*;
 pub use error::*;
 pub use user::*;
 pub use id::*;
+pub use audit::*;
+pub use account_restoration::*;
 
 
 pub type Mailer = lettre::AsyncSmtpTransport<lettre::Tokio1Executor>;
\ No newline at end of file
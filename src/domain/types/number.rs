@@ -44,7 +44,7 @@ impl Number for i64{
     }
 
     fn encode_by_ref(&self, buf: &mut PgArgumentBuffer) -> Result<IsNull> {
-        <i64 as Encode<Postgres>>::encode_by_ref(&self, buf)
+        <i64 as Encode<Postgres>>::encode_by_ref(self, buf)
     }
 }
 
@@ -54,7 +54,7 @@ impl Number for f64{
     }
 
     fn encode_by_ref(&self, buf: &mut PgArgumentBuffer) -> Result<IsNull> {
-        <f64 as Encode<Postgres>>::encode_by_ref(&self, buf)
+        <f64 as Encode<Postgres>>::encode_by_ref(self, buf)
     }
 }
 
@@ -75,7 +75,7 @@ impl Number for i32{
     }
 
     fn encode_by_ref(&self, buf: &mut PgArgumentBuffer) -> Result<IsNull> {
-        <i32 as Encode<Postgres>>::encode_by_ref(&self, buf)
+        <i32 as Encode<Postgres>>::encode_by_ref(self, buf)
     }
 }
 
@@ -85,7 +85,7 @@ impl Number for f32{
     }
 
     fn encode_by_ref(&self, buf: &mut PgArgumentBuffer) -> Result<IsNull> {
-        <f32 as Encode<Postgres>>::encode_by_ref(&self, buf)
+        <f32 as Encode<Postgres>>::encode_by_ref(self, buf)
     }
 }
 
@@ -106,7 +106,7 @@ impl Number for i16{
     }
 
     fn encode_by_ref(&self, buf: &mut PgArgumentBuffer) -> Result<IsNull> {
-        <i16 as Encode<Postgres>>::encode_by_ref(&self, buf)
+        <i16 as Encode<Postgres>>::encode_by_ref(self, buf)
     }
 }
 
@@ -0,0 +1,124 @@
+use sqlx::{Encode, Decode, Type, Postgres, FromRow, types::Uuid, postgres::{PgValueRef, PgTypeInfo, PgArgumentBuffer}};
+use sqlx::{Sqlite, sqlite::{SqliteArgumentValue, SqliteTypeInfo, SqliteValueRef}};
+use serde::{Serialize, Deserialize};
+use chrono::{DateTime, Utc};
+use std::str::FromStr;
+use super::Id;
+
+
+#[derive(Clone, Debug, Serialize, Deserialize, FromRow)]
+pub struct Organization {
+    #[serde(default)]
+    pub id: Id,
+    pub name: String,
+    #[serde(default = "Utc::now")]
+    pub created_at: DateTime<Utc>,
+}
+
+
+///The role of a member within an organization.
+/// Owners may do everything, admins manage members and invitations, members may only read.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OrganizationRole {
+    Member,
+    Admin,
+    Owner,
+}
+
+
+impl OrganizationRole {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Member => "member",
+            Self::Admin => "admin",
+            Self::Owner => "owner",
+        }
+    }
+}
+
+
+impl FromStr for OrganizationRole {
+    type Err = String;
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "member" => Ok(Self::Member),
+            "admin" => Ok(Self::Admin),
+            "owner" => Ok(Self::Owner),
+            _ => Err(format!("unknown organization role: {}", s)),
+        }
+    }
+}
+
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync + 'static>>;
+
+
+impl Type<Postgres> for OrganizationRole {
+    fn type_info() -> PgTypeInfo {
+        <str as Type<Postgres>>::type_info()
+    }
+
+    fn compatible(ty: &PgTypeInfo) -> bool {
+        <str as Type<Postgres>>::compatible(ty)
+    }
+}
+
+
+impl<'q> Encode<'q, Postgres> for OrganizationRole {
+    fn encode_by_ref(&self, buf: &mut PgArgumentBuffer) -> Result<sqlx::encode::IsNull> {
+        <&str as Encode<Postgres>>::encode(self.as_str(), buf)
+    }
+}
+
+
+impl<'r> Decode<'r, Postgres> for OrganizationRole {
+    fn decode(value: PgValueRef<'r>) -> Result<Self> {
+        let value = <&str as Decode<Postgres>>::decode(value)?;
+        Ok(value.parse()?)
+    }
+}
+
+
+impl Type<Sqlite> for OrganizationRole {
+    fn type_info() -> SqliteTypeInfo {
+        <str as Type<Sqlite>>::type_info()
+    }
+}
+
+
+impl<'q> Encode<'q, Sqlite> for OrganizationRole {
+    fn encode_by_ref(&self, buf: &mut Vec<SqliteArgumentValue<'q>>) -> Result<sqlx::encode::IsNull> {
+        <&str as Encode<Sqlite>>::encode(self.as_str(), buf)
+    }
+}
+
+
+impl<'r> Decode<'r, Sqlite> for OrganizationRole {
+    fn decode(value: SqliteValueRef<'r>) -> Result<Self> {
+        let value = <&str as Decode<Sqlite>>::decode(value)?;
+        Ok(value.parse()?)
+    }
+}
+
+
+#[derive(Clone, Debug, Serialize, Deserialize, FromRow)]
+pub struct Membership {
+    pub organization_id: Id,
+    pub user_id: Id,
+    pub role: OrganizationRole,
+    pub created_at: DateTime<Utc>,
+}
+
+
+///An invitation to join an organization sent to an email address.
+#[derive(Clone, Debug, Serialize, Deserialize, FromRow)]
+pub struct Invitation {
+    pub id: Uuid,
+    pub organization_id: Id,
+    pub email: String,
+    pub role: OrganizationRole,
+    pub invited_by: Id,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
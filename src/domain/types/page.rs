@@ -0,0 +1,59 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use serde::{Serialize, Deserialize};
+use chrono::{DateTime, Utc};
+use super::Id;
+
+
+///A page of a listing and the cursor to fetch the next one with.
+#[derive(Clone, Debug, Serialize)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    ///`None` on the last page.
+    pub next_cursor: Option<String>,
+}
+
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    Asc,
+    #[default]
+    Desc,
+}
+
+
+///The position after the last item of a page, ordered by creation time and then by id.
+/// It is handed to clients as an opaque string.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Cursor {
+    pub created_at: DateTime<Utc>,
+    pub id: Id,
+}
+
+
+impl Cursor {
+    pub fn encode(&self) -> String {
+        URL_SAFE_NO_PAD.encode(format!("{}:{}", self.created_at.timestamp_micros(), self.id.to_hex()))
+    }
+
+    pub fn decode(cursor: &str) -> Option<Self> {
+        let bytes = URL_SAFE_NO_PAD.decode(cursor).ok()?;
+        let decoded = String::from_utf8(bytes).ok()?;
+        let (micros, id) = decoded.split_once(':')?;
+        let created_at = DateTime::from_timestamp_micros(micros.parse().ok()?)?;
+        Some(Self{created_at, id: id.parse().ok()?})
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cursor_round_trip() {
+        let cursor = Cursor{created_at: DateTime::from_timestamp_micros(Utc::now().timestamp_micros()).unwrap(), id: Id::default()};
+        assert_eq!(Cursor::decode(&cursor.encode()), Some(cursor));
+        assert_eq!(Cursor::decode("not a cursor"), None);
+    }
+}
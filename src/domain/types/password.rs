@@ -0,0 +1,59 @@
+use sqlx::{types::Uuid, FromRow};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::fmt;
+use super::Id;
+
+
+///A rule of the password policy a password breaks.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(tag = "rule", rename_all = "snake_case")]
+pub enum PasswordViolation {
+    TooShort{min_length: usize},
+    TooLong{max_length: usize},
+    MissingLowercase,
+    MissingUppercase,
+    MissingDigit,
+    MissingSymbol,
+    ///The password resembles one of the fields of the user.
+    SimilarToUserData{field: &'static str},
+    Common,
+    ///The password appears in a known data breach.
+    Breached,
+}
+
+
+impl fmt::Display for PasswordViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use PasswordViolation::*;
+        match self {
+            TooShort{min_length} => write!(f, "the password needs at least {} characters", min_length),
+            TooLong{max_length} => write!(f, "the password can have at most {} characters", max_length),
+            MissingLowercase => write!(f, "the password needs a lowercase letter"),
+            MissingUppercase => write!(f, "the password needs an uppercase letter"),
+            MissingDigit => write!(f, "the password needs a digit"),
+            MissingSymbol => write!(f, "the password needs a symbol"),
+            SimilarToUserData{field} => write!(f, "the password is too similar to the {}", field.replace('_', " ")),
+            Common => write!(f, "the password is too common"),
+            Breached => write!(f, "the password appears in a known data breach"),
+        }
+    }
+}
+
+
+///A request to reset the password of a user, sent to them as a link.
+#[derive(Clone, Debug, FromRow)]
+pub struct PasswordReset {
+    pub id: Uuid,
+    pub user_id: Id,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+
+///How many password hashes were made with the current Argon2 parameters and pepper, and how many were not.
+#[derive(Debug, Serialize)]
+pub struct PasswordHashReport {
+    pub total: i64,
+    pub outdated: i64,
+}
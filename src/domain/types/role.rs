@@ -0,0 +1,35 @@
+use serde::{Serialize, Deserialize};
+use chrono::{DateTime, Utc};
+use sqlx::FromRow;
+
+
+///The role every permission is granted to. It is created with the database.
+pub const ADMIN_ROLE: &str = "admin";
+///Grants every permission.
+pub const PERMISSION_ALL: &str = "*";
+pub const PERMISSION_USERS_READ: &str = "users:read";
+pub const PERMISSION_USERS_WRITE: &str = "users:write";
+pub const PERMISSION_USERS_DELETE: &str = "users:delete";
+pub const PERMISSION_USERS_EXPORT_HASHES: &str = "users:export-hashes";
+pub const PERMISSION_ROLES_MANAGE: &str = "roles:manage";
+pub const PERMISSION_AUDIT_READ: &str = "audit:read";
+
+
+#[derive(Clone, Debug, Serialize, Deserialize, FromRow)]
+pub struct Role {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub permissions: Vec<String>,
+    #[serde(default = "Utc::now")]
+    pub created_at: DateTime<Utc>,
+}
+
+
+///The roles of a user and the permissions they grant.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Access {
+    pub roles: Vec<String>,
+    pub permissions: Vec<String>,
+}
@@ -1,17 +1,20 @@
 use chrono::{DateTime, offset::Utc, TimeZone};
 use serde::{Serialize, Deserialize};
-use sqlx::{Encode, Decode, FromRow};
+use sqlx::{Encode, Decode, FromRow, types::Uuid};
 use super::{Id, EmailAddress};
 
 
-pub const FIELDS: &'static [&'static str] = &["id", "email", "user_name", "first_name", "last_name", "password", "created_at", "profile_picture"];
+pub const FIELDS: &[&str] = &["id", "email", "email_verified_at", "user_name", "first_name", "last_name", "password", "created_at", "profile_picture"];
 
 
 #[derive(Clone, Debug, Serialize, Deserialize, Encode, Decode, FromRow)]
 pub struct User {
     #[serde(default)]
     pub id: Id,
+    #[sqlx(flatten)]
     pub email: EmailAddress,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub email_verified_at: Option<DateTime<Utc>>,
     pub user_name: String,
     pub first_name: String,
     pub last_name: String,
@@ -26,6 +29,15 @@ pub struct User {
 
 
 impl User {
+    ///When the email address was verified. Users verified before it was recorded
+    /// count as verified when they were created.
+    pub fn verified_at(&self) -> Option<DateTime<Utc>> {
+        match self.email {
+            EmailAddress::Verified(_) => Some(self.email_verified_at.unwrap_or(self.created_at)),
+            EmailAddress::New(_) => None
+        }
+    }
+
     const LENGTH: usize = FIELDS.len()-1;
 
     pub fn fields() -> [&'static str; Self::LENGTH] {
@@ -42,4 +54,21 @@ impl User {
         }
         filtered
     }
-}
\ No newline at end of file
+}
+
+
+///A user named Jane Doe with an unverified email and no password, for the tests.
+#[cfg(test)]
+pub fn test_user(email: &str) -> User {
+    User {
+        id: Default::default(),
+        email: EmailAddress::New(email.parse().unwrap()),
+        email_verified_at: None,
+        user_name: "jane".into(),
+        first_name: "Jane".into(),
+        last_name: "Doe".into(),
+        password: String::new(),
+        created_at: Utc::now(),
+        profile_picture: None,
+    }
+}
@@ -1,4 +1,5 @@
 use sqlx::{postgres::{PgArgumentBuffer, Postgres, PgTypeInfo}, Encode, encode::IsNull, types::Json, Type};
+use sqlx::sqlite::{Sqlite, SqliteArgumentValue, SqliteTypeInfo};
 use std::{any::TypeId, error::Error as StdError};
 use serde::{Serialize, Deserialize};
 use std::fmt::{Formatter, Display};
@@ -34,10 +35,7 @@ impl<N: Number> Type<Postgres> for Value<N> {
 
 impl<'a, N: Number + 'static + Encode<'a, Postgres> + Serialize + Default + PartialEq> Value<N> {
     pub fn is_map(&self) -> bool {
-        match self {
-            Self::Map(_) => true,
-            _ => false,
-        }
+        matches!(self, Self::Map(_))
     }
 
     pub fn type_id(&self) -> Option<TypeId> {
@@ -52,14 +50,14 @@ impl<'a, N: Number + 'static + Encode<'a, Postgres> + Serialize + Default + Part
     }
 
 
-    fn encode_array(array: &Vec<Value<N>>, buf: &mut PgArgumentBuffer) -> Result<IsNull> {
+    fn encode_array(array: &[Value<N>], buf: &mut PgArgumentBuffer) -> Result<IsNull> {
         let mut type_id = None;
-        if array.len() == 0 {
+        if array.is_empty() {
             return Ok(IsNull::Yes);
         }
 
         buf.push(b'{');
-        for (index, value) in array.into_iter().enumerate() {
+        for (index, value) in array.iter().enumerate() {
             if index > 0 {
                 buf.push(b',');
             }
@@ -99,15 +97,7 @@ impl<'a, N: Number + 'static + Encode<'a, Postgres> + Serialize + Default + Part
     }
 
     pub fn as_option_from_option(option: Option<Self>) -> Option<Self> {
-        if let Some(value) = option {
-            if value.is_empty() || value.is_zero() {
-                None
-            }else {
-                Some(value)
-            }
-        }else {
-            None
-        }
+        option.filter(|value| !(value.is_empty() || value.is_zero()))
     }
 
     fn array_postgres_type(&self) -> Option<<Postgres as sqlx::Database>::TypeInfo> {
@@ -117,7 +107,7 @@ impl<'a, N: Number + 'static + Encode<'a, Postgres> + Serialize + Default + Part
         }?;
         let mut type_option = None;
         for item in array {
-            if let Some(typ) = item.produces() {
+            if let Some(typ) = <Value<N> as Encode<Postgres>>::produces(item) {
                 match type_option {
                     None => type_option = Some(typ),
                     Some(ref type_id) => if typ != *type_id {return None}
@@ -156,4 +146,33 @@ impl<'a, N: Number + 'static + Encode<'a, Postgres> + Serialize + Default + Part
             Self::Map(_) => Some(<Value as Type<Postgres>>::type_info())
         }
     }
-}
\ No newline at end of file
+}
+
+
+impl<N: Number> Type<Sqlite> for Value<N> {
+    fn type_info() -> SqliteTypeInfo {
+        <str as Type<Sqlite>>::type_info()
+    }
+
+    fn compatible(_ty: &SqliteTypeInfo) -> bool {
+        true
+    }
+}
+
+
+///SQLite columns take any type, so numbers are stored as numbers and arrays and maps as JSON text.
+impl<'q, N: Number + Serialize> Encode<'q, Sqlite> for Value<N> {
+    fn encode_by_ref(&self, buf: &mut Vec<SqliteArgumentValue<'q>>) -> Result<IsNull> {
+        match self {
+            Value::None => Ok(IsNull::Yes),
+            Value::Bool(b) => <bool as Encode<Sqlite>>::encode_by_ref(b, buf),
+            Value::Number(n) => match serde_json::to_value(n)? {
+                serde_json::Value::Number(n) if n.is_i64() => <i64 as Encode<Sqlite>>::encode(n.as_i64().unwrap_or_default(), buf),
+                serde_json::Value::Number(n) => <f64 as Encode<Sqlite>>::encode(n.as_f64().unwrap_or_default(), buf),
+                _ => <String as Encode<Sqlite>>::encode(n.to_string(), buf),
+            },
+            Value::String(s) => <String as Encode<Sqlite>>::encode(s.clone(), buf),
+            Value::Array(_) | Value::Map(_) => <String as Encode<Sqlite>>::encode(serde_json::to_string(self)?, buf),
+        }
+    }
+}
@@ -3,7 +3,7 @@ use sqlx::{types::Uuid, FromRow};
 use chrono::{DateTime, Utc};
 use super::Id;
 
-#[derive(Debug, Serialize, Deserialize, FromRow)]
+#[derive(Clone, Debug, Serialize, Deserialize, FromRow)]
 pub struct Verification {
     pub id: Uuid,
     pub user_id: Id,
@@ -2,14 +2,18 @@
 mod server;
 mod domain;
 mod config;
+mod cli;
 
 
 use domain::*;
+use clap::Parser;
 
 
 type Result<T> = std::result::Result<T, Box<dyn std::error::Error + 'static>>;
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    Ok(server::start().await?)
+    // What the server does in the background is logged, at the level of `RUST_LOG`.
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+    cli::Cli::parse().run().await
 }
@@ -0,0 +1,49 @@
+use actix_web::{delete, get, post, web::{Data, Json, Path}, HttpResponse, Responder, http::StatusCode};
+use crate::{api_key::{self, NewApiKey}, ApiKeyStore, Error, Id, RoleStore, UserStore, PERMISSION_USERS_READ, PERMISSION_USERS_WRITE};
+use super::auth::{Authenticated, Credential};
+use super::audit::Audit;
+use crate::{AUDIT_API_KEY_CREATED, AUDIT_API_KEY_REVOKED};
+use sqlx::types::Uuid;
+use serde_json::json;
+use super::*;
+
+
+fn parse_id(id: &str) -> Result<Id> {
+    id.parse().map_err(|_| Error::Custom(StatusCode::BAD_REQUEST, "invalid id".into()))
+}
+
+
+///Creates an API key. The key is only part of this response.
+/// Users create keys for themselves, and only when signed in with a token rather than with another key.
+#[post("/users/{id}/api-keys")]
+async fn create_api_key(id: Path<String>, new_key: Json<NewApiKey>, api_keys: Data<dyn ApiKeyStore>, caller: Authenticated, audit: Audit) -> Result<impl Responder> {
+    let id = parse_id(&id)?;
+    if caller.user_id != id || matches!(caller.credential, Credential::ApiKey(_)) {
+        return Err(Error::Forbidden);
+    }
+    let (api_key, key) = api_key::create_api_key(&**api_keys, &id, new_key.into_inner()).await?;
+    audit.record(Some(&caller.user_id), AUDIT_API_KEY_CREATED, Some(&id), json!({"api_key_id": api_key.id, "name": api_key.name, "scopes": api_key.scopes})).await;
+    Ok(HttpResponse::Created().json(json!({"api_key": api_key, "key": key})))
+}
+
+
+#[get("/users/{id}/api-keys")]
+async fn get_api_keys(id: Path<String>, users: Data<dyn UserStore>, api_keys: Data<dyn ApiKeyStore>, roles: Data<dyn RoleStore>, caller: Authenticated) -> Result<impl Responder> {
+    let id = parse_id(&id)?;
+    caller.require_self_or(&**roles, &id, PERMISSION_USERS_READ).await?;
+    let api_keys = api_key::get_api_keys(&**users, &**api_keys, &id).await?;
+    Ok(HttpResponse::Ok().json(api_keys))
+}
+
+
+///Revokes an API key. Revoked keys are still listed so their use can be audited.
+#[delete("/users/{id}/api-keys/{key_id}")]
+async fn delete_api_key(path: Path<(String, String)>, api_keys: Data<dyn ApiKeyStore>, roles: Data<dyn RoleStore>, caller: Authenticated, audit: Audit) -> Result<impl Responder> {
+    let (id, key_id) = path.into_inner();
+    let id = parse_id(&id)?;
+    let key_id = key_id.parse::<Uuid>().map_err(|_| Error::Custom(StatusCode::BAD_REQUEST, "Invalid UUID format".into()))?;
+    caller.require_self_or(&**roles, &id, PERMISSION_USERS_WRITE).await?;
+    api_key::revoke_api_key(&**api_keys, &id, &key_id).await?;
+    audit.record(Some(&caller.user_id), AUDIT_API_KEY_REVOKED, Some(&id), json!({"api_key_id": key_id})).await;
+    Ok(HttpResponse::Ok().json(json!("api key revoked successfully")))
+}
@@ -0,0 +1,65 @@
+use actix_web::{dev::Payload, get, http::header, web::{Data, Path, Query}, FromRequest, HttpRequest, HttpResponse, Responder, http::StatusCode};
+use crate::audit::{self, AuditListing, Origin};
+use crate::{AuditStore, Error, Id, RoleStore, PERMISSION_AUDIT_READ};
+use super::auth::{Authenticated, Authorized, ReadAudit};
+use std::future::{ready, Ready};
+use log::error;
+use super::*;
+
+
+///Records the audit events of a request along with where the request came from.
+pub struct Audit {
+    store: Data<dyn AuditStore>,
+    origin: Origin,
+}
+
+
+impl Audit {
+    ///Records that the actor did something, to the target user if there is one.
+    /// The details are any JSON object. It is recorded after what it describes is done, which stays done
+    /// when the event can not be recorded, so the failure is logged rather than failing the response.
+    pub async fn record(&self, actor_id: Option<&Id>, action: &str, target_id: Option<&Id>, details: serde_json::Value) {
+        let result = match serde_json::from_value(details) {
+            Ok(details) => audit::record(&**self.store, &self.origin, actor_id, action, target_id, details).await,
+            Err(_) => Err("the details of the audit event are not valid".into()),
+        };
+        if let Err(err) = result {
+            error!("could not record the audit event {}: {}", action, err);
+        }
+    }
+}
+
+
+impl FromRequest for Audit {
+    type Error = Error;
+    type Future = Ready<Result<Self>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let origin = Origin {
+            ip: client_ip(req).map(|address| address.to_string()),
+            user_agent: req.headers().get(header::USER_AGENT).and_then(|value| value.to_str().ok()).map(str::to_string),
+        };
+        ready(match req.app_data::<Data<dyn AuditStore>>() {
+            Some(store) => Ok(Self{store: store.clone(), origin}),
+            None => Err("the audit store is missing".into()),
+        })
+    }
+}
+
+
+///Lists the audit events, filtered and paginated by the query string.
+#[get("/audit-events")]
+async fn get_audit_events(listing: Query<AuditListing>, audit: Data<dyn AuditStore>, _: Authorized<ReadAudit>) -> Result<impl Responder> {
+    let events = audit::get_events(&**audit, listing.into_inner()).await?;
+    Ok(HttpResponse::Ok().json(events))
+}
+
+
+///Lists what happened to the account of a user, for the user themselves or whoever may read the audit events.
+#[get("/users/{id}/activity")]
+async fn get_user_activity(id: Path<String>, listing: Query<AuditListing>, audit: Data<dyn AuditStore>, roles: Data<dyn RoleStore>, caller: Authenticated) -> Result<impl Responder> {
+    let id = id.as_str().parse().map_err(|_|{Error::Custom(StatusCode::BAD_REQUEST, "invalid id".into())})?;
+    caller.require_self_or(&**roles, &id, PERMISSION_AUDIT_READ).await?;
+    let events = audit::get_activity(&**audit, &id, listing.into_inner()).await?;
+    Ok(HttpResponse::Ok().json(events))
+}
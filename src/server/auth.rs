@@ -0,0 +1,211 @@
+use actix_web::{dev::Payload, web::Data, FromRequest, HttpRequest, http::header};
+use crate::{api_key, rbac, token, ApiKey, ApiKeyStore, Error, Id, RoleStore, UserStore};
+use std::{future::Future, pin::Pin};
+use crate::token::Claims;
+use std::marker::PhantomData;
+use crate::config::Config;
+
+type LocalBoxFuture<T> = Pin<Box<dyn Future<Output = T>>>;
+
+
+///How the caller of a request proved who they are.
+#[derive(Clone, Debug)]
+pub enum Credential {
+    Token(Claims),
+    ApiKey(ApiKey),
+}
+
+
+///The caller of a request, identified by the JWT or API key in its `Authorization` header.
+/// Deleted users are turned away, though their tokens have not expired yet.
+#[derive(Clone, Debug)]
+pub struct Authenticated {
+    pub user_id: Id,
+    pub credential: Credential,
+}
+
+
+impl Authenticated {
+    fn authenticate(req: &HttpRequest) -> LocalBoxFuture<Result<Self, Error>> {
+        let config = req.app_data::<Data<Config>>().cloned();
+        let api_keys = req.app_data::<Data<dyn ApiKeyStore>>().cloned();
+        let users = req.app_data::<Data<dyn UserStore>>().cloned();
+        let credential = req.headers().get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map(|value| value.trim().to_string());
+        Box::pin(async move {
+            let credential = credential.ok_or(Error::Unauthorized)?;
+            if credential.starts_with(api_key::KEY_PREFIX) {
+                let api_keys = api_keys.ok_or("the API key store is missing")?;
+                let api_key = api_key::authenticate(&**api_keys, &credential).await?;
+                return Ok(Self{user_id: api_key.user_id.clone(), credential: Credential::ApiKey(api_key)});
+            }
+            let config = config.ok_or("the server configuration is missing")?;
+            let claims = token::verify_token(&config.jwt, &credential).map_err(|_| Error::Unauthorized)?;
+            let user_id = claims.sub.parse().map_err(|_| Error::Unauthorized)?;
+            let users = users.ok_or("the user store is missing")?;
+            match users.get_user_by_id(&user_id).await {
+                Ok(_) => Ok(Self{user_id, credential: Credential::Token(claims)}),
+                Err(Error::UserNotFound) => Err(Error::Unauthorized),
+                Err(err) => Err(err),
+            }
+        })
+    }
+
+    ///The organization the caller is acting in, as chosen when the token was issued.
+    pub fn organization(&self) -> Option<Id> {
+        match self.credential {
+            Credential::Token(ref claims) => claims.org.as_deref().and_then(|id| id.parse().ok()),
+            Credential::ApiKey(_) => None,
+        }
+    }
+
+    ///Fails with `Error::Forbidden` when the caller uses an API key whose scopes do not cover the permission.
+    fn require_scope(&self, permission: &str) -> Result<(), Error> {
+        match self.credential {
+            Credential::ApiKey(ref api_key) if !api_key.allows(permission) => Err(Error::Forbidden),
+            _ => Ok(())
+        }
+    }
+
+    ///Fails with `Error::Forbidden` unless the caller has the permission.
+    pub async fn require(&self, roles: &dyn RoleStore, permission: &str) -> Result<(), Error> {
+        self.require_scope(permission)?;
+        rbac::require_permission(roles, &self.user_id, permission).await
+    }
+
+    ///Fails with `Error::Forbidden` unless the caller is the target user or has the permission.
+    pub async fn require_self_or(&self, roles: &dyn RoleStore, target: &Id, permission: &str) -> Result<(), Error> {
+        self.require_scope(permission)?;
+        rbac::require_self_or_permission(roles, &self.user_id, target, permission).await
+    }
+}
+
+
+impl FromRequest for Authenticated {
+    type Error = Error;
+    type Future = LocalBoxFuture<Result<Self, Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        Authenticated::authenticate(req)
+    }
+}
+
+
+///A permission an endpoint can require through the [`Authorized`] extractor.
+pub trait Permission {
+    const NAME: &'static str;
+}
+
+
+pub struct ManageRoles;
+
+impl Permission for ManageRoles {
+    const NAME: &'static str = crate::PERMISSION_ROLES_MANAGE;
+}
+
+
+pub struct ReadUsers;
+
+impl Permission for ReadUsers {
+    const NAME: &'static str = crate::PERMISSION_USERS_READ;
+}
+
+
+pub struct WriteUsers;
+
+impl Permission for WriteUsers {
+    const NAME: &'static str = crate::PERMISSION_USERS_WRITE;
+}
+
+
+pub struct DeleteUsers;
+
+impl Permission for DeleteUsers {
+    const NAME: &'static str = crate::PERMISSION_USERS_DELETE;
+}
+
+
+pub struct ReadAudit;
+
+impl Permission for ReadAudit {
+    const NAME: &'static str = crate::PERMISSION_AUDIT_READ;
+}
+
+
+///An authenticated caller whose roles grant the permission `P`.
+pub struct Authorized<P: Permission> {
+    pub caller: Authenticated,
+    permission: PhantomData<P>,
+}
+
+
+impl<P: Permission + 'static> FromRequest for Authorized<P> {
+    type Error = Error;
+    type Future = LocalBoxFuture<Result<Self, Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let caller = Authenticated::authenticate(req);
+        let roles = req.app_data::<Data<dyn RoleStore>>().cloned();
+        Box::pin(async move {
+            let caller = caller.await?;
+            let roles = roles.ok_or("the role store is missing")?;
+            caller.require(&**roles, P::NAME).await?;
+            Ok(Self{caller, permission: PhantomData})
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+    use crate::test_user;
+    use chrono::Utc;
+
+    #[actix_web::test]
+    async fn test_authenticated_reads_bearer_token() {
+        let config = super::super::test_config();
+        let user = test_user("user@domain.com");
+        let token = token::issue_token(&config.jwt, &user, None, None).unwrap();
+        let data = Data::new(config);
+        let users = super::super::Stores::new(crate::MemoryStore::default()).users;
+        users.create_user(&user, "user@domain.com").await.unwrap();
+        let request = || TestRequest::default().app_data(data.clone()).app_data(users.clone());
+
+        let req = request().insert_header((header::AUTHORIZATION, format!("Bearer {}", token))).to_http_request();
+        let caller = Authenticated::extract(&req).await.unwrap();
+        assert_eq!(caller.user_id, user.id);
+
+        let req = request().to_http_request();
+        assert!(matches!(Authenticated::extract(&req).await, Err(Error::Unauthorized)));
+
+        let req = request().insert_header((header::AUTHORIZATION, "Bearer invalid")).to_http_request();
+        assert!(matches!(Authenticated::extract(&req).await, Err(Error::Unauthorized)));
+
+        users.delete_user_by_id(&user.id, Utc::now() + chrono::Duration::days(1)).await.unwrap();
+        let req = request().insert_header((header::AUTHORIZATION, format!("Bearer {}", token))).to_http_request();
+        assert!(matches!(Authenticated::extract(&req).await, Err(Error::Unauthorized)));
+    }
+
+    #[test]
+    fn test_api_keys_are_limited_to_their_scopes() {
+        let api_key = ApiKey {
+            id: Default::default(),
+            user_id: Default::default(),
+            name: "ci".into(),
+            prefix: "abcdefgh".into(),
+            secret_hash: Default::default(),
+            scopes: vec![crate::PERMISSION_USERS_READ.into()],
+            expires_at: None,
+            last_used_at: None,
+            revoked_at: None,
+            created_at: Utc::now(),
+        };
+        let caller = Authenticated{user_id: api_key.user_id.clone(), credential: Credential::ApiKey(api_key)};
+        assert!(caller.require_scope(crate::PERMISSION_USERS_READ).is_ok());
+        assert!(matches!(caller.require_scope(crate::PERMISSION_USERS_DELETE), Err(Error::Forbidden)));
+    }
+}
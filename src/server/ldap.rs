@@ -0,0 +1,51 @@
+use actix_web::{post, web::{Data, Json}, HttpResponse, Responder, http::StatusCode};
+use crate::ldap::{self, LdapDirectory};
+use crate::{token, Error, IdentityStore, RoleStore, UserStore, AUDIT_IDENTITY_LINKED, AUDIT_LOGIN_FAILED, AUDIT_LOGIN_SUCCEEDED};
+use crate::email_domain::DomainPolicy;
+use super::oidc::require_token;
+use super::auth::Authenticated;
+use super::audit::Audit;
+use crate::config::Config;
+use serde::Deserialize;
+use serde_json::json;
+use super::*;
+
+
+#[derive(Deserialize)]
+struct LdapLogin {
+    username: String,
+    password: String,
+}
+
+
+///Signs a user in with their directory credentials.
+/// The local user is created on the first sign in and updated from the directory on every sign in.
+#[post("/ldap/login")]
+async fn ldap_login(login: Json<LdapLogin>, config: Data<Config>, users: Data<dyn UserStore>, identities: Data<dyn IdentityStore>, roles: Data<dyn RoleStore>, domains: Data<DomainPolicy>, audit: Audit) -> Result<impl Responder> {
+    let ldap_config = config.ldap.as_ref().ok_or_else(|| Error::Custom(StatusCode::NOT_FOUND, "ldap authentication is not enabled".into()))?;
+    let mut directory = LdapDirectory::connect(ldap_config).await?;
+    let directory_user = match ldap::authenticate(&mut directory, ldap_config, &login.username, &login.password).await {
+        Ok(directory_user) => directory_user,
+        Err(err) => {
+            audit.record(None, AUDIT_LOGIN_FAILED, None, json!({"method": "ldap", "username": login.username, "reason": err.to_string()})).await;
+            return Err(err);
+        }
+    };
+    let user = ldap::provision(&**users, &**identities, &**roles, &config.email_normalization, &domains, ldap_config, &directory_user).await?;
+    audit.record(Some(&user.id), AUDIT_LOGIN_SUCCEEDED, Some(&user.id), json!({"method": "ldap"})).await;
+    let token = token::issue_user_token(&**roles, &config.jwt, &user, None).await?;
+    Ok(HttpResponse::Ok().json(json!({"user": user, "token": token})))
+}
+
+
+///Links the directory account of the signed in caller to their account, given its credentials.
+#[post("/ldap/link")]
+async fn ldap_link(login: Json<LdapLogin>, config: Data<Config>, users: Data<dyn UserStore>, identities: Data<dyn IdentityStore>, caller: Authenticated, audit: Audit) -> Result<impl Responder> {
+    require_token(&caller)?;
+    let ldap_config = config.ldap.as_ref().ok_or_else(|| Error::Custom(StatusCode::NOT_FOUND, "ldap authentication is not enabled".into()))?;
+    let mut directory = LdapDirectory::connect(ldap_config).await?;
+    let directory_user = ldap::authenticate(&mut directory, ldap_config, &login.username, &login.password).await?;
+    let user = ldap::link(&**users, &**identities, &caller.user_id, &directory_user).await?;
+    audit.record(Some(&user.id), AUDIT_IDENTITY_LINKED, Some(&user.id), json!({"provider": ldap::PROVIDER})).await;
+    Ok(HttpResponse::Ok().json(json!({"user": user})))
+}
@@ -1,17 +1,38 @@
-use actix_web::{HttpServer, App, Responder, web, get, post, error::{InternalError, JsonPayloadError}, HttpRequest, HttpResponse, Error as ActixError};
-use sqlx::{Pool, Postgres};
+use actix_web::{HttpServer, App, Responder, web, get, post, middleware, error::{InternalError, JsonPayloadError}, HttpRequest, HttpResponse, Error as ActixError};
 use static_init::dynamic;
 use serde_json::json;
 use verification::{verify_magic_link, verify_user};
-use super::Error;
+use oidc::{oidc_login, oidc_link, oidc_callback};
+use ldap::{ldap_login, ldap_link};
+use saml::{saml_metadata, saml_login, saml_link, saml_acs};
+use role::{get_roles, create_role, update_role, delete_role, get_user_roles, assign_role, unassign_role};
+use organization::{
+    create_organization, get_organizations, switch_organization, get_organization, update_organization, delete_organization,
+    get_members, update_member, remove_member, invite, get_organization_invitations, revoke_invitation,
+    get_invitations, accept_invitation, decline_invitation,
+};
+use api_key::{create_api_key, get_api_keys, delete_api_key};
+use audit::{get_audit_events, get_user_activity};
+use super::{AccountRestorationStore, ApiKeyStore, AuditStore, Error, IdentityStore, LoginFailureStore, OrganizationStore};
+use super::{PasswordResetStore, RateLimitStore, RoleStore, Store, UserStore, VerificationStore};
+use std::sync::Arc;
+use std::net::IpAddr;
 use user::*;
 
 mod user;
 mod verification;
+mod oidc;
+mod ldap;
+mod saml;
+mod auth;
+mod role;
+mod organization;
+mod api_key;
+mod rate_limit;
+mod audit;
 
 
 type Result<T> = std::result::Result<T, Error>;
-type Db = Pool<Postgres>;
 
 #[dynamic]
 static PORT: u16 = read_port("PORT").unwrap_or(8080);
@@ -19,29 +40,157 @@ static PORT: u16 = read_port("PORT").unwrap_or(8080);
 ///Start a new Http server.
 pub async fn start() -> super::Result<()> {
     let config = crate::config::Config::read().await?;
-    let db = config.database.init().await?;
+    let stores = match config.database.is_sqlite() {
+        true => Stores::new(config.database.init_sqlite().await?),
+        false => Stores::new(config.database.init().await?),
+    };
+    crate::rbac::bootstrap_admins(&**stores.users, &**stores.roles, &config.email_normalization, &config.admins).await?;
     let mailer = config.mail.mailer()?;
-    let argon2 = config.argon.initialize_argon2().await;
-    let data = web::Data::new((db, mailer, argon2));
+    let argon2 = config.argon.initialize_argon2();
+    let data = web::Data::new((mailer, argon2));
+    let rate_limiter = web::Data::new(rate_limit::RateLimiter::new(&config.rate_limit, stores.rate_limits.clone()));
+    let breached = web::Data::new(crate::breach::BreachCorpus::load(&config.breached_passwords)?);
+    let domains = web::Data::new(crate::email_domain::DomainPolicy::load(&config.email_domains)?);
+    let client = web::Data::new(reqwest::Client::new());
+    let config = web::Data::new(config);
+    let purge = {
+        let (users, audit, config) = (stores.users.clone(), stores.audit.clone(), config.clone());
+        async move { crate::user::purge_deleted_users(&**users, &**audit, &config).await }
+    };
+    let checkpoints = {
+        let (audit, config) = (stores.audit.clone(), config.clone());
+        async move { crate::audit::sign_checkpoints(&**audit, &config).await }
+    };
     let json_config = web::JsonConfig::default().error_handler(json_error_handler);
-    HttpServer::new(move|| {
+    let server = HttpServer::new(move|| {
         App::new()
+        .wrap(middleware::from_fn(rate_limit::rate_limit))
         .app_data(json_config.clone())
+        .app_data(rate_limiter.clone())
         .app_data(data.clone())
-        .service(hello)
+        .configure(|service| stores.configure(service))
+        .app_data(config.clone())
+        .app_data(breached.clone())
+        .app_data(domains.clone())
+        .app_data(client.clone())
         .service(signup)
+        .service(password_login)
+        .service(change_password)
+        .service(change_email)
+        .service(request_password_reset)
+        .service(reset_password)
+        .service(get_password_hash_report)
+        .service(unlock_user)
+        .service(get_users)
+        // Registered before `get_user`, whose `/users/{id}` would match it.
+        .service(export_users)
         .service(get_user)
         .service(delete_user)
+        .service(restore_account)
+        .service(restore_user)
         .service(update_user)
         .service(verify_magic_link)
         .service(verify_user)
+        .service(oidc_login)
+        .service(oidc_link)
+        .service(oidc_callback)
+        .service(ldap_login)
+        .service(ldap_link)
+        .service(saml_metadata)
+        .service(saml_login)
+        .service(saml_link)
+        .service(saml_acs)
+        .service(get_roles)
+        .service(create_role)
+        .service(update_role)
+        .service(delete_role)
+        .service(get_user_roles)
+        .service(assign_role)
+        .service(unassign_role)
+        .service(create_organization)
+        .service(get_organizations)
+        .service(switch_organization)
+        .service(get_organization)
+        .service(update_organization)
+        .service(delete_organization)
+        .service(get_members)
+        .service(update_member)
+        .service(remove_member)
+        .service(invite)
+        .service(get_organization_invitations)
+        .service(revoke_invitation)
+        .service(get_invitations)
+        .service(accept_invitation)
+        .service(decline_invitation)
+        .service(create_api_key)
+        .service(get_api_keys)
+        .service(delete_api_key)
+        .service(get_audit_events)
+        .service(get_user_activity)
+        // Registered last, its path would match every other single segment path.
+        .service(hello)
     })
     .bind(("127.0.0.1", *PORT))?
-    .run()
-    .await?;
+    .run();
+    // The futures of the stores are not `Send`, so the purge and the checkpoints run on this thread alongside the server.
+    let local = tokio::task::LocalSet::new();
+    local.spawn_local(purge);
+    local.spawn_local(checkpoints);
+    local.run_until(server).await?;
     Ok(())
 }
 
+///Every store the handlers take, shared from one backend.
+#[derive(Clone)]
+pub struct Stores {
+    pub users: web::Data<dyn UserStore>,
+    pub verifications: web::Data<dyn VerificationStore>,
+    pub login_failures: web::Data<dyn LoginFailureStore>,
+    pub audit: web::Data<dyn AuditStore>,
+    pub roles: web::Data<dyn RoleStore>,
+    pub organizations: web::Data<dyn OrganizationStore>,
+    pub api_keys: web::Data<dyn ApiKeyStore>,
+    pub identities: web::Data<dyn IdentityStore>,
+    pub password_resets: web::Data<dyn PasswordResetStore>,
+    pub account_restorations: web::Data<dyn AccountRestorationStore>,
+    pub rate_limits: Arc<dyn RateLimitStore>,
+}
+
+
+impl Stores {
+    pub fn new<B: Store + 'static>(backend: B) -> Self {
+        let backend = Arc::new(backend);
+        Self {
+            users: web::Data::from(backend.clone() as Arc<dyn UserStore>),
+            verifications: web::Data::from(backend.clone() as Arc<dyn VerificationStore>),
+            login_failures: web::Data::from(backend.clone() as Arc<dyn LoginFailureStore>),
+            audit: web::Data::from(backend.clone() as Arc<dyn AuditStore>),
+            roles: web::Data::from(backend.clone() as Arc<dyn RoleStore>),
+            organizations: web::Data::from(backend.clone() as Arc<dyn OrganizationStore>),
+            api_keys: web::Data::from(backend.clone() as Arc<dyn ApiKeyStore>),
+            identities: web::Data::from(backend.clone() as Arc<dyn IdentityStore>),
+            password_resets: web::Data::from(backend.clone() as Arc<dyn PasswordResetStore>),
+            account_restorations: web::Data::from(backend.clone() as Arc<dyn AccountRestorationStore>),
+            rate_limits: backend,
+        }
+    }
+
+    ///Registers the stores as the app data of the handlers.
+    pub fn configure(&self, service: &mut web::ServiceConfig) {
+        service
+            .app_data(self.users.clone())
+            .app_data(self.verifications.clone())
+            .app_data(self.login_failures.clone())
+            .app_data(self.audit.clone())
+            .app_data(self.roles.clone())
+            .app_data(self.organizations.clone())
+            .app_data(self.api_keys.clone())
+            .app_data(self.identities.clone())
+            .app_data(self.password_resets.clone())
+            .app_data(self.account_restorations.clone());
+    }
+}
+
 /// This function reads the posrt to be used from the environment variable with the given key.
 /// if No value was set it returns None.
 /// if the value set could not be converted to an int. also returns None.
@@ -49,14 +198,31 @@ fn read_port(key: &'static str) -> Option<u16> {
     match std::env::var(key) {
         Err(_) => None,
         Ok(value) => {
-            match value.parse::<u16>() {
-                Err(_) => None,
-                Ok(value) => Some(value)
-            }
+            value.parse::<u16>().ok()
         }
     }
 }
 
+///Returns the address of the client. When the request comes from a trusted proxy, it is the last address
+/// of the `X-Forwarded-For` header that is not one of a trusted proxy, as the ones before it could be forged.
+fn client_ip(req: &HttpRequest) -> Option<IpAddr> {
+    let peer = req.peer_addr()?.ip();
+    let trusted = match req.app_data::<web::Data<crate::config::Config>>() {
+        Some(config) => &config.trusted_proxies,
+        None => return Some(peer),
+    };
+    if !trusted.contains(&peer) {
+        return Some(peer);
+    }
+    let forwarded: Vec<IpAddr> = req.headers().get_all("X-Forwarded-For")
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|address| address.trim().parse().ok())
+        .collect();
+    let client = forwarded.iter().rev().find(|address| !trusted.contains(address)).or(forwarded.first());
+    Some(*client.unwrap_or(&peer))
+}
+
 #[get("/{name}")]
 async fn hello(name: web::Path<String>) -> impl Responder {
     format!("<h1>Hello {name}</h1>")
@@ -67,3 +233,52 @@ fn json_error_handler(err: JsonPayloadError, _req: &HttpRequest) -> ActixError {
     let res = HttpResponse::BadRequest().json(json!({"message": format!("{}", err)}));
     InternalError::from_response(err, res).into()
 }
+
+
+///A configuration with every section at its default, for tests.
+#[cfg(test)]
+fn test_config() -> crate::config::Config {
+    crate::config::Config {
+        mail: crate::config::Mail {credentials: None, url: "smtp://localhost".into(), sender: "sender@domain.com".parse().unwrap()},
+        database: Default::default(),
+        argon: Default::default(),
+        jwt: Default::default(),
+        public_url: "http://localhost:8080".into(),
+        lockout: Default::default(),
+        deletion: Default::default(),
+        audit_log: Default::default(),
+        rate_limit: Default::default(),
+        password_policy: Default::default(),
+        breached_passwords: Default::default(),
+        email_normalization: Default::default(),
+        email_domains: Default::default(),
+        oidc: Default::default(),
+        ldap: Default::default(),
+        saml: Default::default(),
+        admins: Default::default(),
+        trusted_proxies: Default::default(),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    #[test]
+    fn test_client_ip_is_only_forwarded_by_trusted_proxies() {
+        let proxy: IpAddr = "10.0.0.1".parse().unwrap();
+        let config = web::Data::new(crate::config::Config{trusted_proxies: vec![proxy], ..test_config()});
+        let request = |peer: &str, forwarded: &str| TestRequest::default()
+            .app_data(config.clone())
+            .peer_addr(format!("{}:1234", peer).parse().unwrap())
+            .insert_header(("X-Forwarded-For", forwarded))
+            .to_http_request();
+        let ip = |address: &str| Some(address.parse::<IpAddr>().unwrap());
+        assert_eq!(client_ip(&request("203.0.113.9", "198.51.100.1")), ip("203.0.113.9"));
+        assert_eq!(client_ip(&request("10.0.0.1", "198.51.100.1")), ip("198.51.100.1"));
+        assert_eq!(client_ip(&request("10.0.0.1", "192.0.2.7, 198.51.100.1, 10.0.0.1")), ip("198.51.100.1"));
+        assert_eq!(client_ip(&request("10.0.0.1", "garbage")), ip("10.0.0.1"));
+    }
+}
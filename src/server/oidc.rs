@@ -0,0 +1,185 @@
+use actix_web::{get, post, web::{Data, Path, Query}, HttpRequest, HttpResponse, Responder, http::{header, StatusCode}};
+use actix_web::cookie::{time, Cookie, SameSite};
+use crate::identity::{PendingLink, LINK_EXPIRES_IN};
+use crate::{oidc, token, Error, Id, IdentityStore, RoleStore, UserStore, AUDIT_IDENTITY_LINKED, AUDIT_LOGIN_SUCCEEDED};
+use crate::email_domain::DomainPolicy;
+use super::auth::{Authenticated, Credential};
+use super::audit::Audit;
+use crate::config::{Config, OidcProvider};
+use serde::Deserialize;
+use serde_json::json;
+use super::*;
+
+
+///The cookie the secret of a pending link is kept in until the provider sends the browser back.
+const LINK_COOKIE: &str = "identity_link";
+
+
+#[derive(Deserialize)]
+struct CallbackQuery {
+    code: String,
+    state: String,
+}
+
+
+fn provider<'a>(config: &'a Config, name: &str) -> Result<&'a OidcProvider> {
+    config.oidc_provider(name).ok_or_else(|| Error::Custom(StatusCode::NOT_FOUND, "unknown identity provider".into()))
+}
+
+
+fn redirect_uri(config: &Config, provider: &OidcProvider) -> String {
+    config.link(&callback_path(provider))
+}
+
+
+///Fails unless the caller signed in with a token rather than an API key. Users link identities to their account themselves.
+pub(super) fn require_token(caller: &Authenticated) -> Result<()> {
+    match caller.credential {
+        Credential::ApiKey(_) => Err(Error::Forbidden),
+        Credential::Token(_) => Ok(()),
+    }
+}
+
+
+///The cookie keeping the secret of a pending link. It is only sent back to the path the provider returns the browser to,
+/// with the given same site policy, as some providers return it with a cross site `POST`.
+pub(super) fn link_cookie(config: &Config, path: &str, secret: String, same_site: SameSite) -> Cookie<'static> {
+    let path = url::Url::parse(&config.link(path)).map(|url| url.path().to_string()).unwrap_or_else(|_| path.to_string());
+    Cookie::build(LINK_COOKIE, secret)
+        .path(path)
+        .http_only(true)
+        .secure(true)
+        .same_site(same_site)
+        .max_age(time::Duration::seconds(LINK_EXPIRES_IN))
+        .finish()
+}
+
+
+///Returns the user the pending link is to, when the browser completing it is the one which started it,
+/// along with the removal of the cookie, as the link is done with.
+pub(super) fn complete_link(req: &HttpRequest, config: &Config, path: &str, link: PendingLink) -> Result<(Id, Cookie<'static>)> {
+    let secret = req.cookie(LINK_COOKIE);
+    let user_id = link.verify(secret.as_ref().map(|cookie| cookie.value()))?;
+    let mut removal = link_cookie(config, path, String::new(), SameSite::Lax);
+    removal.make_removal();
+    Ok((user_id, removal))
+}
+
+
+fn callback_path(provider: &OidcProvider) -> String {
+    format!("/oidc/{}/callback", provider.name)
+}
+
+
+///Redirects the user to the identity provider to sign in.
+#[get("/oidc/{provider}/login")]
+async fn oidc_login(name: Path<String>, config: Data<Config>, client: Data<reqwest::Client>) -> Result<impl Responder> {
+    let provider = provider(&config, &name)?;
+    let redirect_uri = redirect_uri(&config, provider);
+    let url = oidc::authorization_url(&client, provider, &config.jwt, &redirect_uri, None).await?;
+    Ok(HttpResponse::Found().insert_header((header::LOCATION, url.as_str())).finish())
+}
+
+
+///Returns the url the signed in caller has to send their browser to, to link their identity at the provider to their account.
+/// The link is completed when the provider sends the same browser back to the callback, with the cookie set here.
+#[post("/oidc/{provider}/link")]
+async fn oidc_link(name: Path<String>, config: Data<Config>, client: Data<reqwest::Client>, caller: Authenticated) -> Result<impl Responder> {
+    require_token(&caller)?;
+    let provider = provider(&config, &name)?;
+    let redirect_uri = redirect_uri(&config, provider);
+    let (link, secret) = PendingLink::new(&caller.user_id);
+    let url = oidc::authorization_url(&client, provider, &config.jwt, &redirect_uri, Some(link)).await?;
+    Ok(HttpResponse::Ok()
+        .cookie(link_cookie(&config, &callback_path(provider), secret, SameSite::Lax))
+        .json(json!({"url": url.as_str()})))
+}
+
+
+///The identity provider redirects the user back here after they have signed in.
+/// When the login was started to link the identity, the browser has to be the one which started it.
+#[get("/oidc/{provider}/callback")]
+#[allow(clippy::too_many_arguments)]
+async fn oidc_callback(name: Path<String>, query: Query<CallbackQuery>, config: Data<Config>, client: Data<reqwest::Client>, users: Data<dyn UserStore>, identities: Data<dyn IdentityStore>, roles: Data<dyn RoleStore>, domains: Data<DomainPolicy>, audit: Audit, req: HttpRequest) -> Result<impl Responder> {
+    let provider = provider(&config, &name)?;
+    let (claims, link) = oidc::authenticate(&client, provider, &config.jwt, &query.code, &query.state).await?;
+    if let Some(link) = link {
+        let (user_id, removal) = complete_link(&req, &config, &callback_path(provider), link)?;
+        let user = oidc::link_or_create_user(&**users, &**identities, &config.email_normalization, &domains, provider, claims, Some(&user_id)).await?;
+        audit.record(Some(&user.id), AUDIT_IDENTITY_LINKED, Some(&user.id), json!({"provider": provider.name})).await;
+        return Ok(HttpResponse::Ok().cookie(removal).json(json!({"user": user})));
+    }
+    let user = oidc::link_or_create_user(&**users, &**identities, &config.email_normalization, &domains, provider, claims, None).await?;
+    audit.record(Some(&user.id), AUDIT_LOGIN_SUCCEEDED, Some(&user.id), json!({"method": "oidc", "provider": provider.name})).await;
+    let token = token::issue_user_token(&**roles, &config.jwt, &user, None).await?;
+    Ok(HttpResponse::Ok().json(json!({"user": user, "token": token})))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, App};
+    use crate::{test_user, MemoryStore};
+    use crate::oidc::mock::mock_provider;
+    use super::super::Stores;
+    use std::collections::HashMap;
+
+    ///Links an identity at the mock provider to a signed in user. The provider sends the browser back without the token,
+    /// so the link is only completed by the browser holding the cookie set when it was started.
+    async fn test_link_identity(stores: Stores) {
+        let provider = mock_provider().await;
+        let config = Config{oidc: vec![provider], ..super::super::test_config()};
+        let user = test_user("jane@example.com");
+        stores.users.create_user(&user, "jane@example.com").await.unwrap();
+        let bearer = (header::AUTHORIZATION, format!("Bearer {}", token::issue_token(&config.jwt, &user, None, None).unwrap()));
+        let identities = stores.identities.clone();
+        let app = test::init_service(App::new()
+            .app_data(Data::new(DomainPolicy::load(&config.email_domains).unwrap()))
+            .app_data(Data::new(config))
+            .app_data(Data::new(reqwest::Client::new()))
+            .configure(|service| stores.configure(service))
+            .service(oidc_link)
+            .service(oidc_callback)
+        ).await;
+
+        let res = test::call_service(&app, test::TestRequest::post().uri("/oidc/mock/link").to_request()).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        let res = test::call_service(&app, test::TestRequest::post().uri("/oidc/mock/link").insert_header(bearer.clone()).to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let cookie = res.response().cookies().find(|cookie| cookie.name() == LINK_COOKIE).unwrap().into_owned();
+        assert_eq!(cookie.path(), Some("/oidc/mock/callback"));
+        assert_eq!(cookie.http_only(), Some(true));
+        let body: serde_json::Value = test::read_body_json(res).await;
+        let url = url::Url::parse(body["url"].as_str().unwrap()).unwrap();
+        let query: HashMap<_, _> = url.query_pairs().into_owned().collect();
+        // The mock provider takes the nonce as the authorization code.
+        let callback = format!("/oidc/mock/callback?code={}&state={}", query["nonce"], query["state"]);
+
+        let res = test::call_service(&app, test::TestRequest::get().uri(&callback).insert_header(bearer).to_request()).await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        let other_browser = Cookie::new(LINK_COOKIE, "another secret");
+        let res = test::call_service(&app, test::TestRequest::get().uri(&callback).cookie(other_browser).to_request()).await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        assert!(identities.get_identity("mock", "subject").await.unwrap().is_none());
+
+        let res = test::call_service(&app, test::TestRequest::get().uri(&callback).cookie(cookie).to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let removal = res.response().cookies().find(|cookie| cookie.name() == LINK_COOKIE).unwrap().into_owned();
+        assert_eq!(removal.max_age(), Some(time::Duration::ZERO));
+        let body: serde_json::Value = test::read_body_json(res).await;
+        assert_eq!(body["user"]["id"], json!(user.id));
+        assert!(body.get("token").is_none());
+        assert_eq!(identities.get_identity("mock", "subject").await.unwrap().unwrap().user_id, user.id);
+    }
+    #[actix_web::test]
+    async fn test_link_identity_in_memory() {
+        test_link_identity(Stores::new(MemoryStore::default())).await;
+    }
+
+    #[actix_web::test]
+    async fn test_link_identity_on_sqlite() {
+        let database = crate::config::Database{url: "sqlite::memory:".into(), ..Default::default()};
+        test_link_identity(Stores::new(database.init_sqlite().await.unwrap())).await;
+    }
+}
@@ -0,0 +1,159 @@
+use actix_web::{delete, get, post, put, web::{Data, Json, Path}, HttpResponse, Responder, http::StatusCode};
+use crate::{organization::{self, InvitationRequest}, Error, Id, Mailer, OrganizationRole, OrganizationStore, RoleStore, UserStore};
+use super::auth::Authenticated;
+use crate::config::Config;
+use serde::Deserialize;
+use sqlx::types::Uuid;
+use serde_json::json;
+use argon2::Argon2;
+use super::*;
+
+
+#[derive(Deserialize)]
+struct OrganizationBody {
+    name: String,
+}
+
+
+#[derive(Deserialize)]
+struct MemberRole {
+    role: OrganizationRole,
+}
+
+
+#[derive(Deserialize)]
+struct SwitchOrganization {
+    ///The organization to act in. `null` switches back to acting outside of any organization.
+    organization: Option<String>,
+}
+
+
+fn parse_id(id: &str) -> Result<Id> {
+    id.parse().map_err(|_| Error::Custom(StatusCode::BAD_REQUEST, "invalid id".into()))
+}
+
+
+fn parse_uuid(id: &str) -> Result<Uuid> {
+    id.parse().map_err(|_| Error::Custom(StatusCode::BAD_REQUEST, "Invalid UUID format".into()))
+}
+
+
+#[post("/organizations")]
+async fn create_organization(body: Json<OrganizationBody>, organizations: Data<dyn OrganizationStore>, caller: Authenticated) -> Result<impl Responder> {
+    let organization = organization::create_organization(&**organizations, &caller.user_id, &body.name).await?;
+    Ok(HttpResponse::Created().json(organization))
+}
+
+
+#[get("/organizations")]
+async fn get_organizations(organizations: Data<dyn OrganizationStore>, caller: Authenticated) -> Result<impl Responder> {
+    let organizations = organization::get_organizations(&**organizations, &caller.user_id).await?;
+    Ok(HttpResponse::Ok().json(organizations))
+}
+
+
+///Returns a new token scoped to the chosen organization.
+#[post("/organizations/switch")]
+async fn switch_organization(body: Json<SwitchOrganization>, users: Data<dyn UserStore>, organizations: Data<dyn OrganizationStore>, roles: Data<dyn RoleStore>, config: Data<Config>, caller: Authenticated) -> Result<impl Responder> {
+    let id = body.organization.as_deref().map(parse_id).transpose()?;
+    let token = organization::switch_organization(&**users, &**organizations, &**roles, &config.jwt, &caller.user_id, id.as_ref()).await?;
+    Ok(HttpResponse::Ok().json(json!({"token": token})))
+}
+
+
+#[get("/organizations/{id}")]
+async fn get_organization(id: Path<String>, organizations: Data<dyn OrganizationStore>, caller: Authenticated) -> Result<impl Responder> {
+    let id = parse_id(&id)?;
+    let organization = organization::get_organization(&**organizations, &caller.user_id, &id).await?;
+    Ok(HttpResponse::Ok().json(organization))
+}
+
+
+#[put("/organizations/{id}")]
+async fn update_organization(id: Path<String>, body: Json<OrganizationBody>, organizations: Data<dyn OrganizationStore>, caller: Authenticated) -> Result<impl Responder> {
+    let id = parse_id(&id)?;
+    let organization = organization::update_organization(&**organizations, &caller.user_id, &id, &body.name).await?;
+    Ok(HttpResponse::Ok().json(organization))
+}
+
+
+#[delete("/organizations/{id}")]
+async fn delete_organization(id: Path<String>, organizations: Data<dyn OrganizationStore>, caller: Authenticated) -> Result<impl Responder> {
+    let id = parse_id(&id)?;
+    organization::delete_organization(&**organizations, &caller.user_id, &id).await?;
+    Ok(HttpResponse::Ok().json(json!("organization deleted successfully")))
+}
+
+
+#[get("/organizations/{id}/members")]
+async fn get_members(id: Path<String>, organizations: Data<dyn OrganizationStore>, caller: Authenticated) -> Result<impl Responder> {
+    let id = parse_id(&id)?;
+    let members = organization::get_members(&**organizations, &caller.user_id, &id).await?;
+    Ok(HttpResponse::Ok().json(members))
+}
+
+
+#[put("/organizations/{id}/members/{user_id}")]
+async fn update_member(path: Path<(String, String)>, body: Json<MemberRole>, organizations: Data<dyn OrganizationStore>, caller: Authenticated) -> Result<impl Responder> {
+    let (id, member_id) = path.into_inner();
+    let (id, member_id) = (parse_id(&id)?, parse_id(&member_id)?);
+    let membership = organization::update_member_role(&**organizations, &caller.user_id, &id, &member_id, body.role).await?;
+    Ok(HttpResponse::Ok().json(membership))
+}
+
+
+#[delete("/organizations/{id}/members/{user_id}")]
+async fn remove_member(path: Path<(String, String)>, organizations: Data<dyn OrganizationStore>, caller: Authenticated) -> Result<impl Responder> {
+    let (id, member_id) = path.into_inner();
+    let (id, member_id) = (parse_id(&id)?, parse_id(&member_id)?);
+    organization::remove_member(&**organizations, &caller.user_id, &id, &member_id).await?;
+    Ok(HttpResponse::Ok().json(json!("member removed successfully")))
+}
+
+
+#[post("/organizations/{id}/invitations")]
+async fn invite(id: Path<String>, body: Json<InvitationRequest>, users: Data<dyn UserStore>, organizations: Data<dyn OrganizationStore>, data: Data<(Mailer, Argon2<'_>)>, config: Data<Config>, caller: Authenticated) -> Result<impl Responder> {
+    let id = parse_id(&id)?;
+    let invitation = organization::invite(&**users, &**organizations, &data.0, &config, &caller.user_id, &id, body.into_inner()).await?;
+    Ok(HttpResponse::Created().json(invitation))
+}
+
+
+#[get("/organizations/{id}/invitations")]
+async fn get_organization_invitations(id: Path<String>, organizations: Data<dyn OrganizationStore>, caller: Authenticated) -> Result<impl Responder> {
+    let id = parse_id(&id)?;
+    let invitations = organization::get_organization_invitations(&**organizations, &caller.user_id, &id).await?;
+    Ok(HttpResponse::Ok().json(invitations))
+}
+
+
+#[delete("/organizations/{id}/invitations/{invitation_id}")]
+async fn revoke_invitation(path: Path<(String, String)>, organizations: Data<dyn OrganizationStore>, caller: Authenticated) -> Result<impl Responder> {
+    let (id, invitation_id) = path.into_inner();
+    let (id, invitation_id) = (parse_id(&id)?, parse_uuid(&invitation_id)?);
+    organization::revoke_invitation(&**organizations, &caller.user_id, &id, &invitation_id).await?;
+    Ok(HttpResponse::Ok().json(json!("invitation revoked successfully")))
+}
+
+
+#[get("/invitations")]
+async fn get_invitations(users: Data<dyn UserStore>, organizations: Data<dyn OrganizationStore>, caller: Authenticated) -> Result<impl Responder> {
+    let invitations = organization::get_user_invitations(&**users, &**organizations, &caller.user_id).await?;
+    Ok(HttpResponse::Ok().json(invitations))
+}
+
+
+#[post("/invitations/{id}/accept")]
+async fn accept_invitation(id: Path<String>, users: Data<dyn UserStore>, organizations: Data<dyn OrganizationStore>, caller: Authenticated) -> Result<impl Responder> {
+    let id = parse_uuid(&id)?;
+    let membership = organization::accept_invitation(&**users, &**organizations, &caller.user_id, &id).await?;
+    Ok(HttpResponse::Ok().json(membership))
+}
+
+
+#[post("/invitations/{id}/decline")]
+async fn decline_invitation(id: Path<String>, users: Data<dyn UserStore>, organizations: Data<dyn OrganizationStore>, caller: Authenticated) -> Result<impl Responder> {
+    let id = parse_uuid(&id)?;
+    organization::decline_invitation(&**users, &**organizations, &caller.user_id, &id).await?;
+    Ok(HttpResponse::Ok().json(json!("invitation declined successfully")))
+}
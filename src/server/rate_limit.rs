@@ -0,0 +1,167 @@
+use actix_web::{body::{EitherBody, MessageBody}, dev::{ServiceRequest, ServiceResponse}, middleware::Next};
+use actix_web::{http::header::{self, HeaderName, HeaderValue}, web::{Bytes, Data}, Error as ActixError, ResponseError};
+use crate::config::{Config, RateLimit, RateLimitKey, RateLimitPolicy};
+use crate::rate_limit::{Decision, RateLimitBuckets};
+use crate::RateLimitStore;
+use std::collections::HashMap;
+use std::sync::Arc;
+use crate::{token, Error};
+use super::client_ip;
+use serde_json::Value;
+
+
+///The rate limiting policies and the buckets they are counted in.
+pub struct RateLimiter {
+    pub policies: Vec<RateLimitPolicy>,
+    pub buckets: RateLimitBuckets,
+}
+
+
+impl RateLimiter {
+    pub fn new(config: &RateLimit, store: Arc<dyn RateLimitStore>) -> Self {
+        Self{policies: config.policies.clone(), buckets: RateLimitBuckets::new(config.backend, store)}
+    }
+}
+
+
+///Matches a path against a route pattern and returns the values of its `{name}` segments.
+fn match_path<'a>(pattern: &'a str, path: &'a str) -> Option<HashMap<&'a str, &'a str>> {
+    let patterns: Vec<&str> = pattern.trim_end_matches('/').split('/').collect();
+    let segments: Vec<&str> = path.trim_end_matches('/').split('/').collect();
+    if patterns.len() != segments.len() {
+        return None;
+    }
+    let mut params = HashMap::new();
+    for (pattern, segment) in patterns.into_iter().zip(segments) {
+        match pattern.strip_prefix('{').and_then(|name| name.strip_suffix('}')) {
+            Some(name) if !segment.is_empty() => { params.insert(name, segment); },
+            Some(_) => return None,
+            None if pattern == segment => (),
+            None => return None,
+        }
+    }
+    Some(params)
+}
+
+
+fn bearer_subject(req: &ServiceRequest) -> Option<String> {
+    let config = req.app_data::<Data<Config>>()?;
+    let token = req.headers().get(header::AUTHORIZATION)?.to_str().ok()?.strip_prefix("Bearer ")?;
+    token::verify_token(&config.jwt, token.trim()).ok().map(|claims| claims.sub)
+}
+
+
+///Reads the `email` field of a JSON body and puts the body back for the handler.
+async fn body_email(req: &mut ServiceRequest) -> Option<String> {
+    let body = req.extract::<Bytes>().await.ok()?;
+    let email = serde_json::from_slice::<Value>(&body).ok()
+        .and_then(|json| json.get("email")?.as_str().map(|email| email.trim().to_lowercase()));
+    let (_, mut payload) = actix_http::h1::Payload::create(true);
+    payload.unread_data(body);
+    req.set_payload(payload.into());
+    email
+}
+
+
+fn insert_headers(headers: &mut header::HeaderMap, decision: &Decision) {
+    let values = [("ratelimit-limit", decision.limit as i64), ("ratelimit-remaining", decision.remaining as i64), ("ratelimit-reset", decision.reset)];
+    for (name, value) in values {
+        headers.insert(HeaderName::from_static(name), HeaderValue::from(value));
+    }
+}
+
+
+///Limits the requests to the routes with a policy, and answers `429 Too Many Requests` once a bucket is empty.
+/// The responses carry the `RateLimit-*` headers of the most restrictive policy.
+pub async fn rate_limit(mut req: ServiceRequest, next: Next<impl MessageBody>) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, ActixError> {
+    let limiter = match req.app_data::<Data<RateLimiter>>() {
+        Some(limiter) => limiter.clone(),
+        None => return Ok(next.call(req).await?.map_into_left_body()),
+    };
+    let path = req.path().to_string();
+    let policies: Vec<(&RateLimitPolicy, HashMap<&str, &str>)> = limiter.policies.iter()
+        .filter(|policy| policy.method.eq_ignore_ascii_case(req.method().as_str()))
+        .filter_map(|policy| Some((policy, match_path(&policy.path, &path)?)))
+        .collect();
+    if policies.is_empty() {
+        return Ok(next.call(req).await?.map_into_left_body());
+    }
+
+    let ip = client_ip(req.request()).map(|address| address.to_string()).unwrap_or_default();
+    let email = match policies.iter().any(|(policy, _)| policy.key == RateLimitKey::Email) {
+        true => body_email(&mut req).await,
+        false => None,
+    };
+    let mut strictest: Option<Decision> = None;
+    for (policy, params) in &policies {
+        let key = match policy.key {
+            RateLimitKey::Ip => None,
+            RateLimitKey::User => params.get("id").map(|id| id.to_string()).or_else(|| bearer_subject(&req)),
+            RateLimitKey::Email => email.clone(),
+        };
+        let (kind, value) = match key {
+            Some(value) => (format!("{:?}", policy.key).to_lowercase(), value),
+            None => ("ip".to_string(), ip.clone()),
+        };
+        let key = format!("{} {} {}:{}", policy.method.to_uppercase(), policy.path, kind, value);
+        let decision = limiter.buckets.take(&key, policy).await?;
+        strictest = match strictest {
+            Some(strictest) if (strictest.allowed, strictest.remaining) <= (decision.allowed, decision.remaining) => Some(strictest),
+            _ => Some(decision),
+        };
+    }
+    let decision = strictest.expect("at least one policy applies");
+
+    if !decision.allowed {
+        let mut response = Error::TooManyRequests(decision.retry_after).error_response();
+        insert_headers(response.headers_mut(), &decision);
+        return Ok(req.into_response(response).map_into_right_body());
+    }
+    let mut response = next.call(req).await?;
+    insert_headers(response.headers_mut(), &decision);
+    Ok(response.map_into_left_body())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_match_path() {
+        assert_eq!(match_path("/signup", "/signup"), Some(HashMap::new()));
+        assert_eq!(match_path("/magic-link/{id}", "/magic-link/abc"), Some(HashMap::from([("id", "abc")])));
+        assert_eq!(match_path("/magic-link/{id}", "/magic-link/"), None);
+        assert_eq!(match_path("/users/verify-email/{id}", "/users/abc"), None);
+        assert_eq!(match_path("/signup", "/login"), None);
+    }
+
+    #[actix_web::test]
+    async fn test_rate_limit_by_email() {
+        use actix_web::{middleware::from_fn, test, web, App, HttpResponse};
+
+        let policy = RateLimitPolicy{method: "POST".into(), path: "/signup".into(), key: RateLimitKey::Email, capacity: 1, period: 60};
+        let limiter = Data::new(RateLimiter{policies: vec![policy], buckets: RateLimitBuckets::Memory(Default::default())});
+        let app = test::init_service(
+            App::new()
+            .wrap(from_fn(rate_limit))
+            .app_data(limiter)
+            .route("/signup", web::post().to(|body: Bytes| async move { HttpResponse::Ok().body(body) }))
+        ).await;
+        let signup = |email: &str| test::TestRequest::post().uri("/signup").set_json(serde_json::json!({"email": email}));
+
+        let response = test::call_service(&app, signup("jane@example.com").to_request()).await;
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.headers().get("ratelimit-remaining").unwrap(), "0");
+        let body = test::read_body(response).await;
+        assert_eq!(body, serde_json::to_vec(&serde_json::json!({"email": "jane@example.com"})).unwrap());
+
+        let response = test::call_service(&app, signup("JANE@example.com").to_request()).await;
+        assert_eq!(response.status(), 429);
+        assert_eq!(response.headers().get("retry-after").unwrap(), "60");
+        assert_eq!(response.headers().get("ratelimit-limit").unwrap(), "1");
+
+        let response = test::call_service(&app, signup("john@example.com").to_request()).await;
+        assert_eq!(response.status(), 200);
+    }
+}
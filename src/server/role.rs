@@ -0,0 +1,144 @@
+use actix_web::{delete, get, post, put, web::{Data, Json, Path}, HttpResponse, Responder, http::StatusCode};
+use super::auth::{Authorized, ManageRoles};
+use crate::{rbac, Error, Id, Role, RoleStore, UserStore};
+use crate::{AUDIT_ROLE_CREATED, AUDIT_ROLE_UPDATED, AUDIT_ROLE_DELETED, AUDIT_ROLE_ASSIGNED, AUDIT_ROLE_UNASSIGNED};
+use super::audit::Audit;
+use serde::Deserialize;
+use serde_json::json;
+use super::*;
+
+
+#[derive(Deserialize)]
+struct RoleUpdate {
+    description: Option<String>,
+    permissions: Option<Vec<String>>,
+}
+
+
+fn parse_id(id: &str) -> Result<Id> {
+    id.parse().map_err(|_| Error::Custom(StatusCode::BAD_REQUEST, "invalid id".into()))
+}
+
+
+#[get("/roles")]
+async fn get_roles(roles: Data<dyn RoleStore>, _: Authorized<ManageRoles>) -> Result<impl Responder> {
+    let roles = rbac::get_roles(&**roles).await?;
+    Ok(HttpResponse::Ok().json(roles))
+}
+
+
+#[post("/roles")]
+async fn create_role(role: Json<Role>, roles: Data<dyn RoleStore>, authorized: Authorized<ManageRoles>, audit: Audit) -> Result<impl Responder> {
+    let role = rbac::create_role(&**roles, role.into_inner()).await?;
+    audit.record(Some(&authorized.caller.user_id), AUDIT_ROLE_CREATED, None, json!({"role": role.name, "permissions": role.permissions})).await;
+    Ok(HttpResponse::Created().json(role))
+}
+
+
+#[put("/roles/{name}")]
+async fn update_role(name: Path<String>, update: Json<RoleUpdate>, roles: Data<dyn RoleStore>, authorized: Authorized<ManageRoles>, audit: Audit) -> Result<impl Responder> {
+    let role = rbac::update_role(&**roles, &name, update.description.as_deref(), update.permissions.as_deref()).await?;
+    audit.record(Some(&authorized.caller.user_id), AUDIT_ROLE_UPDATED, None, json!({"role": role.name, "permissions": role.permissions})).await;
+    Ok(HttpResponse::Ok().json(role))
+}
+
+
+#[delete("/roles/{name}")]
+async fn delete_role(name: Path<String>, roles: Data<dyn RoleStore>, authorized: Authorized<ManageRoles>, audit: Audit) -> Result<impl Responder> {
+    rbac::delete_role(&**roles, &name).await?;
+    audit.record(Some(&authorized.caller.user_id), AUDIT_ROLE_DELETED, None, json!({"role": *name})).await;
+    Ok(HttpResponse::Ok().json(json!("role deleted successfully")))
+}
+
+
+#[get("/users/{id}/roles")]
+async fn get_user_roles(id: Path<String>, users: Data<dyn UserStore>, roles: Data<dyn RoleStore>, _: Authorized<ManageRoles>) -> Result<impl Responder> {
+    let id = parse_id(&id)?;
+    let roles = rbac::get_user_roles(&**users, &**roles, &id).await?;
+    Ok(HttpResponse::Ok().json(roles))
+}
+
+
+#[put("/users/{id}/roles/{role}")]
+async fn assign_role(path: Path<(String, String)>, users: Data<dyn UserStore>, roles: Data<dyn RoleStore>, authorized: Authorized<ManageRoles>, audit: Audit) -> Result<impl Responder> {
+    let (id, role) = path.into_inner();
+    let id = parse_id(&id)?;
+    rbac::assign_role(&**users, &**roles, &id, &role).await?;
+    audit.record(Some(&authorized.caller.user_id), AUDIT_ROLE_ASSIGNED, Some(&id), json!({"role": role})).await;
+    Ok(HttpResponse::Ok().json(json!("role assigned successfully")))
+}
+
+
+#[delete("/users/{id}/roles/{role}")]
+async fn unassign_role(path: Path<(String, String)>, roles: Data<dyn RoleStore>, authorized: Authorized<ManageRoles>, audit: Audit) -> Result<impl Responder> {
+    let (id, role) = path.into_inner();
+    let id = parse_id(&id)?;
+    rbac::unassign_role(&**roles, &id, &role).await?;
+    audit.record(Some(&authorized.caller.user_id), AUDIT_ROLE_UNASSIGNED, Some(&id), json!({"role": role})).await;
+    Ok(HttpResponse::Ok().json(json!("role unassigned successfully")))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{http::header, test, App};
+    use crate::{test_user, token, MemoryStore, ADMIN_ROLE};
+    use super::super::Stores;
+
+    ///Manages roles as an admin and is turned away as a user without the permission.
+    async fn test_manage_roles(stores: Stores) {
+        let config = super::super::test_config();
+        let (admin, user) = (test_user("admin@example.com"), test_user("user@example.com"));
+        stores.users.create_user(&admin, "admin@example.com").await.unwrap();
+        stores.users.create_user(&user, "user@example.com").await.unwrap();
+        stores.roles.assign_role(&admin.id, ADMIN_ROLE).await.unwrap();
+        let bearer = |user: &crate::User| (header::AUTHORIZATION, format!("Bearer {}", token::issue_token(&config.jwt, user, None, None).unwrap()));
+        let (as_admin, as_user) = (bearer(&admin), bearer(&user));
+        let roles = stores.roles.clone();
+        let app = test::init_service(App::new()
+            .app_data(Data::new(config))
+            .configure(|service| stores.configure(service))
+            .service(get_roles)
+            .service(create_role)
+            .service(get_user_roles)
+            .service(assign_role)
+        ).await;
+
+        let support = json!({"name": "support", "permissions": [crate::PERMISSION_USERS_READ]});
+        let res = test::call_service(&app, test::TestRequest::post().uri("/roles").insert_header(as_user.clone()).set_json(&support).to_request()).await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        let res = test::call_service(&app, test::TestRequest::post().uri("/roles").insert_header(as_admin.clone()).set_json(&support).to_request()).await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+        let res = test::call_service(&app, test::TestRequest::post().uri("/roles").insert_header(as_admin.clone()).set_json(&support).to_request()).await;
+        assert_eq!(res.status(), StatusCode::CONFLICT);
+
+        let uri = format!("/users/{}/roles/unknown", user.id.to_hex());
+        let res = test::call_service(&app, test::TestRequest::put().uri(&uri).insert_header(as_admin.clone()).to_request()).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        let uri = format!("/users/{}/roles/support", user.id.to_hex());
+        let res = test::call_service(&app, test::TestRequest::put().uri(&uri).insert_header(as_admin.clone()).to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(roles.has_permission(&user.id, crate::PERMISSION_USERS_READ).await.unwrap());
+        let uri = format!("/users/{}/roles", user.id.to_hex());
+        let body: serde_json::Value = test::call_and_read_body_json(&app, test::TestRequest::get().uri(&uri).insert_header(as_admin.clone()).to_request()).await;
+        assert_eq!(body[0]["name"], "support");
+
+        let res = test::call_service(&app, test::TestRequest::get().uri("/roles").insert_header(as_user).to_request()).await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        let body: serde_json::Value = test::call_and_read_body_json(&app, test::TestRequest::get().uri("/roles").insert_header(as_admin).to_request()).await;
+        let names: Vec<&str> = body.as_array().unwrap().iter().map(|role| role["name"].as_str().unwrap()).collect();
+        assert_eq!(names, [ADMIN_ROLE, "support"]);
+    }
+
+    #[actix_web::test]
+    async fn test_manage_roles_in_memory() {
+        test_manage_roles(Stores::new(MemoryStore::default())).await;
+    }
+
+    #[actix_web::test]
+    async fn test_manage_roles_on_sqlite() {
+        let database = crate::config::Database{url: "sqlite::memory:".into(), ..Default::default()};
+        test_manage_roles(Stores::new(database.init_sqlite().await.unwrap())).await;
+    }
+}
@@ -0,0 +1,96 @@
+use actix_web::{get, post, web::{Data, Form, Path}, HttpRequest, HttpResponse, Responder, http::{header, StatusCode}, cookie::SameSite};
+use crate::saml::{self, ServiceProvider};
+use crate::config::{Config, SamlProvider};
+use crate::{token, Error, IdentityStore, RoleStore, UserStore, AUDIT_IDENTITY_LINKED, AUDIT_LOGIN_SUCCEEDED};
+use crate::email_domain::DomainPolicy;
+use super::oidc::{complete_link, link_cookie, require_token};
+use crate::identity::PendingLink;
+use super::auth::Authenticated;
+use super::audit::Audit;
+use serde::Deserialize;
+use serde_json::json;
+use super::*;
+
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct AcsForm {
+    #[serde(rename = "SAMLResponse")]
+    saml_response: String,
+    relay_state: Option<String>,
+}
+
+
+fn provider<'a>(config: &'a Config, name: &str) -> Result<&'a SamlProvider> {
+    config.saml_provider(name).ok_or_else(|| Error::Custom(StatusCode::NOT_FOUND, "unknown identity provider".into()))
+}
+
+
+fn acs_path(provider: &SamlProvider) -> String {
+    format!("/saml/{}/acs", provider.name)
+}
+
+
+fn service_provider(config: &Config, provider: &SamlProvider) -> ServiceProvider {
+    ServiceProvider {
+        entity_id: config.link(&format!("/saml/{}/metadata", provider.name)),
+        acs_url: config.link(&acs_path(provider)),
+    }
+}
+
+
+///The metadata of this server as a service provider of the identity provider.
+#[get("/saml/{provider}/metadata")]
+async fn saml_metadata(name: Path<String>, config: Data<Config>) -> Result<impl Responder> {
+    let provider = provider(&config, &name)?;
+    let metadata = saml::metadata(&service_provider(&config, provider));
+    Ok(HttpResponse::Ok().content_type("application/samlmetadata+xml").body(metadata))
+}
+
+
+///Redirects the user to the identity provider with an `AuthnRequest`.
+#[get("/saml/{provider}/login")]
+async fn saml_login(name: Path<String>, config: Data<Config>) -> Result<impl Responder> {
+    let provider = provider(&config, &name)?;
+    let url = saml::authn_request_url(provider, &service_provider(&config, provider), &config.jwt, None)?;
+    Ok(HttpResponse::Found().insert_header((header::LOCATION, url.as_str())).finish())
+}
+
+
+///Returns the url the signed in caller has to send their browser to, to link their identity at the provider to their account.
+/// The link is completed when the identity provider has the same browser post its response, with the cookie set here.
+/// The post comes from the identity provider's site, so the cookie has to be sent along with cross site requests.
+#[post("/saml/{provider}/link")]
+async fn saml_link(name: Path<String>, config: Data<Config>, caller: Authenticated) -> Result<impl Responder> {
+    require_token(&caller)?;
+    let provider = provider(&config, &name)?;
+    let (link, secret) = PendingLink::new(&caller.user_id);
+    let url = saml::authn_request_url(provider, &service_provider(&config, provider), &config.jwt, Some(link))?;
+    Ok(HttpResponse::Ok()
+        .cookie(link_cookie(&config, &acs_path(provider), secret, SameSite::None))
+        .json(json!({"url": url.as_str()})))
+}
+
+
+///The assertion consumer service. The identity provider posts its response here.
+/// When the login was started to link the identity, the browser has to be the one which started it.
+#[post("/saml/{provider}/acs")]
+#[allow(clippy::too_many_arguments)]
+async fn saml_acs(name: Path<String>, form: Form<AcsForm>, config: Data<Config>, users: Data<dyn UserStore>, identities: Data<dyn IdentityStore>, roles: Data<dyn RoleStore>, domains: Data<DomainPolicy>, audit: Audit, req: HttpRequest) -> Result<impl Responder> {
+    let provider = provider(&config, &name)?;
+    let sp = service_provider(&config, provider);
+    let (request_id, link) = saml::request_id(provider, &config.jwt, form.relay_state.as_deref())?;
+    let link = match link {
+        Some(link) => Some(complete_link(&req, &config, &acs_path(provider), link)?),
+        None => None,
+    };
+    let link_to = link.as_ref().map(|(user_id, _)| user_id);
+    let user = saml::login(&**users, &**identities, &config.email_normalization, &domains, provider, &sp, &form.saml_response, request_id.as_deref(), link_to).await?;
+    if let Some((_, removal)) = link {
+        audit.record(Some(&user.id), AUDIT_IDENTITY_LINKED, Some(&user.id), json!({"provider": format!("saml:{}", provider.name)})).await;
+        return Ok(HttpResponse::Ok().cookie(removal).json(json!({"user": user})));
+    }
+    audit.record(Some(&user.id), AUDIT_LOGIN_SUCCEEDED, Some(&user.id), json!({"method": "saml", "provider": provider.name})).await;
+    let token = token::issue_user_token(&**roles, &config.jwt, &user, None).await?;
+    Ok(HttpResponse::Ok().json(json!({"user": user, "token": token})))
+}
@@ -1,50 +1,388 @@
-use actix_web::{http::StatusCode, web::{Data, Json, Path}, HttpResponse, delete, put};
-use crate::{User, Value, Mailer};
+use actix_web::{http::{header, StatusCode}, web::{Data, Json, Path, Query}, HttpResponse, HttpResponseBuilder, delete, put};
+use crate::{User, UserStore, VerificationStore, LoginFailureStore, PasswordResetStore, AccountRestorationStore, RoleStore, Value, Mailer, PasswordViolation, PERMISSION_USERS_READ, PERMISSION_USERS_WRITE, PERMISSION_USERS_DELETE, PERMISSION_USERS_EXPORT_HASHES};
+use crate::{AUDIT_SIGNUP, AUDIT_EMAIL_CHANGED, AUDIT_LOGIN_SUCCEEDED, AUDIT_LOGIN_FAILED, AUDIT_PASSWORD_CHANGED, AUDIT_PASSWORD_RESET_REQUESTED, AUDIT_PASSWORD_RESET};
+use crate::{AUDIT_USER_UPDATED, AUDIT_USER_UNLOCKED, AUDIT_USER_DELETED, AUDIT_USER_RESTORED, AUDIT_USERS_EXPORTED};
+use crate::{export, import::FileFormat};
+use futures_util::StreamExt;
+use crate::breach::BreachCorpus;
+use crate::email_domain::DomainPolicy;
+use sqlx::types::Uuid;
+use super::auth::{Authenticated, Authorized, DeleteUsers, ReadUsers, WriteUsers};
+use super::audit::Audit;
+use crate::config::Config;
+use serde::Deserialize;
+use crate::token;
+use crate::user::UserListing;
 use std::collections::HashMap;
 use serde_json::json;
 use argon2::Argon2;
 use crate::user;
 use super::*;
 
+///Adds a `Warning` header for every warning about a password.
+fn warn(mut response: HttpResponseBuilder, warnings: &[PasswordViolation]) -> HttpResponseBuilder {
+    for warning in warnings {
+        response.append_header((header::WARNING, format!("299 - \"{}\"", warning)));
+    }
+    response
+}
+
+
 #[post("/signup")]
-async fn signup(user: Json<User>, data: Data<(Db, Mailer, Argon2<'_>)>, req: HttpRequest) -> Result<impl Responder> {
-    let executor = &data.0;
+#[allow(clippy::too_many_arguments)]
+async fn signup(user: Json<User>, users: Data<dyn UserStore>, verifications: Data<dyn VerificationStore>, data: Data<(Mailer, Argon2<'_>)>, config: Data<Config>, breached: Data<BreachCorpus>, domains: Data<DomainPolicy>, audit: Audit) -> Result<impl Responder> {
     let user = user.into_inner();
-    let mailer = &data.1;
-    let mail_config = &crate::config::Config::read().await.map_err(|e| Error::Custom(StatusCode::INTERNAL_SERVER_ERROR, e.into()))?.mail;
-    let scheme = req.headers().get("X-Forwarded-Proto").and_then(|v| v.to_str().ok()).unwrap_or("http");
-    let host = req.headers().get("Host").and_then(|v| v.to_str().ok()).unwrap_or("localhost");
-    let created_user = user::signup(executor, user, mailer, mail_config, scheme, host).await?;
-    Ok(HttpResponse::Created().json(created_user))
+    let mailer = &data.0;
+    let (created_user, warnings) = user::signup(&**users, &**verifications, user, mailer, &config, &data.1, &breached, &domains).await?;
+    audit.record(Some(&created_user.id), AUDIT_SIGNUP, Some(&created_user.id), json!({"email": created_user.email.address()})).await;
+    Ok(warn(HttpResponse::Created(), &warnings).json(created_user))
+}
+
+
+///Lists the users, filtered, searched and paginated by the query string.
+#[get("/users")]
+async fn get_users(listing: Query<UserListing>, users: Data<dyn UserStore>, _: Authorized<ReadUsers>) -> Result<impl Responder> {
+    let users = user::get_users(&**users, listing.into_inner()).await?;
+    Ok(HttpResponse::Ok().json(users))
+}
+
+
+#[derive(Deserialize)]
+struct ExportQuery {
+    #[serde(default)]
+    format: FileFormat,
+    ///A comma separated list of the fields to export. All of them when not given.
+    fields: Option<String>,
+    #[serde(default)]
+    include_hashes: bool,
+}
+
+
+///Streams every user as JSON Lines or CSV. Password hashes are included only for callers allowed to export them.
+#[get("/users/export")]
+async fn export_users(query: Query<ExportQuery>, users: Data<dyn UserStore>, roles: Data<dyn RoleStore>, authorized: Authorized<ReadUsers>, audit: Audit) -> Result<impl Responder> {
+    if query.include_hashes {
+        authorized.caller.require(&**roles, PERMISSION_USERS_EXPORT_HASHES).await?;
+    }
+    let fields = export::export_fields(query.fields.as_deref(), query.include_hashes)?;
+    audit.record(Some(&authorized.caller.user_id), AUDIT_USERS_EXPORTED, None, json!({"format": query.format.extension(), "fields": fields, "include_hashes": query.include_hashes})).await;
+    let lines = export::export_users(users.into_inner(), query.format, fields).map(|line| line.map(web::Bytes::from));
+    Ok(HttpResponse::Ok()
+        .content_type(query.format.content_type())
+        .insert_header((header::CONTENT_DISPOSITION, format!("attachment; filename=\"users.{}\"", query.format.extension())))
+        .streaming(lines))
+}
+
+
+#[derive(Deserialize)]
+struct Login {
+    email: String,
+    password: String,
+}
+
+
+///Signs a user in with their email and password.
+/// Failed attempts are recorded against the account the email belongs to, if any.
+#[post("/login")]
+#[allow(clippy::too_many_arguments)]
+async fn password_login(credentials: Json<Login>, users: Data<dyn UserStore>, login_failures: Data<dyn LoginFailureStore>, roles: Data<dyn RoleStore>, data: Data<(Mailer, Argon2<'_>)>, config: Data<Config>, audit: Audit, req: HttpRequest) -> Result<impl Responder> {
+    let ip = client_ip(&req);
+    let user = match user::login(&**users, &**login_failures, &data.0, &config, &data.1, &credentials.email, &credentials.password, ip).await {
+        Ok(user) => user,
+        Err(err) => {
+            let target = user::get_user_by_email(&**users, &config, &credentials.email).await.ok().map(|user| user.id);
+            audit.record(None, AUDIT_LOGIN_FAILED, target.as_ref(), json!({"method": "password", "email": credentials.email, "reason": err.to_string()})).await;
+            return Err(err);
+        }
+    };
+    audit.record(Some(&user.id), AUDIT_LOGIN_SUCCEEDED, Some(&user.id), json!({"method": "password"})).await;
+    let token = token::issue_user_token(&**roles, &config.jwt, &user, None).await?;
+    Ok(HttpResponse::Ok().json(json!({"user": user, "token": token})))
+}
+
+
+#[derive(Deserialize)]
+struct PasswordChange {
+    current_password: String,
+    new_password: String,
+}
+
+
+///Changes the password of the caller, who has to know their current one.
+#[put("/users/{id}/password")]
+#[allow(clippy::too_many_arguments)]
+async fn change_password(id: Path<String>, change: Json<PasswordChange>, users: Data<dyn UserStore>, data: Data<(Mailer, Argon2<'_>)>, config: Data<Config>, breached: Data<BreachCorpus>, caller: Authenticated, audit: Audit) -> Result<impl Responder> {
+    let id = id.as_str().parse().map_err(|_|{Error::Custom(StatusCode::BAD_REQUEST, "invalid id".into())})?;
+    if caller.user_id != id {
+        return Err(Error::Forbidden);
+    }
+    let warnings = user::change_password(&**users, &config, &data.1, &breached, &id, &change.current_password, &change.new_password).await?;
+    audit.record(Some(&caller.user_id), AUDIT_PASSWORD_CHANGED, Some(&id), json!({})).await;
+    Ok(warn(HttpResponse::Ok(), &warnings).json(json!("password changed successfully")))
+}
+
+
+#[derive(Deserialize)]
+struct EmailChange {
+    email: String,
+}
+
+
+///Changes the email of a user, who has to verify the new one. The domain policy of signups applies to it.
+#[put("/users/{id}/email")]
+#[allow(clippy::too_many_arguments)]
+async fn change_email(id: Path<String>, change: Json<EmailChange>, users: Data<dyn UserStore>, verifications: Data<dyn VerificationStore>, roles: Data<dyn RoleStore>, data: Data<(Mailer, Argon2<'_>)>, config: Data<Config>, domains: Data<DomainPolicy>, caller: Authenticated, audit: Audit) -> Result<impl Responder> {
+    let id = id.as_str().parse().map_err(|_|{Error::Custom(StatusCode::BAD_REQUEST, "invalid id".into())})?;
+    caller.require_self_or(&**roles, &id, PERMISSION_USERS_WRITE).await?;
+    let previous = user::get_user_by_id(&**users, &id).await?;
+    let user = user::change_email(&**users, &**verifications, &data.0, &config, &domains, &id, &change.email).await?;
+    audit.record(Some(&caller.user_id), AUDIT_EMAIL_CHANGED, Some(&id), json!({"from": previous.email.address(), "to": user.email.address()})).await;
+    Ok(HttpResponse::Ok().json(json!(user)))
+}
+
+
+#[derive(Deserialize)]
+struct PasswordResetRequest {
+    email: String,
+}
+
+
+///Mails a password reset link to the owner of the email, if there is one.
+#[post("/password-reset")]
+async fn request_password_reset(request: Json<PasswordResetRequest>, users: Data<dyn UserStore>, resets: Data<dyn PasswordResetStore>, data: Data<(Mailer, Argon2<'_>)>, config: Data<Config>, audit: Audit) -> Result<impl Responder> {
+    let target = user::request_password_reset(&**users, &**resets, &data.0, &config, &request.email).await?;
+    audit.record(None, AUDIT_PASSWORD_RESET_REQUESTED, target.as_ref(), json!({"email": request.email})).await;
+    Ok(HttpResponse::Accepted().json(json!("if the email belongs to an account, a password reset link was sent to it")))
+}
+
+
+#[derive(Deserialize)]
+struct PasswordResetBody {
+    password: String,
+}
+
+
+///Sets a new password through the link of a password reset email.
+#[post("/password-reset/{id}")]
+#[allow(clippy::too_many_arguments)]
+async fn reset_password(id: Path<String>, body: Json<PasswordResetBody>, users: Data<dyn UserStore>, resets: Data<dyn PasswordResetStore>, login_failures: Data<dyn LoginFailureStore>, data: Data<(Mailer, Argon2<'_>)>, config: Data<Config>, breached: Data<BreachCorpus>, audit: Audit) -> Result<impl Responder> {
+    let id = id.parse::<Uuid>().map_err(|_| Error::Custom(StatusCode::BAD_REQUEST, "invalid password reset id".into()))?;
+    let (user_id, warnings) = user::reset_password(&**users, &**resets, &**login_failures, &config, &data.1, &breached, &id, &body.password).await?;
+    audit.record(Some(&user_id), AUDIT_PASSWORD_RESET, Some(&user_id), json!({})).await;
+    Ok(warn(HttpResponse::Ok(), &warnings).json(json!("password reset successfully")))
+}
+
+
+///Tells how many users still have a password hash made with outdated Argon2 parameters or pepper.
+#[get("/password-hashes")]
+async fn get_password_hash_report(users: Data<dyn UserStore>, config: Data<Config>, _: Authorized<ReadUsers>) -> Result<impl Responder> {
+    let report = user::get_password_hash_report(&**users, &config).await?;
+    Ok(HttpResponse::Ok().json(report))
+}
+
+
+///Lifts the lock put on an account after too many failed logins.
+#[delete("/users/{id}/lock")]
+async fn unlock_user(id: Path<String>, users: Data<dyn UserStore>, login_failures: Data<dyn LoginFailureStore>, config: Data<Config>, authorized: Authorized<WriteUsers>, audit: Audit) -> Result<impl Responder> {
+    let id = id.as_str().parse().map_err(|_|{Error::Custom(StatusCode::BAD_REQUEST, "invalid id".into())})?;
+    user::unlock_user(&**users, &**login_failures, &config, &id).await?;
+    audit.record(Some(&authorized.caller.user_id), AUDIT_USER_UNLOCKED, Some(&id), json!({})).await;
+    Ok(HttpResponse::Ok().json(json!("user unlocked successfully")))
 }
 
 
 #[get("/users/{id}")]
-async fn get_user(id: Path<String>, data: Data<(Db, Mailer, Argon2<'_>)>) -> Result<impl Responder> {
+async fn get_user(id: Path<String>, users: Data<dyn UserStore>, roles: Data<dyn RoleStore>, caller: Authenticated) -> Result<impl Responder> {
     let id = id.into_inner();
     let id = id.as_str().parse().map_err(|_|{Error::Custom(StatusCode::BAD_REQUEST, "invalid id".into())})?;
-    let executor = &data.0;
-    let user = user::get_user_by_id(executor, &id).await?;
+    caller.require_self_or(&**roles, &id, PERMISSION_USERS_READ).await?;
+    let user = user::get_user_by_id(&**users, &id).await?;
     Ok(HttpResponse::Ok().json(user))
 }
 
 
+///Deletes a user, who can be restored until the grace period of the deletion ends.
 #[delete("/users/{id}")]
-async fn delete_user(id: Path<String>, data: Data<(Db, Mailer, Argon2<'_>)>) -> Result<impl Responder> {
+#[allow(clippy::too_many_arguments)]
+async fn delete_user(id: Path<String>, users: Data<dyn UserStore>, restorations: Data<dyn AccountRestorationStore>, roles: Data<dyn RoleStore>, data: Data<(Mailer, Argon2<'_>)>, config: Data<Config>, caller: Authenticated, audit: Audit) -> Result<impl Responder> {
     let id = id.into_inner();
     let id = id.as_str().parse().map_err(|_|{Error::Custom(StatusCode::BAD_REQUEST, "invalid id".into())})?;
-    let executor = &data.0;
-    user::delete_user_by_id(executor, &id).await?;
+    caller.require_self_or(&**roles, &id, PERMISSION_USERS_DELETE).await?;
+    user::delete_user_by_id(&**users, &**restorations, &data.0, &config, &id).await?;
+    audit.record(Some(&caller.user_id), AUDIT_USER_DELETED, Some(&id), json!({})).await;
     Ok(HttpResponse::Ok().json(json!("user delted successfully")))
 }
 
 
+///Restores a deleted account through the link mailed to its owner.
+#[post("/users/restore/{id}")]
+async fn restore_account(id: Path<String>, users: Data<dyn UserStore>, restorations: Data<dyn AccountRestorationStore>, audit: Audit) -> Result<impl Responder> {
+    let id = id.parse::<Uuid>().map_err(|_| Error::Custom(StatusCode::BAD_REQUEST, "invalid restore link".into()))?;
+    let user = user::restore_account(&**users, &**restorations, &id).await?;
+    audit.record(Some(&user.id), AUDIT_USER_RESTORED, Some(&user.id), json!({"method": "link"})).await;
+    Ok(HttpResponse::Ok().json(json!(user)))
+}
+
+
+///Restores a deleted user whose grace period has not ended.
+#[post("/users/{id}/restore")]
+async fn restore_user(id: Path<String>, users: Data<dyn UserStore>, restorations: Data<dyn AccountRestorationStore>, authorized: Authorized<DeleteUsers>, audit: Audit) -> Result<impl Responder> {
+    let id = id.as_str().parse().map_err(|_|{Error::Custom(StatusCode::BAD_REQUEST, "invalid id".into())})?;
+    let user = user::restore_user(&**users, &**restorations, &id).await?;
+    audit.record(Some(&authorized.caller.user_id), AUDIT_USER_RESTORED, Some(&id), json!({"method": "admin"})).await;
+    Ok(HttpResponse::Ok().json(json!(user)))
+}
+
+
 #[put("/users/{id}")]
-async fn update_user(id: Path<String>, data: Data<(Db, Mailer, Argon2<'_>)>, map: Json<HashMap<String, Value>>) -> Result<impl Responder> {
+async fn update_user(id: Path<String>, users: Data<dyn UserStore>, roles: Data<dyn RoleStore>, map: Json<HashMap<String, Value>>, caller: Authenticated, audit: Audit) -> Result<impl Responder> {
     let id = id.into_inner();
     let id = id.as_str().parse().map_err(|_|{Error::Custom(StatusCode::BAD_REQUEST, "invalid id".into())})?;
-    let executor = &data.0;
+    caller.require_self_or(&**roles, &id, PERMISSION_USERS_WRITE).await?;
     let map = map.0;
-    let user = user::update_user_by_id(executor, &id, map).await?;
+    let mut fields: Vec<String> = map.keys().cloned().collect();
+    fields.sort();
+    let user = user::update_user_by_id(&**users, &id, map).await?;
+    audit.record(Some(&caller.user_id), AUDIT_USER_UPDATED, Some(&id), json!({"fields": fields})).await;
     Ok(HttpResponse::Ok().json(json!(user)))
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, App};
+    use crate::MemoryStore;
+    use super::super::Stores;
+
+    ///Signs a user up, verifies their email, logs them in and edits their profile and password,
+    /// with everything but the stores left unreachable.
+    async fn test_account_flow(stores: Stores) {
+        let config = super::super::test_config();
+        let jwt = config.jwt.clone();
+        let (users, verifications) = (stores.users.clone(), stores.verifications.clone());
+        let data = Data::new((Mailer::unencrypted_localhost(), config.argon.initialize_argon2()));
+        let domains = DomainPolicy::load(&config.email_domains).unwrap();
+        let app = test::init_service(App::new()
+            .app_data(data)
+            .app_data(Data::new(config))
+            .app_data(Data::new(BreachCorpus::default()))
+            .app_data(Data::new(domains))
+            .configure(|service| stores.configure(service))
+            .service(signup)
+            .service(verify_user)
+            .service(password_login)
+            .service(get_user)
+            .service(update_user)
+            .service(change_password)
+            .service(change_email)
+            .service(super::super::audit::get_user_activity)
+        ).await;
+
+        let new_user = json!({"email": "jane@example.com", "user_name": "jane", "first_name": "Jane", "last_name": "Doe", "password": "violet-Harbor-2031!"});
+        let res = test::call_service(&app, test::TestRequest::post().uri("/signup").set_json(&new_user).to_request()).await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+        let body: serde_json::Value = test::read_body_json(res).await;
+        assert!(body.get("password").is_none());
+        let user = users.get_user_by_email("jane@example.com").await.unwrap();
+        let res = test::call_service(&app, test::TestRequest::post().uri("/signup").set_json(&new_user).to_request()).await;
+        assert_eq!(res.status(), StatusCode::CONFLICT);
+        let mut shouted = new_user.clone();
+        shouted["email"] = json!("Jane@EXAMPLE.com");
+        let res = test::call_service(&app, test::TestRequest::post().uri("/signup").set_json(&shouted).to_request()).await;
+        assert_eq!(res.status(), StatusCode::CONFLICT);
+        let mut disposable = new_user.clone();
+        disposable["email"] = json!("jane@mailinator.com");
+        let res = test::call_service(&app, test::TestRequest::post().uri("/signup").set_json(&disposable).to_request()).await;
+        assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        let verification = verifications.get_latest_verification_by_user_id(&user.id).await.unwrap();
+        let uri = format!("/users/verify-email/{}?code={}", user.id.to_hex(), verification.code);
+        let res = test::call_service(&app, test::TestRequest::patch().uri(&uri).to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let login = |password: &str| test::TestRequest::post().uri("/login").set_json(json!({"email": "jane@example.com", "password": password})).to_request();
+        let res = test::call_service(&app, login("wrong")).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        let res = test::call_service(&app, login("violet-Harbor-2031!")).await;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let bearer = (header::AUTHORIZATION, format!("Bearer {}", token::issue_token(&jwt, &user, None, None).unwrap()));
+        let uri = format!("/users/{}", user.id.to_hex());
+        let res = test::call_service(&app, test::TestRequest::get().uri(&uri).insert_header(bearer.clone()).to_request()).await;
+        let body: serde_json::Value = test::read_body_json(res).await;
+        assert_eq!(body["email"], json!({"email": "jane@example.com", "verified": true}));
+
+        let req = test::TestRequest::put().uri(&uri).insert_header(bearer.clone()).set_json(json!({"first_name": "Janet"})).to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["first_name"], "Janet");
+
+        let uri = format!("/users/{}/password", user.id.to_hex());
+        let change = |current: &str| json!({"current_password": current, "new_password": "amber-Lantern-1987?"});
+        let res = test::call_service(&app, test::TestRequest::put().uri(&uri).insert_header(bearer.clone()).set_json(change("wrong")).to_request()).await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        let res = test::call_service(&app, test::TestRequest::put().uri(&uri).insert_header(bearer.clone()).set_json(change("violet-Harbor-2031!")).to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let hash = users.get_user_with_password_by_id(&user.id).await.unwrap().password;
+        assert!(crate::password::verify_password(&Default::default(), &hash, "amber-Lantern-1987?"));
+
+        let uri = format!("/users/{}/activity?limit=5", user.id.to_hex());
+        let body: serde_json::Value = test::call_and_read_body_json(&app, test::TestRequest::get().uri(&uri).insert_header(bearer.clone()).to_request()).await;
+        let actions: Vec<&str> = body["items"].as_array().unwrap().iter().map(|event| event["action"].as_str().unwrap()).collect();
+        assert_eq!(actions, ["user.password_changed", "user.updated", "login.succeeded", "login.failed", "user.email_verified"]);
+        assert_eq!(body["items"][3]["details"]["email"], "jane@example.com");
+        let uri = format!("{}&cursor={}", uri, body["next_cursor"].as_str().unwrap());
+        let body: serde_json::Value = test::call_and_read_body_json(&app, test::TestRequest::get().uri(&uri).insert_header(bearer.clone()).to_request()).await;
+        assert_eq!(body["items"][0]["action"], "user.signup");
+        assert!(body["next_cursor"].is_null());
+
+        let uri = format!("/users/{}/email", user.id.to_hex());
+        let res = test::call_service(&app, test::TestRequest::put().uri(&uri).insert_header(bearer.clone()).set_json(json!({"email": "jane@mailinator.com"})).to_request()).await;
+        assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let req = test::TestRequest::put().uri(&uri).insert_header(bearer).set_json(json!({"email": "Janet@example.com"})).to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["email"], json!({"email": "Janet@example.com", "verified": false}));
+        assert_eq!(users.get_user_by_email("janet@example.com").await.unwrap().id, user.id);
+    }
+
+    #[actix_web::test]
+    async fn test_account_flow_in_memory() {
+        test_account_flow(Stores::new(MemoryStore::default())).await;
+    }
+
+    #[actix_web::test]
+    async fn test_account_flow_on_sqlite() {
+        let database = crate::config::Database{url: "sqlite::memory:".into(), ..Default::default()};
+        test_account_flow(Stores::new(database.init_sqlite().await.unwrap())).await;
+    }
+
+    ///Signs up with an id, an email claimed to be verified and a verification time,
+    /// none of which the user gets, so identities with the email are not linked to them.
+    #[actix_web::test]
+    async fn test_self_verified_signup_is_not_linkable() {
+        let config = super::super::test_config();
+        let stores = Stores::new(MemoryStore::default());
+        let users = stores.users.clone();
+        let app = test::init_service(App::new()
+            .app_data(Data::new((Mailer::unencrypted_localhost(), config.argon.initialize_argon2())))
+            .app_data(Data::new(DomainPolicy::load(&config.email_domains).unwrap()))
+            .app_data(Data::new(config))
+            .app_data(Data::new(BreachCorpus::default()))
+            .configure(|service| stores.configure(service))
+            .service(signup)
+        ).await;
+
+        let id = crate::Id::default();
+        let new_user = json!({
+            "id": id.to_hex(), "email": {"email": "jane@example.com", "verified": true}, "email_verified_at": "2020-01-01T00:00:00Z",
+            "user_name": "jane", "first_name": "Jane", "last_name": "Doe", "password": "violet-Harbor-2031!",
+        });
+        let res = test::call_service(&app, test::TestRequest::post().uri("/signup").set_json(&new_user).to_request()).await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+        let user = users.get_user_by_email("jane@example.com").await.unwrap();
+        assert_ne!(user.id, id);
+        assert_eq!(user.verified_at(), None);
+        let email = "jane@example.com".parse().unwrap();
+        assert!(!crate::identity::may_link(&user, &["example.com".to_string()], &email));
+    }
+}
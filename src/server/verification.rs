@@ -1,5 +1,7 @@
 use actix_web::{get, patch, web::{Data, Path, Query}, HttpResponse, Responder, http::StatusCode};
-use crate::{verification, Error, Mailer};
+use crate::{verification, Error, UserStore, VerificationStore, AUDIT_EMAIL_VERIFIED};
+use super::audit::Audit;
+use serde_json::json;
 use serde::Deserialize;
 use sqlx::types::Uuid;
 use crate::Id;
@@ -14,14 +16,14 @@ struct VerifyQuery {
 
 
 #[get("/magic-link/{id}")]
-async fn verify_magic_link(id: Path<String>, data: Data<(Db, Mailer)>) -> Result<impl Responder> {
+async fn verify_magic_link(id: Path<String>, users: Data<dyn UserStore>, verifications: Data<dyn VerificationStore>, audit: Audit) -> Result<impl Responder> {
     let id_str = id.into_inner();
     let verification_id = id_str.parse::<Uuid>().map_err(|_| {
         Error::Custom(StatusCode::BAD_REQUEST, "Invalid UUID format".into())
     })?;
 
-    let executor = &data.0;
-    let updated_user = verification::verify_magic_link(executor, &verification_id).await?;
+    let updated_user = verification::verify_magic_link(&**users, &**verifications, &verification_id).await?;
+    audit.record(Some(&updated_user.id), AUDIT_EMAIL_VERIFIED, Some(&updated_user.id), json!({"method": "magic_link"})).await;
 
     Ok(HttpResponse::Ok().json(updated_user))
 }
@@ -30,7 +32,9 @@ async fn verify_magic_link(id: Path<String>, data: Data<(Db, Mailer)>) -> Result
 async fn verify_user(
     id: Path<String>,
     query: Query<VerifyQuery>,
-    data: Data<(Db, Mailer)>,
+    users: Data<dyn UserStore>,
+    verifications: Data<dyn VerificationStore>,
+    audit: Audit,
 ) -> Result<impl Responder> {
     let id_str = id.into_inner();
     let user_id = id_str.parse::<Id>().map_err(|_| {
@@ -39,8 +43,8 @@ async fn verify_user(
 
     let code = &query.code;
 
-    let executor = &data.0;
-    let updated_user = verification::verify_code_and_update_user(executor, user_id, code).await?;
+    let updated_user = verification::verify_code_and_update_user(&**users, &**verifications, user_id, code).await?;
+    audit.record(Some(&updated_user.id), AUDIT_EMAIL_VERIFIED, Some(&updated_user.id), json!({"method": "code"})).await;
 
     Ok(HttpResponse::Ok().json(updated_user))
 }